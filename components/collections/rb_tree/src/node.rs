@@ -161,6 +161,17 @@ impl<T> Node<T> {
         }
     }
 
+    // pushes mutable references to every value
+    // under this node onto out, in order
+    #[cfg(feature = "map")]
+    pub fn values_mut<'a>(&'a mut self, out: &mut Vec<&'a mut T>) {
+        if let Internal(n) = self {
+            n.l_child.values_mut(out);
+            out.push(&mut n.value);
+            n.r_child.values_mut(out);
+        }
+    }
+
     pub fn swap_colour(&mut self) {
         if let Internal(n) = self {
             n.swap_colour();
@@ -1,6 +1,5 @@
 use crate::helpers::write_to_level;
 use crate::mapper::Mapper;
-use crate::rbtree;
 use crate::{RBMap, RBTree};
 
 use std::fmt::{Debug, Display, Formatter, Result};
@@ -542,8 +541,10 @@ impl<K: PartialOrd, V> RBMap<K, V> {
     /// assert_eq!(pairs.next(), None);
     /// ```
     pub fn iter_mut(&mut self) -> IterMut<K, V> {
+        let mut ordered = Vec::with_capacity(self.len());
+        self.map.root.values_mut(&mut ordered);
         IterMut {
-            iter: self.map.iter(),
+            iter: ordered.into_iter(),
         }
     }
 
@@ -906,22 +907,15 @@ impl<'a, K: PartialOrd, V> ExactSizeIterator for ValuesMut<'a, K, V> {
 impl<'a, K: PartialOrd, V> FusedIterator for ValuesMut<'a, K, V> {}
 
 pub struct IterMut<'a, K: PartialOrd, V> {
-    iter: rbtree::Iter<'a, Mapper<K, V>>,
+    iter: std::vec::IntoIter<&'a mut Mapper<K, V>>,
 }
 
 impl<'a, K: PartialOrd, V> Iterator for IterMut<'a, K, V> {
     type Item = (&'a K, &'a mut V);
 
     fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
-        let next = self.iter.next();
-        match next {
-            Some(iv) => {
-                let v = unsafe {
-                    let ptr = iv as *const Mapper<K, V>;
-                    &mut *(ptr as *mut Mapper<K, V>)
-                };
-                Some(v.mut_pair())
-            }
+        match self.iter.next() {
+            Some(v) => Some(v.mut_pair()),
             None => None,
         }
     }
@@ -1,8 +1,9 @@
 use super::{
-    checksum::SIOChksum, HOST_BYTEORDER, SPA_ASIZEBITS, SPA_COMPRESSBITS, SPA_DVAS_PER_BP,
-    SPA_MINBLOCKSHIFT, SPA_VDEVBITS,
+    checksum::SIOChksum, error::BlkptrError, BPE_PAYLOAD_SIZE, HOST_BYTEORDER, SIO_DATA_IV_LEN,
+    SIO_DATA_MAC_LEN, SIO_DATA_SALT_LEN, SPA_ASIZEBITS, SPA_BLKPTRSIZE, SPA_COMPRESSBITS,
+    SPA_DVASIZE, SPA_DVAS_PER_BP, SPA_LSIZEBITS, SPA_MINBLOCKSHIFT, SPA_PSIZEBITS, SPA_VDEVBITS,
 };
-use crate::sio;
+use crate::{dmu, sio};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use sys::BitOptExt;
 
@@ -15,11 +16,37 @@ pub enum EmbeddedType {
     Types,
 }
 
+/// Reads the `i`th 64-bit word of an on-disk structure, swapping it when the
+/// structure was written with the other byte order.
+#[inline]
+pub(crate) fn read_word(buf: &[u8], i: usize, byteswap: bool) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&buf[i * 8..(i + 1) * 8]);
+    let w = u64::from_ne_bytes(b);
+    if byteswap {
+        w.swap_bytes()
+    } else {
+        w
+    }
+}
+
+#[inline]
+pub(crate) fn write_word(buf: &mut [u8], i: usize, byteswap: bool, w: u64) {
+    let w = if byteswap { w.swap_bytes() } else { w };
+    buf[i * 8..(i + 1) * 8].copy_from_slice(&w.to_ne_bytes());
+}
+
+// Number of 64-bit words in a blkptr, and the words that are not payload in
+// an embedded bp.
+const BP_NUM_WORDS: usize = SPA_BLKPTRSIZE / 8;
+const BP_PROP_WORD: usize = 6;
+const BP_BIRTH_WORD: usize = 10;
+
 /// All SPA data is represented by 128-bit data virtual addresses (DVAs).
 /// The members of the Dva should be considered opaque outside the SPA.
 #[derive(Debug, Clone)]
 pub struct Dva {
-    dva_word: [u64; 2],
+    pub(super) dva_word: [u64; 2],
 }
 
 impl Dva {
@@ -30,6 +57,24 @@ impl Dva {
         }
     }
 
+    /// Decodes a DVA from its 16-byte on-disk form. `byteswap` is set when the
+    /// block holding the DVA was written with the non-native byte order.
+    #[inline]
+    pub fn from_bytes(buf: &[u8; SPA_DVASIZE], byteswap: bool) -> Self {
+        Dva {
+            dva_word: [read_word(buf, 0, byteswap), read_word(buf, 1, byteswap)],
+        }
+    }
+
+    /// Encodes the DVA into its 16-byte on-disk form.
+    #[inline]
+    pub fn to_bytes(&self, byteswap: bool) -> [u8; SPA_DVASIZE] {
+        let mut buf = [0u8; SPA_DVASIZE];
+        write_word(&mut buf, 0, byteswap, self.dva_word[0]);
+        write_word(&mut buf, 1, byteswap, self.dva_word[1]);
+        buf
+    }
+
     #[inline]
     pub fn get_asize(&self) -> u64 {
         self.dva_word[0].bf_get_sb(0, SPA_ASIZEBITS, SPA_MINBLOCKSHIFT, 0)
@@ -70,14 +115,15 @@ impl Dva {
         self.dva_word[1].bf_set_sb(0, 63, SPA_MINBLOCKSHIFT, 0, offset)
     }
 
+    /// The G bit: the DVA points at a gang header rather than at the data.
     #[inline]
     pub fn get_gang(&self) -> u64 {
-        self.dva_word[1].bf_get_sb(0, 63, SPA_MINBLOCKSHIFT, 0)
+        self.dva_word[1].bf_get(63, 1)
     }
 
     #[inline]
     pub fn set_gang(&mut self, x: u64) {
-        self.dva_word[1].bf_set_sb(0, 63, SPA_MINBLOCKSHIFT, 0, x)
+        self.dva_word[1].bf_set(63, 1, x)
     }
 
     #[inline]
@@ -99,6 +145,7 @@ impl PartialEq for Dva {
 
 /// Some checksums/hashes need a 256-bit initialization salt. This salt is kept
 /// secret and is suitable for use in MAC algorithms as the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SIOCheckSumSalt {
     pub zcs_bytes: [u8; 32],
 }
@@ -368,6 +415,73 @@ impl Blkptr {
         }
     }
 
+    /// Decodes a block pointer from its 128-byte on-disk form, following the
+    /// word layout described above.
+    ///
+    /// A blkptr is always stored in the byte order of the block that contains
+    /// it, which is recorded in the B bit of the parent blkptr. Callers reading
+    /// bps out of an indirect block should therefore pass
+    /// `parent.should_byteswap()`.
+    pub fn from_bytes(buf: &[u8; SPA_BLKPTRSIZE], byteswap: bool) -> Self {
+        let mut bp = Blkptr::new();
+        for i in 0..BP_NUM_WORDS {
+            *bp.word_mut(i) = read_word(buf, i, byteswap);
+        }
+        bp
+    }
+
+    /// Encodes the block pointer into its 128-byte on-disk form. `byteswap`
+    /// must match the byte order of the block the bp is written into.
+    pub fn to_bytes(&self, byteswap: bool) -> [u8; SPA_BLKPTRSIZE] {
+        let mut buf = [0u8; SPA_BLKPTRSIZE];
+        for i in 0..BP_NUM_WORDS {
+            write_word(&mut buf, i, byteswap, self.word(i));
+        }
+        buf
+    }
+
+    /// Returns the `i`th 64-bit word of the on-disk layout.
+    #[inline]
+    fn word(&self, i: usize) -> u64 {
+        match i {
+            0..=5 => self.blk_dva[i / 2].dva_word[i % 2],
+            6 => self.blk_prop,
+            7 | 8 => self.blk_pad[i - 7],
+            9 => self.blk_phys_birth,
+            10 => self.blk_birth,
+            11 => self.blk_fill,
+            12..=15 => self.blk_cksum.zc_word[i - 12],
+            _ => unreachable!("blkptr has {} words", BP_NUM_WORDS),
+        }
+    }
+
+    #[inline]
+    fn word_mut(&mut self, i: usize) -> &mut u64 {
+        match i {
+            0..=5 => &mut self.blk_dva[i / 2].dva_word[i % 2],
+            6 => &mut self.blk_prop,
+            7 | 8 => &mut self.blk_pad[i - 7],
+            9 => &mut self.blk_phys_birth,
+            10 => &mut self.blk_birth,
+            11 => &mut self.blk_fill,
+            12..=15 => &mut self.blk_cksum.zc_word[i - 12],
+            _ => unreachable!("blkptr has {} words", BP_NUM_WORDS),
+        }
+    }
+
+    /// Decodes every blkptr in an indirect block. The children are stored in
+    /// the byte order described by `parent`.
+    pub fn children_from_bytes(parent: &Blkptr, buf: &[u8]) -> Vec<Blkptr> {
+        let byteswap = parent.should_byteswap();
+        buf.chunks_exact(SPA_BLKPTRSIZE)
+            .map(|c| {
+                let mut b = [0u8; SPA_BLKPTRSIZE];
+                b.copy_from_slice(c);
+                Blkptr::from_bytes(&b, byteswap)
+            })
+            .collect()
+    }
+
     #[inline]
     pub fn get_etype(&self) -> u64 {
         self.blk_prop.bf_get(40, 8)
@@ -378,29 +492,141 @@ impl Blkptr {
         self.blk_prop.bf_set(40, 8, val)
     }
 
+    /// Logical size of an embedded bp, in bytes.
     #[inline]
-    pub fn get_lsize(&self) -> u64 {
+    pub fn bpe_get_lsize(&self) -> u64 {
+        assert!(self.is_embedded());
         self.blk_prop.bf_get_sb(0, 25, 0, 1)
     }
 
     #[inline]
-    pub fn set_lsize(&mut self, lsize: u64) {
+    pub fn bpe_set_lsize(&mut self, lsize: u64) {
+        assert!(self.is_embedded());
         self.blk_prop.bf_set_sb(0, 25, 0, 1, lsize);
     }
 
+    /// Size of the embedded payload after compression, in bytes.
     #[inline]
-    pub fn get_psize(&self) -> u64 {
+    pub fn bpe_get_psize(&self) -> u64 {
+        assert!(self.is_embedded());
         self.blk_prop.bf_get_sb(25, 7, 0, 1)
     }
 
     #[inline]
-    pub fn set_psize(&mut self, psize: u64) {
+    pub fn bpe_set_psize(&mut self, psize: u64) {
+        assert!(self.is_embedded());
         self.blk_prop.bf_set_sb(25, 7, 0, 1, psize);
     }
 
+    /// Logical size in bytes. Embedded bps only carry a logical size when
+    /// they hold data; other embedded types (e.g. redacted) report zero.
     #[inline]
-    pub fn is_payload_word(&self, wp: u64) -> bool {
-        (wp != self.blk_prop) && (wp != self.blk_birth)
+    pub fn get_lsize(&self) -> u64 {
+        if self.is_embedded() {
+            let data: u8 = EmbeddedType::Data.into();
+            if self.get_etype() == data as u64 {
+                return self.bpe_get_lsize();
+            }
+            return 0;
+        }
+        self.blk_prop
+            .bf_get_sb(0, SPA_LSIZEBITS, SPA_MINBLOCKSHIFT, 1)
+    }
+
+    #[inline]
+    pub fn set_lsize(&mut self, lsize: u64) {
+        assert!(!self.is_embedded());
+        self.blk_prop
+            .bf_set_sb(0, SPA_LSIZEBITS, SPA_MINBLOCKSHIFT, 1, lsize);
+    }
+
+    /// Physical size in bytes. Embedded bps occupy no space on disk.
+    #[inline]
+    pub fn get_psize(&self) -> u64 {
+        if self.is_embedded() {
+            return 0;
+        }
+        self.blk_prop
+            .bf_get_sb(16, SPA_PSIZEBITS, SPA_MINBLOCKSHIFT, 1)
+    }
+
+    #[inline]
+    pub fn set_psize(&mut self, psize: u64) {
+        assert!(!self.is_embedded());
+        self.blk_prop
+            .bf_set_sb(16, SPA_PSIZEBITS, SPA_MINBLOCKSHIFT, 1, psize);
+    }
+
+    /// Whether word `i` of an embedded bp carries payload. `blk_prop` and
+    /// `blk_birth` keep their usual meaning.
+    #[inline]
+    pub fn is_payload_word(i: usize) -> bool {
+        i != BP_PROP_WORD && i != BP_BIRTH_WORD
+    }
+
+    /// Stores `data` in the payload words of an embedded bp. `data` holds the
+    /// payload after compression with `comp`, and `lsize` is its logical
+    /// size. Bytes are packed into the low bits of each word first, so the
+    /// payload reads back correctly once the words are in host order.
+    pub fn set_embedded_payload(
+        &mut self,
+        etype: EmbeddedType,
+        comp: sio::SIOCompress,
+        data: &[u8],
+        lsize: u64,
+    ) -> Result<(), BlkptrError> {
+        let psize = data.len() as u64;
+        if data.len() > BPE_PAYLOAD_SIZE {
+            return Err(BlkptrError::PayloadTooLarge(data.len()));
+        }
+        if psize == 0
+            || lsize == 0
+            || lsize > (1 << 25)
+            || (comp == sio::SIOCompress::OFF && lsize != psize)
+        {
+            return Err(BlkptrError::InvalidEmbeddedSize { lsize, psize });
+        }
+
+        self.zero();
+        self.set_embeded(1);
+        let et: u8 = etype.into();
+        self.set_etype(et as u64);
+        let c: u8 = comp.into();
+        self.set_compress(c as u64);
+        self.set_byteorder(HOST_BYTEORDER);
+        self.bpe_set_lsize(lsize);
+        self.bpe_set_psize(psize);
+
+        let words = (0..BP_NUM_WORDS).filter(|i| Blkptr::is_payload_word(*i));
+        for (chunk, i) in data.chunks(8).zip(words) {
+            let mut w = 0u64;
+            for (j, b) in chunk.iter().enumerate() {
+                w.bf_set((j * 8) as u64, 8, *b as u64);
+            }
+            *self.word_mut(i) = w;
+        }
+        Ok(())
+    }
+
+    /// Reads back the (possibly compressed) payload of an embedded bp. The
+    /// returned buffer is `bpe_get_psize()` bytes long.
+    pub fn get_embedded_payload(&self) -> Result<Vec<u8>, BlkptrError> {
+        if !self.is_embedded() {
+            return Err(BlkptrError::NotEmbedded);
+        }
+        let psize = self.bpe_get_psize() as usize;
+        let mut buf = Vec::with_capacity(psize);
+        let words = (0..BP_NUM_WORDS).filter(|i| Blkptr::is_payload_word(*i));
+        for i in words.take(psize.div_ceil(8)) {
+            let w = self.word(i);
+            for j in 0..8 {
+                if buf.len() == psize {
+                    break;
+                }
+                buf.push(w.bf_get(j * 8, 8) as u8);
+            }
+        }
+        Ok(buf)
     }
 
     #[inline]
@@ -424,19 +650,22 @@ impl Blkptr {
     }
 
     #[inline]
-    pub fn get_checksum(&self) -> sio::SIOChecksum {
+    pub fn get_checksum(&self) -> Result<sio::SIOChecksum, BlkptrError> {
         if self.is_embedded() {
-            return sio::SIOChecksum::OFF;
+            return Ok(sio::SIOChecksum::OFF);
         }
-        let cks: sio::SIOChecksum = (self.blk_prop.bf_get(40, 8) as u8).try_into().unwrap();
-        cks
+        let val = self.blk_prop.bf_get(40, 8);
+        sio::SIOChecksum::try_from(val as u8)
+            .ok()
+            .filter(|&c| c != sio::SIOChecksum::FUNCTIONS)
+            .ok_or(BlkptrError::InvalidChecksum(val))
     }
 
     #[inline]
     pub fn set_checksum(&mut self, cks: sio::SIOChecksum) {
-        assert!(self.is_embedded());
+        assert!(!self.is_embedded());
         let val: u8 = cks.into();
-        self.blk_prop.bf_set(39, 1, val as u64);
+        self.blk_prop.bf_set(40, 8, val as u64);
     }
 
     #[inline]
@@ -449,6 +678,20 @@ impl Blkptr {
         self.blk_prop.bf_set(48, 8, val as u64)
     }
 
+    /// The legacy object type of the block, or `None` for new-style types,
+    /// which are only described by their flag bits.
+    #[inline]
+    pub fn get_object_type(&self) -> Option<dmu::DmuObjectType> {
+        dmu::DmuObjectType::try_from(self.get_type())
+            .ok()
+            .filter(|t| *t != dmu::DmuObjectType::NUMTYPES)
+    }
+
+    #[inline]
+    pub fn set_object_type(&mut self, ot: dmu::DmuObjectType) {
+        self.set_type(ot.into())
+    }
+
     #[inline]
     pub fn get_level(&self) -> u8 {
         self.blk_prop.bf_get(56, 5) as u8
@@ -472,19 +715,17 @@ impl Blkptr {
 
     #[inline]
     pub fn is_encrypted(&self) -> bool {
-        self.get_user_crypt() && self.get_level() <= 0
-        //  && !DMU_OT_IS_ENCRYPTED(self.get_type())
+        self.get_user_crypt() && self.get_level() == 0 && dmu::ot_is_encrypted(self.get_type())
     }
 
     #[inline]
     pub fn is_authenticated(&self) -> bool {
-        self.get_user_crypt() && (self.get_type() <= 0)
-        // && !DMU_OT_IS_ENCRYPTED(self.get_type())
+        self.get_user_crypt() && self.get_level() == 0 && !dmu::ot_is_encrypted(self.get_type())
     }
 
     #[inline]
     pub fn has_indirect_mac_chksum(&self) -> bool {
-        self.get_user_crypt() && (self.get_type() > 0)
+        self.get_user_crypt() && self.get_level() > 0
     }
 
     #[inline]
@@ -540,6 +781,14 @@ impl Blkptr {
         self.blk_phys_birth = physical;
     }
 
+    /// Embedded bps only record a logical birth; the physical birth word is
+    /// part of the payload.
+    #[inline]
+    pub fn set_birth_embedded(&mut self, logical: u64) {
+        assert!(self.is_embedded());
+        self.blk_birth = logical;
+    }
+
     #[inline]
     pub fn get_fill(&self) -> u64 {
         if self.is_encrypted() {
@@ -561,21 +810,150 @@ impl Blkptr {
     }
 
     #[inline]
-    pub fn get_iv2(&self) -> u64 {
-        assert!(self.is_encrypted());
-        self.blk_fill.bf_get(32, 32)
+    pub fn get_iv2(&self) -> Result<u64, BlkptrError> {
+        if !self.is_encrypted() {
+            return Err(BlkptrError::NotEncrypted);
+        }
+        Ok(self.blk_fill.bf_get(32, 32))
     }
 
     #[inline]
-    pub fn set_iv2(&mut self, iv2: u64) {
-        assert!(self.is_encrypted());
-        self.blk_fill.bf_set(32, 32, iv2)
+    pub fn set_iv2(&mut self, iv2: u64) -> Result<(), BlkptrError> {
+        if !self.is_encrypted() {
+            return Err(BlkptrError::NotEncrypted);
+        }
+        self.blk_fill.bf_set(32, 32, iv2);
+        Ok(())
+    }
+
+    // The encryption parameters are byte strings. They are copied into the
+    // bp words as-is when the bp is in host byte order, and swapped otherwise,
+    // so that they read back identically on any host.
+    #[inline]
+    fn crypt_word(&self, bytes: &[u8]) -> u64 {
+        let mut b = [0u8; 8];
+        b[..bytes.len()].copy_from_slice(bytes);
+        let w = u64::from_ne_bytes(b);
+        if self.should_byteswap() {
+            w.swap_bytes()
+        } else {
+            w
+        }
+    }
+
+    #[inline]
+    fn crypt_bytes(&self, w: u64) -> [u8; 8] {
+        if self.should_byteswap() {
+            w.swap_bytes().to_ne_bytes()
+        } else {
+            w.to_ne_bytes()
+        }
+    }
+
+    /// Salt used to derive the encryption key, stored in DVA[2].
+    pub fn get_salt(&self) -> Result<[u8; SIO_DATA_SALT_LEN], BlkptrError> {
+        if !self.is_encrypted() {
+            return Err(BlkptrError::NotEncrypted);
+        }
+        Ok(self.crypt_bytes(self.blk_dva[2].dva_word[0]))
+    }
+
+    pub fn set_salt(&mut self, salt: &[u8; SIO_DATA_SALT_LEN]) -> Result<(), BlkptrError> {
+        if !self.is_encrypted() {
+            return Err(BlkptrError::NotEncrypted);
+        }
+        self.blk_dva[2].dva_word[0] = self.crypt_word(salt);
+        Ok(())
+    }
+
+    /// The 96-bit IV: the first 64 bits live in DVA[2], the last 32 bits in
+    /// the upper half of the fill count.
+    pub fn get_iv(&self) -> Result<[u8; SIO_DATA_IV_LEN], BlkptrError> {
+        if !self.is_encrypted() {
+            return Err(BlkptrError::NotEncrypted);
+        }
+        let mut iv = [0u8; SIO_DATA_IV_LEN];
+        iv[..8].copy_from_slice(&self.crypt_bytes(self.blk_dva[2].dva_word[1]));
+        let iv2 = self.get_iv2()? as u32;
+        let iv2 = if self.should_byteswap() {
+            iv2.swap_bytes()
+        } else {
+            iv2
+        };
+        iv[8..].copy_from_slice(&iv2.to_ne_bytes());
+        Ok(iv)
+    }
+
+    pub fn set_iv(&mut self, iv: &[u8; SIO_DATA_IV_LEN]) -> Result<(), BlkptrError> {
+        if !self.is_encrypted() {
+            return Err(BlkptrError::NotEncrypted);
+        }
+        self.blk_dva[2].dva_word[1] = self.crypt_word(&iv[..8]);
+        let mut b = [0u8; 4];
+        b.copy_from_slice(&iv[8..]);
+        let iv2 = u32::from_ne_bytes(b);
+        let iv2 = if self.should_byteswap() {
+            iv2.swap_bytes()
+        } else {
+            iv2
+        };
+        self.set_iv2(iv2 as u64)
+    }
+
+    #[inline]
+    fn get_mac_words(&self) -> [u8; SIO_DATA_MAC_LEN] {
+        let mut mac = [0u8; SIO_DATA_MAC_LEN];
+        mac[..8].copy_from_slice(&self.crypt_bytes(self.blk_cksum.zc_word[2]));
+        mac[8..].copy_from_slice(&self.crypt_bytes(self.blk_cksum.zc_word[3]));
+        mac
+    }
+
+    #[inline]
+    fn set_mac_words(&mut self, mac: &[u8; SIO_DATA_MAC_LEN]) {
+        self.blk_cksum.zc_word[2] = self.crypt_word(&mac[..8]);
+        self.blk_cksum.zc_word[3] = self.crypt_word(&mac[8..]);
+    }
+
+    /// The 128-bit MAC of an encrypted or authenticated block, stored in
+    /// checksum words 2 and 3.
+    pub fn get_mac(&self) -> Result<[u8; SIO_DATA_MAC_LEN], BlkptrError> {
+        if !self.is_protected() {
+            return Err(BlkptrError::NotProtected);
+        }
+        Ok(self.get_mac_words())
+    }
+
+    pub fn set_mac(&mut self, mac: &[u8; SIO_DATA_MAC_LEN]) -> Result<(), BlkptrError> {
+        if !self.is_protected() {
+            return Err(BlkptrError::NotProtected);
+        }
+        self.set_mac_words(mac);
+        Ok(())
+    }
+
+    /// Indirect bps of encrypted datasets store a checksum of the MACs of
+    /// their children in the MAC words instead.
+    pub fn get_indirect_mac_cksum(&self) -> Result<[u8; SIO_DATA_MAC_LEN], BlkptrError> {
+        if !self.has_indirect_mac_chksum() {
+            return Err(BlkptrError::NoIndirectMac);
+        }
+        Ok(self.get_mac_words())
+    }
+
+    pub fn set_indirect_mac_cksum(
+        &mut self,
+        mac: &[u8; SIO_DATA_MAC_LEN],
+    ) -> Result<(), BlkptrError> {
+        if !self.has_indirect_mac_chksum() {
+            return Err(BlkptrError::NoIndirectMac);
+        }
+        self.set_mac_words(mac);
+        Ok(())
     }
 
     #[inline]
     pub fn is_metadata(&self) -> bool {
-        self.get_level() > 0
-        // || (DMU_OT_IS_METADATA(BP_GET_TYPE(bp)))
+        self.get_level() > 0 || dmu::ot_is_metadata(self.get_type())
     }
 
     #[inline]
@@ -711,7 +1089,13 @@ impl PartialEq for Blkptr {
 
 #[cfg(test)]
 mod tests {
-    use super::Dva;
+    use super::{Blkptr, Dva, EmbeddedType};
+    use crate::blkptr::{
+        error::BlkptrError, sample_bp, BPE_PAYLOAD_SIZE, SIO_DATA_IV_LEN, SIO_DATA_MAC_LEN,
+        SPA_BLKPTRSIZE, SPA_DVASIZE,
+    };
+    use crate::dmu::{DmuObjectType, DMU_OT_METADATA, DMU_OT_NEWTYPE};
+    use crate::sio::SIOCompress;
 
     #[test]
     pub fn dva_asize() {
@@ -732,4 +1116,237 @@ mod tests {
         d.set_offset(offset);
         assert_eq!(d.get_offset(), offset);
     }
+
+    #[test]
+    fn dva_gang_bit() {
+        let mut d = Dva::new();
+        d.set_offset(0x7fff_fe00);
+        d.set_gang(1);
+        assert_eq!(d.get_gang(), 1);
+        assert_eq!(d.get_offset(), 0x7fff_fe00);
+        assert_eq!(d.dva_word[1] >> 63, 1);
+
+        d.set_offset(0x1000);
+        assert_eq!(d.get_gang(), 1);
+        d.set_gang(0);
+        assert_eq!(d.get_offset(), 0x1000);
+        assert_eq!(d.dva_word[1], 0x1000 >> 9);
+
+        let mut bp = Blkptr::new();
+        bp.set_lsize(0x20000);
+        bp.set_psize(0x20000);
+        for dva in bp.blk_dva.iter_mut().take(2) {
+            dva.set_asize(0x1000);
+            dva.set_offset(0x40_0000);
+        }
+        assert!(!bp.is_gang());
+        assert_eq!(bp.count_gang(), 0);
+        bp.blk_dva[0].set_gang(1);
+        bp.blk_dva[1].set_gang(1);
+        assert!(bp.is_gang());
+        assert_eq!(bp.count_gang(), 2);
+    }
+
+    #[test]
+    pub fn dva_bytes_roundtrip() {
+        let mut d = Dva::new();
+        d.set_asize(8192);
+        d.set_vdev(9);
+        d.set_offset(1 << 30);
+
+        for byteswap in [false, true] {
+            let buf = d.to_bytes(byteswap);
+            assert_eq!(buf.len(), SPA_DVASIZE);
+            assert_eq!(Dva::from_bytes(&buf, byteswap), d);
+        }
+        let mut swapped = d.to_bytes(true);
+        swapped[..8].reverse();
+        swapped[8..].reverse();
+        assert_eq!(swapped, d.to_bytes(false));
+    }
+
+    #[test]
+    pub fn blkptr_bytes_roundtrip() {
+        let bp = sample_bp();
+        for byteswap in [false, true] {
+            let buf = bp.to_bytes(byteswap);
+            let decoded = Blkptr::from_bytes(&buf, byteswap);
+            assert_eq!(decoded.to_bytes(byteswap), buf);
+            assert_eq!(decoded.blk_prop, bp.blk_prop);
+            assert_eq!(decoded.blk_fill, bp.blk_fill);
+            assert_eq!(decoded.blk_cksum.zc_word, bp.blk_cksum.zc_word);
+            assert_eq!(decoded, bp);
+        }
+    }
+
+    #[test]
+    pub fn blkptr_bytes_layout() {
+        let bp = sample_bp();
+        let buf = bp.to_bytes(false);
+        let word = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&buf[i * 8..(i + 1) * 8]);
+            u64::from_ne_bytes(b)
+        };
+        assert_eq!(word(6), bp.blk_prop);
+        assert_eq!(word(9), 8);
+        assert_eq!(word(10), 10);
+        assert_eq!(word(11), 1);
+        assert_eq!(word(15), 0x4444);
+    }
+
+    #[test]
+    pub fn blkptr_children_follow_parent_byteorder() {
+        let child = sample_bp();
+        let mut parent = Blkptr::new();
+        parent.set_byteorder(1 - super::HOST_BYTEORDER);
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&child.to_bytes(true));
+        buf.extend_from_slice(&child.to_bytes(true));
+        let children = Blkptr::children_from_bytes(&parent, &buf);
+        assert_eq!(children.len(), buf.len() / SPA_BLKPTRSIZE);
+        assert_eq!(children[1].blk_prop, child.blk_prop);
+    }
+
+    #[test]
+    pub fn blkptr_sizes() {
+        let bp = sample_bp();
+        assert_eq!(bp.get_lsize(), 0x20000);
+        assert_eq!(bp.get_psize(), 0x2000);
+        assert!(!bp.is_embedded());
+    }
+
+    #[test]
+    pub fn embedded_payload_roundtrip() {
+        let data: Vec<u8> = (0..BPE_PAYLOAD_SIZE as u32)
+            .map(|i| (i * 7) as u8)
+            .collect();
+        let mut bp = sample_bp();
+        bp.set_embedded_payload(EmbeddedType::Data, SIOCompress::LZ4, &data, 4096)
+            .unwrap();
+        bp.set_birth_embedded(42);
+
+        assert!(bp.is_embedded());
+        assert_eq!(bp.bpe_get_psize(), BPE_PAYLOAD_SIZE as u64);
+        assert_eq!(bp.get_lsize(), 4096);
+        assert_eq!(bp.get_psize(), 0);
+        assert_eq!(bp.get_compress(), u8::from(SIOCompress::LZ4) as u64);
+        assert_eq!(bp.blk_birth, 42);
+        assert_eq!(bp.get_embedded_payload().unwrap(), data);
+
+        // The payload survives the trip through the on-disk form.
+        let decoded = Blkptr::from_bytes(&bp.to_bytes(true), true);
+        assert_eq!(decoded.get_embedded_payload().unwrap(), data);
+    }
+
+    #[test]
+    pub fn embedded_payload_partial_word() {
+        let data = b"hello, embedded world";
+        let mut bp = Blkptr::new();
+        bp.set_embedded_payload(
+            EmbeddedType::Data,
+            SIOCompress::OFF,
+            data,
+            data.len() as u64,
+        )
+        .unwrap();
+        assert_eq!(bp.get_embedded_payload().unwrap(), data.to_vec());
+        assert_eq!(bp.blk_dva[0].dva_word[0] & 0xff, b'h' as u64);
+    }
+
+    #[test]
+    pub fn embedded_payload_errors() {
+        let mut bp = Blkptr::new();
+        let big = vec![0u8; BPE_PAYLOAD_SIZE + 1];
+        assert_eq!(
+            bp.set_embedded_payload(EmbeddedType::Data, SIOCompress::LZ4, &big, 4096),
+            Err(BlkptrError::PayloadTooLarge(BPE_PAYLOAD_SIZE + 1))
+        );
+        assert_eq!(
+            bp.set_embedded_payload(EmbeddedType::Data, SIOCompress::OFF, &[1, 2], 4),
+            Err(BlkptrError::InvalidEmbeddedSize { lsize: 4, psize: 2 })
+        );
+        assert_eq!(
+            sample_bp().get_embedded_payload(),
+            Err(BlkptrError::NotEmbedded)
+        );
+    }
+
+    #[test]
+    pub fn crypt_params_roundtrip() {
+        let salt = [1, 2, 3, 4, 5, 6, 7, 8];
+        let iv: [u8; SIO_DATA_IV_LEN] = [9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20];
+        let mac: [u8; SIO_DATA_MAC_LEN] = [0xa5; SIO_DATA_MAC_LEN];
+
+        for order in [0, 1] {
+            let mut bp = sample_bp();
+            bp.set_level(0);
+            bp.set_byteorder(order);
+            bp.set_user_crypt(true);
+            bp.set_fill(5);
+            bp.set_salt(&salt).unwrap();
+            bp.set_iv(&iv).unwrap();
+            bp.set_mac(&mac).unwrap();
+
+            assert_eq!(bp.get_salt().unwrap(), salt);
+            assert_eq!(bp.get_iv().unwrap(), iv);
+            assert_eq!(bp.get_mac().unwrap(), mac);
+            assert_eq!(bp.get_fill(), 5);
+            assert_eq!(bp.get_ndvas(), 2);
+        }
+    }
+
+    #[test]
+    pub fn crypt_params_misuse() {
+        let mut bp = sample_bp();
+        bp.set_level(0);
+        assert_eq!(bp.get_salt(), Err(BlkptrError::NotEncrypted));
+        assert_eq!(bp.get_iv2(), Err(BlkptrError::NotEncrypted));
+        assert_eq!(bp.set_iv2(1), Err(BlkptrError::NotEncrypted));
+        assert_eq!(
+            bp.set_iv(&[0; SIO_DATA_IV_LEN]),
+            Err(BlkptrError::NotEncrypted)
+        );
+        assert_eq!(bp.get_mac(), Err(BlkptrError::NotProtected));
+        assert_eq!(bp.get_indirect_mac_cksum(), Err(BlkptrError::NoIndirectMac));
+
+        // Indirect bps use the MAC words for a checksum of MACs only.
+        bp.set_level(1);
+        bp.set_user_crypt(true);
+        assert_eq!(bp.get_salt(), Err(BlkptrError::NotEncrypted));
+        assert_eq!(bp.get_mac(), Err(BlkptrError::NotProtected));
+        bp.set_indirect_mac_cksum(&[7; SIO_DATA_MAC_LEN]).unwrap();
+        assert_eq!(bp.get_indirect_mac_cksum().unwrap(), [7; SIO_DATA_MAC_LEN]);
+    }
+
+    #[test]
+    pub fn object_type_predicates() {
+        let mut bp = sample_bp();
+        bp.blk_dva[2].set_asize(0x2000);
+        bp.set_object_type(DmuObjectType::DNODE);
+        assert_eq!(bp.get_object_type(), Some(DmuObjectType::DNODE));
+        assert!(bp.is_metadata());
+        assert_eq!(bp.get_ucsize(), bp.get_psize());
+
+        bp.set_user_crypt(true);
+        assert!(bp.is_encrypted());
+        assert!(!bp.is_authenticated());
+
+        // Object sets are authenticated but never encrypted.
+        bp.set_object_type(DmuObjectType::OBJSET);
+        assert!(!bp.is_encrypted());
+        assert!(bp.is_authenticated());
+        assert!(bp.is_protected());
+        assert_eq!(bp.get_ndvas(), 3);
+
+        bp.set_object_type(DmuObjectType::PLAIN_FILE_CONTENTS);
+        bp.set_user_crypt(false);
+        assert!(!bp.is_metadata());
+        assert_eq!(bp.get_ucsize(), bp.get_lsize());
+
+        bp.set_type(DMU_OT_NEWTYPE | DMU_OT_METADATA);
+        assert_eq!(bp.get_object_type(), None);
+        assert!(bp.is_metadata());
+    }
 }
@@ -1,5 +1,9 @@
+use std::fmt;
+
+use crate::sio::SIOChecksum;
+
 /// Each block has a 256-bit checksum -- strong enough for cryptographic hashes.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct SIOChksum {
     pub zc_word: [u64; 4],
 }
@@ -20,7 +24,7 @@ impl SIOChksum {
 
     #[inline]
     pub fn is_zero(&self) -> bool {
-        self.zc_word[0] == 0 | self.zc_word[1] | self.zc_word[2] | self.zc_word[3]
+        (self.zc_word[0] | self.zc_word[1] | self.zc_word[2] | self.zc_word[3]) == 0
     }
 
     #[inline]
@@ -30,13 +34,144 @@ impl SIOChksum {
         self.zc_word[2] = self.zc_word[2].swap_bytes();
         self.zc_word[3] = self.zc_word[3].swap_bytes();
     }
+
+    /// Compares the checksum stored for a block (`self`) with the one computed
+    /// from its data. `checksum` and `byteswap` describe how `actual` was
+    /// computed and are only recorded in the report.
+    pub fn compare(
+        &self,
+        actual: &SIOChksum,
+        checksum: SIOChecksum,
+        byteswap: bool,
+    ) -> Result<(), ChecksumMismatch> {
+        if self == actual {
+            return Ok(());
+        }
+        Err(ChecksumMismatch::new(*self, *actual, checksum, byteswap))
+    }
+}
+
+/// Describes a block whose data does not match its checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    pub expected: SIOChksum,
+    pub actual: SIOChksum,
+    pub checksum: SIOChecksum,
+    /// Whether the block was checksummed in the non-native byte order.
+    pub byteswap: bool,
+    /// For each bit position, the number of checksum words that differ there.
+    /// Boxed to keep `Result<(), ChecksumMismatch>` small.
+    pub histogram: Box<[u32; 64]>,
+}
+
+impl ChecksumMismatch {
+    pub fn new(
+        expected: SIOChksum,
+        actual: SIOChksum,
+        checksum: SIOChecksum,
+        byteswap: bool,
+    ) -> Self {
+        let mut histogram = Box::new([0u32; 64]);
+        for (e, a) in expected.zc_word.iter().zip(actual.zc_word.iter()) {
+            let mut diff = e ^ a;
+            while diff != 0 {
+                histogram[diff.trailing_zeros() as usize] += 1;
+                diff &= diff - 1;
+            }
+        }
+        ChecksumMismatch {
+            expected,
+            actual,
+            checksum,
+            byteswap,
+            histogram,
+        }
+    }
+
+    /// Total number of differing bits.
+    #[inline]
+    pub fn bits_differ(&self) -> u32 {
+        self.histogram.iter().sum()
+    }
 }
 
-impl PartialEq for SIOChksum {
-    fn eq(&self, other: &SIOChksum) -> bool {
-        (self.zc_word[0] - other.zc_word[0] == 0)
-            | (self.zc_word[1] - other.zc_word[1] == 0)
-            | (self.zc_word[2] - other.zc_word[2] == 0)
-            | (self.zc_word[3] - other.zc_word[3] == 0)
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let e = &self.expected.zc_word;
+        let a = &self.actual.zc_word;
+        write!(
+            f,
+            "{} checksum mismatch{}: expected {:x}:{:x}:{:x}:{:x} actual {:x}:{:x}:{:x}:{:x} ({} bits differ)",
+            self.checksum.name(),
+            if self.byteswap { " (byteswapped)" } else { "" },
+            e[0],
+            e[1],
+            e[2],
+            e[3],
+            a[0],
+            a[1],
+            a[2],
+            a[3],
+            self.bits_differ()
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chksum_eq() {
+        let mut a = SIOChksum::new();
+        a.set_checksum(1, 2, 3, 4);
+        let mut b = a;
+        assert_eq!(a, b);
+
+        // A single matching word is not a match, and no word may underflow.
+        b.set_checksum(1, 0, 0, u64::MAX);
+        assert_ne!(a, b);
+        assert_ne!(b, a);
+    }
+
+    #[test]
+    fn chksum_is_zero() {
+        let mut zc = SIOChksum::new();
+        assert!(zc.is_zero());
+        zc.set_checksum(0, 0, 0, 1);
+        assert!(!zc.is_zero());
+        zc.set_checksum(1, 0, 0, 0);
+        assert!(!zc.is_zero());
+    }
+
+    #[test]
+    fn chksum_compare() {
+        let mut expected = SIOChksum::new();
+        expected.set_checksum(0xf0, 1, 2, 3);
+        assert_eq!(
+            expected.compare(&expected, SIOChecksum::FLETCHER_4, false),
+            Ok(())
+        );
+
+        let mut actual = expected;
+        actual.zc_word[0] ^= 0b101;
+        actual.zc_word[3] ^= 1 | (1 << 63);
+        let err = expected
+            .compare(&actual, SIOChecksum::SHA256, true)
+            .unwrap_err();
+        assert_eq!(err.expected, expected);
+        assert_eq!(err.actual, actual);
+        assert_eq!(err.checksum, SIOChecksum::SHA256);
+        assert!(err.byteswap);
+        assert_eq!(err.histogram[0], 2);
+        assert_eq!(err.histogram[2], 1);
+        assert_eq!(err.histogram[63], 1);
+        assert_eq!(err.bits_differ(), 4);
+        assert_eq!(
+            err.to_string(),
+            "sha256 checksum mismatch (byteswapped): expected f0:1:2:3 actual f5:1:2:8000000000000002 (4 bits differ)"
+        );
     }
 }
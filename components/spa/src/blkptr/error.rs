@@ -0,0 +1,81 @@
+use std::fmt;
+
+/// Errors reported while building or decoding a block pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlkptrError {
+    /// The operation only applies to embedded block pointers.
+    NotEmbedded,
+    /// The operation does not apply to embedded block pointers.
+    Embedded,
+    /// The embedded payload does not fit in the payload words.
+    PayloadTooLarge(usize),
+    /// The embedded LSIZE/PSIZE combination is not representable.
+    InvalidEmbeddedSize { lsize: u64, psize: u64 },
+    /// The operation only applies to encrypted block pointers.
+    NotEncrypted,
+    /// The operation only applies to encrypted or authenticated level 0 bps.
+    NotProtected,
+    /// The operation only applies to indirect bps carrying a checksum of MACs.
+    NoIndirectMac,
+    /// A textual block pointer could not be parsed.
+    Parse(String),
+    /// The DMU object type is unknown.
+    InvalidType(u8),
+    /// The checksum function is unknown.
+    InvalidChecksum(u64),
+    /// The compression function is unknown.
+    InvalidCompress(u64),
+    /// The logical size exceeds the largest supported block.
+    InvalidLsize(u64),
+    /// The physical size exceeds the largest supported block.
+    InvalidPsize(u64),
+    /// The embedded data type is unknown.
+    InvalidEtype(u64),
+    /// Encrypted block pointers cannot carry embedded data.
+    EncryptedEmbedded,
+    /// A DVA refers to a top-level vdev that does not exist.
+    InvalidVdev { dva: usize, vdev: u64 },
+    /// A DVA refers to a hole vdev left behind by device removal.
+    HoleVdev { dva: usize, vdev: u64 },
+    /// A DVA extends past the end of its vdev.
+    InvalidOffset { dva: usize, offset: u64, asize: u64 },
+}
+
+impl fmt::Display for BlkptrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlkptrError::NotEmbedded => write!(f, "blkptr is not embedded"),
+            BlkptrError::Embedded => write!(f, "blkptr is embedded"),
+            BlkptrError::PayloadTooLarge(size) => {
+                write!(f, "embedded payload of {} bytes is too large", size)
+            }
+            BlkptrError::InvalidEmbeddedSize { lsize, psize } => {
+                write!(f, "invalid embedded sizes lsize={} psize={}", lsize, psize)
+            }
+            BlkptrError::NotEncrypted => write!(f, "blkptr is not encrypted"),
+            BlkptrError::NotProtected => write!(f, "blkptr is not encrypted or authenticated"),
+            BlkptrError::NoIndirectMac => write!(f, "blkptr has no indirect MAC checksum"),
+            BlkptrError::Parse(msg) => write!(f, "invalid blkptr: {}", msg),
+            BlkptrError::InvalidType(t) => write!(f, "blkptr has invalid TYPE {}", t),
+            BlkptrError::InvalidChecksum(c) => write!(f, "blkptr has invalid CHECKSUM {}", c),
+            BlkptrError::InvalidCompress(c) => write!(f, "blkptr has invalid COMPRESS {}", c),
+            BlkptrError::InvalidLsize(s) => write!(f, "blkptr has invalid LSIZE {}", s),
+            BlkptrError::InvalidPsize(s) => write!(f, "blkptr has invalid PSIZE {}", s),
+            BlkptrError::InvalidEtype(e) => write!(f, "blkptr has invalid ETYPE {}", e),
+            BlkptrError::EncryptedEmbedded => write!(f, "blkptr is both encrypted and embedded"),
+            BlkptrError::InvalidVdev { dva, vdev } => {
+                write!(f, "blkptr DVA {} has invalid VDEV {}", dva, vdev)
+            }
+            BlkptrError::HoleVdev { dva, vdev } => {
+                write!(f, "blkptr DVA {} has hole VDEV {}", dva, vdev)
+            }
+            BlkptrError::InvalidOffset { dva, offset, asize } => write!(
+                f,
+                "blkptr DVA {} has invalid OFFSET {} (asize {})",
+                dva, offset, asize
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BlkptrError {}
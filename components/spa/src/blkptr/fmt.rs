@@ -0,0 +1,570 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{FromStr, SplitWhitespace};
+
+use super::blkptr::{Blkptr, Dva};
+use super::error::BlkptrError;
+use super::{
+    SPA_ASIZEBITS, SPA_DVAS_PER_BP, SPA_LSIZEBITS, SPA_MINBLOCKSHIFT, SPA_PSIZEBITS, SPA_VDEVBITS,
+};
+use crate::dmu;
+use crate::sio::{SIOChecksum, SIOCompress};
+use sys::P2Ext;
+
+const COPYNAME: [&str; 4] = ["zero", "single", "double", "triple"];
+
+// Crypt types, in the order they are checked.
+const CRYPT_UNENCRYPTED: &str = "unencrypted";
+const CRYPT_ENCRYPTED: &str = "encrypted";
+const CRYPT_AUTHENTICATED: &str = "authenticated";
+const CRYPT_INDIRECT_MAC: &str = "indirect-MAC";
+
+fn checksum_name(bp: &Blkptr) -> &'static str {
+    bp.get_checksum().map(|c| c.name()).unwrap_or("invalid")
+}
+
+fn compress_name(bp: &Blkptr) -> &'static str {
+    SIOCompress::try_from(bp.get_compress() as u8)
+        .map(|c| c.name())
+        .unwrap_or("invalid")
+}
+
+fn crypt_type(bp: &Blkptr) -> &'static str {
+    if bp.is_encrypted() {
+        CRYPT_ENCRYPTED
+    } else if bp.is_authenticated() {
+        CRYPT_AUTHENTICATED
+    } else if bp.has_indirect_mac_chksum() {
+        CRYPT_INDIRECT_MAC
+    } else {
+        CRYPT_UNENCRYPTED
+    }
+}
+
+/// Legacy object types are printed by name, new-style types by number.
+struct TypeName(u8);
+
+impl fmt::Display for TypeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match dmu::ot_name(self.0) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Dva {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{:x}:{:x}",
+            self.get_vdev(),
+            self.get_offset(),
+            self.get_asize()
+        )
+    }
+}
+
+/// Formats a block pointer the way zdb prints it, e.g.
+///
+/// ```text
+/// DVA[0]=<0:4a000:2000> [L0 ZFS plain file] fletcher4 lz4 unencrypted LE contiguous unique
+/// single size=20000L/2000P birth=10L/10P fill=1 cksum=...
+/// ```
+///
+/// Holes, embedded and redacted block pointers use shorter forms since they
+/// have no DVAs.
+impl fmt::Display for Blkptr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_hole() {
+            return write!(
+                f,
+                "HOLE [L{} {}] size={:x}L birth={}L",
+                self.get_level(),
+                TypeName(self.get_type()),
+                self.get_lsize(),
+                self.blk_birth
+            );
+        }
+
+        if self.is_redacted() {
+            return write!(
+                f,
+                "REDACTED [L{} {}] size={:x}L birth={}L",
+                self.get_level(),
+                TypeName(self.get_type()),
+                self.bpe_get_lsize(),
+                self.blk_birth
+            );
+        }
+
+        if self.is_embedded() {
+            return write!(
+                f,
+                "EMBEDDED [L{} {}] et={} {} size={:x}L/{:x}P birth={}L",
+                self.get_level(),
+                TypeName(self.get_type()),
+                self.get_etype(),
+                compress_name(self),
+                self.bpe_get_lsize(),
+                self.bpe_get_psize(),
+                self.blk_birth
+            );
+        }
+
+        let mut copies = 0usize;
+        for d in 0..self.get_ndvas() as usize {
+            let dva = &self.blk_dva[d];
+            if dva.is_valid() {
+                copies += 1;
+            }
+            write!(f, "DVA[{}]=<{}> ", d, dva)?;
+        }
+
+        if let Ok(iv2) = self.get_iv2() {
+            write!(
+                f,
+                "salt={:x} iv={:x}:{:x} ",
+                self.blk_dva[2].dva_word[0], self.blk_dva[2].dva_word[1], iv2
+            )?;
+        }
+
+        // Gang blocks with a small third DVA use it for the gang header copy.
+        if self.is_gang() && self.blk_dva[2].get_asize() <= self.blk_dva[1].get_asize() / 2 {
+            copies = copies.saturating_sub(1);
+        }
+
+        write!(
+            f,
+            "[L{} {}] {} {} {} {} {} {} {} ",
+            self.get_level(),
+            TypeName(self.get_type()),
+            checksum_name(self),
+            compress_name(self),
+            crypt_type(self),
+            if self.get_byteorder() == 0 {
+                "LE"
+            } else {
+                "BE"
+            },
+            if self.is_gang() { "gang" } else { "contiguous" },
+            if self.get_dedup() { "dedup" } else { "unique" },
+            COPYNAME[copies]
+        )?;
+
+        let c = &self.blk_cksum.zc_word;
+        write!(
+            f,
+            "size={:x}L/{:x}P birth={}L/{}P fill={} cksum={:016x}:{:016x}:{:016x}:{:016x}",
+            self.get_lsize(),
+            self.get_psize(),
+            self.blk_birth,
+            self.physical_birth(),
+            self.get_fill(),
+            c[0],
+            c[1],
+            c[2],
+            c[3]
+        )
+    }
+}
+
+fn parse_err<T>(msg: impl Into<String>) -> Result<T, BlkptrError> {
+    Err(BlkptrError::Parse(msg.into()))
+}
+
+fn dec(s: &str) -> Result<u64, BlkptrError> {
+    s.parse::<u64>()
+        .or_else(|_| parse_err(format!("bad number '{}'", s)))
+}
+
+fn hex(s: &str) -> Result<u64, BlkptrError> {
+    u64::from_str_radix(s, 16).or_else(|_| parse_err(format!("bad hex number '{}'", s)))
+}
+
+fn strip<'a>(tok: &'a str, prefix: &str, suffix: &str) -> Result<&'a str, BlkptrError> {
+    tok.strip_prefix(prefix)
+        .and_then(|t| t.strip_suffix(suffix))
+        .map_or_else(
+            || parse_err(format!("expected {}...{}, got '{}'", prefix, suffix, tok)),
+            Ok,
+        )
+}
+
+/// Checks that `val` fits a field of `len` bits stored with `shift`/`bias`,
+/// which the bit-field setters would otherwise assert on.
+fn check_sb(val: u64, len: u64, shift: u64, bias: u64, what: &str) -> Result<u64, BlkptrError> {
+    if !val.is_p2aligned(1 << shift) || (val >> shift) < bias || (val >> shift) - bias >= 1 << len {
+        return parse_err(format!("{} {:#x} out of range", what, val));
+    }
+    Ok(val)
+}
+
+fn check_bits(val: u64, len: u64, what: &str) -> Result<u64, BlkptrError> {
+    check_sb(val, len, 0, 0, what)
+}
+
+type Tokens<'a> = Peekable<SplitWhitespace<'a>>;
+
+fn next<'a>(toks: &mut Tokens<'a>) -> Result<&'a str, BlkptrError> {
+    toks.next()
+        .map_or_else(|| parse_err("unexpected end of input"), Ok)
+}
+
+/// Parses "[L<level> <type>]". Type names may span several tokens.
+fn parse_level_type(toks: &mut Tokens<'_>) -> Result<(u8, u8), BlkptrError> {
+    let level = check_bits(dec(strip(next(toks)?, "[L", "")?)?, 5, "level")?;
+    let mut name = Vec::new();
+    loop {
+        let tok = next(toks)?;
+        match tok.strip_suffix(']') {
+            Some(last) => {
+                name.push(last);
+                break;
+            }
+            None => name.push(tok),
+        }
+    }
+    let name = name.join(" ");
+    let typ = match dmu::ot_from_name(&name) {
+        Some(ot) => ot.into(),
+        None => check_bits(dec(&name)?, 8, "type")? as u8,
+    };
+    Ok((level as u8, typ))
+}
+
+/// Parses "size=<lsize>L" or "size=<lsize>L/<psize>P".
+fn parse_sizes(tok: &str) -> Result<(u64, Option<u64>), BlkptrError> {
+    let sizes = strip(tok, "size=", "")?;
+    match sizes.split_once('/') {
+        Some((l, p)) => Ok((hex(strip(l, "", "L")?)?, Some(hex(strip(p, "", "P")?)?))),
+        None => Ok((hex(strip(sizes, "", "L")?)?, None)),
+    }
+}
+
+impl FromStr for Dva {
+    type Err = BlkptrError;
+
+    /// Parses "vdev:offset:asize", optionally wrapped in angle brackets.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('<').unwrap_or(s);
+        let s = s.strip_suffix('>').unwrap_or(s);
+        let fields: Vec<&str> = s.split(':').collect();
+        if fields.len() != 3 {
+            return parse_err(format!("bad dva '{}'", s));
+        }
+
+        let mut dva = Dva::new();
+        dva.set_vdev(check_bits(dec(fields[0])?, SPA_VDEVBITS, "vdev")?);
+        dva.set_offset(check_sb(
+            hex(fields[1])?,
+            63,
+            SPA_MINBLOCKSHIFT,
+            0,
+            "offset",
+        )?);
+        dva.set_asize(check_sb(
+            hex(fields[2])?,
+            SPA_ASIZEBITS,
+            SPA_MINBLOCKSHIFT,
+            0,
+            "asize",
+        )?);
+        Ok(dva)
+    }
+}
+
+impl FromStr for Blkptr {
+    type Err = BlkptrError;
+
+    /// Parses the output of `Display`. Embedded payloads are not part of the
+    /// text form, so they come back zeroed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut toks = s.split_whitespace().peekable();
+        let bp = match toks.peek().copied() {
+            Some("HOLE") | Some("REDACTED") => parse_short(&mut toks)?,
+            Some("EMBEDDED") => parse_embedded(&mut toks)?,
+            Some(_) => parse_normal(&mut toks)?,
+            None => return parse_err("empty input"),
+        };
+        if let Some(tok) = toks.next() {
+            return parse_err(format!("trailing input '{}'", tok));
+        }
+        Ok(bp)
+    }
+}
+
+fn parse_short(toks: &mut Tokens<'_>) -> Result<Blkptr, BlkptrError> {
+    let redacted = next(toks)? == "REDACTED";
+    let (level, typ) = parse_level_type(toks)?;
+    let (lsize, _) = parse_sizes(next(toks)?)?;
+    let birth = dec(strip(next(toks)?, "birth=", "L")?)?;
+
+    let mut bp = Blkptr::new();
+    if redacted {
+        bp.set_redacted();
+        bp.bpe_set_lsize(check_sb(lsize, 25, 0, 1, "lsize")?);
+    } else {
+        bp.set_lsize(check_sb(
+            lsize,
+            SPA_LSIZEBITS,
+            SPA_MINBLOCKSHIFT,
+            1,
+            "lsize",
+        )?);
+    }
+    bp.set_level(level);
+    bp.set_type(typ);
+    bp.blk_birth = birth;
+    Ok(bp)
+}
+
+fn parse_embedded(toks: &mut Tokens<'_>) -> Result<Blkptr, BlkptrError> {
+    next(toks)?;
+    let (level, typ) = parse_level_type(toks)?;
+    let etype = check_bits(dec(strip(next(toks)?, "et=", "")?)?, 8, "etype")?;
+    let comp = next(toks)?;
+    let comp = SIOCompress::from_name(comp)
+        .map_or_else(|| parse_err(format!("unknown compression '{}'", comp)), Ok)?;
+    let (lsize, psize) = parse_sizes(next(toks)?)?;
+    let psize = psize.map_or_else(|| parse_err("missing psize"), Ok)?;
+    let birth = dec(strip(next(toks)?, "birth=", "L")?)?;
+
+    let mut bp = Blkptr::new();
+    bp.set_embeded(1);
+    bp.set_etype(etype);
+    bp.set_compress(u8::from(comp) as u64);
+    bp.bpe_set_lsize(check_sb(lsize, 25, 0, 1, "lsize")?);
+    bp.bpe_set_psize(check_sb(psize, 7, 0, 1, "psize")?);
+    bp.set_level(level);
+    bp.set_type(typ);
+    bp.set_birth_embedded(birth);
+    Ok(bp)
+}
+
+fn parse_normal(toks: &mut Tokens<'_>) -> Result<Blkptr, BlkptrError> {
+    let mut bp = Blkptr::new();
+
+    let mut ndvas = 0;
+    while let Some(tok) = toks.peek().copied() {
+        let Some(rest) = tok.strip_prefix("DVA[") else {
+            break;
+        };
+        toks.next();
+        let (idx, dva) = rest
+            .split_once("]=")
+            .map_or_else(|| parse_err(format!("bad dva '{}'", tok)), Ok)?;
+        if dec(idx)? != ndvas as u64 || ndvas >= SPA_DVAS_PER_BP {
+            return parse_err(format!("unexpected dva '{}'", tok));
+        }
+        bp.blk_dva[ndvas] = dva.parse()?;
+        ndvas += 1;
+    }
+
+    let mut crypt_params = None;
+    if toks.peek().is_some_and(|t| t.starts_with("salt=")) {
+        let salt = hex(strip(next(toks)?, "salt=", "")?)?;
+        let iv = strip(next(toks)?, "iv=", "")?;
+        let (iv1, iv2) = iv
+            .split_once(':')
+            .map_or_else(|| parse_err(format!("bad iv '{}'", iv)), Ok)?;
+        crypt_params = Some((salt, hex(iv1)?, check_bits(hex(iv2)?, 32, "iv2")?));
+    }
+
+    let (level, typ) = parse_level_type(toks)?;
+    let cksum = next(toks)?;
+    let cksum = SIOChecksum::from_name(cksum)
+        .map_or_else(|| parse_err(format!("unknown checksum '{}'", cksum)), Ok)?;
+    let comp = next(toks)?;
+    let comp = SIOCompress::from_name(comp)
+        .map_or_else(|| parse_err(format!("unknown compression '{}'", comp)), Ok)?;
+    let crypt = match next(toks)? {
+        CRYPT_UNENCRYPTED => false,
+        CRYPT_ENCRYPTED | CRYPT_AUTHENTICATED | CRYPT_INDIRECT_MAC => true,
+        t => return parse_err(format!("unknown crypt type '{}'", t)),
+    };
+    let byteorder = match next(toks)? {
+        "LE" => 0,
+        "BE" => 1,
+        t => return parse_err(format!("unknown byteorder '{}'", t)),
+    };
+    let gang = match next(toks)? {
+        "gang" => true,
+        "contiguous" => false,
+        t => return parse_err(format!("unknown gang state '{}'", t)),
+    };
+    let dedup = match next(toks)? {
+        "dedup" => true,
+        "unique" => false,
+        t => return parse_err(format!("unknown dedup state '{}'", t)),
+    };
+    let copies = next(toks)?;
+    if !COPYNAME.contains(&copies) {
+        return parse_err(format!("unknown copies '{}'", copies));
+    }
+
+    let (lsize, psize) = parse_sizes(next(toks)?)?;
+    let psize = psize.map_or_else(|| parse_err("missing psize"), Ok)?;
+    let births = strip(next(toks)?, "birth=", "P")?;
+    let (birth, phys_birth) = births
+        .split_once("L/")
+        .map_or_else(|| parse_err(format!("bad birth '{}'", births)), Ok)?;
+    let (birth, phys_birth) = (dec(birth)?, dec(phys_birth)?);
+    let fill = dec(strip(next(toks)?, "fill=", "")?)?;
+    let words: Vec<&str> = strip(next(toks)?, "cksum=", "")?.split(':').collect();
+    if words.len() != 4 {
+        return parse_err("checksum needs 4 words");
+    }
+
+    bp.set_level(level);
+    bp.set_type(typ);
+    bp.set_checksum(cksum);
+    bp.set_compress(u8::from(comp) as u64);
+    bp.set_user_crypt(crypt);
+    bp.set_byteorder(byteorder);
+    bp.set_dedup(dedup);
+    if gang {
+        // The text form has a single gang flag; every DVA of a gang bp points
+        // at a copy of the gang header.
+        for dva in bp.blk_dva.iter_mut().take(ndvas) {
+            dva.set_gang(1);
+        }
+    }
+    bp.set_lsize(check_sb(
+        lsize,
+        SPA_LSIZEBITS,
+        SPA_MINBLOCKSHIFT,
+        1,
+        "lsize",
+    )?);
+    bp.set_psize(check_sb(
+        psize,
+        SPA_PSIZEBITS,
+        SPA_MINBLOCKSHIFT,
+        1,
+        "psize",
+    )?);
+    bp.set_birth(birth, if phys_birth == birth { 0 } else { phys_birth });
+    bp.blk_cksum.set_checksum(
+        hex(words[0])?,
+        hex(words[1])?,
+        hex(words[2])?,
+        hex(words[3])?,
+    );
+
+    if let Some((salt, iv1, iv2)) = crypt_params {
+        if !bp.is_encrypted() || ndvas == SPA_DVAS_PER_BP {
+            return parse_err("salt and iv need an encrypted bp with at most 2 dvas");
+        }
+        bp.blk_dva[2].dva_word = [salt, iv1];
+        bp.set_iv2(iv2)?;
+    }
+    // Encrypted bps share the fill word with iv2.
+    if bp.is_encrypted() {
+        bp.set_fill(check_bits(fill, 32, "fill")?);
+    } else {
+        bp.set_fill(fill);
+    }
+    Ok(bp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blkptr, Dva};
+    use crate::blkptr::blkptr::EmbeddedType;
+    use crate::blkptr::error::BlkptrError;
+    use crate::blkptr::sample_bp;
+    use crate::sio::SIOCompress;
+
+    #[test]
+    fn dva_display_parse() {
+        let dva: Dva = "<1:4a000:2000>".parse().unwrap();
+        assert_eq!(dva.get_vdev(), 1);
+        assert_eq!(dva.get_offset(), 0x4a000);
+        assert_eq!(dva.get_asize(), 0x2000);
+        assert_eq!(dva.to_string(), "1:4a000:2000");
+        assert_eq!("1:4a000:2000".parse::<Dva>().unwrap(), dva);
+    }
+
+    #[test]
+    fn blkptr_display_parse() {
+        let bp = sample_bp();
+        let s = bp.to_string();
+        assert!(s.starts_with("DVA[0]=<0:4a000:2000> DVA[1]=<1:94000:2000> [L0 ZFS plain file] "));
+        assert!(s.contains(" fletcher4 lz4 unencrypted LE "));
+        assert!(s.contains(" contiguous unique double "));
+        assert!(s.contains(" size=20000L/2000P birth=10L/8P fill=1 "));
+        assert!(s.ends_with(
+            "cksum=0000000000000001:0000000000000022:0000000000000333:0000000000004444"
+        ));
+
+        let parsed: Blkptr = s.parse().unwrap();
+        assert_eq!(parsed.to_string(), s);
+        assert_eq!(parsed.to_bytes(false), bp.to_bytes(false));
+
+        let mut gang = sample_bp();
+        gang.blk_dva[0].set_gang(1);
+        gang.blk_dva[1].set_gang(1);
+        let s = gang.to_string();
+        assert!(s.contains(" LE gang unique single "));
+        let parsed: Blkptr = s.parse().unwrap();
+        assert_eq!(parsed.to_bytes(false), gang.to_bytes(false));
+    }
+
+    #[test]
+    fn blkptr_short_forms() {
+        let mut hole = Blkptr::new();
+        hole.set_lsize(0x20000);
+        hole.set_type(19);
+        hole.blk_birth = 7;
+        assert_eq!(
+            hole.to_string(),
+            "HOLE [L0 ZFS plain file] size=20000L birth=7L"
+        );
+
+        let mut redacted = Blkptr::new();
+        redacted.set_redacted();
+        redacted.bpe_set_lsize(0x20000);
+        redacted.set_level(1);
+        assert_eq!(
+            redacted.to_string(),
+            "REDACTED [L1 unallocated] size=20000L birth=0L"
+        );
+
+        let mut embedded = Blkptr::new();
+        embedded
+            .set_embedded_payload(EmbeddedType::Data, SIOCompress::LZ4, &[1; 40], 0x200)
+            .unwrap();
+        embedded.set_birth_embedded(3);
+        assert_eq!(
+            embedded.to_string(),
+            "EMBEDDED [L0 unallocated] et=0 lz4 size=200L/28P birth=3L"
+        );
+
+        for bp in [hole, redacted, embedded] {
+            let s = bp.to_string();
+            assert_eq!(s.parse::<Blkptr>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn blkptr_parse_errors() {
+        let s = sample_bp().to_string();
+        for bad in [
+            "",
+            "HOLE [L0 ZFS plain file] size=20000L",
+            "HOLE [L0 ZFS plain file size=20000L birth=7L",
+            "DVA[0]=<0:4a001:2000> [L0 ZFS plain file]",
+            &s.replace("fletcher4", "crc32"),
+            &s.replace("fill=1", "fill=1 extra"),
+            &s.replace("unencrypted", "encrypted")
+                .replace("fill=1", "fill=4294967296"),
+        ] {
+            assert!(
+                matches!(bad.parse::<Blkptr>(), Err(BlkptrError::Parse(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+}
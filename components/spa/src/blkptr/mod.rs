@@ -1,5 +1,8 @@
 pub mod blkptr;
 pub mod checksum;
+pub mod error;
+pub mod fmt;
+pub mod verify;
 
 use std::alloc::Layout;
 
@@ -21,8 +24,9 @@ use checksum::*;
 const SPA_MINBLOCKSHIFT: u64 = 9;
 const SPA_OLD_MAXBLOCKSHIFT: u64 = 17;
 const SPA_MAXBLOCKSHIFT: u64 = 24;
-const SPA_MINBLOCKSIZE: u64 = 1 << SPA_MINBLOCKSHIFT;
+pub const SPA_MINBLOCKSIZE: u64 = 1 << SPA_MINBLOCKSHIFT;
 const SPA_OLD_MAXBLOCKSIZE: u64 = 1 << SPA_OLD_MAXBLOCKSHIFT;
+const SPA_MAXBLOCKSIZE: u64 = 1 << SPA_MAXBLOCKSHIFT;
 
 // Alignment Shift (ashift) is an immutable, internal top-level vdev property
 // which can only be set at vdev creation time. Physical writes are always done
@@ -30,8 +34,8 @@ const SPA_OLD_MAXBLOCKSIZE: u64 = 1 << SPA_OLD_MAXBLOCKSHIFT;
 //
 // We currently allow values ranging from 512 bytes (2^9 = 512) to 64 KiB
 // (2^16 = 65,536).
-const ASHIFT_MIN: usize = 9;
-const ASHIFT_MAX: usize = 16;
+pub const ASHIFT_MIN: usize = 9;
+pub const ASHIFT_MAX: usize = 16;
 
 // Size of block to hold the configuration data (a packed nvlist)
 const SPA_CONFIG_BLOCKSIZE: usize = 1 << 14;
@@ -52,14 +56,46 @@ const SPA_COMPRESSMASK: u64 = (1 << SPA_COMPRESSBITS) - 1;
 const BPE_NUM_WORDS: usize = 14;
 const BPE_PAYLOAD_SIZE: usize = BPE_NUM_WORDS * Layout::new::<u64>().align();
 
+// Sizes of the encryption parameters stored in an encrypted blkptr.
+pub const SIO_DATA_SALT_LEN: usize = 8;
+pub const SIO_DATA_IV_LEN: usize = 12;
+pub const SIO_DATA_MAC_LEN: usize = 16;
+
 // blkptr_t is 128 bytes
 const SPA_BLKPTRSHIFT: u64 = 7;
+pub const SPA_BLKPTRSIZE: usize = 1 << SPA_BLKPTRSHIFT;
+// dva_t is 16 bytes
+pub const SPA_DVASIZE: usize = 16;
+// Gang headers are the smallest block we can allocate
+pub const SPA_GANGBLOCKSIZE: usize = SPA_MINBLOCKSIZE as usize;
 // Number of DVAs in a bp
 const SPA_DVAS_PER_BP: usize = 3;
 // min vdevs to update during sync
 const SPA_SYNC_MIN_VDEVS: u64 = 3;
 
 #[cfg(target_endian = "little")]
-const HOST_BYTEORDER: u8 = 0;
+pub(crate) const HOST_BYTEORDER: u8 = 0;
 #[cfg(target_endian = "big")]
-const HOST_BYTEORDER: u8 = 1;
+pub(crate) const HOST_BYTEORDER: u8 = 1;
+
+/// An L0 bp with two DVAs on vdevs 0 and 1, for tests to start from.
+#[cfg(test)]
+pub(crate) fn sample_bp() -> Blkptr {
+    use crate::sio::{SIOChecksum, SIOCompress};
+
+    let mut bp = Blkptr::new();
+    for (i, d) in bp.blk_dva.iter_mut().take(2).enumerate() {
+        d.set_asize(0x2000);
+        d.set_vdev(i as u64);
+        d.set_offset(0x4a000 * (i as u64 + 1));
+    }
+    bp.set_lsize(0x20000);
+    bp.set_psize(0x2000);
+    bp.set_checksum(SIOChecksum::FLETCHER_4);
+    bp.set_compress(u8::from(SIOCompress::LZ4) as u64);
+    bp.set_type(19);
+    bp.set_birth(10, 8);
+    bp.set_fill(1);
+    bp.blk_cksum.set_checksum(0x1, 0x22, 0x333, 0x4444);
+    bp
+}
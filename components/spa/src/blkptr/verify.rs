@@ -0,0 +1,245 @@
+use super::blkptr::{Blkptr, EmbeddedType};
+use super::error::BlkptrError;
+use super::{BPE_PAYLOAD_SIZE, SPA_GANGBLOCKSIZE, SPA_MAXBLOCKSIZE};
+use crate::dmu;
+use crate::sio::{SIOChecksum, SIOCompress};
+use sys::P2Ext;
+
+/// The state of a top-level vdev, as far as block pointer verification is
+/// concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopVdevInfo {
+    /// Placeholder left behind by device removal; nothing may point into it.
+    Hole,
+    /// Present in the config but not opened; its DVAs cannot be checked.
+    Missing,
+    /// An open vdev with `asize` allocatable bytes.
+    Present { asize: u64, ashift: u64 },
+}
+
+/// What `Blkptr::verify` needs to know about the pool a bp belongs to.
+#[derive(Debug, Clone, Default)]
+pub struct PoolInfo {
+    /// DVAs are only checked against the vdev tree when the config is trusted,
+    /// e.g. not while an untrusted config is being imported.
+    pub trust_config: bool,
+    /// Top-level vdevs, indexed by vdev id.
+    pub vdevs: Vec<TopVdevInfo>,
+}
+
+impl Blkptr {
+    /// Checks that the block pointer is self-consistent and only refers to
+    /// space that exists in `pool`. Block pointers read from disk must pass
+    /// this before any of their fields are trusted, since a corrupt bp would
+    /// otherwise make us read from (or free) random locations.
+    pub fn verify(&self, pool: &PoolInfo) -> Result<(), BlkptrError> {
+        let typ = self.get_type();
+        if !dmu::ot_is_valid(typ) {
+            return Err(BlkptrError::InvalidType(typ));
+        }
+        // inherit and on are property values that select a function; they
+        // are resolved before a bp is written and never valid on disk.
+        let cksum = self.get_checksum()?;
+        if matches!(cksum, SIOChecksum::INHERIT | SIOChecksum::ON) {
+            return Err(BlkptrError::InvalidChecksum(u8::from(cksum) as u64));
+        }
+        let comp = self.get_compress();
+        if comp <= u8::from(SIOCompress::ON) as u64
+            || comp >= u8::from(SIOCompress::FUNCTIONS) as u64
+        {
+            return Err(BlkptrError::InvalidCompress(comp));
+        }
+        if self.get_lsize() > SPA_MAXBLOCKSIZE {
+            return Err(BlkptrError::InvalidLsize(self.get_lsize()));
+        }
+        if self.get_psize() > SPA_MAXBLOCKSIZE {
+            return Err(BlkptrError::InvalidPsize(self.get_psize()));
+        }
+
+        if self.is_embedded() {
+            let etype = self.get_etype();
+            if etype >= u8::from(EmbeddedType::Types) as u64 {
+                return Err(BlkptrError::InvalidEtype(etype));
+            }
+            // Encrypted bps use the payload words for their parameters.
+            if self.get_user_crypt() {
+                return Err(BlkptrError::EncryptedEmbedded);
+            }
+            let psize = self.bpe_get_psize();
+            if psize > BPE_PAYLOAD_SIZE as u64 {
+                return Err(BlkptrError::InvalidEmbeddedSize {
+                    lsize: self.bpe_get_lsize(),
+                    psize,
+                });
+            }
+            return Ok(());
+        }
+
+        if !pool.trust_config {
+            return Ok(());
+        }
+
+        for d in 0..self.get_ndvas() as usize {
+            let dva = &self.blk_dva[d];
+            let vdev = dva.get_vdev();
+            let (vdev_asize, ashift) = match pool.vdevs.get(vdev as usize) {
+                None => return Err(BlkptrError::InvalidVdev { dva: d, vdev }),
+                Some(TopVdevInfo::Hole) => return Err(BlkptrError::HoleVdev { dva: d, vdev }),
+                // The vdev may come back later; there is nothing to check yet.
+                Some(TopVdevInfo::Missing) => continue,
+                Some(TopVdevInfo::Present { asize, ashift }) => (*asize, *ashift),
+            };
+
+            let offset = dva.get_offset();
+            // A gang DVA only covers the gang header; the asize recorded in
+            // it is the total for the whole gang tree.
+            let asize = if dva.get_gang() != 0 {
+                (SPA_GANGBLOCKSIZE as u64).p2roundup(1 << ashift)
+            } else {
+                dva.get_asize()
+            };
+            if offset.checked_add(asize).is_none_or(|end| end > vdev_asize) {
+                return Err(BlkptrError::InvalidOffset {
+                    dva: d,
+                    offset,
+                    asize,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PoolInfo, TopVdevInfo};
+    use crate::blkptr::blkptr::{Blkptr, EmbeddedType};
+    use crate::blkptr::error::BlkptrError;
+    use crate::blkptr::sample_bp;
+    use crate::sio::{SIOChecksum, SIOCompress};
+    use sys::BitOptExt;
+
+    const VDEV_ASIZE: u64 = 1 << 30;
+
+    fn pool() -> PoolInfo {
+        PoolInfo {
+            trust_config: true,
+            vdevs: vec![
+                TopVdevInfo::Present {
+                    asize: VDEV_ASIZE,
+                    ashift: 12,
+                },
+                TopVdevInfo::Present {
+                    asize: VDEV_ASIZE,
+                    ashift: 12,
+                },
+                TopVdevInfo::Hole,
+                TopVdevInfo::Missing,
+            ],
+        }
+    }
+
+    #[test]
+    fn verify_valid() {
+        assert_eq!(sample_bp().verify(&pool()), Ok(()));
+
+        let mut missing = sample_bp();
+        missing.blk_dva[2].set_asize(0x2000);
+        missing.blk_dva[2].set_vdev(3);
+        missing.blk_dva[2].set_offset(VDEV_ASIZE * 4);
+        assert_eq!(missing.verify(&pool()), Ok(()));
+    }
+
+    #[test]
+    fn verify_fields() {
+        let mut bp = sample_bp();
+        bp.set_type(100);
+        assert_eq!(bp.verify(&pool()), Err(BlkptrError::InvalidType(100)));
+
+        let mut bp = sample_bp();
+        bp.blk_prop.bf_set(40, 8, 0xee);
+        assert_eq!(bp.get_checksum(), Err(BlkptrError::InvalidChecksum(0xee)));
+        assert_eq!(bp.verify(&pool()), Err(BlkptrError::InvalidChecksum(0xee)));
+        // FUNCTIONS counts the checksum functions; it is not one itself.
+        let functions = u8::from(SIOChecksum::FUNCTIONS) as u64;
+        bp.blk_prop.bf_set(40, 8, functions);
+        assert_eq!(
+            bp.verify(&pool()),
+            Err(BlkptrError::InvalidChecksum(functions))
+        );
+
+        for c in [SIOChecksum::INHERIT, SIOChecksum::ON] {
+            let mut bp = sample_bp();
+            bp.set_checksum(c);
+            let c = u8::from(c) as u64;
+            assert_eq!(bp.verify(&pool()), Err(BlkptrError::InvalidChecksum(c)));
+        }
+
+        let mut bp = sample_bp();
+        bp.set_compress(100);
+        assert_eq!(bp.verify(&pool()), Err(BlkptrError::InvalidCompress(100)));
+        for c in [SIOCompress::INHERIT, SIOCompress::ON] {
+            let c = u8::from(c) as u64;
+            bp.set_compress(c);
+            assert_eq!(bp.verify(&pool()), Err(BlkptrError::InvalidCompress(c)));
+        }
+
+        let mut bp = sample_bp();
+        bp.set_lsize(32 << 20);
+        assert_eq!(bp.verify(&pool()), Err(BlkptrError::InvalidLsize(32 << 20)));
+    }
+
+    #[test]
+    fn verify_dvas() {
+        let mut bp = sample_bp();
+        bp.blk_dva[0].set_vdev(7);
+        assert_eq!(
+            bp.verify(&pool()),
+            Err(BlkptrError::InvalidVdev { dva: 0, vdev: 7 })
+        );
+
+        bp.blk_dva[0].set_vdev(2);
+        assert_eq!(
+            bp.verify(&pool()),
+            Err(BlkptrError::HoleVdev { dva: 0, vdev: 2 })
+        );
+
+        let mut bp = sample_bp();
+        bp.blk_dva[0].set_offset(VDEV_ASIZE - 0x1000);
+        assert!(matches!(
+            bp.verify(&pool()),
+            Err(BlkptrError::InvalidOffset { dva: 0, .. })
+        ));
+
+        // Only the gang header has to fit for a gang DVA.
+        let mut gang = sample_bp();
+        gang.blk_dva[0].set_asize(0x10000);
+        gang.blk_dva[0].set_offset(VDEV_ASIZE - 0x1000);
+        assert!(gang.verify(&pool()).is_err());
+        gang.blk_dva[0].set_gang(1);
+        assert_eq!(gang.verify(&pool()), Ok(()));
+
+        // DVAs are not checked against an untrusted config.
+        let untrusted = PoolInfo {
+            trust_config: false,
+            ..pool()
+        };
+        assert_eq!(bp.verify(&untrusted), Ok(()));
+    }
+
+    #[test]
+    fn verify_embedded() {
+        let mut bp = Blkptr::new();
+        bp.set_embedded_payload(EmbeddedType::Data, SIOCompress::OFF, &[1; 16], 16)
+            .unwrap();
+        assert_eq!(bp.verify(&pool()), Ok(()));
+
+        bp.set_user_crypt(true);
+        assert_eq!(bp.verify(&pool()), Err(BlkptrError::EncryptedEmbedded));
+
+        bp.set_user_crypt(false);
+        bp.set_etype(9);
+        assert_eq!(bp.verify(&pool()), Err(BlkptrError::InvalidEtype(9)));
+    }
+}
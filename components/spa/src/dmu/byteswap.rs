@@ -0,0 +1,601 @@
+//! Byteswap functions for blocks written on a host of the other endianness.
+//!
+//! Every object type names one of these through `DmuByteswap`. Each function
+//! converts a block in place from the foreign byte order to the host's. The
+//! structured formats (ZAP, dnode, objset, ACL) swap each field at its own
+//! width and leave byte arrays alone. Fields that say how to interpret the
+//! rest of the block are read after they are swapped. A malformed block is
+//! swapped as far as it can be and then left for its consumer to reject.
+
+use super::{ot_byteswap, DmuByteswap, DMU_BSWAP_NUMFUNCS};
+
+pub type ByteswapFunc = fn(&mut [u8]);
+
+/// Reverses the bytes of the `width`-byte field at `at`, if it is in bounds.
+#[inline]
+fn swap(buf: &mut [u8], at: usize, width: usize) {
+    if let Some(field) = buf.get_mut(at..at + width) {
+        field.reverse();
+    }
+}
+
+#[inline]
+fn get_u16(buf: &[u8], at: usize) -> u16 {
+    buf.get(at..at + 2)
+        .map_or(0, |b| u16::from_ne_bytes([b[0], b[1]]))
+}
+
+#[inline]
+fn get_u64(buf: &[u8], at: usize) -> u64 {
+    buf.get(at..at + 8).map_or(0, |b| {
+        let mut w = [0u8; 8];
+        w.copy_from_slice(b);
+        u64::from_ne_bytes(w)
+    })
+}
+
+fn swap_array(buf: &mut [u8], width: usize) {
+    for field in buf.chunks_exact_mut(width) {
+        field.reverse();
+    }
+}
+
+pub fn byteswap_uint8_array(_buf: &mut [u8]) {}
+
+pub fn byteswap_uint16_array(buf: &mut [u8]) {
+    swap_array(buf, 2);
+}
+
+pub fn byteswap_uint32_array(buf: &mut [u8]) {
+    swap_array(buf, 4);
+}
+
+pub fn byteswap_uint64_array(buf: &mut [u8]) {
+    swap_array(buf, 8);
+}
+
+// ZAP block types, in the first word of every ZAP block.
+pub const ZBT_LEAF: u64 = 1 << 63;
+pub const ZBT_HEADER: u64 = (1 << 63) + 1;
+pub const ZBT_MICRO: u64 = (1 << 63) + 3;
+
+const MZAP_ENT_LEN: usize = 64;
+
+const ZAP_LEAF_HDR_SIZE: usize = 48;
+const ZAP_LEAF_CHUNKSIZE: usize = 24;
+const ZAP_CHUNK_FREE: u8 = 253;
+const ZAP_CHUNK_ENTRY: u8 = 252;
+const ZAP_CHUNK_ARRAY: u8 = 251;
+
+/// A microzap is a header followed by fixed size entries, each a value, a
+/// collision differentiator and a name.
+fn mzap_byteswap(buf: &mut [u8]) {
+    // mz_block_type, mz_salt, mz_normflags
+    for at in [0, 8, 16] {
+        swap(buf, at, 8);
+    }
+    for ent in buf.chunks_exact_mut(MZAP_ENT_LEN).skip(1) {
+        swap(ent, 0, 8); // mze_value
+        swap(ent, 8, 4); // mze_cd
+    }
+}
+
+/// A fat ZAP leaf: a header, a hash table of chunk indices, then chunks
+/// that are entries, pieces of names and values, or free.
+fn zap_leaf_byteswap(buf: &mut [u8]) {
+    swap(buf, 0, 8); // lh_block_type
+    swap(buf, 16, 8); // lh_prefix
+    swap(buf, 24, 4); // lh_magic
+    for at in [28, 30, 32, 34] {
+        // lh_nfree, lh_nentries, lh_prefix_len, lh_freelist
+        swap(buf, at, 2);
+    }
+
+    if buf.len() < ZAP_LEAF_HDR_SIZE {
+        return;
+    }
+    let shift = usize::BITS - 1 - buf.len().leading_zeros();
+    let hash_entries = 1usize << shift.saturating_sub(5);
+    let chunks_at = ZAP_LEAF_HDR_SIZE + 2 * hash_entries;
+    let nchunks =
+        ((1usize << shift).saturating_sub(2 * hash_entries) / ZAP_LEAF_CHUNKSIZE).saturating_sub(2);
+    if chunks_at > buf.len() {
+        return;
+    }
+    byteswap_uint16_array(&mut buf[ZAP_LEAF_HDR_SIZE..chunks_at]);
+
+    for chunk in buf[chunks_at..]
+        .chunks_exact_mut(ZAP_LEAF_CHUNKSIZE)
+        .take(nchunks)
+    {
+        match chunk[0] {
+            ZAP_CHUNK_ENTRY => {
+                // le_next, le_name_chunk, le_name_numints, le_value_chunk,
+                // le_value_numints
+                for at in [2, 4, 6, 8, 10] {
+                    swap(chunk, at, 2);
+                }
+                swap(chunk, 12, 4); // le_cd
+                swap(chunk, 16, 8); // le_hash
+            }
+            // lf_next, la_next; array contents are stored big-endian.
+            ZAP_CHUNK_FREE | ZAP_CHUNK_ARRAY => swap(chunk, 22, 2),
+            _ => {}
+        }
+    }
+}
+
+/// The header block and pointer table blocks of a fat ZAP are all 64-bit
+/// words.
+fn fzap_byteswap(buf: &mut [u8]) {
+    let block_type = get_u64(buf, 0);
+    if block_type == ZBT_LEAF || block_type == ZBT_LEAF.swap_bytes() {
+        zap_leaf_byteswap(buf);
+    } else {
+        byteswap_uint64_array(buf);
+    }
+}
+
+pub fn zap_byteswap(buf: &mut [u8]) {
+    let block_type = get_u64(buf, 0);
+    if block_type == ZBT_MICRO || block_type == ZBT_MICRO.swap_bytes() {
+        mzap_byteswap(buf);
+    } else {
+        fzap_byteswap(buf);
+    }
+}
+
+pub const DNODE_SHIFT: usize = 9;
+pub const DNODE_SIZE: usize = 1 << DNODE_SHIFT;
+/// The fixed fields before the blkptrs.
+pub const DNODE_CORE_SIZE: usize = 64;
+pub const DNODE_FLAG_SPILL_BLKPTR: u8 = 1 << 2;
+const DN_BLKPTR_SIZE: usize = 128;
+
+/// Swaps one dnode at the start of `buf`, including its extra slots, and
+/// returns the number of slots it occupies. Free dnodes are cleared.
+fn dnode_byteswap(buf: &mut [u8]) -> usize {
+    if buf.len() < DNODE_SIZE {
+        return 1;
+    }
+    if buf[0] == 0 {
+        buf[..DNODE_SIZE].fill(0);
+        return 1;
+    }
+
+    swap(buf, 8, 2); // dn_datablkszsec
+    swap(buf, 10, 2); // dn_bonuslen
+    swap(buf, 16, 8); // dn_maxblkid
+    swap(buf, 24, 8); // dn_used
+
+    let slots = (buf[12] as usize + 1).min(buf.len() / DNODE_SIZE);
+    let end = slots * DNODE_SIZE;
+    let (nblkptr, bonustype, flags) = (buf[3] as usize, buf[4], buf[7]);
+    let spill = flags & DNODE_FLAG_SPILL_BLKPTR != 0;
+    let bonus_end = if spill { end - DN_BLKPTR_SIZE } else { end };
+    let bonus = (DNODE_CORE_SIZE + nblkptr * DN_BLKPTR_SIZE).min(bonus_end);
+
+    byteswap_uint64_array(&mut buf[DNODE_CORE_SIZE..bonus]);
+    // dn_bonuslen is only tested for zero, which reads the same either way.
+    if get_u16(buf, 10) != 0 {
+        if let Some(bswap) = ot_byteswap(bonustype) {
+            bswap.func()(&mut buf[bonus..bonus_end]);
+        }
+    }
+    if spill {
+        byteswap_uint64_array(&mut buf[bonus_end..end]);
+    }
+    slots
+}
+
+/// A block of dnodes. A dnode may take several consecutive slots; the extra
+/// slots are covered by its own swap.
+pub fn dnode_buf_byteswap(buf: &mut [u8]) {
+    let mut at = 0;
+    while at + DNODE_SIZE <= buf.len() {
+        at += dnode_byteswap(&mut buf[at..]) * DNODE_SIZE;
+    }
+}
+
+pub const OBJSET_PHYS_SIZE_V1: usize = 1024;
+pub const OBJSET_PHYS_SIZE_V2: usize = 2048;
+pub const OBJSET_PHYS_SIZE_V3: usize = 4096;
+const OS_ZIL_HEADER: usize = DNODE_SIZE;
+const OS_ZIL_HEADER_SIZE: usize = 192;
+const OS_TYPE: usize = OS_ZIL_HEADER + OS_ZIL_HEADER_SIZE;
+const OS_FLAGS: usize = OS_TYPE + 8;
+
+/// An objset block: the meta dnode, the ZIL header, the type and flags,
+/// and in later versions the dnodes of the space accounting objects.
+pub fn dmu_objset_byteswap(buf: &mut [u8]) {
+    if buf.len() < OBJSET_PHYS_SIZE_V1 {
+        return;
+    }
+    dnode_byteswap(&mut buf[..DNODE_SIZE]);
+    byteswap_uint64_array(&mut buf[OS_ZIL_HEADER..OS_TYPE]);
+    swap(buf, OS_TYPE, 8);
+    swap(buf, OS_FLAGS, 8);
+
+    // os_userused_dnode, os_groupused_dnode, os_projectused_dnode
+    let used = [
+        (OBJSET_PHYS_SIZE_V2, OBJSET_PHYS_SIZE_V1),
+        (OBJSET_PHYS_SIZE_V2, OBJSET_PHYS_SIZE_V1 + DNODE_SIZE),
+        (OBJSET_PHYS_SIZE_V3, OBJSET_PHYS_SIZE_V2),
+    ];
+    for (min_size, at) in used {
+        if buf.len() >= min_size {
+            dnode_byteswap(&mut buf[at..at + DNODE_SIZE]);
+        }
+    }
+}
+
+pub const ACE_OWNER: u16 = 0x1000;
+pub const ACE_GROUP: u16 = 0x2000;
+pub const ACE_EVERYONE: u16 = 0x4000;
+pub const ACE_IDENTIFIER_GROUP: u16 = 0x0040;
+pub const ACE_TYPE_FLAGS: u16 = ACE_OWNER | ACE_GROUP | ACE_EVERYONE | ACE_IDENTIFIER_GROUP;
+/// group@, the owning group.
+const ACE_OWNING_GROUP: u16 = ACE_IDENTIFIER_GROUP | ACE_GROUP;
+
+pub const ACE_ACCESS_ALLOWED_OBJECT_ACE_TYPE: u16 = 0x05;
+pub const ACE_ACCESS_DENIED_OBJECT_ACE_TYPE: u16 = 0x06;
+pub const ACE_SYSTEM_AUDIT_OBJECT_ACE_TYPE: u16 = 0x07;
+pub const ACE_SYSTEM_ALARM_OBJECT_ACE_TYPE: u16 = 0x08;
+
+/// `ace_t`: who, access mask, flags, type.
+const ACE_SIZE: usize = 12;
+/// `zfs_ace_hdr_t`: type, flags, access mask.
+const ZFS_ACE_HDR_SIZE: usize = 8;
+/// `zfs_ace_t`: the header and a FUID.
+const ZFS_ACE_SIZE: usize = 16;
+/// `zfs_object_ace_t`: a `zfs_ace_t` and two GUIDs.
+const ZFS_OBJECT_ACE_SIZE: usize = 48;
+
+/// Old-style ACLs are an array of fixed size `ace_t`.
+pub fn zfs_oldacl_byteswap(buf: &mut [u8]) {
+    for ace in buf.chunks_exact_mut(ACE_SIZE) {
+        swap(ace, 0, 4); // a_who
+        swap(ace, 4, 4); // a_access_mask
+        swap(ace, 8, 2); // a_flags
+        swap(ace, 10, 2); // a_type
+    }
+}
+
+/// FUID-style ACLs pack entries of three sizes; which one follows from the
+/// entry's flags and type.
+pub fn zfs_acl_byteswap(buf: &mut [u8]) {
+    let mut at = 0;
+    while at + ZFS_ACE_HDR_SIZE <= buf.len() {
+        let ace = &mut buf[at..];
+        swap(ace, 0, 2); // z_type
+        swap(ace, 2, 2); // z_flags
+        swap(ace, 4, 4); // z_access_mask
+        let (ace_type, flags) = (get_u16(ace, 0), get_u16(ace, 2));
+
+        at += match flags & ACE_TYPE_FLAGS {
+            ACE_OWNER | ACE_OWNING_GROUP | ACE_EVERYONE => ZFS_ACE_HDR_SIZE,
+            _ => {
+                swap(ace, 8, 8); // z_fuid
+                match ace_type {
+                    ACE_ACCESS_ALLOWED_OBJECT_ACE_TYPE
+                    | ACE_ACCESS_DENIED_OBJECT_ACE_TYPE
+                    | ACE_SYSTEM_AUDIT_OBJECT_ACE_TYPE
+                    | ACE_SYSTEM_ALARM_OBJECT_ACE_TYPE => ZFS_OBJECT_ACE_SIZE,
+                    _ => ZFS_ACE_SIZE,
+                }
+            }
+        };
+    }
+}
+
+pub const ZFS_ACL_VERSION: u16 = 1;
+/// ACEs that fit inside a znode before the ACL spills to its own object.
+const ACE_SLOT_CNT: usize = 6;
+/// The 64-bit fields of `znode_phys_t` before its embedded ACL.
+const ZNODE_WORDS: usize = 22;
+const ZP_ACL: usize = ZNODE_WORDS * 8;
+const ZP_ACE_DATA: usize = ZP_ACL + 16;
+
+/// The legacy ZPL znode, kept as the bonus buffer of file dnodes.
+pub fn zfs_znode_byteswap(buf: &mut [u8]) {
+    if buf.len() < ZP_ACE_DATA {
+        return;
+    }
+    byteswap_uint64_array(&mut buf[..ZP_ACL]);
+    swap(buf, ZP_ACL, 8); // z_acl_extern_obj
+    swap(buf, ZP_ACL + 8, 4); // z_acl_size
+    swap(buf, ZP_ACL + 12, 2); // z_acl_version
+    swap(buf, ZP_ACL + 14, 2); // z_acl_count
+
+    let end = (ZP_ACE_DATA + ACE_SLOT_CNT * ACE_SIZE).min(buf.len());
+    if get_u16(buf, ZP_ACL + 12) == ZFS_ACL_VERSION {
+        zfs_acl_byteswap(&mut buf[ZP_ACE_DATA..end]);
+    } else {
+        zfs_oldacl_byteswap(&mut buf[ZP_ACE_DATA..end]);
+    }
+}
+
+/// Indexed by `DmuByteswap`.
+pub static DMU_OT_BYTESWAP: [ByteswapFunc; DMU_BSWAP_NUMFUNCS as usize] = [
+    byteswap_uint8_array,
+    byteswap_uint16_array,
+    byteswap_uint32_array,
+    byteswap_uint64_array,
+    zap_byteswap,
+    dnode_buf_byteswap,
+    dmu_objset_byteswap,
+    zfs_znode_byteswap,
+    zfs_oldacl_byteswap,
+    zfs_acl_byteswap,
+];
+
+impl DmuByteswap {
+    /// Panics for `NUMFUNCS`, which is not a byteswap function.
+    #[inline]
+    pub fn func(&self) -> ByteswapFunc {
+        DMU_OT_BYTESWAP[u8::from(*self) as usize]
+    }
+}
+
+/// Converts a block from the other byte order. Indirect blocks (`level` >
+/// 0) hold blkptrs, whatever the object type. Returns false if `ot` is not a
+/// valid object type, leaving `buf` untouched.
+pub fn dmu_byteswap_block(ot: u8, level: u8, buf: &mut [u8]) -> bool {
+    if level > 0 {
+        byteswap_uint64_array(buf);
+        return true;
+    }
+    match ot_byteswap(ot) {
+        Some(bswap) => {
+            bswap.func()(buf);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmu::{DmuObjectType, DMU_OT_NEWTYPE};
+
+    // Writers for fields as a host of the other endianness would lay them
+    // out, and readers for the host-order result.
+    fn put16(buf: &mut [u8], at: usize, v: u16) {
+        buf[at..at + 2].copy_from_slice(&v.swap_bytes().to_ne_bytes());
+    }
+    fn put32(buf: &mut [u8], at: usize, v: u32) {
+        buf[at..at + 4].copy_from_slice(&v.swap_bytes().to_ne_bytes());
+    }
+    fn put64(buf: &mut [u8], at: usize, v: u64) {
+        buf[at..at + 8].copy_from_slice(&v.swap_bytes().to_ne_bytes());
+    }
+    fn get32(buf: &[u8], at: usize) -> u32 {
+        u32::from_ne_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn byteswap_arrays() {
+        let mut buf = [1u8, 2, 3, 4, 5, 6, 7, 8];
+        byteswap_uint8_array(&mut buf);
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+        byteswap_uint16_array(&mut buf);
+        assert_eq!(buf, [2, 1, 4, 3, 6, 5, 8, 7]);
+        byteswap_uint32_array(&mut buf);
+        assert_eq!(buf, [3, 4, 1, 2, 7, 8, 5, 6]);
+        byteswap_uint64_array(&mut buf);
+        assert_eq!(buf, [6, 5, 8, 7, 2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn byteswap_table() {
+        assert_eq!(
+            DmuByteswap::ZAP.func() as usize,
+            zap_byteswap as ByteswapFunc as usize
+        );
+        assert_eq!(
+            DmuByteswap::ACL.func() as usize,
+            zfs_acl_byteswap as ByteswapFunc as usize
+        );
+
+        // Indirect blocks are swapped as words regardless of type.
+        let mut buf = [0u8; 16];
+        put64(&mut buf, 8, 0x1122);
+        let dnode = u8::from(DmuObjectType::DNODE);
+        assert!(dmu_byteswap_block(dnode, 1, &mut buf));
+        assert_eq!(get_u64(&buf, 8), 0x1122);
+
+        let new_uint64 = DMU_OT_NEWTYPE | u8::from(DmuByteswap::UINT64);
+        assert!(dmu_byteswap_block(new_uint64, 0, &mut buf));
+        assert_eq!(get_u64(&buf, 8), 0x1122u64.swap_bytes());
+        assert!(!dmu_byteswap_block(DMU_OT_NEWTYPE | 0x1f, 0, &mut buf));
+        assert_eq!(get_u64(&buf, 8), 0x1122u64.swap_bytes());
+    }
+
+    #[test]
+    fn byteswap_microzap() {
+        let mut buf = vec![0u8; 512];
+        put64(&mut buf, 0, ZBT_MICRO);
+        put64(&mut buf, 8, 0xdead_beef);
+        put64(&mut buf, 64, 42);
+        put32(&mut buf, 72, 7);
+        buf[78..83].copy_from_slice(b"hello");
+
+        zap_byteswap(&mut buf);
+        assert_eq!(get_u64(&buf, 0), ZBT_MICRO);
+        assert_eq!(get_u64(&buf, 8), 0xdead_beef);
+        assert_eq!(get_u64(&buf, 64), 42);
+        assert_eq!(get32(&buf, 72), 7);
+        assert_eq!(&buf[78..83], b"hello");
+    }
+
+    #[test]
+    fn byteswap_fat_zap() {
+        // A 4K leaf: 128 hash entries, then 157 chunks.
+        let mut leaf = vec![0u8; 4096];
+        put64(&mut leaf, 0, ZBT_LEAF);
+        put32(&mut leaf, 24, 0x2ab1eaf);
+        put16(&mut leaf, 30, 1);
+        put16(&mut leaf, 48 + 2 * 6, 0x0102);
+        let chunk = |i: usize| 48 + 2 * 128 + i * ZAP_LEAF_CHUNKSIZE;
+        let (e, a, f) = (chunk(0), chunk(1), chunk(2));
+        leaf[e] = ZAP_CHUNK_ENTRY;
+        leaf[e + 1] = 8;
+        put16(&mut leaf, e + 4, 1);
+        put32(&mut leaf, e + 12, 3);
+        put64(&mut leaf, e + 16, 0xabcd << 40);
+        leaf[a] = ZAP_CHUNK_ARRAY;
+        leaf[a + 1..a + 4].copy_from_slice(b"key");
+        put16(&mut leaf, a + 22, 0xffff);
+        leaf[f] = ZAP_CHUNK_FREE;
+        put16(&mut leaf, f + 22, 3);
+
+        zap_byteswap(&mut leaf);
+        assert_eq!(get_u64(&leaf, 0), ZBT_LEAF);
+        assert_eq!(get32(&leaf, 24), 0x2ab1eaf);
+        assert_eq!(get_u16(&leaf, 30), 1);
+        assert_eq!(get_u16(&leaf, 48 + 2 * 6), 0x0102);
+        assert_eq!(get_u16(&leaf, e + 4), 1);
+        assert_eq!(get32(&leaf, e + 12), 3);
+        assert_eq!(get_u64(&leaf, e + 16), 0xabcd << 40);
+        assert_eq!(&leaf[a + 1..a + 4], b"key");
+        assert_eq!(get_u16(&leaf, a + 22), 0xffff);
+        assert_eq!(get_u16(&leaf, f + 22), 3);
+
+        // Header and pointer table blocks are plain words.
+        let mut hdr = vec![0u8; 4096];
+        put64(&mut hdr, 0, ZBT_HEADER);
+        put64(&mut hdr, 2048, 17);
+        zap_byteswap(&mut hdr);
+        assert_eq!(get_u64(&hdr, 0), ZBT_HEADER);
+        assert_eq!(get_u64(&hdr, 2048), 17);
+    }
+
+    /// A foreign dnode of `ot` with one blkptr and a bonus buffer.
+    fn dnode(buf: &mut [u8], ot: DmuObjectType, bonustype: DmuObjectType, flags: u8) {
+        buf[0] = ot.into();
+        buf[3] = 1;
+        buf[4] = bonustype.into();
+        buf[7] = flags;
+        put16(buf, 8, 32);
+        put16(buf, 10, 64);
+        put64(buf, 16, 99);
+        put64(buf, 64, 0x1000);
+        put64(buf, 192, 5);
+    }
+
+    #[test]
+    fn byteswap_dnodes() {
+        let mut buf = vec![0u8; 4 * DNODE_SIZE];
+        // A large dnode over slots 0 and 1, with a spill blkptr.
+        dnode(
+            &mut buf,
+            DmuObjectType::PLAIN_FILE_CONTENTS,
+            DmuObjectType::UINT64_OTHER,
+            DNODE_FLAG_SPILL_BLKPTR,
+        );
+        buf[12] = 1;
+        put64(&mut buf, 2 * DNODE_SIZE - 128, 0x77);
+        // Slot 2 is free but dirty; slot 3 has a plain byte bonus.
+        buf[2 * DNODE_SIZE + 100] = 0xaa;
+        dnode(
+            &mut buf[3 * DNODE_SIZE..],
+            DmuObjectType::ZVOL,
+            DmuObjectType::PACKED_NVLIST,
+            0,
+        );
+
+        dnode_buf_byteswap(&mut buf);
+        assert_eq!(get_u16(&buf, 8), 32);
+        assert_eq!(get_u16(&buf, 10), 64);
+        assert_eq!(get_u64(&buf, 16), 99);
+        assert_eq!(get_u64(&buf, 64), 0x1000);
+        assert_eq!(get_u64(&buf, 192), 5);
+        assert_eq!(get_u64(&buf, 2 * DNODE_SIZE - 128), 0x77);
+        assert!(buf[2 * DNODE_SIZE..3 * DNODE_SIZE].iter().all(|&b| b == 0));
+        let d3 = &buf[3 * DNODE_SIZE..];
+        assert_eq!(get_u64(d3, 64), 0x1000);
+        assert_eq!(get_u64(d3, 192), 5u64.swap_bytes());
+    }
+
+    #[test]
+    fn byteswap_objset() {
+        let mut buf = vec![0u8; OBJSET_PHYS_SIZE_V3];
+        dnode(&mut buf, DmuObjectType::DNODE, DmuObjectType::NONE, 0);
+        put64(&mut buf, OS_ZIL_HEADER, 1234);
+        put64(&mut buf, OS_TYPE, 2);
+        put64(&mut buf, OS_FLAGS, 1);
+        buf[OS_FLAGS + 8] = 0xee;
+        dnode(
+            &mut buf[OBJSET_PHYS_SIZE_V2..],
+            DmuObjectType::USERGROUP_USED,
+            DmuObjectType::NONE,
+            0,
+        );
+
+        dmu_objset_byteswap(&mut buf);
+        assert_eq!(get_u64(&buf, 16), 99);
+        assert_eq!(get_u64(&buf, OS_ZIL_HEADER), 1234);
+        assert_eq!(get_u64(&buf, OS_TYPE), 2);
+        assert_eq!(get_u64(&buf, OS_FLAGS), 1);
+        assert_eq!(buf[OS_FLAGS + 8], 0xee);
+        assert_eq!(get_u64(&buf, OBJSET_PHYS_SIZE_V2 + 64), 0x1000);
+    }
+
+    #[test]
+    fn byteswap_acl() {
+        let mut buf = vec![0u8; 8 + 16 + 48];
+        // owner@: header only.
+        put16(&mut buf, 0, 0);
+        put16(&mut buf, 2, ACE_OWNER);
+        put32(&mut buf, 4, 0x1f);
+        // A user entry with a FUID.
+        put16(&mut buf, 8, 1);
+        put16(&mut buf, 10, 0);
+        put64(&mut buf, 16, 1000);
+        // An object entry for a group.
+        put16(&mut buf, 24, ACE_ACCESS_DENIED_OBJECT_ACE_TYPE);
+        put16(&mut buf, 26, ACE_IDENTIFIER_GROUP);
+        put64(&mut buf, 32, 2000);
+        buf[40] = 0x5c;
+
+        zfs_acl_byteswap(&mut buf);
+        assert_eq!(get_u16(&buf, 2), ACE_OWNER);
+        assert_eq!(get32(&buf, 4), 0x1f);
+        assert_eq!(get_u16(&buf, 8), 1);
+        assert_eq!(get_u64(&buf, 16), 1000);
+        assert_eq!(get_u16(&buf, 24), ACE_ACCESS_DENIED_OBJECT_ACE_TYPE);
+        assert_eq!(get_u64(&buf, 32), 2000);
+        assert_eq!(buf[40], 0x5c);
+
+        let mut old = vec![0u8; 2 * ACE_SIZE];
+        put32(&mut old, 12, 501);
+        put16(&mut old, 20, ACE_EVERYONE);
+        zfs_oldacl_byteswap(&mut old);
+        assert_eq!(get32(&old, 12), 501);
+        assert_eq!(get_u16(&old, 20), ACE_EVERYONE);
+    }
+
+    #[test]
+    fn byteswap_znode_bonus() {
+        let mut buf = vec![0u8; DNODE_SIZE];
+        dnode(
+            &mut buf,
+            DmuObjectType::PLAIN_FILE_CONTENTS,
+            DmuObjectType::ZNODE,
+            0,
+        );
+        put16(&mut buf, 10, 264);
+        let zp = 192;
+        put64(&mut buf, zp + 8 * 9, 0o100644); // zp_mode
+        put16(&mut buf, zp + ZP_ACL + 12, ZFS_ACL_VERSION);
+        put16(&mut buf, zp + ZP_ACE_DATA + 2, ACE_EVERYONE);
+        put32(&mut buf, zp + ZP_ACE_DATA + 4, 0x7);
+
+        dnode_buf_byteswap(&mut buf);
+        assert_eq!(get_u64(&buf, zp + 8 * 9), 0o100644);
+        assert_eq!(get_u16(&buf, zp + ZP_ACL + 12), ZFS_ACL_VERSION);
+        assert_eq!(get_u16(&buf, zp + ZP_ACE_DATA + 2), ACE_EVERYONE);
+        assert_eq!(get32(&buf, zp + ZP_ACE_DATA + 4), 0x7);
+    }
+}
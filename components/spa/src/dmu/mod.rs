@@ -0,0 +1,16 @@
+pub mod byteswap;
+pub mod object_type;
+
+pub use byteswap::*;
+pub use object_type::*;
+
+// Object types added after the original fixed set are not listed in the type
+// table. Instead the type byte carries flags describing how to handle them.
+pub const DMU_OT_NEWTYPE: u8 = 0x80;
+pub const DMU_OT_METADATA: u8 = 0x40;
+pub const DMU_OT_ENCRYPTED: u8 = 0x20;
+pub const DMU_OT_BYTESWAP_MASK: u8 = 0x1f;
+
+// Number of legacy object types and byteswap functions.
+pub const DMU_OT_NUMTYPES: u8 = DmuObjectType::NUMTYPES as u8;
+pub const DMU_BSWAP_NUMFUNCS: u8 = DmuByteswap::NUMFUNCS as u8;
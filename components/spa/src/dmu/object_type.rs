@@ -0,0 +1,555 @@
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+use super::{
+    DMU_BSWAP_NUMFUNCS, DMU_OT_BYTESWAP_MASK, DMU_OT_ENCRYPTED, DMU_OT_METADATA, DMU_OT_NEWTYPE,
+    DMU_OT_NUMTYPES,
+};
+
+/// Byteswap functions used to convert blocks of an object type written on a
+/// host with the other endianness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum DmuByteswap {
+    UINT8,
+    UINT16,
+    UINT32,
+    UINT64,
+    ZAP,
+    DNODE,
+    OBJSET,
+    ZNODE,
+    OLDACL,
+    ACL,
+    NUMFUNCS,
+}
+
+impl DmuByteswap {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DmuByteswap::UINT8 => "uint8",
+            DmuByteswap::UINT16 => "uint16",
+            DmuByteswap::UINT32 => "uint32",
+            DmuByteswap::UINT64 => "uint64",
+            DmuByteswap::ZAP => "zap",
+            DmuByteswap::DNODE => "dnode",
+            DmuByteswap::OBJSET => "objset",
+            DmuByteswap::ZNODE => "znode",
+            DmuByteswap::OLDACL => "oldacl",
+            DmuByteswap::ACL => "acl",
+            DmuByteswap::NUMFUNCS => "numfuncs",
+        }
+    }
+}
+
+/// Legacy DMU object types. Types added later are not listed here; they are
+/// described by the flag bits of the type byte instead (see `DMU_OT_NEWTYPE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+#[allow(non_camel_case_types)]
+pub enum DmuObjectType {
+    NONE,
+    OBJECT_DIRECTORY,
+    OBJECT_ARRAY,
+    PACKED_NVLIST,
+    PACKED_NVLIST_SIZE,
+    BPOBJ,
+    BPOBJ_HDR,
+    SPACE_MAP_HEADER,
+    SPACE_MAP,
+    INTENT_LOG,
+    DNODE,
+    OBJSET,
+    DSL_DIR,
+    DSL_DIR_CHILD_MAP,
+    DSL_DS_SNAP_MAP,
+    DSL_PROPS,
+    DSL_DATASET,
+    ZNODE,
+    OLDACL,
+    PLAIN_FILE_CONTENTS,
+    DIRECTORY_CONTENTS,
+    MASTER_NODE,
+    UNLINKED_SET,
+    ZVOL,
+    ZVOL_PROP,
+    PLAIN_OTHER,
+    UINT64_OTHER,
+    ZAP_OTHER,
+    ERROR_LOG,
+    SPA_HISTORY,
+    SPA_HISTORY_OFFSETS,
+    POOL_PROPS,
+    DSL_PERMS,
+    ACL,
+    SYSACL,
+    FUID,
+    FUID_SIZE,
+    NEXT_CLONES,
+    SCAN_QUEUE,
+    USERGROUP_USED,
+    USERGROUP_QUOTA,
+    USERREFS,
+    DDT_ZAP,
+    DDT_STATS,
+    SA,
+    SA_MASTER_NODE,
+    SA_ATTR_REGISTRATION,
+    SA_ATTR_LAYOUTS,
+    SCAN_XLATE,
+    DEDUP,
+    DEADLIST,
+    DEADLIST_HDR,
+    DSL_CLONES,
+    BPOBJ_SUBOBJ,
+    NUMTYPES,
+}
+
+/// Per-type attributes of the legacy object types.
+#[derive(Debug, Clone, Copy)]
+pub struct DmuObjectTypeInfo {
+    /// How to byteswap blocks of this type.
+    pub byteswap: DmuByteswap,
+    /// Metadata blocks get extra copies and are cached preferentially.
+    pub metadata: bool,
+    /// Whether blocks of this type are encrypted in encrypted datasets, rather
+    /// than only authenticated.
+    pub encrypted: bool,
+    pub name: &'static str,
+}
+
+pub const DMU_OT: [DmuObjectTypeInfo; DMU_OT_NUMTYPES as usize] = [
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: true,
+        encrypted: false,
+        name: "unallocated",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "object directory",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "object array",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: true,
+        encrypted: false,
+        name: "packed nvlist",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "packed nvlist size",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "bpobj",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "bpobj header",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "SPA space map header",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "SPA space map",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: true,
+        name: "ZIL intent log",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::DNODE,
+        metadata: true,
+        encrypted: true,
+        name: "DMU dnode",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::OBJSET,
+        metadata: true,
+        encrypted: false,
+        name: "DMU objset",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "DSL directory",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DSL directory child map",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DSL dataset snap map",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DSL props",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "DSL dataset",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZNODE,
+        metadata: true,
+        encrypted: false,
+        name: "ZFS znode",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::OLDACL,
+        metadata: true,
+        encrypted: true,
+        name: "ZFS V0 ACL",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: false,
+        encrypted: true,
+        name: "ZFS plain file",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: true,
+        name: "ZFS directory",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "ZFS master node",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: true,
+        name: "ZFS delete queue",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: false,
+        encrypted: true,
+        name: "zvol object",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "zvol prop",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: false,
+        encrypted: true,
+        name: "other uint8[]",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: false,
+        encrypted: true,
+        name: "other uint64[]",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "other ZAP",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "persistent error log",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: true,
+        encrypted: false,
+        name: "SPA history",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "SPA history offsets",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "Pool properties",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DSL permissions",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ACL,
+        metadata: true,
+        encrypted: true,
+        name: "ZFS ACL",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: true,
+        encrypted: true,
+        name: "ZFS SYSACL",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: true,
+        encrypted: true,
+        name: "FUID table",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "FUID table size",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DSL dataset next clones",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "scan work queue",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: true,
+        name: "ZFS user/group/project used",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: true,
+        name: "ZFS user/group/project quota",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "snapshot refcount tags",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DDT ZAP algorithm",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DDT statistics",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: true,
+        encrypted: true,
+        name: "System attributes",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: true,
+        name: "SA master node",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: true,
+        name: "SA attr registration",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: true,
+        name: "SA attr layouts",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "scan translations",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT8,
+        metadata: false,
+        encrypted: true,
+        name: "deduplicated block",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DSL deadlist map",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "DSL deadlist map hdr",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::ZAP,
+        metadata: true,
+        encrypted: false,
+        name: "DSL dir clones",
+    },
+    DmuObjectTypeInfo {
+        byteswap: DmuByteswap::UINT64,
+        metadata: true,
+        encrypted: false,
+        name: "bpobj subobj",
+    },
+];
+
+impl DmuObjectType {
+    #[inline]
+    pub fn info(&self) -> &'static DmuObjectTypeInfo {
+        &DMU_OT[*self as usize]
+    }
+}
+
+/// Whether `ot` names a known legacy type or a well-formed new-style type.
+#[inline]
+pub fn ot_is_valid(ot: u8) -> bool {
+    if ot & DMU_OT_NEWTYPE != 0 {
+        (ot & DMU_OT_BYTESWAP_MASK) < DMU_BSWAP_NUMFUNCS
+    } else {
+        ot < DMU_OT_NUMTYPES
+    }
+}
+
+/// Whether blocks of type `ot` are metadata. Unknown types are not.
+#[inline]
+pub fn ot_is_metadata(ot: u8) -> bool {
+    if ot & DMU_OT_NEWTYPE != 0 {
+        ot & DMU_OT_METADATA != 0
+    } else {
+        DMU_OT.get(ot as usize).is_some_and(|i| i.metadata)
+    }
+}
+
+/// Whether blocks of type `ot` are encrypted in encrypted datasets.
+#[inline]
+pub fn ot_is_encrypted(ot: u8) -> bool {
+    if ot & DMU_OT_NEWTYPE != 0 {
+        ot & DMU_OT_ENCRYPTED != 0
+    } else {
+        DMU_OT.get(ot as usize).is_some_and(|i| i.encrypted)
+    }
+}
+
+/// The byteswap function for blocks of type `ot`, if the type is valid.
+#[inline]
+pub fn ot_byteswap(ot: u8) -> Option<DmuByteswap> {
+    if ot & DMU_OT_NEWTYPE != 0 {
+        DmuByteswap::try_from(ot & DMU_OT_BYTESWAP_MASK)
+            .ok()
+            .filter(|b| *b != DmuByteswap::NUMFUNCS)
+    } else {
+        DMU_OT.get(ot as usize).map(|i| i.byteswap)
+    }
+}
+
+/// Name of a legacy object type, as printed in block pointers.
+#[inline]
+pub fn ot_name(ot: u8) -> Option<&'static str> {
+    if ot & DMU_OT_NEWTYPE != 0 {
+        return None;
+    }
+    DMU_OT.get(ot as usize).map(|i| i.name)
+}
+
+/// Looks up a legacy object type by its name.
+pub fn ot_from_name(name: &str) -> Option<DmuObjectType> {
+    DMU_OT
+        .iter()
+        .position(|i| i.name == name)
+        .and_then(|t| DmuObjectType::try_from(t as u8).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_types() {
+        assert_eq!(DMU_OT_NUMTYPES, 54);
+        assert_eq!(u8::from(DmuObjectType::PLAIN_FILE_CONTENTS), 19);
+        assert_eq!(u8::from(DmuObjectType::BPOBJ_SUBOBJ), 53);
+
+        let dnode = u8::from(DmuObjectType::DNODE);
+        assert!(ot_is_metadata(dnode));
+        assert!(ot_is_encrypted(dnode));
+        assert_eq!(ot_byteswap(dnode), Some(DmuByteswap::DNODE));
+
+        let objset = u8::from(DmuObjectType::OBJSET);
+        assert!(ot_is_metadata(objset));
+        assert!(!ot_is_encrypted(objset));
+
+        let file = u8::from(DmuObjectType::PLAIN_FILE_CONTENTS);
+        assert!(!ot_is_metadata(file));
+        assert_eq!(ot_name(file), Some("ZFS plain file"));
+        assert_eq!(
+            ot_from_name("ZFS plain file"),
+            Some(DmuObjectType::PLAIN_FILE_CONTENTS)
+        );
+    }
+
+    #[test]
+    fn new_types() {
+        let zap_metadata = DMU_OT_NEWTYPE | DMU_OT_METADATA | u8::from(DmuByteswap::ZAP);
+        assert!(ot_is_valid(zap_metadata));
+        assert!(ot_is_metadata(zap_metadata));
+        assert!(!ot_is_encrypted(zap_metadata));
+        assert_eq!(ot_byteswap(zap_metadata), Some(DmuByteswap::ZAP));
+        assert_eq!(ot_name(zap_metadata), None);
+
+        let bad = DMU_OT_NEWTYPE | 0x1f;
+        assert!(!ot_is_valid(bad));
+        assert_eq!(ot_byteswap(bad), None);
+        assert!(!ot_is_valid(DMU_OT_NUMTYPES));
+    }
+}
@@ -1,11 +1,12 @@
 use bitflags::bitflags;
 
 pub mod blkptr;
+pub mod dmu;
 pub mod sio;
-pub mod stat;
 pub mod spa_log;
 pub mod space_map;
-pub mod dmu;
+pub mod stat;
+pub mod vdev;
 
 bitflags! {
     pub struct ImportType: u8 {
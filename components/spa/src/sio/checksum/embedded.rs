@@ -0,0 +1,150 @@
+//! Embedded checksums.
+//!
+//! Blocks that are not reached through a blkptr carrying their checksum
+//! (vdev labels, gang headers) end with a trailer holding a magic number and
+//! the checksum. While computing the checksum the trailer holds a verifier
+//! instead, derived from where the block lives, so that a block which is
+//! intact but written to the wrong place still fails verification.
+//!
+//! The trailer is written in host byte order; the magic number tells a reader
+//! whether the block needs swapping.
+
+use super::{checksum_compute, ChecksumTemplates};
+use crate::blkptr::blkptr::Blkptr;
+use crate::blkptr::checksum::{ChecksumMismatch, SIOChksum};
+use crate::sio::SIOChecksum;
+
+pub const SIO_ECK_MAGIC: u64 = 0x210da7ab10c7a11;
+
+/// Size of the trailer: the magic number followed by a 256-bit checksum.
+pub const SIO_ECK_SIZE: usize = 40;
+
+/// Verifier for a gang header: the identity of the gang bp that points at it.
+pub fn gang_verifier(bp: &Blkptr) -> SIOChksum {
+    let dva = bp.get_identify();
+    let mut zc = SIOChksum::new();
+    zc.set_checksum(dva.get_vdev(), dva.get_offset(), bp.physical_birth(), 0);
+    zc
+}
+
+/// Verifier for a vdev label: its offset on the device.
+pub fn label_verifier(offset: u64) -> SIOChksum {
+    let mut zc = SIOChksum::new();
+    zc.set_checksum(offset, 0, 0, 0);
+    zc
+}
+
+#[inline]
+fn eck_offset(buf: &[u8]) -> usize {
+    assert!(
+        buf.len() >= SIO_ECK_SIZE,
+        "block too small for a checksum trailer"
+    );
+    buf.len() - SIO_ECK_SIZE
+}
+
+fn write_eck(buf: &mut [u8], magic: u64, zc: &SIOChksum) {
+    let off = eck_offset(buf);
+    buf[off..off + 8].copy_from_slice(&magic.to_ne_bytes());
+    for (i, w) in zc.zc_word.iter().enumerate() {
+        let at = off + 8 + i * 8;
+        buf[at..at + 8].copy_from_slice(&w.to_ne_bytes());
+    }
+}
+
+fn read_eck(buf: &[u8]) -> (u64, SIOChksum) {
+    let off = eck_offset(buf);
+    let word = |at: usize| {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&buf[at..at + 8]);
+        u64::from_ne_bytes(b)
+    };
+    let mut zc = SIOChksum::new();
+    for (i, w) in zc.zc_word.iter_mut().enumerate() {
+        *w = word(off + 8 + i * 8);
+    }
+    (word(off), zc)
+}
+
+/// Fills in the trailer at the end of `buf` with `checksum` of the block.
+/// Panics if `checksum` is not a concrete algorithm.
+pub fn checksum_compute_embedded(
+    checksum: SIOChecksum,
+    buf: &mut [u8],
+    verifier: &SIOChksum,
+    tmpls: &ChecksumTemplates,
+) {
+    write_eck(buf, SIO_ECK_MAGIC, verifier);
+    let zc = checksum_compute(checksum, buf, false, tmpls)
+        .unwrap_or_else(|| panic!("{} has no checksum function", checksum.name()));
+    write_eck(buf, SIO_ECK_MAGIC, &zc);
+}
+
+/// Checks the trailer at the end of `buf`. On success returns whether the
+/// block was written in the other byte order.
+pub fn checksum_verify_embedded(
+    checksum: SIOChecksum,
+    buf: &[u8],
+    verifier: &SIOChksum,
+    tmpls: &ChecksumTemplates,
+) -> Result<bool, ChecksumMismatch> {
+    let (magic, mut expected) = read_eck(buf);
+    let byteswap = magic == SIO_ECK_MAGIC.swap_bytes();
+
+    let mut verifier = *verifier;
+    if byteswap {
+        verifier.swap_bytes();
+        expected.swap_bytes();
+    }
+
+    let mut data = buf.to_vec();
+    write_eck(&mut data, magic, &verifier);
+    let actual = checksum_compute(checksum, &data, byteswap, tmpls).unwrap_or_default();
+    expected.compare(&actual, checksum, byteswap)?;
+    Ok(byteswap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blkptr::blkptr::SIOCheckSumSalt;
+
+    #[test]
+    fn embedded_roundtrip() {
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let verifier = label_verifier(0x4000);
+        let mut buf: Vec<u8> = (0..1024u32).map(|i| (i * 7) as u8).collect();
+        checksum_compute_embedded(SIOChecksum::LABEL, &mut buf, &verifier, &tmpls);
+        assert_eq!(read_eck(&buf).0, SIO_ECK_MAGIC);
+        assert_eq!(
+            checksum_verify_embedded(SIOChecksum::LABEL, &buf, &verifier, &tmpls),
+            Ok(false)
+        );
+
+        // Same data at another offset.
+        let err = checksum_verify_embedded(SIOChecksum::LABEL, &buf, &label_verifier(0), &tmpls)
+            .unwrap_err();
+        assert_eq!(err.checksum, SIOChecksum::LABEL);
+
+        buf[3] ^= 0x10;
+        assert!(checksum_verify_embedded(SIOChecksum::LABEL, &buf, &verifier, &tmpls).is_err());
+    }
+
+    #[test]
+    fn embedded_other_byteorder() {
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let verifier = label_verifier(0x4000);
+        let mut buf: Vec<u8> = (0..512u32).map(|i| (i * 13) as u8).collect();
+        checksum_compute_embedded(SIOChecksum::LABEL, &mut buf, &verifier, &tmpls);
+
+        // What the other host would have written: every word swapped.
+        let swapped: Vec<u8> = buf
+            .chunks(8)
+            .flat_map(|w| w.iter().rev().copied().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(
+            checksum_verify_embedded(SIOChecksum::LABEL, &swapped, &verifier, &tmpls),
+            Ok(true)
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Fletcher checksums.
+//!
+//! Fletcher-2 runs two interleaved Fletcher sums over 64-bit words; Fletcher-4
+//! runs four cascaded sums over 32-bit words. Both produce 256 bits of state
+//! which is stored directly as the block checksum.
+//!
+//! The "native" variants read words in host byte order and the "byteswap"
+//! variants in the other order, so that a block written on a host of either
+//! endianness can be verified with the variant its blkptr asks for.
+//!
+//! Input that does not fill a whole word (16 bytes for Fletcher-2, 4 bytes for
+//! Fletcher-4) is ignored, like the on-disk format expects. Blocks are always
+//! a multiple of 512 bytes, so this only matters for callers streaming data
+//! in odd-sized pieces, which `FletcherStream` handles.
+
+use crate::blkptr::checksum::SIOChksum;
+
+const FLETCHER_2_UNIT: usize = 16;
+const FLETCHER_4_UNIT: usize = 4;
+
+#[inline]
+fn word64(buf: &[u8], byteswap: bool) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(buf);
+    let w = u64::from_ne_bytes(b);
+    if byteswap {
+        w.swap_bytes()
+    } else {
+        w
+    }
+}
+
+#[inline]
+fn word32(buf: &[u8], byteswap: bool) -> u64 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(buf);
+    let w = u32::from_ne_bytes(b);
+    if byteswap {
+        w.swap_bytes() as u64
+    } else {
+        w as u64
+    }
+}
+
+fn fletcher_2_incremental(buf: &[u8], zcp: &mut SIOChksum, byteswap: bool) {
+    let [mut a0, mut a1, mut b0, mut b1] = zcp.zc_word;
+    for chunk in buf.chunks_exact(FLETCHER_2_UNIT) {
+        a0 = a0.wrapping_add(word64(&chunk[..8], byteswap));
+        a1 = a1.wrapping_add(word64(&chunk[8..], byteswap));
+        b0 = b0.wrapping_add(a0);
+        b1 = b1.wrapping_add(a1);
+    }
+    zcp.set_checksum(a0, a1, b0, b1);
+}
+
+fn fletcher_4_incremental(buf: &[u8], zcp: &mut SIOChksum, byteswap: bool) {
+    let [mut a, mut b, mut c, mut d] = zcp.zc_word;
+    for chunk in buf.chunks_exact(FLETCHER_4_UNIT) {
+        a = a.wrapping_add(word32(chunk, byteswap));
+        b = b.wrapping_add(a);
+        c = c.wrapping_add(b);
+        d = d.wrapping_add(c);
+    }
+    zcp.set_checksum(a, b, c, d);
+}
+
+/// Folds `buf` into a running Fletcher-2 checksum.
+#[inline]
+pub fn fletcher_2_incremental_native(buf: &[u8], zcp: &mut SIOChksum) {
+    fletcher_2_incremental(buf, zcp, false)
+}
+
+#[inline]
+pub fn fletcher_2_incremental_byteswap(buf: &[u8], zcp: &mut SIOChksum) {
+    fletcher_2_incremental(buf, zcp, true)
+}
+
+#[inline]
+pub fn fletcher_2_native(buf: &[u8]) -> SIOChksum {
+    let mut zc = SIOChksum::new();
+    fletcher_2_incremental_native(buf, &mut zc);
+    zc
+}
+
+#[inline]
+pub fn fletcher_2_byteswap(buf: &[u8]) -> SIOChksum {
+    let mut zc = SIOChksum::new();
+    fletcher_2_incremental_byteswap(buf, &mut zc);
+    zc
+}
+
+/// Folds `buf` into a running Fletcher-4 checksum.
+#[inline]
+pub fn fletcher_4_incremental_native(buf: &[u8], zcp: &mut SIOChksum) {
+    fletcher_4_incremental(buf, zcp, false)
+}
+
+#[inline]
+pub fn fletcher_4_incremental_byteswap(buf: &[u8], zcp: &mut SIOChksum) {
+    fletcher_4_incremental(buf, zcp, true)
+}
+
+#[inline]
+pub fn fletcher_4_native(buf: &[u8]) -> SIOChksum {
+    let mut zc = SIOChksum::new();
+    fletcher_4_incremental_native(buf, &mut zc);
+    zc
+}
+
+#[inline]
+pub fn fletcher_4_byteswap(buf: &[u8]) -> SIOChksum {
+    let mut zc = SIOChksum::new();
+    fletcher_4_incremental_byteswap(buf, &mut zc);
+    zc
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fletcher {
+    Fletcher2,
+    Fletcher4,
+}
+
+impl Fletcher {
+    #[inline]
+    fn unit(&self) -> usize {
+        match self {
+            Fletcher::Fletcher2 => FLETCHER_2_UNIT,
+            Fletcher::Fletcher4 => FLETCHER_4_UNIT,
+        }
+    }
+}
+
+/// Computes a Fletcher checksum over data that arrives in pieces of any size.
+/// Feeding a buffer in several `update` calls gives the same result as
+/// checksumming it in one go.
+#[derive(Debug, Clone)]
+pub struct FletcherStream {
+    algo: Fletcher,
+    byteswap: bool,
+    zc: SIOChksum,
+    pending: [u8; FLETCHER_2_UNIT],
+    npending: usize,
+}
+
+impl FletcherStream {
+    #[inline]
+    pub fn new(algo: Fletcher, byteswap: bool) -> Self {
+        FletcherStream {
+            algo,
+            byteswap,
+            zc: SIOChksum::new(),
+            pending: [0; FLETCHER_2_UNIT],
+            npending: 0,
+        }
+    }
+
+    #[inline]
+    fn fold(&mut self, buf: &[u8]) {
+        match self.algo {
+            Fletcher::Fletcher2 => fletcher_2_incremental(buf, &mut self.zc, self.byteswap),
+            Fletcher::Fletcher4 => fletcher_4_incremental(buf, &mut self.zc, self.byteswap),
+        }
+    }
+
+    pub fn update(&mut self, mut buf: &[u8]) {
+        let unit = self.algo.unit();
+        if self.npending > 0 {
+            let n = (unit - self.npending).min(buf.len());
+            self.pending[self.npending..self.npending + n].copy_from_slice(&buf[..n]);
+            self.npending += n;
+            buf = &buf[n..];
+            if self.npending < unit {
+                return;
+            }
+            let pending = self.pending;
+            self.fold(&pending[..unit]);
+            self.npending = 0;
+        }
+
+        let whole = buf.len() - buf.len() % unit;
+        self.fold(&buf[..whole]);
+        let rest = &buf[whole..];
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.npending = rest.len();
+    }
+
+    /// Returns the checksum of everything fed so far. A trailing partial word
+    /// is ignored.
+    #[inline]
+    pub fn finish(self) -> SIOChksum {
+        self.zc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern() -> Vec<u8> {
+        (0..4096u32).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    // Reference values for `pattern()`, read as little- and big-endian words.
+    const F2_LE: [u64; 4] = [
+        0x797a7b7c7d7e7f00,
+        0x8182838485868700,
+        0x159657589a1b9b80,
+        0xd5765778da7c1780,
+    ];
+    const F2_BE: [u64; 4] = [
+        0x7e7d7c7b7a797800,
+        0x8685848382818000,
+        0x1998575696159800,
+        0x79d8775675d53400,
+    ];
+    const F4_LE: [u64; 4] = [
+        0x000001f9fe020400,
+        0x0003f56a75807600,
+        0x054a3344a4a66000,
+        0x4e81cf973f7dbb00,
+    ];
+    const F4_BE: [u64; 4] = [
+        0x0000020601fdf800,
+        0x000410796e637000,
+        0x05704de085ec5c00,
+        0x76adf38fb9d57800,
+    ];
+
+    fn host_and_swapped(le: [u64; 4], be: [u64; 4]) -> ([u64; 4], [u64; 4]) {
+        if cfg!(target_endian = "little") {
+            (le, be)
+        } else {
+            (be, le)
+        }
+    }
+
+    #[test]
+    fn fletcher_2_known_answer() {
+        let buf = pattern();
+        let (native, swapped) = host_and_swapped(F2_LE, F2_BE);
+        assert_eq!(fletcher_2_native(&buf).zc_word, native);
+        assert_eq!(fletcher_2_byteswap(&buf).zc_word, swapped);
+    }
+
+    #[test]
+    fn fletcher_4_known_answer() {
+        let buf = pattern();
+        let (native, swapped) = host_and_swapped(F4_LE, F4_BE);
+        assert_eq!(fletcher_4_native(&buf).zc_word, native);
+        assert_eq!(fletcher_4_byteswap(&buf).zc_word, swapped);
+
+        // Sums wrap rather than overflow on large, dense blocks.
+        let ones = vec![0xffu8; 128 * 1024];
+        assert_eq!(
+            fletcher_4_native(&ones).zc_word,
+            [
+                0x00007fffffff8000,
+                0x20003fffdfffc000,
+                0x75557aaa8aaa8000,
+                0xc7556d5537ffe000
+            ]
+        );
+        assert_eq!(fletcher_4_native(&[0u8; 512]).zc_word, [0; 4]);
+    }
+
+    #[test]
+    fn fletcher_incremental() {
+        let buf = pattern();
+        let mut zc = SIOChksum::new();
+        fletcher_4_incremental_native(&buf[..1024], &mut zc);
+        fletcher_4_incremental_native(&buf[1024..], &mut zc);
+        assert_eq!(zc.zc_word, fletcher_4_native(&buf).zc_word);
+
+        let mut zc = SIOChksum::new();
+        fletcher_2_incremental_byteswap(&buf[..512], &mut zc);
+        fletcher_2_incremental_byteswap(&buf[512..], &mut zc);
+        assert_eq!(zc.zc_word, fletcher_2_byteswap(&buf).zc_word);
+    }
+
+    #[test]
+    fn fletcher_stream() {
+        let buf = pattern();
+        for algo in [Fletcher::Fletcher2, Fletcher::Fletcher4] {
+            for byteswap in [false, true] {
+                let expected = match (algo, byteswap) {
+                    (Fletcher::Fletcher2, false) => fletcher_2_native(&buf),
+                    (Fletcher::Fletcher2, true) => fletcher_2_byteswap(&buf),
+                    (Fletcher::Fletcher4, false) => fletcher_4_native(&buf),
+                    (Fletcher::Fletcher4, true) => fletcher_4_byteswap(&buf),
+                };
+                let mut stream = FletcherStream::new(algo, byteswap);
+                for piece in buf.chunks(7) {
+                    stream.update(piece);
+                }
+                assert_eq!(stream.finish().zc_word, expected.zc_word);
+            }
+        }
+    }
+
+    #[test]
+    fn fletcher_byteswap_matches_swapped_data() {
+        let buf = pattern();
+        let swapped: Vec<u8> = buf
+            .chunks(4)
+            .flat_map(|w| w.iter().rev().copied().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(
+            fletcher_4_byteswap(&swapped).zc_word,
+            fletcher_4_native(&buf).zc_word
+        );
+    }
+}
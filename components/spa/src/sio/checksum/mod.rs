@@ -0,0 +1,392 @@
+//! Checksum function table.
+//!
+//! Every `SIOChecksum` has an entry describing how to compute it (in host and
+//! in swapped byte order), whether it needs a per-pool template, and what it
+//! may be used for.
+
+pub mod embedded;
+pub mod fletcher;
+pub mod sha2;
+pub mod skein;
+
+pub use embedded::*;
+pub use fletcher::*;
+pub use sha2::*;
+pub use skein::*;
+
+use bitflags::bitflags;
+
+use super::SIOChecksum;
+use crate::blkptr::blkptr::SIOCheckSumSalt;
+use crate::blkptr::checksum::{ChecksumMismatch, SIOChksum};
+
+bitflags! {
+    pub struct ChecksumFlags: u8 {
+        /// Strong enough to protect metadata.
+        const METADATA = 1 << 1;
+        /// The checksum is stored in a trailer inside the block (labels,
+        /// gang headers, log blocks) rather than in the blkptr.
+        const EMBEDDED = 1 << 2;
+        /// Collision resistant, so equal checksums may be taken as equal data.
+        const DEDUP = 1 << 3;
+        /// Keyed with the pool's checksum salt.
+        const SALTED = 1 << 4;
+        /// Strong enough to skip rewriting a block whose checksum is unchanged.
+        const NOPWRITE = 1 << 5;
+    }
+}
+
+/// Precomputed per-pool state for a salted checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumTemplate {
+    Skein(SkeinCtx),
+}
+
+/// Returns `None` for a salted checksum called without its template.
+pub type ChecksumFunc = fn(&[u8], Option<&ChecksumTemplate>) -> Option<SIOChksum>;
+pub type ChecksumTemplateInit = fn(&SIOCheckSumSalt) -> ChecksumTemplate;
+
+pub struct SIOChecksumInfo {
+    /// Native and byteswap variants, or `None` for values that only select
+    /// another checksum (`inherit`, `on`).
+    pub func: Option<[ChecksumFunc; 2]>,
+    pub tmpl_init: Option<ChecksumTemplateInit>,
+    pub flags: ChecksumFlags,
+}
+
+fn checksum_off(_buf: &[u8], _tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    Some(SIOChksum::new())
+}
+
+fn checksum_fletcher_2_native(buf: &[u8], _tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    Some(fletcher_2_native(buf))
+}
+
+fn checksum_fletcher_2_byteswap(buf: &[u8], _tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    Some(fletcher_2_byteswap(buf))
+}
+
+fn checksum_fletcher_4_native(buf: &[u8], _tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    Some(fletcher_4_native(buf))
+}
+
+fn checksum_fletcher_4_byteswap(buf: &[u8], _tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    Some(fletcher_4_byteswap(buf))
+}
+
+fn checksum_sha256(buf: &[u8], _tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    Some(sha256(buf))
+}
+
+fn checksum_sha512(buf: &[u8], _tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    Some(sha512_256(buf))
+}
+
+fn checksum_skein_native(buf: &[u8], tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    let ChecksumTemplate::Skein(ctx) = tmpl?;
+    Some(skein_native(buf, ctx))
+}
+
+fn checksum_skein_byteswap(buf: &[u8], tmpl: Option<&ChecksumTemplate>) -> Option<SIOChksum> {
+    let ChecksumTemplate::Skein(ctx) = tmpl?;
+    Some(skein_byteswap(buf, ctx))
+}
+
+fn skein_tmpl_init(salt: &SIOCheckSumSalt) -> ChecksumTemplate {
+    ChecksumTemplate::Skein(SkeinCtx::new(&salt.zcs_bytes))
+}
+
+const FLETCHER_2: Option<[ChecksumFunc; 2]> =
+    Some([checksum_fletcher_2_native, checksum_fletcher_2_byteswap]);
+const FLETCHER_4: Option<[ChecksumFunc; 2]> =
+    Some([checksum_fletcher_4_native, checksum_fletcher_4_byteswap]);
+const OFF: Option<[ChecksumFunc; 2]> = Some([checksum_off, checksum_off]);
+
+/// Indexed by `SIOChecksum`.
+pub static SIO_CHECKSUM_TABLE: [SIOChecksumInfo; SIOChecksum::FUNCTIONS as usize] = [
+    // inherit
+    SIOChecksumInfo {
+        func: None,
+        tmpl_init: None,
+        flags: ChecksumFlags::empty(),
+    },
+    // on
+    SIOChecksumInfo {
+        func: None,
+        tmpl_init: None,
+        flags: ChecksumFlags::empty(),
+    },
+    // off
+    SIOChecksumInfo {
+        func: OFF,
+        tmpl_init: None,
+        flags: ChecksumFlags::empty(),
+    },
+    // label
+    SIOChecksumInfo {
+        func: FLETCHER_2,
+        tmpl_init: None,
+        flags: ChecksumFlags::METADATA.union(ChecksumFlags::EMBEDDED),
+    },
+    // gang_header
+    SIOChecksumInfo {
+        func: FLETCHER_2,
+        tmpl_init: None,
+        flags: ChecksumFlags::METADATA.union(ChecksumFlags::EMBEDDED),
+    },
+    // silog
+    SIOChecksumInfo {
+        func: FLETCHER_2,
+        tmpl_init: None,
+        flags: ChecksumFlags::EMBEDDED,
+    },
+    // fletcher2
+    SIOChecksumInfo {
+        func: FLETCHER_2,
+        tmpl_init: None,
+        flags: ChecksumFlags::METADATA,
+    },
+    // fletcher4
+    SIOChecksumInfo {
+        func: FLETCHER_4,
+        tmpl_init: None,
+        flags: ChecksumFlags::METADATA,
+    },
+    // sha256
+    SIOChecksumInfo {
+        func: Some([checksum_sha256, checksum_sha256]),
+        tmpl_init: None,
+        flags: ChecksumFlags::METADATA
+            .union(ChecksumFlags::DEDUP)
+            .union(ChecksumFlags::NOPWRITE),
+    },
+    // silog2
+    SIOChecksumInfo {
+        func: FLETCHER_4,
+        tmpl_init: None,
+        flags: ChecksumFlags::EMBEDDED,
+    },
+    // noparity
+    SIOChecksumInfo {
+        func: OFF,
+        tmpl_init: None,
+        flags: ChecksumFlags::empty(),
+    },
+    // sha512
+    SIOChecksumInfo {
+        func: Some([checksum_sha512, checksum_sha512]),
+        tmpl_init: None,
+        flags: ChecksumFlags::METADATA
+            .union(ChecksumFlags::DEDUP)
+            .union(ChecksumFlags::NOPWRITE),
+    },
+    // skein
+    SIOChecksumInfo {
+        func: Some([checksum_skein_native, checksum_skein_byteswap]),
+        tmpl_init: Some(skein_tmpl_init),
+        flags: ChecksumFlags::METADATA
+            .union(ChecksumFlags::DEDUP)
+            .union(ChecksumFlags::SALTED)
+            .union(ChecksumFlags::NOPWRITE),
+    },
+];
+
+impl SIOChecksum {
+    /// Panics for `FUNCTIONS`, which is not a checksum.
+    #[inline]
+    pub fn info(&self) -> &'static SIOChecksumInfo {
+        &SIO_CHECKSUM_TABLE[u8::from(*self) as usize]
+    }
+
+    #[inline]
+    pub fn flags(&self) -> ChecksumFlags {
+        self.info().flags
+    }
+
+    #[inline]
+    pub fn is_dedup_capable(&self) -> bool {
+        self.flags().contains(ChecksumFlags::DEDUP)
+    }
+
+    #[inline]
+    pub fn is_nopwrite_capable(&self) -> bool {
+        self.flags().contains(ChecksumFlags::NOPWRITE)
+    }
+
+    #[inline]
+    pub fn is_salted(&self) -> bool {
+        self.flags().contains(ChecksumFlags::SALTED)
+    }
+
+    #[inline]
+    pub fn is_embedded(&self) -> bool {
+        self.flags().contains(ChecksumFlags::EMBEDDED)
+    }
+}
+
+/// A pool's checksum salt and the templates derived from it. The salt is
+/// chosen at pool creation and never changes, so templates are computed once
+/// when the pool is opened.
+#[derive(Debug, Clone)]
+pub struct ChecksumTemplates {
+    salt: SIOCheckSumSalt,
+    tmpls: Vec<Option<ChecksumTemplate>>,
+}
+
+impl ChecksumTemplates {
+    pub fn new(salt: SIOCheckSumSalt) -> Self {
+        let tmpls = SIO_CHECKSUM_TABLE
+            .iter()
+            .map(|ci| ci.tmpl_init.map(|init| init(&salt)))
+            .collect();
+        ChecksumTemplates { salt, tmpls }
+    }
+
+    #[inline]
+    pub fn salt(&self) -> &SIOCheckSumSalt {
+        &self.salt
+    }
+
+    #[inline]
+    pub fn get(&self, checksum: SIOChecksum) -> Option<&ChecksumTemplate> {
+        self.tmpls
+            .get(u8::from(checksum) as usize)
+            .and_then(|t| t.as_ref())
+    }
+}
+
+/// Computes `checksum` over `buf`. `byteswap` selects the variant for data
+/// written on a host of the other endianness. Returns `None` for `inherit`,
+/// `on` and `functions`, which do not name a concrete algorithm, and for a
+/// salted checksum `tmpls` has no template for.
+pub fn checksum_compute(
+    checksum: SIOChecksum,
+    buf: &[u8],
+    byteswap: bool,
+    tmpls: &ChecksumTemplates,
+) -> Option<SIOChksum> {
+    let func = SIO_CHECKSUM_TABLE.get(u8::from(checksum) as usize)?.func?;
+    func[byteswap as usize](buf, tmpls.get(checksum))
+}
+
+/// Checks `buf` against `expected`, the checksum recorded for it. A checksum
+/// that does not name a concrete algorithm can never verify, so it is
+/// reported as a mismatch against an all-zero actual value.
+pub fn checksum_verify(
+    checksum: SIOChecksum,
+    buf: &[u8],
+    byteswap: bool,
+    expected: &SIOChksum,
+    tmpls: &ChecksumTemplates,
+) -> Result<(), ChecksumMismatch> {
+    match checksum_compute(checksum, buf, byteswap, tmpls) {
+        Some(actual) => expected.compare(&actual, checksum, byteswap),
+        None => Err(ChecksumMismatch::new(
+            *expected,
+            SIOChksum::new(),
+            checksum,
+            byteswap,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern() -> Vec<u8> {
+        (0..4096u32).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    #[test]
+    fn checksum_table_flags() {
+        assert!(SIOChecksum::SHA256.is_dedup_capable());
+        assert!(SIOChecksum::SHA512.is_dedup_capable());
+        assert!(SIOChecksum::SKEIN.is_dedup_capable());
+        assert!(!SIOChecksum::FLETCHER_4.is_dedup_capable());
+
+        assert!(SIOChecksum::SKEIN.is_salted());
+        assert!(!SIOChecksum::SHA256.is_salted());
+
+        assert!(SIOChecksum::LABEL.is_embedded());
+        assert!(SIOChecksum::GANG_HEADER.is_embedded());
+        assert!(SIOChecksum::SILOG2.is_embedded());
+        assert!(!SIOChecksum::FLETCHER_2.is_embedded());
+
+        // Only salted algorithms get a template.
+        for c in 0..u8::from(SIOChecksum::FUNCTIONS) {
+            let c = SIOChecksum::try_from(c).unwrap();
+            assert_eq!(c.info().tmpl_init.is_some(), c.is_salted());
+        }
+    }
+
+    #[test]
+    fn checksum_compute_dispatch() {
+        let buf = pattern();
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+
+        let zc = checksum_compute(SIOChecksum::FLETCHER_4, &buf, true, &tmpls).unwrap();
+        assert_eq!(zc.zc_word, fletcher_4_byteswap(&buf).zc_word);
+        let zc = checksum_compute(SIOChecksum::SHA256, &buf, false, &tmpls).unwrap();
+        assert_eq!(zc.zc_word, sha256(&buf).zc_word);
+        let zc = checksum_compute(SIOChecksum::SHA512, &buf, true, &tmpls).unwrap();
+        assert_eq!(zc.zc_word, sha512_256(&buf).zc_word);
+        let zc = checksum_compute(SIOChecksum::OFF, &buf, false, &tmpls).unwrap();
+        assert_eq!(zc.zc_word, [0; 4]);
+
+        assert!(checksum_compute(SIOChecksum::INHERIT, &buf, false, &tmpls).is_none());
+        assert!(checksum_compute(SIOChecksum::FUNCTIONS, &buf, false, &tmpls).is_none());
+    }
+
+    #[test]
+    fn checksum_verify_mismatch() {
+        let mut buf = pattern();
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let expected = fletcher_4_native(&buf);
+        assert_eq!(
+            checksum_verify(SIOChecksum::FLETCHER_4, &buf, false, &expected, &tmpls),
+            Ok(())
+        );
+
+        buf[100] ^= 1;
+        let err =
+            checksum_verify(SIOChecksum::FLETCHER_4, &buf, false, &expected, &tmpls).unwrap_err();
+        assert_eq!(err.expected, expected);
+        assert_eq!(err.actual, fletcher_4_native(&buf));
+        assert_eq!(err.checksum, SIOChecksum::FLETCHER_4);
+        assert!(!err.byteswap);
+        assert!(err.bits_differ() > 0);
+
+        let err = checksum_verify(SIOChecksum::ON, &buf, true, &expected, &tmpls).unwrap_err();
+        assert!(err.actual.is_zero());
+        assert!(err.byteswap);
+    }
+
+    #[test]
+    fn checksum_salted_template() {
+        let buf = pattern();
+        let mut salt = SIOCheckSumSalt::new();
+        salt.zcs_bytes[0] = 0x5a;
+        let a = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let b = ChecksumTemplates::new(salt.clone());
+        assert_eq!(b.salt(), &salt);
+        assert!(a.get(SIOChecksum::SHA256).is_none());
+
+        let za = checksum_compute(SIOChecksum::SKEIN, &buf, false, &a).unwrap();
+        let zb = checksum_compute(SIOChecksum::SKEIN, &buf, false, &b).unwrap();
+        assert_ne!(za.zc_word, zb.zc_word);
+
+        // The template is only a shortcut for keying with the salt.
+        let direct = skein_native(&buf, &SkeinCtx::new(&salt.zcs_bytes));
+        assert_eq!(zb.zc_word, direct.zc_word);
+
+        let mut swapped = checksum_compute(SIOChecksum::SKEIN, &buf, true, &b).unwrap();
+        swapped.swap_bytes();
+        assert_eq!(swapped.zc_word, zb.zc_word);
+
+        // Without its template there is no salt to key Skein with.
+        let [native, byteswap] = SIOChecksum::SKEIN.info().func.unwrap();
+        assert!(native(&buf, None).is_none());
+        assert!(byteswap(&buf, None).is_none());
+    }
+}
@@ -0,0 +1,201 @@
+//! Skein-512-256.
+//!
+//! Skein is keyed with the pool's checksum salt. Hashing the key and the
+//! configuration block only depends on the salt, so that part is done once
+//! per pool and kept as a `SkeinCtx` template which every checksum starts
+//! from.
+
+use crate::blkptr::checksum::SIOChksum;
+
+const SKEIN_512_WORDS: usize = 8;
+const SKEIN_512_BLOCK: usize = SKEIN_512_WORDS * 8;
+const SKEIN_OUTPUT_BITS: u64 = 256;
+
+/// Key schedule parity constant.
+const SKEIN_KS_PARITY: u64 = 0x1bd11bdaa9fc1a22;
+
+const SKEIN_ROTATIONS: [[u32; 4]; 8] = [
+    [46, 36, 19, 37],
+    [33, 27, 14, 42],
+    [17, 49, 36, 39],
+    [44, 9, 54, 56],
+    [39, 30, 34, 24],
+    [13, 50, 10, 17],
+    [25, 29, 39, 43],
+    [8, 35, 56, 22],
+];
+
+const SKEIN_ROUNDS: usize = 72;
+
+// UBI block types, stored in the top byte of the tweak.
+const SKEIN_BLK_TYPE_KEY: u64 = 0;
+const SKEIN_BLK_TYPE_CFG: u64 = 4;
+const SKEIN_BLK_TYPE_MSG: u64 = 48;
+const SKEIN_BLK_TYPE_OUT: u64 = 63;
+
+const SKEIN_T1_FIRST: u64 = 1 << 62;
+const SKEIN_T1_FINAL: u64 = 1 << 63;
+
+/// "SHA3" in little-endian, version 1.
+const SKEIN_SCHEMA_VERSION: u64 = 0x0000_0001_3341_4853;
+
+fn threefish_512(key: &[u64; 8], tweak: [u64; 2], block: &[u64; 8]) -> [u64; 8] {
+    let mut k = [0u64; SKEIN_512_WORDS + 1];
+    k[..SKEIN_512_WORDS].copy_from_slice(key);
+    k[SKEIN_512_WORDS] = key.iter().fold(SKEIN_KS_PARITY, |p, w| p ^ w);
+    let t = [tweak[0], tweak[1], tweak[0] ^ tweak[1]];
+
+    let inject = |v: &mut [u64; 8], s: usize| {
+        for (i, w) in v.iter_mut().enumerate() {
+            let mut ks = k[(s + i) % 9];
+            match i {
+                5 => ks = ks.wrapping_add(t[s % 3]),
+                6 => ks = ks.wrapping_add(t[(s + 1) % 3]),
+                7 => ks = ks.wrapping_add(s as u64),
+                _ => {}
+            }
+            *w = w.wrapping_add(ks);
+        }
+    };
+
+    let mut v = *block;
+    for d in 0..SKEIN_ROUNDS {
+        if d % 4 == 0 {
+            inject(&mut v, d / 4);
+        }
+        let r = SKEIN_ROTATIONS[d % 8];
+        for j in 0..4 {
+            v[2 * j] = v[2 * j].wrapping_add(v[2 * j + 1]);
+            v[2 * j + 1] = v[2 * j + 1].rotate_left(r[j]) ^ v[2 * j];
+        }
+        v = [v[2], v[1], v[4], v[7], v[6], v[5], v[0], v[3]];
+    }
+    inject(&mut v, SKEIN_ROUNDS / 4);
+    v
+}
+
+/// Folds `msg` into the chaining value `h` as one UBI invocation of type
+/// `typ`. An empty message is processed as a single zero block.
+fn ubi(h: &mut [u64; 8], msg: &[u8], typ: u64) {
+    let nblocks = msg.len().div_ceil(SKEIN_512_BLOCK).max(1);
+    for i in 0..nblocks {
+        let start = i * SKEIN_512_BLOCK;
+        let end = msg.len().min(start + SKEIN_512_BLOCK);
+        let mut bytes = [0u8; SKEIN_512_BLOCK];
+        bytes[..end - start].copy_from_slice(&msg[start..end]);
+
+        let mut m = [0u64; 8];
+        for (w, b) in m.iter_mut().zip(bytes.chunks_exact(8)) {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(b);
+            *w = u64::from_le_bytes(word);
+        }
+
+        let mut t1 = typ << 56;
+        if i == 0 {
+            t1 |= SKEIN_T1_FIRST;
+        }
+        if i == nblocks - 1 {
+            t1 |= SKEIN_T1_FINAL;
+        }
+        let e = threefish_512(h, [end as u64, t1], &m);
+        for j in 0..SKEIN_512_WORDS {
+            h[j] = e[j] ^ m[j];
+        }
+    }
+}
+
+/// Skein-512-256 state after the key and configuration blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkeinCtx {
+    h: [u64; 8],
+}
+
+impl SkeinCtx {
+    /// Sets up a context keyed with `key`. An empty key gives plain,
+    /// unkeyed Skein.
+    pub fn new(key: &[u8]) -> Self {
+        let mut h = [0u64; 8];
+        if !key.is_empty() {
+            ubi(&mut h, key, SKEIN_BLK_TYPE_KEY);
+        }
+
+        let mut cfg = [0u8; 32];
+        cfg[..8].copy_from_slice(&SKEIN_SCHEMA_VERSION.to_le_bytes());
+        cfg[8..16].copy_from_slice(&SKEIN_OUTPUT_BITS.to_le_bytes());
+        ubi(&mut h, &cfg, SKEIN_BLK_TYPE_CFG);
+
+        SkeinCtx { h }
+    }
+
+    /// Returns the 32-byte digest of `buf`.
+    pub fn digest(&self, buf: &[u8]) -> [u8; 32] {
+        let mut h = self.h;
+        ubi(&mut h, buf, SKEIN_BLK_TYPE_MSG);
+        ubi(&mut h, &0u64.to_le_bytes(), SKEIN_BLK_TYPE_OUT);
+
+        let mut out = [0u8; 32];
+        for (o, w) in out.chunks_exact_mut(8).zip(h) {
+            o.copy_from_slice(&w.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// The digest is copied into the checksum as raw bytes, so the words are in
+/// host order.
+#[inline]
+pub fn skein_native(buf: &[u8], ctx: &SkeinCtx) -> SIOChksum {
+    let digest = ctx.digest(buf);
+    let mut zc = SIOChksum::new();
+    for (w, b) in zc.zc_word.iter_mut().zip(digest.chunks_exact(8)) {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(b);
+        *w = u64::from_ne_bytes(word);
+    }
+    zc
+}
+
+#[inline]
+pub fn skein_byteswap(buf: &[u8], ctx: &SkeinCtx) -> SIOChksum {
+    let mut zc = skein_native(buf, ctx);
+    zc.swap_bytes();
+    zc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(b: &[u8]) -> String {
+        b.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
+    #[test]
+    fn skein_known_answer() {
+        let ctx = SkeinCtx::new(&[]);
+        assert_eq!(
+            hex(&ctx.digest(b"")),
+            "39ccc4554a8b31853b9de7a1fe638a24cce6b35a55f2431009e18780335d2621"
+        );
+        assert_eq!(
+            hex(&ctx.digest(b"The quick brown fox jumps over the lazy dog")),
+            "b3250457e05d3060b1a4bbc1428bc75a3f525ca389aeab96cfa34638d96e492a"
+        );
+    }
+
+    #[test]
+    fn skein_keyed() {
+        let buf: Vec<u8> = (0..4096u32).map(|i| (i * 31 + 7) as u8).collect();
+        let plain = SkeinCtx::new(&[]);
+        let a = SkeinCtx::new(&[1; 32]);
+        let b = SkeinCtx::new(&[2; 32]);
+        assert_ne!(a, b);
+        assert_ne!(a.digest(&buf), plain.digest(&buf));
+        assert_ne!(a.digest(&buf), b.digest(&buf));
+
+        let mut swapped = skein_byteswap(&buf, &a);
+        swapped.swap_bytes();
+        assert_eq!(swapped.zc_word, skein_native(&buf, &a).zc_word);
+    }
+}
@@ -0,0 +1,744 @@
+//! Deflate (RFC 1951) compressor.
+//!
+//! Matches are found with hash chains over a 32K window. Levels 1-3 take the
+//! first match found at each position; levels 4-9 look one byte ahead for a
+//! longer one before committing, and search longer chains. The parameters per
+//! level are zlib's, so ratios are comparable to blocks written by the C
+//! implementation, though the exact output is not.
+//!
+//! Each block of symbols is written with whichever of a stored, fixed or
+//! dynamic Huffman encoding is smallest.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+pub(super) const MIN_MATCH: usize = 3;
+pub(super) const MAX_MATCH: usize = 258;
+pub(super) const WINDOW_SIZE: usize = 32768;
+
+/// Base length and extra bits for length codes 257..285.
+pub(super) const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(super) const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base distance and extra bits for distance codes 0..29.
+pub(super) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(super) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are sent.
+pub(super) const CL_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub(super) const END_OF_BLOCK: usize = 256;
+pub(super) const LITLEN_CODES: usize = 286;
+pub(super) const DIST_CODES: usize = 30;
+const CL_CODES: usize = 19;
+const MAX_BITS: u32 = 15;
+const MAX_CL_BITS: u32 = 7;
+
+const HASH_BITS: u32 = 15;
+/// A match of the minimum length this far back costs more than the literals.
+const TOO_FAR: usize = 4096;
+/// Symbols per block; a new block lets the Huffman codes adapt.
+const BLOCK_SYMBOLS: usize = 16384;
+const MAX_STORED: usize = 65535;
+
+/// Code lengths of the fixed Huffman codes.
+pub(super) fn fixed_lengths() -> ([u8; 288], [u8; 32]) {
+    let mut litlen = [8u8; 288];
+    litlen[144..256].fill(9);
+    litlen[256..280].fill(7);
+    (litlen, [5u8; 32])
+}
+
+struct Config {
+    /// Search less once a match this long is in hand.
+    good: usize,
+    /// Greedy levels: longest match whose positions are all hashed. Lazy
+    /// levels: do not look ahead past a match this long.
+    lazy: usize,
+    /// Stop searching at a match this long.
+    nice: usize,
+    chain: usize,
+}
+
+const CONFIG: [Config; 10] = [
+    Config {
+        good: 0,
+        lazy: 0,
+        nice: 0,
+        chain: 0,
+    },
+    Config {
+        good: 4,
+        lazy: 4,
+        nice: 8,
+        chain: 4,
+    },
+    Config {
+        good: 4,
+        lazy: 5,
+        nice: 16,
+        chain: 8,
+    },
+    Config {
+        good: 4,
+        lazy: 6,
+        nice: 32,
+        chain: 32,
+    },
+    Config {
+        good: 4,
+        lazy: 4,
+        nice: 16,
+        chain: 16,
+    },
+    Config {
+        good: 8,
+        lazy: 16,
+        nice: 32,
+        chain: 32,
+    },
+    Config {
+        good: 8,
+        lazy: 16,
+        nice: 128,
+        chain: 128,
+    },
+    Config {
+        good: 8,
+        lazy: 32,
+        nice: 128,
+        chain: 256,
+    },
+    Config {
+        good: 32,
+        lazy: 128,
+        nice: 258,
+        chain: 1024,
+    },
+    Config {
+        good: 32,
+        lazy: 258,
+        nice: 258,
+        chain: 4096,
+    },
+];
+
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+#[inline]
+fn len_code(len: usize) -> usize {
+    LEN_BASE.partition_point(|&b| b as usize <= len) - 1
+}
+
+#[inline]
+fn dist_code(dist: usize) -> usize {
+    DIST_BASE.partition_point(|&b| b as usize <= dist) - 1
+}
+
+struct Matcher<'a> {
+    src: &'a [u8],
+    cfg: &'static Config,
+    /// Most recent position plus one for each hash; zero means none.
+    head: Vec<u32>,
+    /// Previous position plus one with the same hash.
+    prev: Vec<u32>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(src: &'a [u8], cfg: &'static Config) -> Self {
+        Matcher {
+            src,
+            cfg,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; src.len()],
+        }
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH > self.src.len() {
+            return;
+        }
+        let s = self.src;
+        let seq = (s[i] as u32) << 16 | (s[i + 1] as u32) << 8 | s[i + 2] as u32;
+        let h = (seq.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize;
+        self.prev[i] = self.head[h];
+        self.head[h] = i as u32 + 1;
+    }
+
+    /// Longest match for the already inserted position `i` that beats
+    /// `prev_len`.
+    fn longest(&self, i: usize, prev_len: usize) -> Option<(usize, usize)> {
+        let s = self.src;
+        let max = MAX_MATCH.min(s.len() - i);
+        let mut best = (prev_len.max(MIN_MATCH - 1), 0);
+        if max <= best.0 {
+            return None;
+        }
+        let mut chain = self.cfg.chain;
+        if prev_len >= self.cfg.good {
+            chain >>= 2;
+        }
+
+        let mut cand = self.prev[i];
+        while cand != 0 && chain > 0 {
+            let c = cand as usize - 1;
+            if i - c > WINDOW_SIZE {
+                break;
+            }
+            if s[c + best.0] == s[i + best.0] {
+                let len = s[c..c + max]
+                    .iter()
+                    .zip(&s[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, i - c);
+                    if len >= self.cfg.nice || len == max {
+                        break;
+                    }
+                }
+            }
+            cand = self.prev[c];
+            chain -= 1;
+        }
+
+        match best {
+            (_, 0) => None,
+            (MIN_MATCH, dist) if dist > TOO_FAR => None,
+            best => Some(best),
+        }
+    }
+}
+
+fn find_greedy(src: &[u8], cfg: &'static Config, mut emit: impl FnMut(usize, Symbol)) {
+    let mut m = Matcher::new(src, cfg);
+    let mut i = 0;
+    while i < src.len() {
+        m.insert(i);
+        match m.longest(i, 0) {
+            Some((len, dist)) => {
+                emit(
+                    i + len,
+                    Symbol::Match {
+                        len: len as u16,
+                        dist: dist as u16,
+                    },
+                );
+                if len <= cfg.lazy {
+                    for j in i + 1..i + len {
+                        m.insert(j);
+                    }
+                }
+                i += len;
+            }
+            None => {
+                emit(i + 1, Symbol::Literal(src[i]));
+                i += 1;
+            }
+        }
+    }
+}
+
+fn find_lazy(src: &[u8], cfg: &'static Config, mut emit: impl FnMut(usize, Symbol)) {
+    let mut m = Matcher::new(src, cfg);
+    // A match found at `i - 1`, held back in case `i` has a longer one.
+    let mut pending: Option<(usize, usize)> = None;
+    let mut i = 0;
+    while i < src.len() {
+        m.insert(i);
+        let prev_len = pending.map_or(0, |(len, _)| len);
+        let cur = if prev_len < cfg.lazy {
+            m.longest(i, prev_len)
+        } else {
+            None
+        };
+        match (pending, cur) {
+            (Some((len, dist)), None) => {
+                let start = i - 1;
+                emit(
+                    start + len,
+                    Symbol::Match {
+                        len: len as u16,
+                        dist: dist as u16,
+                    },
+                );
+                for j in i + 1..start + len {
+                    m.insert(j);
+                }
+                pending = None;
+                i = start + len;
+            }
+            (Some(_), Some(_)) => {
+                emit(i, Symbol::Literal(src[i - 1]));
+                pending = cur;
+                i += 1;
+            }
+            (None, Some(_)) => {
+                pending = cur;
+                i += 1;
+            }
+            (None, None) => {
+                emit(i + 1, Symbol::Literal(src[i]));
+                i += 1;
+            }
+        }
+    }
+    if let Some((len, dist)) = pending {
+        emit(
+            i - 1 + len,
+            Symbol::Match {
+                len: len as u16,
+                dist: dist as u16,
+            },
+        );
+    }
+}
+
+/// Huffman code lengths for `freqs`, none longer than `max_bits`. At least
+/// two symbols get a code, so that every code is complete.
+pub(super) fn huffman_lengths(freqs: &[u32], max_bits: u32) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    let used = freqs.iter().filter(|&&f| f > 0).count();
+    if used < 2 {
+        for f in freqs.iter_mut().filter(|f| **f == 0).take(2 - used) {
+            *f = 1;
+        }
+    }
+
+    loop {
+        let lengths = huffman_tree(&freqs);
+        if lengths.iter().all(|&l| l as u32 <= max_bits) {
+            return lengths;
+        }
+        // Flatten the distribution and try again.
+        for f in freqs.iter_mut().filter(|f| **f > 0) {
+            *f = (*f >> 1) | 1;
+        }
+    }
+}
+
+fn huffman_tree(freqs: &[u32]) -> Vec<u8> {
+    let n = freqs.len();
+    // Leaves are 0..n; internal nodes follow, each with its two children.
+    let mut children: Vec<(usize, usize)> = Vec::new();
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = freqs
+        .iter()
+        .enumerate()
+        .filter(|(_, &f)| f > 0)
+        .map(|(s, &f)| Reverse((f as u64, s)))
+        .collect();
+    while heap.len() > 1 {
+        let Reverse((fa, a)) = heap.pop().unwrap();
+        let Reverse((fb, b)) = heap.pop().unwrap();
+        children.push((a, b));
+        heap.push(Reverse((fa + fb, n + children.len() - 1)));
+    }
+
+    let mut depth = vec![0u8; n + children.len()];
+    for (k, &(a, b)) in children.iter().enumerate().rev() {
+        let d = depth[n + k] + 1;
+        depth[a] = d;
+        depth[b] = d;
+    }
+    depth.truncate(n);
+    depth
+}
+
+/// Canonical codes for `lengths`, bit-reversed for writing LSB first.
+pub(super) fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut bl_count = [0u16; MAX_BITS as usize + 1];
+    for &l in lengths {
+        bl_count[l as usize] += 1;
+    }
+    bl_count[0] = 0;
+    let mut next = [0u16; MAX_BITS as usize + 1];
+    let mut code = 0u16;
+    for bits in 1..=MAX_BITS as usize {
+        code = (code + bl_count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|&l| {
+            if l == 0 {
+                return 0;
+            }
+            let c = next[l as usize];
+            next[l as usize] += 1;
+            c.reverse_bits() >> (16 - l)
+        })
+        .collect()
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bitbuf: u64,
+    bitcount: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, bits: u32, n: u32) {
+        self.bitbuf |= (bits as u64) << self.bitcount;
+        self.bitcount += n;
+        while self.bitcount >= 8 {
+            self.out.push(self.bitbuf as u8);
+            self.bitbuf >>= 8;
+            self.bitcount -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bitcount > 0 {
+            self.put(0, 8 - self.bitcount);
+        }
+    }
+}
+
+struct Code {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl Code {
+    fn new(lengths: Vec<u8>) -> Self {
+        let codes = canonical_codes(&lengths);
+        Code { lengths, codes }
+    }
+
+    #[inline]
+    fn put(&self, w: &mut BitWriter, sym: usize) {
+        w.put(self.codes[sym] as u32, self.lengths[sym] as u32);
+    }
+}
+
+/// The code length alphabet: a length, or a run of the previous length
+/// (16) or of zeros (17, 18), with the run's extra bits.
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let v = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == v).count();
+        i += run;
+        if v == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                out.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((v, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                out.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        out.extend(std::iter::repeat_n((v, 0), run));
+    }
+    out
+}
+
+const CL_EXTRA: [u32; 3] = [2, 3, 7];
+
+struct Block<'a> {
+    symbols: &'a [Symbol],
+    /// The input bytes the symbols cover.
+    raw: &'a [u8],
+}
+
+impl Block<'_> {
+    fn freqs(&self) -> (Vec<u32>, Vec<u32>) {
+        let mut litlen = vec![0u32; LITLEN_CODES];
+        let mut dist = vec![0u32; DIST_CODES];
+        for s in self.symbols {
+            match *s {
+                Symbol::Literal(b) => litlen[b as usize] += 1,
+                Symbol::Match { len, dist: d } => {
+                    litlen[257 + len_code(len as usize)] += 1;
+                    dist[dist_code(d as usize)] += 1;
+                }
+            }
+        }
+        litlen[END_OF_BLOCK] += 1;
+        (litlen, dist)
+    }
+
+    /// Size in bits of the symbols with the given code lengths.
+    fn data_bits(&self, litlen: &[u8], dist: &[u8]) -> usize {
+        let mut bits = litlen[END_OF_BLOCK] as usize;
+        for s in self.symbols {
+            bits += match *s {
+                Symbol::Literal(b) => litlen[b as usize] as usize,
+                Symbol::Match { len, dist: d } => {
+                    let lc = len_code(len as usize);
+                    let dc = dist_code(d as usize);
+                    litlen[257 + lc] as usize
+                        + LEN_EXTRA[lc] as usize
+                        + dist[dc] as usize
+                        + DIST_EXTRA[dc] as usize
+                }
+            };
+        }
+        bits
+    }
+
+    fn put_symbols(&self, w: &mut BitWriter, litlen: &Code, dist: &Code) {
+        for s in self.symbols {
+            match *s {
+                Symbol::Literal(b) => litlen.put(w, b as usize),
+                Symbol::Match { len, dist: d } => {
+                    let (len, d) = (len as usize, d as usize);
+                    let lc = len_code(len);
+                    litlen.put(w, 257 + lc);
+                    w.put((len - LEN_BASE[lc] as usize) as u32, LEN_EXTRA[lc] as u32);
+                    let dc = dist_code(d);
+                    dist.put(w, dc);
+                    w.put((d - DIST_BASE[dc] as usize) as u32, DIST_EXTRA[dc] as u32);
+                }
+            }
+        }
+        litlen.put(w, END_OF_BLOCK);
+    }
+
+    fn write(&self, w: &mut BitWriter, last: bool) {
+        let (lf, df) = self.freqs();
+        let mut litlen = huffman_lengths(&lf, MAX_BITS);
+        let mut dist = huffman_lengths(&df, MAX_BITS);
+        let hlit = litlen
+            .iter()
+            .rposition(|&l| l > 0)
+            .unwrap_or(0)
+            .max(END_OF_BLOCK)
+            + 1;
+        let hdist = dist.iter().rposition(|&l| l > 0).unwrap_or(0) + 1;
+        litlen.truncate(hlit);
+        dist.truncate(hdist);
+
+        let mut all = litlen.clone();
+        all.extend_from_slice(&dist);
+        let runs = run_length_encode(&all);
+        let mut cf = vec![0u32; CL_CODES];
+        for &(c, _) in &runs {
+            cf[c as usize] += 1;
+        }
+        let cl = huffman_lengths(&cf, MAX_CL_BITS);
+        let hclen = CL_ORDER
+            .iter()
+            .rposition(|&c| cl[c] > 0)
+            .unwrap_or(0)
+            .max(3)
+            + 1;
+
+        let dynamic_bits = 3
+            + 14
+            + 3 * hclen
+            + runs
+                .iter()
+                .map(|&(c, _)| {
+                    cl[c as usize] as usize
+                        + c.checked_sub(16)
+                            .map_or(0, |k| CL_EXTRA[k as usize] as usize)
+                })
+                .sum::<usize>()
+            + self.data_bits(&litlen, &dist);
+        let (fixed_litlen, fixed_dist) = fixed_lengths();
+        let fixed_bits = 3 + self.data_bits(&fixed_litlen, &fixed_dist);
+        let stored_chunks = self.raw.len().div_ceil(MAX_STORED).max(1);
+        let stored_bits = stored_chunks * (3 + 7 + 32) + 8 * self.raw.len();
+
+        if stored_bits <= fixed_bits.min(dynamic_bits) {
+            put_stored(w, self.raw, last);
+        } else if fixed_bits <= dynamic_bits {
+            w.put(last as u32, 1);
+            w.put(1, 2);
+            self.put_symbols(
+                w,
+                &Code::new(fixed_litlen.to_vec()),
+                &Code::new(fixed_dist.to_vec()),
+            );
+        } else {
+            w.put(last as u32, 1);
+            w.put(2, 2);
+            w.put((hlit - 257) as u32, 5);
+            w.put((hdist - 1) as u32, 5);
+            w.put((hclen - 4) as u32, 4);
+            for &c in &CL_ORDER[..hclen] {
+                w.put(cl[c] as u32, 3);
+            }
+            let cl = Code::new(cl);
+            for &(c, extra) in &runs {
+                cl.put(w, c as usize);
+                if c >= 16 {
+                    w.put(extra as u32, CL_EXTRA[c as usize - 16]);
+                }
+            }
+            self.put_symbols(w, &Code::new(litlen), &Code::new(dist));
+        }
+    }
+}
+
+/// Writes `raw` as stored blocks of at most 64K each.
+fn put_stored(w: &mut BitWriter, raw: &[u8], last: bool) {
+    let nchunks = raw.len().div_ceil(MAX_STORED).max(1);
+    for k in 0..nchunks {
+        let chunk = &raw[k * MAX_STORED..raw.len().min((k + 1) * MAX_STORED)];
+        w.put((last && k == nchunks - 1) as u32, 1);
+        w.put(0, 2);
+        w.align();
+        let len = chunk.len() as u16;
+        w.put(len as u32, 16);
+        w.put(!len as u32, 16);
+        w.out.extend_from_slice(chunk);
+    }
+}
+
+/// Compresses `src` into a raw deflate stream in `dst` at `level` (0-9; 0
+/// only stores). Returns `None` if the stream does not fit.
+pub fn deflate(src: &[u8], dst: &mut [u8], level: i32) -> Option<usize> {
+    let cfg = &CONFIG[level.clamp(0, 9) as usize];
+    let mut w = BitWriter {
+        out: Vec::new(),
+        bitbuf: 0,
+        bitcount: 0,
+    };
+
+    let mut symbols: Vec<Symbol> = Vec::with_capacity(BLOCK_SYMBOLS);
+    // Input covered by the symbols gathered so far.
+    let (mut start, mut end) = (0, 0);
+    let mut overflow = false;
+    {
+        let mut emit = |pos: usize, sym: Symbol| {
+            if overflow {
+                return;
+            }
+            symbols.push(sym);
+            end = pos;
+            if symbols.len() == BLOCK_SYMBOLS && end < src.len() {
+                Block {
+                    symbols: &symbols,
+                    raw: &src[start..end],
+                }
+                .write(&mut w, false);
+                symbols.clear();
+                start = end;
+                overflow = w.out.len() > dst.len();
+            }
+        };
+        match level {
+            0 => {}
+            1..=3 => find_greedy(src, cfg, &mut emit),
+            _ => find_lazy(src, cfg, &mut emit),
+        }
+    }
+    if overflow {
+        return None;
+    }
+    if level <= 0 {
+        put_stored(&mut w, src, true);
+    } else {
+        Block {
+            symbols: &symbols,
+            raw: &src[start..end],
+        }
+        .write(&mut w, true);
+    }
+    w.align();
+
+    let out = dst.get_mut(..w.out.len())?;
+    out.copy_from_slice(&w.out);
+    Some(w.out.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deflate_huffman_lengths() {
+        // Kraft sum of a complete code is exactly one.
+        let kraft = |l: &[u8]| -> f64 {
+            l.iter()
+                .filter(|&&l| l > 0)
+                .map(|&l| 0.5f64.powi(l as i32))
+                .sum()
+        };
+
+        let lengths = huffman_lengths(&[5, 9, 12, 13, 16, 45], MAX_BITS);
+        assert_eq!(lengths, [4, 4, 3, 3, 3, 1]);
+        assert_eq!(kraft(&lengths), 1.0);
+
+        // Fibonacci frequencies would need 24 bits unconstrained.
+        let mut fib = vec![1u32, 1];
+        while fib.len() < 25 {
+            fib.push(fib[fib.len() - 1] + fib[fib.len() - 2]);
+        }
+        let lengths = huffman_lengths(&fib, MAX_BITS);
+        assert!(lengths.iter().all(|&l| l as u32 <= MAX_BITS && l > 0));
+        assert_eq!(kraft(&lengths), 1.0);
+
+        // A lone symbol still gets a code, and a partner.
+        let lengths = huffman_lengths(&[0, 0, 7, 0], MAX_BITS);
+        assert_eq!(lengths, [1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn deflate_canonical_codes() {
+        // The example from RFC 1951 3.2.2, bit-reversed.
+        let codes = canonical_codes(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        let expected: [u16; 8] = [0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111];
+        let lengths = [3, 3, 3, 3, 3, 2, 4, 4];
+        for ((&c, &e), &l) in codes.iter().zip(&expected).zip(&lengths) {
+            assert_eq!(c, e.reverse_bits() >> (16 - l));
+        }
+    }
+
+    #[test]
+    fn deflate_run_length_encode() {
+        let mut lengths = vec![8u8; 10];
+        lengths.extend_from_slice(&[0; 150]);
+        lengths.extend_from_slice(&[5, 5, 0, 0]);
+        assert_eq!(
+            run_length_encode(&lengths),
+            [
+                (8, 0),
+                (16, 3),
+                (16, 0),
+                (18, 127),
+                (18, 1),
+                (5, 0),
+                (5, 0),
+                (0, 0),
+                (0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn deflate_stored() {
+        let src = b"abc";
+        let mut dst = [0u8; 16];
+        let len = deflate(src, &mut dst, 0).unwrap();
+        assert_eq!(&dst[..len], &[1, 3, 0, 0xfc, 0xff, b'a', b'b', b'c']);
+    }
+}
@@ -0,0 +1,161 @@
+//! gzip-1 .. gzip-9.
+//!
+//! Despite the name, blocks hold a zlib stream (RFC 1950) rather than a gzip
+//! file: a two byte header, the deflate data and a big-endian Adler-32 of the
+//! uncompressed bytes.
+
+use super::deflate::{deflate, WINDOW_SIZE};
+use super::inflate::inflate;
+use super::DecompressError;
+
+const ZLIB_HDR_SIZE: usize = 2;
+const ZLIB_TRAILER_SIZE: usize = 4;
+const Z_DEFLATED: u8 = 8;
+const Z_FDICT: u8 = 1 << 5;
+
+const ADLER_BASE: u32 = 65521;
+/// Largest run of bytes whose sums cannot overflow a u32 before reduction.
+const ADLER_NMAX: usize = 5552;
+
+pub fn adler32(adler: u32, buf: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    for chunk in buf.chunks(ADLER_NMAX) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_BASE;
+        b %= ADLER_BASE;
+    }
+    (b << 16) | a
+}
+
+fn zlib_header(level: i32) -> [u8; ZLIB_HDR_SIZE] {
+    let cinfo = (WINDOW_SIZE.trailing_zeros() - 8) as u8;
+    let cmf = cinfo << 4 | Z_DEFLATED;
+    let flevel: u8 = match level {
+        ..=1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let mut flg = flevel << 6;
+    flg += 31 - ((cmf as u16 * 256 + flg as u16) % 31) as u8;
+    [cmf, flg]
+}
+
+pub fn gzip_compress(src: &[u8], dst: &mut [u8], n: i32) -> Option<usize> {
+    if dst.len() < ZLIB_HDR_SIZE + ZLIB_TRAILER_SIZE {
+        return None;
+    }
+    let end = dst.len() - ZLIB_TRAILER_SIZE;
+    let len = deflate(src, &mut dst[ZLIB_HDR_SIZE..end], n)?;
+    dst[..ZLIB_HDR_SIZE].copy_from_slice(&zlib_header(n));
+    let at = ZLIB_HDR_SIZE + len;
+    dst[at..at + ZLIB_TRAILER_SIZE].copy_from_slice(&adler32(1, src).to_be_bytes());
+    Some(at + ZLIB_TRAILER_SIZE)
+}
+
+/// Like `uncompress()`, a stream that ends before filling `dst` is accepted.
+pub fn gzip_decompress(src: &[u8], dst: &mut [u8], _n: i32) -> Result<(), DecompressError> {
+    let hdr = src.get(..ZLIB_HDR_SIZE).ok_or(DecompressError::Corrupt)?;
+    let (cmf, flg) = (hdr[0], hdr[1]);
+    if cmf & 0xf != Z_DEFLATED
+        || (cmf >> 4) as u32 + 8 > WINDOW_SIZE.trailing_zeros()
+        || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31)
+        || flg & Z_FDICT != 0
+    {
+        return Err(DecompressError::Corrupt);
+    }
+
+    let (used, produced) = inflate(&src[ZLIB_HDR_SIZE..], dst)?;
+    let at = ZLIB_HDR_SIZE + used;
+    let trailer = src
+        .get(at..at + ZLIB_TRAILER_SIZE)
+        .ok_or(DecompressError::Corrupt)?;
+    let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if adler32(1, &dst[..produced]) != expected {
+        return Err(DecompressError::Corrupt);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_adler32() {
+        assert_eq!(adler32(1, b""), 1);
+        assert_eq!(adler32(1, b"Wikipedia"), 0x11e60398);
+        // Sums wrap across many chunks.
+        let buf = vec![0xffu8; 100_000];
+        let (a, b) = buf.iter().fold((1u64, 0u64), |(a, b), &x| {
+            let a = (a + x as u64) % ADLER_BASE as u64;
+            (a, (b + a) % ADLER_BASE as u64)
+        });
+        assert_eq!(adler32(1, &buf), ((b << 16) | a) as u32);
+        assert_eq!(
+            adler32(adler32(1, &buf[..777]), &buf[777..]),
+            adler32(1, &buf)
+        );
+    }
+
+    #[test]
+    fn gzip_known_stream() {
+        // zlib.compress(b"hello hello hello hello\n", 9)
+        let z = [
+            0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00, 0x70,
+            0xbe, 0x08, 0xbb,
+        ];
+        let mut out = [0u8; 24];
+        gzip_decompress(&z, &mut out, 9).unwrap();
+        assert_eq!(&out, b"hello hello hello hello\n");
+
+        assert_eq!(zlib_header(9), [0x78, 0xda]);
+        assert_eq!(zlib_header(6), [0x78, 0x9c]);
+        assert_eq!(zlib_header(1), [0x78, 0x01]);
+
+        let mut bad = z;
+        bad[16] ^= 1;
+        assert_eq!(
+            gzip_decompress(&bad, &mut out, 9),
+            Err(DecompressError::Corrupt)
+        );
+        let mut bad = z;
+        bad[1] ^= Z_FDICT;
+        assert_eq!(
+            gzip_decompress(&bad, &mut out, 9),
+            Err(DecompressError::Corrupt)
+        );
+    }
+
+    #[test]
+    fn gzip_levels() {
+        let mut src = Vec::new();
+        let mut i = 0u32;
+        while src.len() < 64 * 1024 {
+            let line = format!(
+                "{} backup: archived /srv/data/{}/part-{:04}.tar\n",
+                1700000000 + i * 37,
+                i % 11,
+                i % 400
+            );
+            src.extend_from_slice(line.as_bytes());
+            i += 1;
+        }
+        src.truncate(64 * 1024);
+
+        let mut sizes = Vec::new();
+        for level in 1..=9 {
+            let mut dst = vec![0u8; src.len()];
+            let len = gzip_compress(&src, &mut dst, level).unwrap();
+            let mut out = vec![0u8; src.len()];
+            gzip_decompress(&dst[..len], &mut out, level).unwrap();
+            assert_eq!(out, src);
+            sizes.push(len);
+        }
+        assert!(sizes[8] <= sizes[0]);
+        assert!(sizes[8] < src.len() / 5);
+    }
+}
@@ -0,0 +1,298 @@
+//! Deflate (RFC 1951) decompressor.
+//!
+//! Huffman codes are decoded a bit at a time from their per-length counts,
+//! which needs no tables beyond the code lengths themselves. All reads and
+//! back references are bounds checked; malformed input is reported as
+//! `DecompressError::Corrupt`.
+
+use super::deflate::{
+    fixed_lengths, CL_ORDER, DIST_BASE, DIST_CODES, DIST_EXTRA, END_OF_BLOCK, LEN_BASE, LEN_EXTRA,
+    LITLEN_CODES,
+};
+use super::DecompressError;
+
+const MAX_BITS: usize = 15;
+
+struct BitReader<'a> {
+    src: &'a [u8],
+    pos: usize,
+    bitbuf: u32,
+    bitcount: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, DecompressError> {
+        while self.bitcount < n {
+            let b = *self.src.get(self.pos).ok_or(DecompressError::Corrupt)?;
+            self.bitbuf |= (b as u32) << self.bitcount;
+            self.pos += 1;
+            self.bitcount += 8;
+        }
+        let v = self.bitbuf & ((1u64 << n) - 1) as u32;
+        self.bitbuf >>= n;
+        self.bitcount -= n;
+        Ok(v)
+    }
+
+    /// Drops the rest of the current byte.
+    fn align(&mut self) {
+        self.bitbuf = 0;
+        self.bitcount = 0;
+    }
+}
+
+struct Huffman {
+    /// Number of codes of each length.
+    count: [u16; MAX_BITS + 1],
+    /// Symbols ordered by code.
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    /// Rejects over-subscribed lengths. Incomplete codes are accepted; an
+    /// unassigned code is only an error if it is actually read.
+    fn new(lengths: &[u8]) -> Result<Self, DecompressError> {
+        let mut count = [0u16; MAX_BITS + 1];
+        for &l in lengths {
+            count[l as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &c in &count[1..] {
+            left = (left << 1) - c as i32;
+            if left < 0 {
+                return Err(DecompressError::Corrupt);
+            }
+        }
+
+        let mut offs = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offs[len + 1] = offs[len] + count[len];
+        }
+        let mut symbol = vec![0u16; offs[MAX_BITS + 1] as usize];
+        for (s, &l) in lengths.iter().enumerate() {
+            if l > 0 {
+                symbol[offs[l as usize] as usize] = s as u16;
+                offs[l as usize] += 1;
+            }
+        }
+        Ok(Huffman { count, symbol })
+    }
+
+    fn decode(&self, br: &mut BitReader) -> Result<usize, DecompressError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.count[1..] {
+            code |= br.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbol[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(DecompressError::Corrupt)
+    }
+}
+
+fn stored(br: &mut BitReader, dst: &mut [u8], op: &mut usize) -> Result<(), DecompressError> {
+    br.align();
+    let len = br.bits(16)? as usize;
+    let nlen = br.bits(16)? as usize;
+    if len != !nlen & 0xffff {
+        return Err(DecompressError::Corrupt);
+    }
+    let src = br
+        .src
+        .get(br.pos..br.pos + len)
+        .ok_or(DecompressError::Corrupt)?;
+    dst.get_mut(*op..*op + len)
+        .ok_or(DecompressError::Corrupt)?
+        .copy_from_slice(src);
+    br.pos += len;
+    *op += len;
+    Ok(())
+}
+
+fn codes(
+    br: &mut BitReader,
+    dst: &mut [u8],
+    op: &mut usize,
+    litlen: &Huffman,
+    dist: &Huffman,
+) -> Result<(), DecompressError> {
+    loop {
+        let sym = litlen.decode(br)?;
+        if sym < END_OF_BLOCK {
+            *dst.get_mut(*op).ok_or(DecompressError::Corrupt)? = sym as u8;
+            *op += 1;
+            continue;
+        }
+        if sym == END_OF_BLOCK {
+            return Ok(());
+        }
+
+        let lc = sym - 257;
+        if lc >= LEN_BASE.len() {
+            return Err(DecompressError::Corrupt);
+        }
+        let len = LEN_BASE[lc] as usize + br.bits(LEN_EXTRA[lc] as u32)? as usize;
+        let dc = dist.decode(br)?;
+        if dc >= DIST_CODES {
+            return Err(DecompressError::Corrupt);
+        }
+        let d = DIST_BASE[dc] as usize + br.bits(DIST_EXTRA[dc] as u32)? as usize;
+        if d > *op || *op + len > dst.len() {
+            return Err(DecompressError::Corrupt);
+        }
+        // Byte by byte: the match may overlap what is being written.
+        for i in *op..*op + len {
+            dst[i] = dst[i - d];
+        }
+        *op += len;
+    }
+}
+
+fn dynamic(br: &mut BitReader) -> Result<(Huffman, Huffman), DecompressError> {
+    let hlit = br.bits(5)? as usize + 257;
+    let hdist = br.bits(5)? as usize + 1;
+    let hclen = br.bits(4)? as usize + 4;
+    if hlit > LITLEN_CODES || hdist > DIST_CODES {
+        return Err(DecompressError::Corrupt);
+    }
+
+    let mut cl = [0u8; 19];
+    for &c in &CL_ORDER[..hclen] {
+        cl[c] = br.bits(3)? as u8;
+    }
+    let cl = Huffman::new(&cl)?;
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let sym = cl.decode(br)?;
+        let (v, n) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or(DecompressError::Corrupt)?;
+                (prev, 3 + br.bits(2)? as usize)
+            }
+            17 => (0, 3 + br.bits(3)? as usize),
+            _ => (0, 11 + br.bits(7)? as usize),
+        };
+        if lengths.len() + n > hlit + hdist {
+            return Err(DecompressError::Corrupt);
+        }
+        lengths.extend(std::iter::repeat_n(v, n));
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(DecompressError::Corrupt);
+    }
+    Ok((
+        Huffman::new(&lengths[..hlit])?,
+        Huffman::new(&lengths[hlit..])?,
+    ))
+}
+
+/// Decompresses the raw deflate stream at the start of `src` into `dst`.
+/// Returns the bytes of `src` consumed and of `dst` produced.
+pub fn inflate(src: &[u8], dst: &mut [u8]) -> Result<(usize, usize), DecompressError> {
+    let mut br = BitReader {
+        src,
+        pos: 0,
+        bitbuf: 0,
+        bitcount: 0,
+    };
+    let mut op = 0;
+    loop {
+        let last = br.bits(1)? == 1;
+        match br.bits(2)? {
+            0 => stored(&mut br, dst, &mut op)?,
+            1 => {
+                let (litlen, dist) = fixed_lengths();
+                let (litlen, dist) = (Huffman::new(&litlen)?, Huffman::new(&dist)?);
+                codes(&mut br, dst, &mut op, &litlen, &dist)?;
+            }
+            2 => {
+                let (litlen, dist) = dynamic(&mut br)?;
+                codes(&mut br, dst, &mut op, &litlen, &dist)?;
+            }
+            _ => return Err(DecompressError::Corrupt),
+        }
+        if last {
+            return Ok((br.pos, op));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::compress::deflate::deflate;
+    use crate::sio::compress::tests::noise;
+
+    #[test]
+    fn inflate_known_streams() {
+        let mut out = [0u8; 64];
+        // Fixed Huffman block, from zlib.compress(b"hello hello hello hello\n").
+        let fixed = [
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00,
+        ];
+        assert_eq!(inflate(&fixed, &mut out), Ok((fixed.len(), 24)));
+        assert_eq!(&out[..24], b"hello hello hello hello\n");
+
+        // Stored block.
+        let stored = [1, 3, 0, 0xfc, 0xff, b'a', b'b', b'c', 0xee];
+        assert_eq!(inflate(&stored, &mut out), Ok((8, 3)));
+        assert_eq!(&out[..3], b"abc");
+    }
+
+    #[test]
+    fn inflate_deflate_levels() {
+        let mut src = Vec::new();
+        for i in 0..3000u32 {
+            src.extend_from_slice(
+                format!("seq={} status=ok path=/var/log/app{}\n", i % 97, i % 5).as_bytes(),
+            );
+        }
+        src.extend_from_slice(&noise(70000));
+        src.extend_from_slice(&[0u8; 1000]);
+
+        for level in 0..=9 {
+            let mut dst = vec![0u8; src.len() + 1024];
+            let len = deflate(&src, &mut dst, level).unwrap();
+            let mut out = vec![0u8; src.len()];
+            assert_eq!(
+                inflate(&dst[..len], &mut out),
+                Ok((len, src.len())),
+                "level {level}"
+            );
+            assert_eq!(out, src, "level {level}");
+        }
+        let mut out = [0u8; 1];
+        let mut dst = [0u8; 16];
+        let len = deflate(&[], &mut dst, 6).unwrap();
+        assert_eq!(inflate(&dst[..len], &mut out), Ok((len, 0)));
+    }
+
+    #[test]
+    fn inflate_corrupt() {
+        let mut out = [0u8; 64];
+        // Reserved block type.
+        assert_eq!(inflate(&[0x07], &mut out), Err(DecompressError::Corrupt));
+        // Stored length check fails.
+        assert_eq!(
+            inflate(&[1, 3, 0, 0xfc, 0xfe, b'a', b'b', b'c'], &mut out),
+            Err(DecompressError::Corrupt)
+        );
+        // Truncated.
+        let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27];
+        assert_eq!(inflate(&fixed, &mut out), Err(DecompressError::Corrupt));
+        // Output too small.
+        let fixed = [
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00,
+        ];
+        assert_eq!(
+            inflate(&fixed, &mut out[..10]),
+            Err(DecompressError::Corrupt)
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! LZ4.
+//!
+//! Blocks are stored as a big-endian 32-bit length followed by a raw LZ4
+//! block of that many bytes; the length is needed because the block may be
+//! followed by padding up to the allocation size.
+//!
+//! An LZ4 block is a list of sequences. Each starts with a token whose high
+//! nibble is the literal count and low nibble the match length minus
+//! `MINMATCH`; a nibble of 15 is continued in following bytes. The literals
+//! come next, then a little-endian 16-bit match offset. The last sequence has
+//! literals only.
+
+use super::DecompressError;
+
+const LZ4_HDR_SIZE: usize = 4;
+
+const MINMATCH: usize = 4;
+/// The last bytes of a block are always literals.
+const LASTLITERALS: usize = 5;
+/// A match may not start within this many bytes of the end of the block.
+const MFLIMIT: usize = 12;
+const MAX_DISTANCE: usize = 65535;
+const ML_MASK: usize = 15;
+const RUN_MASK: usize = 15;
+const HASH_LOG: u32 = 12;
+
+#[inline]
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+#[inline]
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+struct Out<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Out<'_> {
+    #[inline]
+    fn push(&mut self, b: u8) -> Option<()> {
+        *self.buf.get_mut(self.pos)? = b;
+        self.pos += 1;
+        Some(())
+    }
+
+    fn extend(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + bytes.len())?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Some(())
+    }
+
+    /// Continues a length whose nibble in the token was saturated.
+    fn push_len(&mut self, mut len: usize) -> Option<()> {
+        while len >= 255 {
+            self.push(255)?;
+            len -= 255;
+        }
+        self.push(len as u8)
+    }
+
+    fn sequence(&mut self, literals: &[u8], mat: Option<(usize, usize)>) -> Option<()> {
+        let ml = mat.map_or(0, |(_, mlen)| mlen - MINMATCH);
+        let token = (literals.len().min(RUN_MASK) << 4) | ml.min(ML_MASK);
+        self.push(token as u8)?;
+        if literals.len() >= RUN_MASK {
+            self.push_len(literals.len() - RUN_MASK)?;
+        }
+        self.extend(literals)?;
+        if let Some((offset, _)) = mat {
+            self.extend(&(offset as u16).to_le_bytes())?;
+            if ml >= ML_MASK {
+                self.push_len(ml - ML_MASK)?;
+            }
+        }
+        Some(())
+    }
+}
+
+/// Compresses `src` into a raw LZ4 block. Returns `None` if it does not fit
+/// in `dst`.
+pub fn lz4_compress_block(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    let mut out = Out { buf: dst, pos: 0 };
+    let mut anchor = 0;
+
+    if src.len() > MFLIMIT {
+        let mflimit = src.len() - MFLIMIT;
+        let matchlimit = src.len() - LASTLITERALS;
+        // Positions plus one; zero means empty.
+        let mut table = vec![0usize; 1 << HASH_LOG];
+        let mut ip = 0;
+
+        while ip < mflimit {
+            let seq = read_u32(src, ip);
+            let h = hash(seq);
+            let cand = table[h];
+            table[h] = ip + 1;
+
+            let Some(mut m) = cand.checked_sub(1) else {
+                ip += 1;
+                continue;
+            };
+            if ip - m > MAX_DISTANCE || read_u32(src, m) != seq {
+                ip += 1;
+                continue;
+            }
+
+            let mut start = ip;
+            while start > anchor && m > 0 && src[start - 1] == src[m - 1] {
+                start -= 1;
+                m -= 1;
+            }
+            let mut end = ip + MINMATCH;
+            while end < matchlimit && src[end] == src[m + (end - start)] {
+                end += 1;
+            }
+
+            out.sequence(&src[anchor..start], Some((start - m, end - start)))?;
+            ip = end;
+            anchor = ip;
+            if ip >= 2 && ip - 2 < mflimit {
+                table[hash(read_u32(src, ip - 2))] = ip - 2 + 1;
+            }
+        }
+    }
+
+    out.sequence(&src[anchor..], None)?;
+    Some(out.pos)
+}
+
+/// Decodes a raw LZ4 block into `dst` and returns the number of bytes
+/// produced.
+pub fn lz4_decompress_block(src: &[u8], dst: &mut [u8]) -> Result<usize, DecompressError> {
+    let (mut ip, mut op) = (0, 0);
+    let byte = |ip: usize| src.get(ip).copied().ok_or(DecompressError::Corrupt);
+    let read_len = |ip: &mut usize, mut len: usize| -> Result<usize, DecompressError> {
+        loop {
+            let b = byte(*ip)?;
+            *ip += 1;
+            len += b as usize;
+            if b != 255 {
+                return Ok(len);
+            }
+        }
+    };
+
+    loop {
+        let token = byte(ip)? as usize;
+        ip += 1;
+
+        let mut litlen = token >> 4;
+        if litlen == RUN_MASK {
+            litlen = read_len(&mut ip, litlen)?;
+        }
+        let literals = src.get(ip..ip + litlen).ok_or(DecompressError::Corrupt)?;
+        dst.get_mut(op..op + litlen)
+            .ok_or(DecompressError::Corrupt)?
+            .copy_from_slice(literals);
+        ip += litlen;
+        op += litlen;
+        if ip == src.len() {
+            return Ok(op);
+        }
+
+        let offset = byte(ip)? as usize | (byte(ip + 1)? as usize) << 8;
+        ip += 2;
+        if offset == 0 || offset > op {
+            return Err(DecompressError::Corrupt);
+        }
+        let mut mlen = token & ML_MASK;
+        if mlen == ML_MASK {
+            mlen = read_len(&mut ip, mlen)?;
+        }
+        mlen += MINMATCH;
+        if op + mlen > dst.len() {
+            return Err(DecompressError::Corrupt);
+        }
+        // Byte by byte: the match may overlap what is being written.
+        for i in op..op + mlen {
+            dst[i] = dst[i - offset];
+        }
+        op += mlen;
+    }
+}
+
+pub fn lz4_compress(src: &[u8], dst: &mut [u8], _n: i32) -> Option<usize> {
+    if dst.len() < LZ4_HDR_SIZE {
+        return None;
+    }
+    let (hdr, body) = dst.split_at_mut(LZ4_HDR_SIZE);
+    let bufsiz = lz4_compress_block(src, body)?;
+    hdr.copy_from_slice(&(bufsiz as u32).to_be_bytes());
+    Some(bufsiz + LZ4_HDR_SIZE)
+}
+
+/// As in the C implementation, output short of `dst.len()` is accepted; only
+/// malformed input is an error.
+pub fn lz4_decompress(src: &[u8], dst: &mut [u8], _n: i32) -> Result<(), DecompressError> {
+    let hdr = src.get(..LZ4_HDR_SIZE).ok_or(DecompressError::Corrupt)?;
+    let bufsiz = u32::from_be_bytes([hdr[0], hdr[1], hdr[2], hdr[3]]) as usize;
+    let block = src
+        .get(LZ4_HDR_SIZE..LZ4_HDR_SIZE + bufsiz)
+        .ok_or(DecompressError::Corrupt)?;
+    lz4_decompress_block(block, dst)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::compress::tests::noise;
+
+    fn logs() -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut i = 0u32;
+        while buf.len() < 128 * 1024 {
+            let line = format!(
+                "2024-01-{:02} 12:{:02}:{:02} host{} sshd[{}]: Accepted publickey for user{}\n",
+                i % 28 + 1,
+                i % 60,
+                (i * 7) % 60,
+                i % 3,
+                1000 + i % 17,
+                i % 5
+            );
+            buf.extend_from_slice(line.as_bytes());
+            i += 1;
+        }
+        buf.truncate(128 * 1024);
+        buf
+    }
+
+    #[test]
+    fn lz4_known_encoding() {
+        // 32 x 'a': a literal, a 26-byte overlapping match, 5 final literals.
+        let src = [b'a'; 32];
+        let mut dst = [0u8; 64];
+        let len = lz4_compress(&src, &mut dst, 0).unwrap();
+        assert_eq!(
+            &dst[..len],
+            &[0, 0, 0, 11, 0x1f, b'a', 1, 0, 7, 0x50, b'a', b'a', b'a', b'a', b'a']
+        );
+    }
+
+    #[test]
+    fn lz4_roundtrip() {
+        let src = logs();
+        let mut dst = vec![0u8; src.len()];
+        let len = lz4_compress(&src, &mut dst, 0).unwrap();
+        assert!(len < src.len() / 4);
+        let mut out = vec![0u8; src.len()];
+        lz4_decompress(&dst[..len], &mut out, 0).unwrap();
+        assert_eq!(out, src);
+
+        // Padding after the block is ignored.
+        let mut padded = dst[..len].to_vec();
+        padded.resize(len + 512, 0xee);
+        let mut out = vec![0u8; src.len()];
+        lz4_decompress(&padded, &mut out, 0).unwrap();
+        assert_eq!(out, src);
+
+        for n in [0, 1, 12, 13, 100] {
+            let src = &logs()[..n];
+            let mut dst = vec![0u8; n + 16];
+            let len = lz4_compress(src, &mut dst, 0).unwrap();
+            let mut out = vec![0u8; n];
+            lz4_decompress(&dst[..len], &mut out, 0).unwrap();
+            assert_eq!(out, src);
+        }
+    }
+
+    #[test]
+    fn lz4_does_not_fit() {
+        let random = noise(4096);
+        let mut dst = vec![0u8; random.len() - random.len() / 8];
+        assert_eq!(lz4_compress(&random, &mut dst, 0), None);
+        assert_eq!(lz4_compress(&random, &mut [0u8; 3], 0), None);
+    }
+
+    #[test]
+    fn lz4_corrupt() {
+        let mut out = [0u8; 64];
+        // Length header larger than the buffer.
+        assert_eq!(
+            lz4_decompress(&[0, 0, 1, 0, 0x10, b'a'], &mut out, 0),
+            Err(DecompressError::Corrupt)
+        );
+        // Offset reaching before the start of the output.
+        assert_eq!(
+            lz4_decompress(&[0, 0, 0, 5, 0x10, b'a', 2, 0, 0], &mut out, 0),
+            Err(DecompressError::Corrupt)
+        );
+        // Match running past the end of the output.
+        assert_eq!(
+            lz4_decompress(&[0, 0, 0, 5, 0x1f, b'a', 1, 0, 200], &mut out, 0),
+            Err(DecompressError::Corrupt)
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! LZJB, the original ZFS compressor.
+//!
+//! Output is a sequence of groups: a copy map byte followed by eight items,
+//! each either a literal byte or, when its bit in the map is set, a two byte
+//! back reference holding a 6-bit length and a 10-bit offset.
+//!
+//! Candidate matches are found through a small hash table of recent
+//! positions. Positions are kept modulo 2^16 like the C code, which stores
+//! truncated pointers.
+
+use super::DecompressError;
+
+const NBBY: usize = 8;
+const MATCH_BITS: usize = 6;
+const MATCH_MIN: usize = 3;
+const MATCH_MAX: usize = (1 << MATCH_BITS) + (MATCH_MIN - 1);
+const OFFSET_MASK: usize = (1 << (16 - MATCH_BITS)) - 1;
+const LEMPEL_SIZE: usize = 1024;
+
+pub fn lzjb_compress(src: &[u8], dst: &mut [u8], _n: i32) -> Option<usize> {
+    let mut lempel = [0u16; LEMPEL_SIZE];
+    let mut copymask = 1usize << (NBBY - 1);
+    let mut copymap = 0;
+    let (mut s, mut d) = (0, 0);
+
+    while s < src.len() {
+        copymask <<= 1;
+        if copymask == 1 << NBBY {
+            // Room for a copy map and eight two-byte items.
+            if d + 1 + 2 * NBBY >= dst.len() {
+                return None;
+            }
+            copymask = 1;
+            copymap = d;
+            dst[d] = 0;
+            d += 1;
+        }
+        if s + MATCH_MAX > src.len() {
+            dst[d] = src[s];
+            d += 1;
+            s += 1;
+            continue;
+        }
+
+        let mut hash =
+            ((src[s] as usize) << 16) + ((src[s + 1] as usize) << 8) + src[s + 2] as usize;
+        hash += hash >> 9;
+        hash += hash >> 5;
+        let hp = &mut lempel[hash & (LEMPEL_SIZE - 1)];
+        let offset = s.wrapping_sub(*hp as usize) & OFFSET_MASK;
+        *hp = s as u16;
+
+        if offset != 0
+            && offset <= s
+            && src[s..s + MATCH_MIN] == src[s - offset..s - offset + MATCH_MIN]
+        {
+            let cpy = s - offset;
+            dst[copymap] |= copymask as u8;
+            let mut mlen = MATCH_MIN;
+            while mlen < MATCH_MAX && src[s + mlen] == src[cpy + mlen] {
+                mlen += 1;
+            }
+            dst[d] = (((mlen - MATCH_MIN) << (NBBY - MATCH_BITS)) | (offset >> NBBY)) as u8;
+            dst[d + 1] = offset as u8;
+            d += 2;
+            s += mlen;
+        } else {
+            dst[d] = src[s];
+            d += 1;
+            s += 1;
+        }
+    }
+    Some(d)
+}
+
+pub fn lzjb_decompress(src: &[u8], dst: &mut [u8], _n: i32) -> Result<(), DecompressError> {
+    let mut copymap = 0u8;
+    let mut copymask = 1usize << (NBBY - 1);
+    let (mut s, mut d) = (0, 0);
+    let byte = |s: usize| src.get(s).copied().ok_or(DecompressError::Corrupt);
+
+    while d < dst.len() {
+        copymask <<= 1;
+        if copymask == 1 << NBBY {
+            copymask = 1;
+            copymap = byte(s)?;
+            s += 1;
+        }
+        if copymap as usize & copymask != 0 {
+            let (b0, b1) = (byte(s)? as usize, byte(s + 1)? as usize);
+            s += 2;
+            let mlen = ((b0 >> (NBBY - MATCH_BITS)) + MATCH_MIN).min(dst.len() - d);
+            let offset = ((b0 << NBBY) | b1) & OFFSET_MASK;
+            if offset > d {
+                return Err(DecompressError::Corrupt);
+            }
+            // Byte by byte: the source may overlap what is being written.
+            for i in d..d + mlen {
+                dst[i] = dst[i - offset];
+            }
+            d += mlen;
+        } else {
+            dst[d] = byte(s)?;
+            s += 1;
+            d += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::compress::tests::noise;
+
+    #[test]
+    fn lzjb_known_encoding() {
+        let src = b"abcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabc";
+        let mut dst = vec![0u8; src.len()];
+        let len = lzjb_compress(src, &mut dst, 0).unwrap();
+        // Three literals, then one back reference of offset 3 covering the
+        // longest match, then literals for the tail the matcher never reaches.
+        assert_eq!(&dst[..5], &[0b1000, b'a', b'b', b'c', 63 << 2]);
+        assert_eq!(dst[5], 3);
+
+        let mut out = vec![0u8; src.len()];
+        lzjb_decompress(&dst[..len], &mut out, 0).unwrap();
+        assert_eq!(&out[..], &src[..]);
+    }
+
+    #[test]
+    fn lzjb_roundtrip() {
+        let mut src = Vec::new();
+        for i in 0..8192u32 {
+            src.push(if i % 100 < 60 {
+                (i % 7) as u8
+            } else {
+                (i * 31 % 251) as u8
+            });
+        }
+        let mut dst = vec![0u8; src.len()];
+        let len = lzjb_compress(&src, &mut dst, 0).unwrap();
+        assert!(len < src.len() / 2);
+        let mut out = vec![0u8; src.len()];
+        lzjb_decompress(&dst[..len], &mut out, 0).unwrap();
+        assert_eq!(out, src);
+
+        let random = noise(4096);
+        let mut dst = vec![0u8; random.len() - random.len() / 8];
+        assert_eq!(lzjb_compress(&random, &mut dst, 0), None);
+    }
+
+    #[test]
+    fn lzjb_corrupt() {
+        let mut out = [0u8; 32];
+        // A back reference before the start of the output.
+        assert_eq!(
+            lzjb_decompress(&[1, 0, 5], &mut out, 0),
+            Err(DecompressError::Corrupt)
+        );
+        // Input runs out.
+        assert_eq!(
+            lzjb_decompress(&[0, 1, 2], &mut out, 0),
+            Err(DecompressError::Corrupt)
+        );
+    }
+}
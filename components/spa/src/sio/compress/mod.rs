@@ -0,0 +1,366 @@
+//! Compression function table.
+//!
+//! Every `SIOCompress` has an entry giving its compressor, decompressor and
+//! the level passed to both. The encodings match the C implementation so
+//! that blocks can be read by either.
+//!
+//! A block is only stored compressed when that saves at least 12.5%
+//! (`SIO_COMPRESS_SHIFT`); otherwise the space saved would not pay for the
+//! cost of decompressing on every read.
+
+pub mod deflate;
+pub mod gzip;
+pub mod inflate;
+pub mod lz4;
+pub mod lzjb;
+pub mod zle;
+
+pub use deflate::*;
+pub use gzip::*;
+pub use inflate::*;
+pub use lz4::*;
+pub use lzjb::*;
+pub use zle::*;
+
+use std::fmt;
+
+use super::SIOCompress;
+use crate::blkptr::blkptr::Blkptr;
+
+/// A compressed block must be at most `lsize - (lsize >> SIO_COMPRESS_SHIFT)`.
+pub const SIO_COMPRESS_SHIFT: usize = 3;
+
+/// Compresses `src` into `dst` with the given level. Returns the compressed
+/// length, or `None` if the output does not fit in `dst`.
+pub type CompressFunc = fn(&[u8], &mut [u8], i32) -> Option<usize>;
+/// Decompresses `src` into exactly `dst.len()` bytes.
+pub type DecompressFunc = fn(&[u8], &mut [u8], i32) -> Result<(), DecompressError>;
+
+pub struct SIOCompressInfo {
+    /// `None` for values that do not name an algorithm (`inherit`, `on`) and
+    /// for those handled without one (`uncompressed`, `empty`).
+    pub compress: Option<CompressFunc>,
+    pub decompress: Option<DecompressFunc>,
+    pub level: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressError {
+    /// No decompressor for this value, or none implemented yet.
+    Unsupported(SIOCompress),
+    /// The compressed data is malformed.
+    Corrupt,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::Unsupported(c) => write!(f, "unsupported compression {}", c.name()),
+            DecompressError::Corrupt => write!(f, "corrupt compressed data"),
+        }
+    }
+}
+
+impl std::error::Error for DecompressError {}
+
+const NONE: SIOCompressInfo = SIOCompressInfo {
+    compress: None,
+    decompress: None,
+    level: 0,
+};
+
+const fn gzip(level: i32) -> SIOCompressInfo {
+    SIOCompressInfo {
+        compress: Some(gzip_compress),
+        decompress: Some(gzip_decompress),
+        level,
+    }
+}
+
+/// Indexed by `SIOCompress`.
+pub static SIO_COMPRESS_TABLE: [SIOCompressInfo; SIOCompress::FUNCTIONS as usize] = [
+    // inherit
+    NONE,
+    // on
+    NONE,
+    // uncompressed
+    NONE,
+    // lzjb
+    SIOCompressInfo {
+        compress: Some(lzjb_compress),
+        decompress: Some(lzjb_decompress),
+        level: 0,
+    },
+    // empty
+    NONE,
+    // gzip-1 .. gzip-9
+    gzip(1),
+    gzip(2),
+    gzip(3),
+    gzip(4),
+    gzip(5),
+    gzip(6),
+    gzip(7),
+    gzip(8),
+    gzip(9),
+    // zle
+    SIOCompressInfo {
+        compress: Some(zle_compress),
+        decompress: Some(zle_decompress),
+        level: 64,
+    },
+    // lz4
+    SIOCompressInfo {
+        compress: Some(lz4_compress),
+        decompress: Some(lz4_decompress),
+        level: 0,
+    },
+    // zstd
+    NONE,
+];
+
+impl SIOCompress {
+    /// Panics for `FUNCTIONS`, which is not a compression function.
+    #[inline]
+    pub fn info(&self) -> &'static SIOCompressInfo {
+        &SIO_COMPRESS_TABLE[u8::from(*self) as usize]
+    }
+}
+
+/// Compresses `src` with `compress`. Returns `None` if the algorithm has no
+/// compressor or the result would not save at least 12.5%.
+pub fn compress_data(compress: SIOCompress, src: &[u8]) -> Option<Vec<u8>> {
+    let ci = SIO_COMPRESS_TABLE.get(u8::from(compress) as usize)?;
+    let func = ci.compress?;
+    let mut dst = vec![0u8; src.len() - (src.len() >> SIO_COMPRESS_SHIFT)];
+    let len = func(src, &mut dst, ci.level)?;
+    dst.truncate(len);
+    Some(dst)
+}
+
+/// The result of `compress_block`: what to record in the blkptr and what to
+/// write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedBlock {
+    /// `EMPTY` for an all-zero block, `OFF` if compression did not pay.
+    pub compress: SIOCompress,
+    pub psize: usize,
+    /// `psize` bytes, zero padded past the end of the compressed data.
+    pub data: Vec<u8>,
+}
+
+impl CompressedBlock {
+    /// Records the compression and sizes in `bp`. Nothing is written for an
+    /// empty block, so its bp is left alone.
+    pub fn fill_blkptr(&self, bp: &mut Blkptr, lsize: usize) {
+        if self.compress == SIOCompress::EMPTY {
+            return;
+        }
+        bp.set_compress(u8::from(self.compress) as u64);
+        bp.set_lsize(lsize as u64);
+        bp.set_psize(self.psize as u64);
+    }
+}
+
+/// Picks the physical form of `src`. An all-zero block compresses to nothing.
+/// Otherwise the compressed size is rounded up to `min_alloc`, the smallest
+/// allocation the vdev can make, and the block is stored uncompressed if that
+/// does not leave it smaller than `src`.
+pub fn compress_block(compress: SIOCompress, src: &[u8], min_alloc: usize) -> CompressedBlock {
+    if src.iter().all(|&b| b == 0) {
+        return CompressedBlock {
+            compress: SIOCompress::EMPTY,
+            psize: 0,
+            data: Vec::new(),
+        };
+    }
+
+    let uncompressed = || CompressedBlock {
+        compress: SIOCompress::OFF,
+        psize: src.len(),
+        data: src.to_vec(),
+    };
+    let mut data = match compress_data(compress, src) {
+        Some(data) => data,
+        None => return uncompressed(),
+    };
+    let psize = data.len().div_ceil(min_alloc.max(1)) * min_alloc.max(1);
+    if psize >= src.len() {
+        return uncompressed();
+    }
+    data.resize(psize, 0);
+    CompressedBlock {
+        compress,
+        psize,
+        data,
+    }
+}
+
+/// Expands `src`, stored with `compress`, into `dst`, which must be the
+/// block's logical size.
+pub fn decompress_data(
+    compress: SIOCompress,
+    src: &[u8],
+    dst: &mut [u8],
+) -> Result<(), DecompressError> {
+    match compress {
+        SIOCompress::OFF => {
+            let src = src.get(..dst.len()).ok_or(DecompressError::Corrupt)?;
+            dst.copy_from_slice(src);
+            Ok(())
+        }
+        SIOCompress::EMPTY => {
+            dst.fill(0);
+            Ok(())
+        }
+        _ => {
+            let ci = compress.info();
+            let func = ci
+                .decompress
+                .ok_or(DecompressError::Unsupported(compress))?;
+            func(src, dst, ci.level)
+        }
+    }
+}
+
+/// Expands the data read for `bp` according to its compress field.
+pub fn decompress_blkptr(bp: &Blkptr, src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let compress = u8::try_from(bp.get_compress())
+        .ok()
+        .and_then(|c| SIOCompress::try_from(c).ok())
+        .filter(|&c| c != SIOCompress::FUNCTIONS)
+        .ok_or(DecompressError::Unsupported(SIOCompress::FUNCTIONS))?;
+    let mut dst = vec![0u8; bp.get_lsize() as usize];
+    decompress_data(compress, src, &mut dst)?;
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sys::XorShift64;
+
+    fn logs(len: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut i = 0u32;
+        while buf.len() < len {
+            let line = format!(
+                "host{} kernel: eth{} link up, {} Mbps\n",
+                i % 4,
+                i % 2,
+                100 * (i % 3 + 1)
+            );
+            buf.extend_from_slice(line.as_bytes());
+            i += 1;
+        }
+        buf.truncate(len);
+        buf
+    }
+
+    /// Incompressible bytes from a xorshift generator.
+    pub(super) fn noise(len: usize) -> Vec<u8> {
+        let mut rng = XorShift64::new(0x2545f4914f6cdd1d);
+        (0..len).map(|_| (rng.next_u64() >> 56) as u8).collect()
+    }
+
+    #[test]
+    fn compress_table() {
+        assert_eq!(SIOCompress::ZLE.info().level, 64);
+        assert_eq!(SIOCompress::GZIP_1.info().level, 1);
+        assert_eq!(SIOCompress::GZIP_9.info().level, 9);
+        for c in [
+            SIOCompress::LZJB,
+            SIOCompress::GZIP_9,
+            SIOCompress::ZLE,
+            SIOCompress::LZ4,
+        ] {
+            assert!(c.info().compress.is_some() && c.info().decompress.is_some());
+        }
+        for c in [
+            SIOCompress::INHERIT,
+            SIOCompress::ON,
+            SIOCompress::OFF,
+            SIOCompress::EMPTY,
+        ] {
+            assert!(c.info().compress.is_none());
+        }
+        assert_eq!(compress_data(SIOCompress::FUNCTIONS, &logs(512)), None);
+    }
+
+    #[test]
+    fn compress_block_roundtrip() {
+        let src = logs(128 * 1024);
+        for c in [
+            SIOCompress::LZJB,
+            SIOCompress::GZIP_1,
+            SIOCompress::GZIP_9,
+            SIOCompress::LZ4,
+        ] {
+            let blk = compress_block(c, &src, 4096);
+            assert_eq!(blk.compress, c);
+            assert_eq!(blk.psize % 4096, 0);
+            assert_eq!(blk.data.len(), blk.psize);
+            assert!(blk.psize <= src.len() / 2);
+
+            let mut out = vec![0u8; src.len()];
+            decompress_data(blk.compress, &blk.data, &mut out).unwrap();
+            assert_eq!(out, src);
+        }
+    }
+
+    #[test]
+    fn compress_block_policy() {
+        // All zeros: nothing to store.
+        let blk = compress_block(SIOCompress::LZ4, &[0u8; 8192], 512);
+        assert_eq!(blk.compress, SIOCompress::EMPTY);
+        assert_eq!(blk.psize, 0);
+        let mut out = [0xffu8; 8192];
+        decompress_data(blk.compress, &blk.data, &mut out).unwrap();
+        assert!(out.iter().all(|&b| b == 0));
+
+        // Incompressible data is stored as is.
+        let src = noise(8192);
+        let blk = compress_block(SIOCompress::LZ4, &src, 512);
+        assert_eq!(blk.compress, SIOCompress::OFF);
+        assert_eq!(blk.psize, src.len());
+        assert_eq!(blk.data, src);
+
+        // Compressible, but rounding up to the allocation size eats the gain.
+        let mut src = logs(4096);
+        src[2048..].copy_from_slice(&noise(2048));
+        assert_eq!(
+            compress_block(SIOCompress::LZ4, &src, 512).compress,
+            SIOCompress::LZ4
+        );
+        assert_eq!(
+            compress_block(SIOCompress::LZ4, &src, 4096).compress,
+            SIOCompress::OFF
+        );
+
+        // Just under 12.5% saved is not enough.
+        let mut src = noise(4096);
+        src[..400].fill(0);
+        assert!(compress_data(SIOCompress::ZLE, &src).is_none());
+        src[..600].fill(0);
+        assert!(compress_data(SIOCompress::ZLE, &src).is_some());
+    }
+
+    #[test]
+    fn decompress_by_blkptr() {
+        let src = logs(16384);
+        let blk = compress_block(SIOCompress::LZ4, &src, 512);
+        let mut bp = Blkptr::new();
+        blk.fill_blkptr(&mut bp, src.len());
+        assert_eq!(bp.get_compress(), u8::from(SIOCompress::LZ4) as u64);
+        assert_eq!(bp.get_psize(), blk.psize as u64);
+        assert_eq!(decompress_blkptr(&bp, &blk.data).unwrap(), src);
+
+        bp.set_compress(u8::from(SIOCompress::ZSTD) as u64);
+        assert_eq!(
+            decompress_blkptr(&bp, &blk.data),
+            Err(DecompressError::Unsupported(SIOCompress::ZSTD))
+        );
+        bp.set_compress(u8::from(SIOCompress::LZJB) as u64);
+        assert!(decompress_blkptr(&bp, &blk.data).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Zero-length encoding.
+//!
+//! Only runs of zeros are compressed. Each run starts with a length byte: a
+//! value below `n` introduces `value + 1` literal bytes, anything else a run
+//! of `value + 1 - n` zeros.
+
+use super::DecompressError;
+
+pub fn zle_compress(src: &[u8], dst: &mut [u8], n: i32) -> Option<usize> {
+    let n = n as usize;
+    let (mut s, mut d) = (0, 0);
+    while s < src.len() && d + 1 < dst.len() {
+        let first = s;
+        let len = d;
+        d += 1;
+        if src[s] == 0 {
+            let last = (s + 256 - n).min(src.len());
+            while s < last && src[s] == 0 {
+                s += 1;
+            }
+            dst[len] = (s - first - 1 + n) as u8;
+        } else {
+            if dst.len() - d < n {
+                break;
+            }
+            let last = (s + n).min(src.len());
+            while s + 1 < last && (src[s] | src[s + 1]) != 0 {
+                dst[d] = src[s];
+                d += 1;
+                s += 1;
+            }
+            if src[s] != 0 {
+                dst[d] = src[s];
+                d += 1;
+                s += 1;
+            }
+            dst[len] = (s - first - 1) as u8;
+        }
+    }
+    (s == src.len()).then_some(d)
+}
+
+pub fn zle_decompress(src: &[u8], dst: &mut [u8], n: i32) -> Result<(), DecompressError> {
+    let n = n as usize;
+    let (mut s, mut d) = (0, 0);
+    while s < src.len() && d < dst.len() {
+        let mut len = 1 + src[s] as usize;
+        s += 1;
+        if len <= n {
+            if s + len > src.len() || d + len > dst.len() {
+                return Err(DecompressError::Corrupt);
+            }
+            dst[d..d + len].copy_from_slice(&src[s..s + len]);
+            s += len;
+        } else {
+            len -= n;
+            if d + len > dst.len() {
+                return Err(DecompressError::Corrupt);
+            }
+            dst[d..d + len].fill(0);
+        }
+        d += len;
+    }
+    if d != dst.len() {
+        return Err(DecompressError::Corrupt);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: i32 = 64;
+
+    #[test]
+    fn zle_known_encoding() {
+        let mut src = vec![0u8; 10];
+        src.extend_from_slice(&[1, 2, 0, 3]);
+        src.extend_from_slice(&[0; 300]);
+        let mut dst = vec![0u8; src.len()];
+        let len = zle_compress(&src, &mut dst, N).unwrap();
+        // 10 zeros; 4 literals; 192 zeros (the longest run); 108 zeros.
+        assert_eq!(&dst[..len], &[9 + 64, 3, 1, 2, 0, 3, 255, 107 + 64]);
+
+        let mut out = vec![0xffu8; src.len()];
+        zle_decompress(&dst[..len], &mut out, N).unwrap();
+        assert_eq!(out, src);
+    }
+
+    #[test]
+    fn zle_incompressible() {
+        let src: Vec<u8> = (0..512u32).map(|i| (i % 255 + 1) as u8).collect();
+        let mut dst = vec![0u8; src.len() - src.len() / 8];
+        assert_eq!(zle_compress(&src, &mut dst, N), None);
+    }
+
+    #[test]
+    fn zle_corrupt() {
+        let mut out = [0u8; 16];
+        // Literal run past the end of the input.
+        assert_eq!(
+            zle_decompress(&[5, 1, 2], &mut out, N),
+            Err(DecompressError::Corrupt)
+        );
+        // Zero run past the end of the output.
+        assert_eq!(
+            zle_decompress(&[64 + 20], &mut out, N),
+            Err(DecompressError::Corrupt)
+        );
+        // Output not filled.
+        assert_eq!(
+            zle_decompress(&[64 + 3], &mut out, N),
+            Err(DecompressError::Corrupt)
+        );
+    }
+}
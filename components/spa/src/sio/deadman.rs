@@ -0,0 +1,521 @@
+//! Deadman detection of hung requests.
+//!
+//! A pool with a `Deadman` has each physical request tracked from the moment
+//! it enters the pipeline until it completes, along with the stage it is in,
+//! whether waiting in its vdev queue or stuck in the device. `check` scans
+//! the tracked requests, and any outstanding for longer than `ziotime` is
+//! reported as a `DeadmanEvent` and dealt with as `failmode` says. A
+//! `DeadmanScanner` runs `check` every `checktime`. Time comes from a
+//! `Clock`, so tests can move it along by hand.
+//!
+//! The thread doing a hung request is stuck in the device, so `Continue`
+//! cannot take the request back. It issues the same I/O again instead, in
+//! case only the first attempt was lost.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::pipeline::SIOStage;
+use super::request::{SIOFlags, SIOPool, SIOPriority, SIOType, SIO};
+
+/// Where the deadman gets the time from.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since some fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// The real, monotonic time.
+pub struct MonotonicClock(Instant);
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        MonotonicClock(Instant::now())
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Default)]
+pub struct ManualClock(Mutex<Duration>);
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// What to do about a hung request once it has been reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadmanFailmode {
+    /// Keep waiting for it; it is not reported again.
+    Wait,
+    /// Issue the I/O again, and do so and report it again each further
+    /// `ziotime` it stays hung.
+    Continue,
+    /// Panic. From a `DeadmanScanner` this aborts the process.
+    Panic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadmanTunables {
+    /// How long a request may be outstanding before it counts as hung.
+    pub ziotime: Duration,
+    /// How often a `DeadmanScanner` checks.
+    pub checktime: Duration,
+    pub failmode: DeadmanFailmode,
+}
+
+impl Default for DeadmanTunables {
+    fn default() -> Self {
+        DeadmanTunables {
+            ziotime: Duration::from_secs(300),
+            checktime: Duration::from_secs(60),
+            failmode: DeadmanFailmode::Wait,
+        }
+    }
+}
+
+/// A hung request, as reported by `Deadman::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadmanEvent {
+    pub vdev: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub io_type: SIOType,
+    pub stage: SIOStage,
+    /// How long the request had been outstanding.
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for DeadmanEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} hung for {}ms: vdev={} offset={:#x} size={:#x} stage={:?}",
+            self.io_type,
+            self.elapsed.as_millis(),
+            self.vdev,
+            self.offset,
+            self.size,
+            self.stage
+        )
+    }
+}
+
+/// What it takes to issue a tracked request again.
+struct Reissue {
+    pool: Arc<dyn SIOPool>,
+    priority: SIOPriority,
+    /// The data of a write.
+    data: Vec<u8>,
+}
+
+struct Tracked {
+    vdev: u64,
+    offset: u64,
+    size: u64,
+    io_type: SIOType,
+    stage: SIOStage,
+    since: Duration,
+    /// When the request was last reported, if it has been.
+    reported: Option<Duration>,
+    /// Kept only when the failmode is `Continue`.
+    reissue: Option<Reissue>,
+}
+
+impl Tracked {
+    /// A copy of the request, sent straight to the device.
+    fn reissue(&self) -> Option<SIO> {
+        let r = self.reissue.as_ref()?;
+        let mut sio = SIO::null(r.pool.clone()).vdev_child(
+            self.io_type,
+            self.vdev,
+            self.offset,
+            self.size,
+            r.data.clone(),
+        );
+        sio.priority = r.priority;
+        sio.flags |= SIOFlags::DONT_QUEUE | SIOFlags::REEXECUTED;
+        Some(sio)
+    }
+}
+
+pub struct Deadman {
+    tunables: DeadmanTunables,
+    clock: Arc<dyn Clock>,
+    next_id: AtomicU64,
+    tracked: Mutex<HashMap<u64, Tracked>>,
+    events: Mutex<Vec<DeadmanEvent>>,
+}
+
+impl Deadman {
+    pub fn new(tunables: DeadmanTunables) -> Self {
+        Deadman::with_clock(tunables, Arc::new(MonotonicClock::new()))
+    }
+
+    pub fn with_clock(tunables: DeadmanTunables, clock: Arc<dyn Clock>) -> Self {
+        Deadman {
+            tunables,
+            clock,
+            next_id: AtomicU64::new(0),
+            tracked: Mutex::new(HashMap::new()),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn tunables(&self) -> DeadmanTunables {
+        self.tunables
+    }
+
+    /// How many requests are being tracked.
+    pub fn in_flight(&self) -> usize {
+        self.tracked.lock().unwrap().len()
+    }
+
+    fn track(&self, sio: &SIO, stage: SIOStage) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let tracked = Tracked {
+            vdev: sio.vd.unwrap_or_default(),
+            offset: sio.offset,
+            size: sio.size,
+            io_type: sio.io_type,
+            stage,
+            since: self.clock.now(),
+            reported: None,
+            reissue: (self.tunables.failmode == DeadmanFailmode::Continue).then(|| Reissue {
+                pool: sio.pool.clone(),
+                priority: sio.priority,
+                data: sio.pdata.clone(),
+            }),
+        };
+        self.tracked.lock().unwrap().insert(id, tracked);
+        id
+    }
+
+    fn set_stage(&self, id: u64, stage: SIOStage) {
+        if let Some(t) = self.tracked.lock().unwrap().get_mut(&id) {
+            t.stage = stage;
+        }
+    }
+
+    fn untrack(&self, id: u64) {
+        self.tracked.lock().unwrap().remove(&id);
+    }
+
+    /// Reports the requests that have become hung since the last check,
+    /// oldest first, and applies `failmode` to them. The events are also
+    /// kept until `take_events`.
+    pub fn check(&self) -> Vec<DeadmanEvent> {
+        let now = self.clock.now();
+        let ziotime = self.tunables.ziotime;
+        let mut found = Vec::new();
+        let mut reissue = Vec::new();
+        for t in self.tracked.lock().unwrap().values_mut() {
+            let due = match (t.reported, self.tunables.failmode) {
+                (None, _) => t.since + ziotime,
+                (Some(at), DeadmanFailmode::Continue) => at + ziotime,
+                (Some(_), _) => continue,
+            };
+            if now < due {
+                continue;
+            }
+            t.reported = Some(now);
+            reissue.extend(t.reissue());
+            found.push(DeadmanEvent {
+                vdev: t.vdev,
+                offset: t.offset,
+                size: t.size,
+                io_type: t.io_type,
+                stage: t.stage,
+                elapsed: now - t.since,
+            });
+        }
+        found.sort_by_key(|e| Reverse(e.elapsed));
+        self.events.lock().unwrap().extend(&found);
+        for sio in reissue {
+            sio.nowait();
+        }
+
+        if self.tunables.failmode == DeadmanFailmode::Panic {
+            if let Some(e) = found.first() {
+                panic!("deadman: {}", e);
+            }
+        }
+        found
+    }
+
+    /// Takes the events reported so far.
+    pub fn take_events(&self) -> Vec<DeadmanEvent> {
+        mem::take(&mut *self.events.lock().unwrap())
+    }
+
+    /// Starts a thread running `check` every `checktime`.
+    pub fn start(self: &Arc<Self>) -> DeadmanScanner {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let (deadman, signal) = (self.clone(), stop.clone());
+        let thread = thread::Builder::new()
+            .name("deadman".into())
+            .spawn(move || {
+                let mut stopped = signal.0.lock().unwrap();
+                while !*stopped {
+                    let checktime = deadman.tunables.checktime;
+                    stopped = signal.1.wait_timeout(stopped, checktime).unwrap().0;
+                    if *stopped {
+                        break;
+                    }
+                    if panic::catch_unwind(AssertUnwindSafe(|| deadman.check())).is_err() {
+                        process::abort();
+                    }
+                }
+            })
+            .unwrap();
+        DeadmanScanner {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// The thread started by `Deadman::start`. Dropping it stops the thread.
+pub struct DeadmanScanner {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for DeadmanScanner {
+    fn drop(&mut self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl SIO {
+    /// Notes that a physical request is entering `stage`, tracking it from
+    /// its first stage on. By `DONE` its I/O is over, so it is no longer
+    /// tracked while its done callback runs.
+    pub(super) fn deadman_enter(&mut self, stage: SIOStage) {
+        if self.vd.is_none() || self.flags.contains(SIOFlags::REEXECUTED) {
+            return;
+        }
+        if stage == SIOStage::DONE {
+            self.deadman_exit();
+            return;
+        }
+        let pool = self.pool.clone();
+        let deadman = match pool.deadman() {
+            Some(d) => d,
+            None => return,
+        };
+        match self.deadman_id {
+            Some(id) => deadman.set_stage(id, stage),
+            None => self.deadman_id = Some(deadman.track(self, stage)),
+        }
+    }
+
+    /// Stops tracking a request that has completed.
+    pub(super) fn deadman_exit(&mut self) {
+        if let Some(id) = self.deadman_id.take() {
+            if let Some(deadman) = self.pool.deadman() {
+                deadman.untrack(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::sio::test_pool::MemPool;
+    use crate::sio::{Fault, FaultHandler, FaultTarget};
+
+    fn pool_with(tunables: DeadmanTunables, clock: Arc<dyn Clock>) -> Arc<MemPool> {
+        let mut pool = MemPool::new();
+        pool.deadman = Some(Arc::new(Deadman::with_clock(tunables, clock)));
+        Arc::new(pool)
+    }
+
+    fn tunables(ziotime: Duration, failmode: DeadmanFailmode) -> DeadmanTunables {
+        DeadmanTunables {
+            ziotime,
+            checktime: Duration::from_millis(5),
+            failmode,
+        }
+    }
+
+    /// Writes to `vdev`, which the injector holds up for `delay`.
+    fn slow_write(pool: &Arc<MemPool>, vdev: u64, delay: Duration) -> mpsc::Receiver<()> {
+        pool.injector.add(FaultHandler::new(
+            Fault::Latency(delay),
+            FaultTarget::Vdev(vdev),
+        ));
+        let (tx, rx) = mpsc::channel();
+        let root = SIO::null(pool.clone());
+        let sio = root
+            .vdev_child(SIOType::WRITE, vdev, 0x2000, 512, vec![1; 512])
+            .on_done(move |_| tx.send(()).unwrap());
+        sio.nowait();
+        let deadman = pool.deadman.as_ref().unwrap();
+        while deadman.in_flight() == 0 {
+            thread::yield_now();
+        }
+        rx
+    }
+
+    #[test]
+    fn deadman_reports_hung_io() {
+        let clock = Arc::new(ManualClock::new());
+        let ziotime = Duration::from_secs(10);
+        let pool = pool_with(tunables(ziotime, DeadmanFailmode::Wait), clock.clone());
+        let deadman = pool.deadman.clone().unwrap();
+        let done = slow_write(&pool, 1, Duration::from_millis(200));
+
+        clock.advance(Duration::from_secs(9));
+        assert!(deadman.check().is_empty());
+        clock.advance(Duration::from_secs(2));
+        let events = deadman.check();
+        assert_eq!(
+            events,
+            vec![DeadmanEvent {
+                vdev: 1,
+                offset: 0x2000,
+                size: 512,
+                io_type: SIOType::WRITE,
+                stage: SIOStage::VDEV_IO,
+                elapsed: Duration::from_secs(11),
+            }]
+        );
+        assert_eq!(
+            events[0].to_string(),
+            "WRITE hung for 11000ms: vdev=1 offset=0x2000 size=0x200 stage=VDEV_IO"
+        );
+
+        // Waiting means it is reported once.
+        clock.advance(ziotime * 3);
+        assert!(deadman.check().is_empty());
+        assert_eq!(deadman.take_events(), events);
+
+        done.recv().unwrap();
+        assert_eq!(deadman.in_flight(), 0);
+        assert_eq!(pool.read_raw(1, 0x2000), Some(vec![1; 512]));
+    }
+
+    #[test]
+    fn deadman_continue_reports_again() {
+        let clock = Arc::new(ManualClock::new());
+        let ziotime = Duration::from_secs(10);
+        let pool = pool_with(tunables(ziotime, DeadmanFailmode::Continue), clock.clone());
+        let deadman = pool.deadman.clone().unwrap();
+        let done = slow_write(&pool, 2, Duration::from_millis(200));
+
+        clock.advance(ziotime);
+        assert_eq!(deadman.check().len(), 1);
+        clock.advance(ziotime / 2);
+        assert!(deadman.check().is_empty());
+        clock.advance(ziotime / 2);
+        let events = deadman.check();
+        assert_eq!(events[0].elapsed, ziotime * 2);
+
+        done.recv().unwrap();
+        clock.advance(ziotime);
+        assert!(deadman.check().is_empty());
+    }
+
+    #[test]
+    fn deadman_continue_reissues() {
+        let clock = Arc::new(ManualClock::new());
+        let ziotime = Duration::from_secs(10);
+        let pool = pool_with(tunables(ziotime, DeadmanFailmode::Continue), clock.clone());
+        let deadman = pool.deadman.clone().unwrap();
+        let done = slow_write(&pool, 2, Duration::from_millis(200));
+        let (handler, _) = pool.injector.list()[0];
+
+        // Each report sends the write to the device again. The copies are
+        // not tracked, so only the original is reported.
+        clock.advance(ziotime);
+        assert_eq!(deadman.check().len(), 1);
+        clock.advance(ziotime);
+        assert_eq!(deadman.check().len(), 1);
+        assert_eq!(deadman.in_flight(), 1);
+
+        done.recv().unwrap();
+        pool.taskq().wait();
+        assert_eq!(pool.injector.hits(handler), Some(3));
+        assert_eq!(deadman.in_flight(), 0);
+        assert_eq!(pool.read_raw(2, 0x2000), Some(vec![1; 512]));
+
+        // Waiting never issues anything again.
+        let pool = pool_with(tunables(ziotime, DeadmanFailmode::Wait), clock.clone());
+        let deadman = pool.deadman.clone().unwrap();
+        let done = slow_write(&pool, 2, Duration::from_millis(50));
+        clock.advance(ziotime);
+        assert_eq!(deadman.check().len(), 1);
+        done.recv().unwrap();
+        pool.taskq().wait();
+        assert_eq!(pool.injector.hits(handler), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "deadman: READ hung")]
+    fn deadman_panics() {
+        let clock = Arc::new(ManualClock::new());
+        let ziotime = Duration::from_secs(1);
+        let pool = pool_with(tunables(ziotime, DeadmanFailmode::Panic), clock.clone());
+        let deadman = pool.deadman.clone().unwrap();
+
+        let sio = SIO::null(pool.clone()).vdev_child(SIOType::READ, 0, 0, 512, Vec::new());
+        deadman.track(&sio, SIOStage::VDEV_IO);
+        clock.advance(ziotime);
+        deadman.check();
+    }
+
+    #[test]
+    fn deadman_scanner() {
+        let ziotime = Duration::from_millis(20);
+        let clock = Arc::new(MonotonicClock::new());
+        let pool = pool_with(tunables(ziotime, DeadmanFailmode::Wait), clock);
+        let deadman = pool.deadman.clone().unwrap();
+        let scanner = deadman.start();
+        let done = slow_write(&pool, 3, Duration::from_millis(300));
+
+        done.recv().unwrap();
+        drop(scanner);
+        let events = deadman.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].vdev, 3);
+        assert!(events[0].elapsed >= ziotime);
+    }
+}
@@ -0,0 +1,408 @@
+//! Gang blocks.
+//!
+//! When no contiguous region is large enough for a block, it is written as a
+//! gang: every DVA of the bp has the G bit set and points at a copy of a
+//! gang header, which holds up to three child bps. The block's data is the
+//! concatenation of the children's data, in order. Children may themselves
+//! be gangs, so a badly fragmented pool produces a tree of headers.
+
+use std::fmt;
+use std::io;
+
+use super::checksum::{
+    checksum_compute_embedded, checksum_verify, checksum_verify_embedded, gang_verifier,
+    ChecksumTemplates, SIO_ECK_SIZE,
+};
+use super::SIOChecksum;
+use crate::blkptr::blkptr::{read_word, write_word, Blkptr, Dva};
+use crate::blkptr::checksum::ChecksumMismatch;
+use crate::blkptr::error::BlkptrError;
+use crate::blkptr::{SPA_BLKPTRSIZE, SPA_GANGBLOCKSIZE};
+
+pub const SPA_GBH_NBLKPTRS: usize = (SPA_GANGBLOCKSIZE - SIO_ECK_SIZE) / SPA_BLKPTRSIZE;
+pub const SPA_GBH_FILLER: usize =
+    (SPA_GANGBLOCKSIZE - SIO_ECK_SIZE - SPA_GBH_NBLKPTRS * SPA_BLKPTRSIZE) / 8;
+
+/// On-disk gang header: the child bps, unused filler, and an embedded
+/// `GANG_HEADER` checksum in the last `SIO_ECK_SIZE` bytes. Unused child
+/// slots are holes.
+#[derive(Debug, Clone)]
+pub struct GangHeader {
+    pub gh_bp: Vec<Blkptr>,
+    pub gh_filler: [u64; SPA_GBH_FILLER],
+}
+
+impl GangHeader {
+    pub fn new() -> Self {
+        GangHeader {
+            gh_bp: (0..SPA_GBH_NBLKPTRS).map(|_| Blkptr::new()).collect(),
+            gh_filler: [0; SPA_GBH_FILLER],
+        }
+    }
+
+    /// Encodes the header to be written at the DVAs of `gang_bp`. The DVAs
+    /// and birth of `gang_bp` must already be set, since the checksum is
+    /// bound to them.
+    pub fn to_bytes(&self, gang_bp: &Blkptr, tmpls: &ChecksumTemplates) -> [u8; SPA_GANGBLOCKSIZE] {
+        let mut buf = [0u8; SPA_GANGBLOCKSIZE];
+        for (i, bp) in self.gh_bp.iter().enumerate() {
+            buf[i * SPA_BLKPTRSIZE..(i + 1) * SPA_BLKPTRSIZE].copy_from_slice(&bp.to_bytes(false));
+        }
+        let filler = &mut buf[SPA_GBH_NBLKPTRS * SPA_BLKPTRSIZE..];
+        for (i, w) in self.gh_filler.iter().enumerate() {
+            write_word(filler, i, false, *w);
+        }
+        checksum_compute_embedded(
+            SIOChecksum::GANG_HEADER,
+            &mut buf,
+            &gang_verifier(gang_bp),
+            tmpls,
+        );
+        buf
+    }
+
+    /// Decodes and verifies a header read through `gang_bp`. Headers written
+    /// by a host of the other endianness are swapped.
+    pub fn from_bytes(
+        buf: &[u8; SPA_GANGBLOCKSIZE],
+        gang_bp: &Blkptr,
+        tmpls: &ChecksumTemplates,
+    ) -> Result<Self, ChecksumMismatch> {
+        let byteswap = checksum_verify_embedded(
+            SIOChecksum::GANG_HEADER,
+            buf,
+            &gang_verifier(gang_bp),
+            tmpls,
+        )?;
+
+        let mut gh = GangHeader::new();
+        for (i, bp) in gh.gh_bp.iter_mut().enumerate() {
+            let mut b = [0u8; SPA_BLKPTRSIZE];
+            b.copy_from_slice(&buf[i * SPA_BLKPTRSIZE..(i + 1) * SPA_BLKPTRSIZE]);
+            *bp = Blkptr::from_bytes(&b, byteswap);
+        }
+        let filler = &buf[SPA_GBH_NBLKPTRS * SPA_BLKPTRSIZE..];
+        for (i, w) in gh.gh_filler.iter_mut().enumerate() {
+            *w = read_word(filler, i, byteswap);
+        }
+        Ok(gh)
+    }
+}
+
+impl Default for GangHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Errors reported while reading a gang block.
+#[derive(Debug)]
+pub enum GangError {
+    /// The bp passed to `gang_read` is not a gang bp.
+    NotGang,
+    /// A block has no DVA to read from.
+    NoDva,
+    Io(io::Error),
+    /// A gang header or member failed verification.
+    Checksum(ChecksumMismatch),
+    /// A member bp is malformed.
+    Blkptr(BlkptrError),
+    /// The members do not add up to the size of the block they make up.
+    Size {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for GangError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GangError::NotGang => write!(f, "not a gang block"),
+            GangError::NoDva => write!(f, "block has no valid dva"),
+            GangError::Io(e) => write!(f, "gang read failed: {}", e),
+            GangError::Checksum(e) => write!(f, "{}", e),
+            GangError::Blkptr(e) => write!(f, "bad gang member: {}", e),
+            GangError::Size { expected, actual } => write!(
+                f,
+                "gang members hold {:#x} bytes, expected {:#x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GangError {}
+
+impl From<io::Error> for GangError {
+    fn from(e: io::Error) -> Self {
+        GangError::Io(e)
+    }
+}
+
+impl From<ChecksumMismatch> for GangError {
+    fn from(e: ChecksumMismatch) -> Self {
+        GangError::Checksum(e)
+    }
+}
+
+impl From<BlkptrError> for GangError {
+    fn from(e: BlkptrError) -> Self {
+        GangError::Blkptr(e)
+    }
+}
+
+/// Tries each copy of a block in turn and returns the first one `f` accepts,
+/// or the last error if none is.
+fn try_dvas<T, F>(bp: &Blkptr, mut f: F) -> Result<T, GangError>
+where
+    F: FnMut(&Dva) -> Result<T, GangError>,
+{
+    let mut err = GangError::NoDva;
+    for dva in bp.blk_dva.iter().take(bp.get_ndvas() as usize) {
+        if !dva.is_valid() {
+            continue;
+        }
+        match f(dva) {
+            Ok(v) => return Ok(v),
+            Err(e) => err = e,
+        }
+    }
+    Err(err)
+}
+
+fn check_size(expected: usize, actual: usize) -> Result<(), GangError> {
+    if expected != actual {
+        return Err(GangError::Size {
+            expected: expected as u64,
+            actual: actual as u64,
+        });
+    }
+    Ok(())
+}
+
+fn verify_data(bp: &Blkptr, data: &[u8], tmpls: &ChecksumTemplates) -> Result<(), GangError> {
+    checksum_verify(
+        bp.get_checksum()?,
+        data,
+        bp.should_byteswap(),
+        &bp.blk_cksum,
+        tmpls,
+    )?;
+    Ok(())
+}
+
+/// Reads and verifies the PSIZE bytes of any bp, gang or not.
+pub(crate) fn read_block<F>(
+    bp: &Blkptr,
+    tmpls: &ChecksumTemplates,
+    read: &mut F,
+) -> Result<Vec<u8>, GangError>
+where
+    F: FnMut(&Dva, usize) -> io::Result<Vec<u8>>,
+{
+    let psize = bp.get_psize() as usize;
+    if !bp.is_gang() {
+        return try_dvas(bp, |dva| {
+            let data = read(dva, psize)?;
+            check_size(psize, data.len())?;
+            verify_data(bp, &data, tmpls)?;
+            Ok(data)
+        });
+    }
+
+    let gh = try_dvas(bp, |dva| {
+        let buf = read(dva, SPA_GANGBLOCKSIZE)?;
+        check_size(SPA_GANGBLOCKSIZE, buf.len())?;
+        let mut b = [0u8; SPA_GANGBLOCKSIZE];
+        b.copy_from_slice(&buf);
+        Ok(GangHeader::from_bytes(&b, bp, tmpls)?)
+    })?;
+
+    let mut data = Vec::with_capacity(psize);
+    for child in gh.gh_bp.iter().filter(|c| !c.is_hole()) {
+        data.extend(read_block(child, tmpls, read)?);
+    }
+    check_size(psize, data.len())?;
+    verify_data(bp, &data, tmpls)?;
+    Ok(data)
+}
+
+/// Reassembles the block behind gang bp `bp` from its members and returns
+/// its PSIZE bytes of data, verified against the bp's checksum. `read`
+/// fetches `size` bytes at a DVA; the G bit of the DVA tells whether it is a
+/// header or data. Each header and member is read from the first of its
+/// copies that verifies.
+pub fn gang_read<F>(
+    bp: &Blkptr,
+    tmpls: &ChecksumTemplates,
+    mut read: F,
+) -> Result<Vec<u8>, GangError>
+where
+    F: FnMut(&Dva, usize) -> io::Result<Vec<u8>>,
+{
+    if !bp.is_gang() {
+        return Err(GangError::NotGang);
+    }
+    read_block(bp, tmpls, &mut read)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::blkptr::blkptr::SIOCheckSumSalt;
+    use crate::blkptr::HOST_BYTEORDER;
+    use crate::sio::checksum::{fletcher_4_native, sha256};
+
+    /// A pretend vdev 0, keyed by offset.
+    type Disk = HashMap<u64, Vec<u8>>;
+
+    fn bp_at(offset: u64, psize: usize) -> Blkptr {
+        let mut bp = Blkptr::new();
+        bp.blk_dva[0].set_asize(psize as u64);
+        bp.blk_dva[0].set_offset(offset);
+        bp.set_lsize(psize as u64);
+        bp.set_psize(psize as u64);
+        bp.set_byteorder(HOST_BYTEORDER);
+        bp.set_birth(20, 0);
+        bp
+    }
+
+    fn member(disk: &mut Disk, offset: u64, data: &[u8]) -> Blkptr {
+        let mut bp = bp_at(offset, data.len());
+        bp.set_checksum(SIOChecksum::FLETCHER_4);
+        bp.blk_cksum = fletcher_4_native(data);
+        disk.insert(offset, data.to_vec());
+        bp
+    }
+
+    /// Writes a gang block for `data` whose header lives at each of `offsets`.
+    fn gang(
+        disk: &mut Disk,
+        offsets: &[u64],
+        data: &[u8],
+        children: Vec<Blkptr>,
+        tmpls: &ChecksumTemplates,
+    ) -> Blkptr {
+        let mut bp = bp_at(offsets[0], data.len());
+        for (d, off) in offsets.iter().enumerate() {
+            bp.blk_dva[d].set_asize(SPA_GANGBLOCKSIZE as u64);
+            bp.blk_dva[d].set_offset(*off);
+            bp.blk_dva[d].set_gang(1);
+        }
+        bp.set_checksum(SIOChecksum::SHA256);
+        bp.blk_cksum = sha256(data);
+
+        let mut gh = GangHeader::new();
+        for (slot, child) in gh.gh_bp.iter_mut().zip(children) {
+            *slot = child;
+        }
+        let hdr = gh.to_bytes(&bp, tmpls);
+        for off in offsets {
+            disk.insert(*off, hdr.to_vec());
+        }
+        bp
+    }
+
+    fn reader(disk: &Disk) -> impl FnMut(&Dva, usize) -> io::Result<Vec<u8>> + '_ {
+        |dva, size| {
+            let data = disk
+                .get(&dva.get_offset())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no block"))?;
+            assert_eq!(data.len(), size);
+            Ok(data.clone())
+        }
+    }
+
+    #[test]
+    fn gang_header_layout() {
+        assert_eq!(SPA_GBH_NBLKPTRS, 3);
+        assert_eq!(SPA_GBH_FILLER, 11);
+
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let mut disk = Disk::new();
+        let child = member(&mut disk, 0x10000, &[7; 1024]);
+        let bp = gang(
+            &mut disk,
+            &[0x8000],
+            &[7; 1024],
+            vec![child.clone()],
+            &tmpls,
+        );
+
+        let mut hdr = [0u8; SPA_GANGBLOCKSIZE];
+        hdr.copy_from_slice(&disk[&0x8000]);
+        let gh = GangHeader::from_bytes(&hdr, &bp, &tmpls).unwrap();
+        assert_eq!(gh.gh_bp[0].to_bytes(false), child.to_bytes(false));
+        assert!(gh.gh_bp[1].is_hole() && gh.gh_bp[2].is_hole());
+
+        // The header is bound to the bp that points at it.
+        let mut moved = bp.clone();
+        moved.blk_dva[0].set_offset(0xa000);
+        assert!(GangHeader::from_bytes(&hdr, &moved, &tmpls).is_err());
+    }
+
+    #[test]
+    fn gang_reassemble() {
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let data: Vec<u8> = (0..4096u32).map(|i| (i * 31 + 7) as u8).collect();
+        let mut disk = Disk::new();
+
+        // 4K split as 1.5K, then a nested gang of 1K + 512, then 1.5K.
+        let a = member(&mut disk, 0x10000, &data[..1536]);
+        let b1 = member(&mut disk, 0x20000, &data[1536..2560]);
+        let b2 = member(&mut disk, 0x30000, &data[2560..3072]);
+        let b = gang(
+            &mut disk,
+            &[0x9000],
+            &data[1536..3072],
+            vec![b1, b2],
+            &tmpls,
+        );
+        let c = member(&mut disk, 0x40000, &data[3072..]);
+        let bp = gang(&mut disk, &[0x8000, 0xa000], &data, vec![a, b, c], &tmpls);
+
+        assert!(bp.is_gang());
+        assert_eq!(gang_read(&bp, &tmpls, reader(&disk)).unwrap(), data);
+
+        // A damaged header copy is skipped in favour of the next DVA.
+        disk.get_mut(&0x8000).unwrap()[5] ^= 1;
+        assert_eq!(gang_read(&bp, &tmpls, reader(&disk)).unwrap(), data);
+
+        // A damaged member with no other copy fails the read.
+        disk.get_mut(&0x20000).unwrap()[0] ^= 1;
+        match gang_read(&bp, &tmpls, reader(&disk)) {
+            Err(GangError::Checksum(e)) => assert_eq!(e.checksum, SIOChecksum::FLETCHER_4),
+            r => panic!("unexpected {:?}", r.map(|d| d.len())),
+        }
+    }
+
+    #[test]
+    fn gang_read_errors() {
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let mut disk = Disk::new();
+        let plain = member(&mut disk, 0x10000, &[1; 512]);
+        assert!(matches!(
+            gang_read(&plain, &tmpls, reader(&disk)),
+            Err(GangError::NotGang)
+        ));
+
+        // Members that do not cover the whole block.
+        let child = member(&mut disk, 0x20000, &[2; 512]);
+        let bp = gang(&mut disk, &[0x8000], &[2; 1024], vec![child], &tmpls);
+        assert!(matches!(
+            gang_read(&bp, &tmpls, reader(&disk)),
+            Err(GangError::Size {
+                expected: 1024,
+                actual: 512
+            })
+        ));
+
+        disk.clear();
+        assert!(matches!(
+            gang_read(&bp, &tmpls, reader(&disk)),
+            Err(GangError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,693 @@
+//! Fault injection.
+//!
+//! A pool may carry an `Injector` holding fault handlers. Before physical
+//! I/O is done every handler matching it gets a chance to fire, at its
+//! frequency: it may delay the I/O, fail it with EIO, or fail it as if the
+//! device had gone away. After a read, checksum handlers flip bits in what
+//! was read. This is how self-healing and error reporting are tested without
+//! bad disks.
+//!
+//! Handlers have a text form, `fault=io vdev=1 type=read freq=25`, which
+//! `Injector::load` reads. A running pool can follow a file of them with an
+//! `InjectWatcher`; the `stone inject` command edits that file.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sys::XorShift64;
+
+use super::request::{Bookmark, SIOPool, SIOType, SIO};
+
+const EIO: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail the I/O with EIO.
+    Io,
+    /// Flip this many bits in what a read returns.
+    Checksum(u32),
+    /// Hold the I/O up this long before doing it.
+    Latency(Duration),
+    /// Fail every I/O as if the device had been pulled.
+    DeviceGone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultTarget {
+    Vdev(u64),
+    /// I/O for the block at this bookmark.
+    Bookmark(Bookmark),
+    /// I/O overlapping `start..end` on `vdev`.
+    Range {
+        vdev: u64,
+        start: u64,
+        end: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultHandler {
+    pub fault: Fault,
+    pub target: FaultTarget,
+    /// The type of I/O to fault; `None` faults all of them.
+    pub io_type: Option<SIOType>,
+    /// How often the handler fires, in percent.
+    pub freq: u32,
+}
+
+impl FaultHandler {
+    /// A handler firing on every I/O to `target`.
+    pub fn new(fault: Fault, target: FaultTarget) -> Self {
+        FaultHandler {
+            fault,
+            target,
+            io_type: None,
+            freq: 100,
+        }
+    }
+
+    pub fn matches(&self, sio: &SIO) -> bool {
+        let vd = match sio.vd {
+            Some(vd) => vd,
+            None => return false,
+        };
+        if self.io_type.is_some_and(|t| t != sio.io_type) {
+            return false;
+        }
+        match self.target {
+            FaultTarget::Vdev(v) => v == vd,
+            FaultTarget::Bookmark(zb) => zb == sio.bookmark,
+            FaultTarget::Range { vdev, start, end } => {
+                vdev == vd && sio.offset < end && start < sio.offset + sio.size
+            }
+        }
+    }
+}
+
+/// A registered handler and the number of times it has fired.
+struct Registered {
+    id: u64,
+    handler: FaultHandler,
+    hits: AtomicU64,
+}
+
+pub struct Injector {
+    handlers: Mutex<Vec<Registered>>,
+    next_id: AtomicU64,
+    rng: Mutex<XorShift64>,
+}
+
+impl Injector {
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Injector::with_seed(nanos)
+    }
+
+    /// An injector whose handlers fire in the same pattern every run.
+    pub fn with_seed(seed: u64) -> Self {
+        Injector {
+            handlers: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+            rng: Mutex::new(XorShift64::new(seed)),
+        }
+    }
+
+    fn register(&self, handler: FaultHandler) -> Registered {
+        Registered {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            handler,
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers `handler` and returns its id.
+    pub fn add(&self, handler: FaultHandler) -> u64 {
+        let r = self.register(handler);
+        let id = r.id;
+        self.handlers.lock().unwrap().push(r);
+        id
+    }
+
+    /// Removes handler `id`, returning false if there was none.
+    pub fn clear(&self, id: u64) -> bool {
+        let mut handlers = self.handlers.lock().unwrap();
+        let len = handlers.len();
+        handlers.retain(|r| r.id != id);
+        handlers.len() != len
+    }
+
+    /// Registers the handlers in `text`, one per line in their text form.
+    /// Blank lines and lines starting with `#` are skipped. Nothing is
+    /// registered unless every line parses.
+    pub fn load(&self, text: &str) -> Result<Vec<u64>, ParseFaultError> {
+        let handlers = parse_handlers(text)?;
+        Ok(handlers.into_iter().map(|h| self.add(h)).collect())
+    }
+
+    /// Replaces the handlers with those in `text`, read as by `load`.
+    /// Handlers registered already keep their id and hits. Nothing changes
+    /// unless every line parses.
+    pub fn sync(&self, text: &str) -> Result<(), ParseFaultError> {
+        let wanted = parse_handlers(text)?;
+        let mut handlers = self.handlers.lock().unwrap();
+        let mut old = mem::take(&mut *handlers);
+        for h in wanted {
+            match old.iter().position(|r| r.handler == h) {
+                Some(i) => handlers.push(old.remove(i)),
+                None => handlers.push(self.register(h)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear_all(&self) {
+        self.handlers.lock().unwrap().clear();
+    }
+
+    /// The registered handlers with their ids.
+    pub fn list(&self) -> Vec<(u64, FaultHandler)> {
+        let handlers = self.handlers.lock().unwrap();
+        handlers.iter().map(|r| (r.id, r.handler)).collect()
+    }
+
+    /// How many times handler `id` has fired.
+    pub fn hits(&self, id: u64) -> Option<u64> {
+        let handlers = self.handlers.lock().unwrap();
+        let r = handlers.iter().find(|r| r.id == id)?;
+        Some(r.hits.load(Ordering::Relaxed))
+    }
+
+    fn random(&self) -> u64 {
+        self.rng.lock().unwrap().next_u64()
+    }
+
+    /// The faults of the handlers matching `sio` that fire this time.
+    fn fire(&self, sio: &SIO, wanted: impl Fn(&Fault) -> bool) -> Vec<Fault> {
+        let handlers = self.handlers.lock().unwrap();
+        let mut faults = Vec::new();
+        for r in handlers.iter() {
+            let h = &r.handler;
+            if wanted(&h.fault) && h.matches(sio) && self.random() % 100 < h.freq as u64 {
+                r.hits.fetch_add(1, Ordering::Relaxed);
+                faults.push(h.fault);
+            }
+        }
+        faults
+    }
+
+    /// Applies the faults that act before `sio` is done: sleeps for any
+    /// latency, then returns the error of the first failing one.
+    pub fn before_io(&self, sio: &SIO) -> io::Result<()> {
+        let faults = self.fire(sio, |f| !matches!(f, Fault::Checksum(_)));
+        let mut res = Ok(());
+        for fault in faults {
+            match fault {
+                Fault::Latency(d) => thread::sleep(d),
+                Fault::Io if res.is_ok() => res = Err(io::Error::from_raw_os_error(EIO)),
+                Fault::DeviceGone if res.is_ok() => res = Err(io::ErrorKind::NotConnected.into()),
+                _ => {}
+            }
+        }
+        res
+    }
+
+    /// Flips bits in the data a completed read returned.
+    pub fn after_read(&self, sio: &mut SIO) {
+        if sio.io_type != SIOType::READ || sio.pdata.is_empty() {
+            return;
+        }
+        let nbits = sio.pdata.len() as u64 * 8;
+        for fault in self.fire(sio, |f| matches!(f, Fault::Checksum(_))) {
+            if let Fault::Checksum(n) = fault {
+                let mut flipped = Vec::new();
+                while flipped.len() < (n as u64).min(nbits) as usize {
+                    let bit = self.random() % nbits;
+                    if !flipped.contains(&bit) {
+                        sio.pdata[(bit / 8) as usize] ^= 1 << (bit % 8);
+                        flipped.push(bit);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for Injector {
+    fn default() -> Self {
+        Injector::new()
+    }
+}
+
+/// Parses handlers one per line, skipping blank lines and lines starting
+/// with `#`.
+pub fn parse_handlers(text: &str) -> Result<Vec<FaultHandler>, ParseFaultError> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+/// A thread keeping a pool's handlers in sync with a file. Dropping it
+/// stops the thread; the handlers stay as they were.
+pub struct InjectWatcher {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InjectWatcher {
+    /// Syncs the injector of `pool` with the file at `path` now and then
+    /// every `interval`. A missing file means no handlers. A file that
+    /// does not parse is ignored, leaving the handlers as they were, until
+    /// it is fixed. Pools without an injector are left alone.
+    pub fn start<P>(pool: Arc<P>, path: impl Into<PathBuf>, interval: Duration) -> Self
+    where
+        P: SIOPool + ?Sized + 'static,
+    {
+        let path = path.into();
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stop.clone();
+        let thread = thread::Builder::new()
+            .name("inject".into())
+            .spawn(move || {
+                let mut synced = None;
+                let mut stopped = signal.0.lock().unwrap();
+                while !*stopped {
+                    let text = match fs::read_to_string(&path) {
+                        Ok(text) => Some(text),
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => Some(String::new()),
+                        Err(_) => None,
+                    };
+                    if let (Some(text), Some(inj)) = (text, pool.injector()) {
+                        if synced.as_ref() != Some(&text) && inj.sync(&text).is_ok() {
+                            synced = Some(text);
+                        }
+                    }
+                    stopped = signal.1.wait_timeout(stopped, interval).unwrap().0;
+                }
+            })
+            .unwrap();
+        InjectWatcher {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for InjectWatcher {
+    fn drop(&mut self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFaultError(String);
+
+impl fmt::Display for ParseFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid fault handler: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseFaultError {}
+
+fn parse_err<T>(msg: impl Into<String>) -> Result<T, ParseFaultError> {
+    Err(ParseFaultError(msg.into()))
+}
+
+/// Parses a decimal number, or a hex one with a 0x prefix.
+fn num(s: &str) -> Result<u64, ParseFaultError> {
+    let res = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    res.or_else(|_| parse_err(format!("bad number '{}'", s)))
+}
+
+/// Like `num`, for values that must fit in 32 bits.
+fn num32(s: &str) -> Result<u32, ParseFaultError> {
+    u32::try_from(num(s)?).or_else(|_| parse_err(format!("'{}' is out of range", s)))
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Io => write!(f, "io"),
+            Fault::Checksum(bits) => write!(f, "checksum:{}", bits),
+            Fault::Latency(d) => write!(f, "delay:{}", d.as_millis()),
+            Fault::DeviceGone => write!(f, "gone"),
+        }
+    }
+}
+
+impl FromStr for Fault {
+    type Err = ParseFaultError;
+
+    /// Parses "io", "checksum:<bits>", "delay:<ms>" or "gone".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "io" => Ok(Fault::Io),
+            None if s == "gone" => Ok(Fault::DeviceGone),
+            Some(("checksum", bits)) => Ok(Fault::Checksum(num32(bits)?)),
+            Some(("delay", ms)) => Ok(Fault::Latency(Duration::from_millis(num(ms)?))),
+            _ => parse_err(format!("unknown fault '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for FaultTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultTarget::Vdev(vd) => write!(f, "vdev={}", vd),
+            FaultTarget::Bookmark(zb) => write!(
+                f,
+                "bookmark={}:{}:{}:{}",
+                zb.objset, zb.object, zb.level, zb.blkid
+            ),
+            FaultTarget::Range { vdev, start, end } => {
+                write!(f, "range={}:{:#x}-{:#x}", vdev, start, end)
+            }
+        }
+    }
+}
+
+impl fmt::Display for FaultHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let io_type = match self.io_type {
+            Some(SIOType::READ) => "read",
+            Some(SIOType::WRITE) => "write",
+            Some(SIOType::IOCTL) => "flush",
+            _ => "all",
+        };
+        write!(
+            f,
+            "fault={} {} type={} freq={}",
+            self.fault, self.target, io_type, self.freq
+        )
+    }
+}
+
+impl FromStr for FaultHandler {
+    type Err = ParseFaultError;
+
+    /// Parses space-separated `key=value` pairs in any order. `fault` and
+    /// one target are required; `type` defaults to all and `freq` to 100.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut fault, mut target, mut io_type, mut freq) = (None, None, None, 100);
+        for tok in s.split_whitespace() {
+            let (key, val) = match tok.split_once('=') {
+                Some(kv) => kv,
+                None => return parse_err(format!("expected key=value, got '{}'", tok)),
+            };
+            match key {
+                "fault" => fault = Some(val.parse()?),
+                "vdev" => target = Some(FaultTarget::Vdev(num(val)?)),
+                "bookmark" => {
+                    let f: Vec<&str> = val.split(':').collect();
+                    if f.len() != 4 {
+                        return parse_err(format!("bad bookmark '{}'", val));
+                    }
+                    let level = f[2]
+                        .parse()
+                        .or_else(|_| parse_err(format!("bad level '{}'", f[2])))?;
+                    target = Some(FaultTarget::Bookmark(Bookmark {
+                        objset: num(f[0])?,
+                        object: num(f[1])?,
+                        level,
+                        blkid: num(f[3])?,
+                    }));
+                }
+                "range" => {
+                    let (vdev, range) = match val.split_once(':') {
+                        Some(v) => v,
+                        None => return parse_err(format!("bad range '{}'", val)),
+                    };
+                    let (start, end) = match range.split_once('-') {
+                        Some((s, e)) => (num(s)?, num(e)?),
+                        None => return parse_err(format!("bad range '{}'", val)),
+                    };
+                    if start >= end {
+                        return parse_err(format!("empty range '{}'", val));
+                    }
+                    target = Some(FaultTarget::Range {
+                        vdev: num(vdev)?,
+                        start,
+                        end,
+                    });
+                }
+                "type" => {
+                    io_type = match val {
+                        "read" => Some(SIOType::READ),
+                        "write" => Some(SIOType::WRITE),
+                        "flush" => Some(SIOType::IOCTL),
+                        "all" => None,
+                        _ => return parse_err(format!("unknown type '{}'", val)),
+                    }
+                }
+                "freq" => {
+                    freq = num32(val)?;
+                    if freq > 100 {
+                        return parse_err(format!("freq {} is over 100", freq));
+                    }
+                }
+                _ => return parse_err(format!("unknown key '{}'", key)),
+            }
+        }
+        Ok(FaultHandler {
+            fault: fault.map_or_else(|| parse_err("no fault given"), Ok)?,
+            target: target.map_or_else(|| parse_err("no target given"), Ok)?,
+            io_type,
+            freq,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::dmu::DmuObjectType;
+    use crate::sio::request::{SIOError, SIOFlags, SIOPriority, WriteProps};
+    use crate::sio::test_pool::MemPool;
+    use crate::sio::{SIOChecksum, SIOCompress};
+
+    fn physical(pool: &Arc<MemPool>, io_type: SIOType, vd: u64, offset: u64) -> SIO {
+        let data = match io_type {
+            SIOType::WRITE => vec![0xa5; 512],
+            _ => Vec::new(),
+        };
+        SIO::null(pool.clone()).vdev_child(io_type, vd, offset, 512, data)
+    }
+
+    fn run(mut sio: SIO) -> SIO {
+        let _ = sio.wait();
+        sio
+    }
+
+    #[test]
+    fn inject_io_errors() {
+        let pool = Arc::new(MemPool::new());
+        for vd in 0..2 {
+            assert_eq!(run(physical(&pool, SIOType::WRITE, vd, 0)).error, None);
+        }
+        let mut h = FaultHandler::new(Fault::Io, FaultTarget::Vdev(1));
+        h.io_type = Some(SIOType::READ);
+        let id = pool.injector.add(h);
+
+        assert_eq!(run(physical(&pool, SIOType::READ, 0, 0)).error, None);
+        assert!(matches!(
+            run(physical(&pool, SIOType::READ, 1, 0)).error,
+            Some(SIOError::Io(_))
+        ));
+        assert_eq!(run(physical(&pool, SIOType::WRITE, 1, 0)).error, None);
+        assert_eq!(pool.injector.hits(id), Some(1));
+
+        pool.injector
+            .add(FaultHandler::new(Fault::DeviceGone, FaultTarget::Vdev(2)));
+        assert_eq!(
+            SIO::ioctl(pool.clone(), 2).wait(),
+            Err(SIOError::Io(io::ErrorKind::NotConnected))
+        );
+
+        assert!(pool.injector.clear(id));
+        assert!(!pool.injector.clear(id));
+        assert_eq!(run(physical(&pool, SIOType::READ, 1, 0)).error, None);
+    }
+
+    #[test]
+    fn inject_checksum_heals_from_other_copy() {
+        let pool = Arc::new(MemPool::new());
+        let mut props = WriteProps::new(
+            SIOChecksum::SHA256,
+            SIOCompress::OFF,
+            DmuObjectType::PLAIN_FILE_CONTENTS.into(),
+        );
+        props.copies = 2;
+        let data: Vec<u8> = (0..4096u32).map(|i| i as u8).collect();
+        let mut w = SIO::write(
+            pool.clone(),
+            10,
+            data.clone(),
+            props,
+            None,
+            SIOPriority::SYNC_WRITE,
+            SIOFlags::empty(),
+        );
+        w.wait().unwrap();
+        let dva = &w.bp.blk_dva[0];
+        let (vd, offset) = (dva.get_vdev(), dva.get_offset());
+
+        pool.injector.add(FaultHandler::new(
+            Fault::Checksum(3),
+            FaultTarget::Range {
+                vdev: vd,
+                start: offset,
+                end: offset + 1,
+            },
+        ));
+        let mut r = physical(&pool, SIOType::READ, vd, offset);
+        r.size = 4096;
+        let r = run(r);
+        let flipped: u32 = r
+            .pdata
+            .iter()
+            .zip(&data)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        assert_eq!(flipped, 3);
+
+        let mut r = SIO::read(
+            pool.clone(),
+            &w.bp,
+            SIOPriority::SYNC_READ,
+            SIOFlags::empty(),
+        );
+        r.wait().unwrap();
+        assert_eq!(r.dva, 1);
+        assert_eq!(r.data, data);
+    }
+
+    #[test]
+    fn inject_bookmark_and_freq() {
+        let pool = Arc::new(MemPool::new());
+        run(physical(&pool, SIOType::WRITE, 0, 0));
+        let zb = Bookmark {
+            objset: 54,
+            object: 3,
+            level: 0,
+            blkid: 7,
+        };
+        let mut h = FaultHandler::new(Fault::Io, FaultTarget::Bookmark(zb));
+        h.freq = 30;
+        let id = pool.injector.add(h);
+
+        for _ in 0..200 {
+            let mut sio = physical(&pool, SIOType::READ, 0, 0);
+            sio.bookmark = zb;
+            run(sio);
+            assert_eq!(run(physical(&pool, SIOType::READ, 0, 0)).error, None);
+        }
+        let hits = pool.injector.hits(id).unwrap();
+        assert!((30..90).contains(&hits), "{} hits", hits);
+    }
+
+    #[test]
+    fn inject_latency() {
+        let pool = Arc::new(MemPool::new());
+        pool.injector.add(FaultHandler::new(
+            Fault::Latency(Duration::from_millis(20)),
+            FaultTarget::Vdev(0),
+        ));
+        let start = Instant::now();
+        assert_eq!(run(physical(&pool, SIOType::WRITE, 0, 0)).error, None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn fault_handler_text() {
+        for s in [
+            "fault=io vdev=1 type=read freq=25",
+            "fault=checksum:4 bookmark=54:3:-1:0 type=all freq=100",
+            "fault=delay:250 range=2:0x400000-0x480000 type=write freq=100",
+            "fault=gone vdev=0 type=flush freq=5",
+        ] {
+            let h: FaultHandler = s.parse().unwrap();
+            assert_eq!(h.to_string(), s);
+        }
+
+        let h: FaultHandler = "vdev=3 fault=io".parse().unwrap();
+        assert_eq!(h, FaultHandler::new(Fault::Io, FaultTarget::Vdev(3)));
+
+        for bad in [
+            "fault=io",
+            "vdev=1",
+            "fault=io vdev=1 freq=101",
+            "fault=io vdev=1 freq=4294967296",
+            "fault=checksum:4294967297 vdev=1",
+            "fault=melt vdev=1",
+        ] {
+            assert!(bad.parse::<FaultHandler>().is_err(), "{}", bad);
+        }
+
+        let inj = Injector::with_seed(1);
+        let ids = inj
+            .load("# rig faults\nfault=io vdev=1\n\n  fault=gone vdev=2 freq=10\n")
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        let gone: FaultHandler = "fault=gone vdev=2 freq=10".parse().unwrap();
+        assert_eq!(inj.list()[1], (ids[1], gone));
+        assert!(inj.load("fault=io vdev=3\nfault=io").is_err());
+        assert_eq!(inj.list().len(), 2);
+    }
+
+    #[test]
+    fn inject_watches_handler_file() {
+        let path = std::env::temp_dir().join(format!("stone-{}-inject", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let pool = Arc::new(MemPool::new());
+        let wait_for = |n: usize| {
+            let start = Instant::now();
+            while pool.injector.list().len() != n {
+                assert!(start.elapsed() < Duration::from_secs(5), "never saw {}", n);
+                thread::sleep(Duration::from_millis(1));
+            }
+            pool.injector.list()
+        };
+
+        let watcher = InjectWatcher::start(pool.clone(), &path, Duration::from_millis(5));
+        fs::write(&path, "fault=io vdev=1\n").unwrap();
+        let first = wait_for(1);
+        let err = run(physical(&pool, SIOType::WRITE, 1, 0)).error;
+        assert!(matches!(err, Some(SIOError::Io(_))));
+
+        // Unchanged handlers keep their id and hits.
+        fs::write(&path, "fault=gone vdev=2\nfault=io vdev=1\n").unwrap();
+        let both = wait_for(2);
+        assert_eq!(both[1], first[0]);
+        assert_eq!(pool.injector.hits(first[0].0), Some(1));
+
+        // A bad file changes nothing; removing it clears everything.
+        fs::write(&path, "fault=io\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pool.injector.list(), both);
+        fs::remove_file(&path).unwrap();
+        wait_for(0);
+        drop(watcher);
+    }
+}
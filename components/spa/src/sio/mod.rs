@@ -1,7 +1,31 @@
+pub mod checksum;
+pub mod compress;
+pub mod deadman;
+pub mod gang;
+pub mod inject;
+pub mod nopwrite;
+pub mod pipeline;
+pub mod queue;
+pub mod read;
+pub mod request;
+pub mod taskq;
+#[cfg(test)]
+mod test_pool;
+pub mod tree;
+
+pub use deadman::*;
+pub use inject::*;
+pub use pipeline::*;
+pub use queue::*;
+pub use request::*;
+pub use taskq::*;
+pub use tree::*;
+
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
-#[derive(Debug, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 #[repr(u8)]
+#[allow(non_camel_case_types)]
 pub enum SIOChecksum {
     INHERIT,
     ON,
@@ -19,11 +43,87 @@ pub enum SIOChecksum {
     FUNCTIONS,
 }
 
-#[derive(Clone)]
-pub struct SIO {}
+impl SIOChecksum {
+    /// Name used when printing block pointers.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SIOChecksum::INHERIT => "inherit",
+            SIOChecksum::ON => "on",
+            SIOChecksum::OFF => "off",
+            SIOChecksum::LABEL => "label",
+            SIOChecksum::GANG_HEADER => "gang_header",
+            SIOChecksum::SILOG => "silog",
+            SIOChecksum::FLETCHER_2 => "fletcher2",
+            SIOChecksum::FLETCHER_4 => "fletcher4",
+            SIOChecksum::SHA256 => "sha256",
+            SIOChecksum::SILOG2 => "silog2",
+            SIOChecksum::NOPARITY => "noparity",
+            SIOChecksum::SHA512 => "sha512",
+            SIOChecksum::SKEIN => "skein",
+            SIOChecksum::FUNCTIONS => "functions",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..u8::from(SIOChecksum::FUNCTIONS))
+            .filter_map(|c| SIOChecksum::try_from(c).ok())
+            .find(|c| c.name() == name)
+    }
+}
+
+/// Compression functions, stored in the 7-bit comp field of a blkptr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+#[allow(non_camel_case_types)]
+pub enum SIOCompress {
+    INHERIT,
+    ON,
+    OFF,
+    LZJB,
+    EMPTY,
+    GZIP_1,
+    GZIP_2,
+    GZIP_3,
+    GZIP_4,
+    GZIP_5,
+    GZIP_6,
+    GZIP_7,
+    GZIP_8,
+    GZIP_9,
+    ZLE,
+    LZ4,
+    ZSTD,
+    FUNCTIONS,
+}
+
+impl SIOCompress {
+    /// Name used when printing block pointers.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SIOCompress::INHERIT => "inherit",
+            SIOCompress::ON => "on",
+            SIOCompress::OFF => "uncompressed",
+            SIOCompress::LZJB => "lzjb",
+            SIOCompress::EMPTY => "empty",
+            SIOCompress::GZIP_1 => "gzip-1",
+            SIOCompress::GZIP_2 => "gzip-2",
+            SIOCompress::GZIP_3 => "gzip-3",
+            SIOCompress::GZIP_4 => "gzip-4",
+            SIOCompress::GZIP_5 => "gzip-5",
+            SIOCompress::GZIP_6 => "gzip-6",
+            SIOCompress::GZIP_7 => "gzip-7",
+            SIOCompress::GZIP_8 => "gzip-8",
+            SIOCompress::GZIP_9 => "gzip-9",
+            SIOCompress::ZLE => "zle",
+            SIOCompress::LZ4 => "lz4",
+            SIOCompress::ZSTD => "zstd",
+            SIOCompress::FUNCTIONS => "functions",
+        }
+    }
 
-impl SIO {
-    pub fn new() -> Self {
-        SIO {}
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..u8::from(SIOCompress::FUNCTIONS))
+            .filter_map(|c| SIOCompress::try_from(c).ok())
+            .find(|c| c.name() == name)
     }
 }
@@ -0,0 +1,187 @@
+//! Nopwrite.
+//!
+//! When a block is overwritten with data whose cryptographically strong
+//! checksum matches the checksum already stored in its bp, the new data must
+//! be the same as the old. Rather than allocating and writing a new copy the
+//! write keeps the old bp. This only holds if the old block can still be
+//! freed: a block born before the most recent snapshot belongs to that
+//! snapshot and must not be replaced in place.
+
+use std::sync::atomic::Ordering;
+
+use super::{SIOChecksum, SIOCompress};
+use crate::blkptr::blkptr::Blkptr;
+use crate::stat::PoolStats;
+
+/// Whether writes with these properties may be nopwrites. Dedup already
+/// avoids writing duplicate data. Without compression, applications that
+/// overwrite a file to preallocate its space expect the rewrite to really
+/// allocate, so nopwrite is disabled there too.
+pub fn nopwrite_allowed(checksum: SIOChecksum, compress: SIOCompress, dedup: bool) -> bool {
+    !dedup && checksum.is_nopwrite_capable() && compress != SIOCompress::OFF
+}
+
+/// Checks whether the write of `bp`, whose checksum has already been
+/// computed, can reuse `bp_orig`, the bp it replaces. On a match `bp` is
+/// replaced by `bp_orig` and true is returned; the caller then skips
+/// allocation and the vdev write. `copies` is the number of DVAs the write
+/// wants and `prev_snap_txg` the birth txg of the newest snapshot.
+pub fn nop_write(
+    bp: &mut Blkptr,
+    bp_orig: &Blkptr,
+    copies: i32,
+    prev_snap_txg: u64,
+    stats: &PoolStats,
+) -> bool {
+    if bp_orig.is_hole() || bp_orig.is_embedded() || bp_orig.blk_birth <= prev_snap_txg {
+        return false;
+    }
+
+    // Encrypted blocks get a new IV and MAC on every write, so identical
+    // plaintext still gives different ciphertext.
+    if bp.is_encrypted() || bp_orig.is_encrypted() {
+        return false;
+    }
+
+    let checksum = match (bp.get_checksum(), bp_orig.get_checksum()) {
+        (Ok(c), Ok(o)) if c == o => c,
+        _ => return false,
+    };
+    if !checksum.is_nopwrite_capable()
+        || bp.get_compress() != bp_orig.get_compress()
+        || bp.get_dedup() != bp_orig.get_dedup()
+        || bp_orig.get_ndvas() != copies
+    {
+        return false;
+    }
+
+    if bp.blk_cksum != bp_orig.blk_cksum {
+        stats.nopwrite_misses.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+
+    *bp = bp_orig.clone();
+    stats.nopwrite_hits.fetch_add(1, Ordering::Relaxed);
+    stats
+        .nopwrite_bytes
+        .fetch_add(bp_orig.get_psize(), Ordering::Relaxed);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::checksum::{fletcher_4_native, sha256};
+    use crate::sio::compress::compress_block;
+
+    /// A bp for `data` as the write pipeline leaves it before allocation.
+    fn pending(data: &[u8], checksum: SIOChecksum, txg: u64) -> Blkptr {
+        let blk = compress_block(SIOCompress::LZ4, data, 512);
+        let mut bp = Blkptr::new();
+        blk.fill_blkptr(&mut bp, data.len());
+        bp.set_checksum(checksum);
+        bp.blk_cksum = match checksum {
+            SIOChecksum::SHA256 => sha256(&blk.data),
+            _ => fletcher_4_native(&blk.data),
+        };
+        bp.set_birth(txg, txg);
+        bp
+    }
+
+    /// `pending` after it has been allocated and written.
+    fn written(data: &[u8], checksum: SIOChecksum, txg: u64) -> Blkptr {
+        let mut bp = pending(data, checksum, txg);
+        let psize = bp.get_psize();
+        bp.blk_dva[0].set_asize(psize);
+        bp.blk_dva[0].set_offset(0x20_0000);
+        bp
+    }
+
+    fn data(seed: u8) -> Vec<u8> {
+        (0..8192u32).map(|i| (i / 64) as u8 ^ seed).collect()
+    }
+
+    #[test]
+    fn nopwrite_policy() {
+        assert!(nopwrite_allowed(
+            SIOChecksum::SHA256,
+            SIOCompress::LZ4,
+            false
+        ));
+        assert!(nopwrite_allowed(
+            SIOChecksum::SKEIN,
+            SIOCompress::ZLE,
+            false
+        ));
+        assert!(!nopwrite_allowed(
+            SIOChecksum::SHA256,
+            SIOCompress::LZ4,
+            true
+        ));
+        assert!(!nopwrite_allowed(
+            SIOChecksum::SHA256,
+            SIOCompress::OFF,
+            false
+        ));
+        assert!(!nopwrite_allowed(
+            SIOChecksum::FLETCHER_4,
+            SIOCompress::LZ4,
+            false
+        ));
+    }
+
+    #[test]
+    fn nopwrite_unchanged_block() {
+        let stats = PoolStats::new();
+        let orig = written(&data(1), SIOChecksum::SHA256, 10);
+
+        let mut bp = pending(&data(1), SIOChecksum::SHA256, 20);
+        assert!(nop_write(&mut bp, &orig, 1, 5, &stats));
+        assert_eq!(bp, orig);
+        assert_eq!(bp.blk_birth, 10);
+
+        let mut bp = pending(&data(2), SIOChecksum::SHA256, 20);
+        assert!(!nop_write(&mut bp, &orig, 1, 5, &stats));
+        assert_eq!(bp.blk_birth, 20);
+
+        let snap = stats.snapshot();
+        assert_eq!(snap.nopwrite_hits, 1);
+        assert_eq!(snap.nopwrite_misses, 1);
+        assert_eq!(snap.nopwrite_bytes, orig.get_psize());
+    }
+
+    #[test]
+    fn nopwrite_ineligible() {
+        let stats = PoolStats::new();
+        let orig = written(&data(1), SIOChecksum::SHA256, 10);
+
+        // Owned by a snapshot.
+        let mut bp = pending(&data(1), SIOChecksum::SHA256, 20);
+        assert!(!nop_write(&mut bp, &orig, 1, 10, &stats));
+
+        // Fletcher-4 collisions are too easy to trust.
+        let weak = written(&data(1), SIOChecksum::FLETCHER_4, 10);
+        let mut bp = pending(&data(1), SIOChecksum::FLETCHER_4, 20);
+        assert!(!nop_write(&mut bp, &weak, 1, 5, &stats));
+
+        // The old block used a different checksum.
+        let mut bp = pending(&data(1), SIOChecksum::SHA256, 20);
+        assert!(!nop_write(&mut bp, &weak, 1, 5, &stats));
+
+        // More copies are wanted than the old block has.
+        let mut bp = pending(&data(1), SIOChecksum::SHA256, 20);
+        assert!(!nop_write(&mut bp, &orig, 2, 5, &stats));
+
+        let mut bp = pending(&data(1), SIOChecksum::SHA256, 20);
+        bp.set_compress(u8::from(SIOCompress::ZLE) as u64);
+        assert!(!nop_write(&mut bp, &orig, 1, 5, &stats));
+
+        let mut hole = Blkptr::new();
+        hole.set_lsize(8192);
+        let mut bp = pending(&data(1), SIOChecksum::SHA256, 20);
+        assert!(!nop_write(&mut bp, &hole, 1, 5, &stats));
+
+        // None of these got as far as comparing checksums.
+        assert_eq!(stats.snapshot(), Default::default());
+    }
+}
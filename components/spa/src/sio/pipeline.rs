@@ -0,0 +1,754 @@
+//! The I/O pipeline.
+//!
+//! A request runs the stages set in its `pipeline` in bit order. Each stage
+//! is a plain function over the request, looked up in the pool's
+//! `StageTable`, so a stage can be replaced or run on its own. A stage that
+//! fails records the error and skips straight to `DONE`; a stage may also
+//! drop later stages itself, or send the request back to an earlier one.
+//!
+//! Logical requests do their I/O through children (see `tree`): one per
+//! copy written, the copy being read, or each member of a gang block.
+
+use std::io;
+use std::mem;
+
+use bitflags::bitflags;
+
+use super::checksum::{checksum_compute, checksum_verify};
+use super::compress::{compress_block, decompress_blkptr};
+use super::gang::GangHeader;
+use super::nopwrite::nop_write;
+use super::read::bp_byteswap;
+use super::request::{SIOError, SIOFlags, SIOType, SIO};
+use super::tree::ErrorPolicy;
+use super::{SIOChecksum, SIOCompress};
+use crate::blkptr::blkptr::{Blkptr, Dva};
+use crate::blkptr::{HOST_BYTEORDER, SPA_GANGBLOCKSIZE};
+
+bitflags! {
+    /// Pipeline stages, in the order they run.
+    pub struct SIOStage: u32 {
+        const BP_INIT = 1 << 0;
+        const COMPRESS = 1 << 1;
+        const ENCRYPT = 1 << 2;
+        const CHECKSUM_GENERATE = 1 << 3;
+        const NOP_WRITE = 1 << 4;
+        const DVA_ALLOCATE = 1 << 5;
+        const DVA_FREE = 1 << 6;
+        const DVA_CLAIM = 1 << 7;
+        const VDEV_IO = 1 << 8;
+        const VDEV_IO_DONE = 1 << 9;
+        const CHECKSUM_VERIFY = 1 << 10;
+        const DONE = 1 << 11;
+
+        /// Stages every request runs, even after an error.
+        const INTERLOCK = Self::DONE.bits;
+        /// Stages that do not start until every child has completed.
+        const CHILD_WAIT = Self::VDEV_IO_DONE.bits | Self::DONE.bits;
+        const READ_PIPELINE = Self::INTERLOCK.bits
+            | Self::BP_INIT.bits
+            | Self::VDEV_IO.bits
+            | Self::VDEV_IO_DONE.bits
+            | Self::CHECKSUM_VERIFY.bits;
+        const WRITE_PIPELINE = Self::INTERLOCK.bits
+            | Self::COMPRESS.bits
+            | Self::ENCRYPT.bits
+            | Self::CHECKSUM_GENERATE.bits
+            | Self::DVA_ALLOCATE.bits
+            | Self::VDEV_IO.bits
+            | Self::VDEV_IO_DONE.bits;
+        const FREE_PIPELINE = Self::INTERLOCK.bits | Self::BP_INIT.bits | Self::DVA_FREE.bits;
+        const CLAIM_PIPELINE = Self::INTERLOCK.bits | Self::BP_INIT.bits | Self::DVA_CLAIM.bits;
+        const IOCTL_PIPELINE = Self::INTERLOCK.bits | Self::VDEV_IO.bits;
+    }
+}
+
+/// Number of pipeline stages.
+pub const SIO_STAGES: usize = SIOStage::DONE.bits.trailing_zeros() as usize + 1;
+
+pub type StageFunc = fn(&mut SIO) -> Result<(), SIOError>;
+
+/// The function run for each stage, indexed by bit number.
+#[derive(Clone, Copy)]
+pub struct StageTable([StageFunc; SIO_STAGES]);
+
+pub static SIO_STAGE_TABLE: StageTable = StageTable([
+    sio_bp_init,
+    sio_write_compress,
+    sio_encrypt,
+    sio_checksum_generate,
+    sio_nop_write,
+    sio_dva_allocate,
+    sio_dva_free,
+    sio_dva_claim,
+    sio_vdev_io,
+    sio_vdev_io_done,
+    sio_checksum_verify,
+    sio_done,
+]);
+
+impl StageTable {
+    /// Panics unless `stage` is a single stage.
+    pub fn get(&self, stage: SIOStage) -> StageFunc {
+        self.0[stage_index(stage)]
+    }
+
+    pub fn set(&mut self, stage: SIOStage, func: StageFunc) {
+        self.0[stage_index(stage)] = func;
+    }
+}
+
+impl Default for StageTable {
+    fn default() -> Self {
+        SIO_STAGE_TABLE
+    }
+}
+
+fn stage_index(stage: SIOStage) -> usize {
+    assert_eq!(stage.bits().count_ones(), 1, "not a single stage");
+    stage.bits().trailing_zeros() as usize
+}
+
+impl SIO {
+    /// The first stage in the pipeline after the current one.
+    fn next_stage(&self) -> Option<SIOStage> {
+        let done = match self.stage.bits() {
+            0 => 0,
+            bits => (bits << 1) - 1,
+        };
+        let later = self.pipeline.bits() & !done;
+        SIOStage::from_bits(later & later.wrapping_neg()).filter(|s| !s.is_empty())
+    }
+
+    /// Records `err` and skips to the stages run regardless.
+    pub fn fail(&mut self, err: SIOError) {
+        self.error = Some(err);
+        self.pipeline &= SIOStage::INTERLOCK;
+    }
+
+    /// Makes `stage` the next to run, going back if it has already run.
+    pub fn redo_from(&mut self, stage: SIOStage) {
+        self.pipeline |= stage;
+        self.stage = SIOStage::from_bits_truncate(stage.bits() >> 1);
+    }
+}
+
+/// Runs `sio` until it completes, or until it reaches a stage that must wait
+/// for children still running. In that case it is parked, and run again
+/// from the taskq when the last of them completes. Physical I/O may likewise
+/// wait in its vdev queue before `VDEV_IO`, and is tracked by the pool's
+/// deadman, if it has one, until it completes.
+pub(super) fn run(mut sio: SIO) {
+    // Children may have been added before the request was issued.
+    sio.issue_children();
+    while let Some(stage) = sio.next_stage() {
+        sio.deadman_enter(stage);
+        if SIOStage::CHILD_WAIT.contains(stage) {
+            sio = match sio.wait_for_children() {
+                Some(sio) => sio,
+                None => return,
+            };
+            // A child's error may have cut the pipeline short.
+            if !sio.pipeline.contains(stage) {
+                continue;
+            }
+        }
+        if stage == SIOStage::VDEV_IO {
+            sio = match sio.vdev_queue_io() {
+                Some(sio) => sio,
+                None => return,
+            };
+        }
+        sio.stage = stage;
+        let func = sio.pool.stages().get(stage);
+        if let Err(e) = func(&mut sio) {
+            sio.fail(e);
+        }
+        if stage == SIOStage::VDEV_IO {
+            sio.vdev_queue_io_done();
+        }
+        sio.issue_children();
+    }
+    sio.deadman_exit();
+    sio.complete();
+}
+
+fn valid_dvas(bp: &Blkptr) -> impl Iterator<Item = &Dva> {
+    bp.blk_dva
+        .iter()
+        .take(bp.get_ndvas() as usize)
+        .filter(|d| d.is_valid())
+}
+
+/// `on` and `inherit` become the default algorithm.
+fn resolve_checksum(checksum: SIOChecksum) -> SIOChecksum {
+    match checksum {
+        SIOChecksum::INHERIT | SIOChecksum::ON => SIOChecksum::FLETCHER_4,
+        c => c,
+    }
+}
+
+fn resolve_compress(compress: SIOCompress) -> SIOCompress {
+    match compress {
+        SIOCompress::INHERIT | SIOCompress::ON => SIOCompress::LZ4,
+        c => c,
+    }
+}
+
+/// Handles bps that need no I/O: holes read as zeros, embedded bps read from
+/// their payload, and neither has space to free or claim.
+pub fn sio_bp_init(sio: &mut SIO) -> Result<(), SIOError> {
+    let bp = &sio.bp;
+    if bp.is_hole() || bp.is_embedded() {
+        match sio.io_type {
+            SIOType::READ if bp.is_hole() => sio.data = vec![0; bp.get_lsize() as usize],
+            SIOType::READ => sio.pdata = bp.get_embedded_payload()?,
+            _ => {}
+        }
+        sio.pipeline &= SIOStage::INTERLOCK;
+        return Ok(());
+    }
+    if sio.io_type == SIOType::READ
+        && bp.is_encrypted()
+        && !sio.flags.contains(SIOFlags::RAW_ENCRYPT)
+    {
+        return Err(SIOError::NotSupported("encryption"));
+    }
+    Ok(())
+}
+
+/// Compresses the data of a write and fills in the bp's sizes and
+/// properties. All-zero data becomes a hole, which is not written at all.
+pub fn sio_write_compress(sio: &mut SIO) -> Result<(), SIOError> {
+    let props = sio.props;
+    let lsize = sio.data.len() as u64;
+    let compress = resolve_compress(props.compress);
+
+    if sio.flags.contains(SIOFlags::RAW_COMPRESS) {
+        // The caller has recorded the logical size and compression.
+        sio.pdata = sio.data.clone();
+        sio.bp.set_psize(sio.pdata.len() as u64);
+    } else if compress == SIOCompress::OFF {
+        sio.pdata = sio.data.clone();
+        sio.bp.set_compress(u8::from(SIOCompress::OFF) as u64);
+        sio.bp.set_lsize(lsize);
+        sio.bp.set_psize(lsize);
+    } else {
+        let blk = compress_block(compress, &sio.data, sio.pool.min_alloc());
+        if blk.compress == SIOCompress::EMPTY {
+            sio.bp = Blkptr::new();
+            sio.bp.set_lsize(lsize);
+            sio.bp.set_type(props.object_type);
+            sio.bp.set_level(props.level);
+            sio.bp.set_birth(sio.txg, 0);
+            sio.pipeline &= SIOStage::INTERLOCK;
+            return Ok(());
+        }
+        blk.fill_blkptr(&mut sio.bp, sio.data.len());
+        sio.pdata = blk.data;
+    }
+
+    sio.bp.set_checksum(resolve_checksum(props.checksum));
+    sio.bp.set_type(props.object_type);
+    sio.bp.set_level(props.level);
+    sio.bp.set_dedup(props.dedup);
+    sio.bp.set_byteorder(HOST_BYTEORDER);
+    Ok(())
+}
+
+/// There is no encryption yet; only data the caller already encrypted can
+/// be written to an encrypted dataset.
+pub fn sio_encrypt(sio: &mut SIO) -> Result<(), SIOError> {
+    if sio.props.encrypt && !sio.flags.contains(SIOFlags::RAW_ENCRYPT) {
+        return Err(SIOError::NotSupported("encryption"));
+    }
+    Ok(())
+}
+
+pub fn sio_checksum_generate(sio: &mut SIO) -> Result<(), SIOError> {
+    let checksum = sio.bp.get_checksum()?;
+    sio.bp.blk_cksum = checksum_compute(checksum, &sio.pdata, false, sio.pool.checksum_templates())
+        .ok_or(SIOError::NotSupported("checksum"))?;
+    Ok(())
+}
+
+/// Keeps the overwritten bp if the data has not changed, skipping
+/// allocation and I/O.
+pub fn sio_nop_write(sio: &mut SIO) -> Result<(), SIOError> {
+    let orig = match &sio.bp_orig {
+        Some(bp) => bp,
+        None => return Ok(()),
+    };
+    if nop_write(
+        &mut sio.bp,
+        orig,
+        sio.props.copies as i32,
+        sio.props.prev_snap_txg,
+        sio.pool.stats(),
+    ) {
+        sio.flags |= SIOFlags::NOPWRITE;
+        sio.pipeline &= SIOStage::INTERLOCK;
+    }
+    Ok(())
+}
+
+pub fn sio_dva_allocate(sio: &mut SIO) -> Result<(), SIOError> {
+    let dvas = sio
+        .pool
+        .alloc(sio.bp.get_psize(), sio.props.copies as usize, sio.txg)?;
+    for (slot, dva) in sio.bp.blk_dva.iter_mut().zip(dvas) {
+        *slot = dva;
+    }
+    sio.bp.set_birth(sio.txg, 0);
+    Ok(())
+}
+
+pub fn sio_dva_free(sio: &mut SIO) -> Result<(), SIOError> {
+    for dva in valid_dvas(&sio.bp) {
+        sio.pool.free(dva, sio.txg);
+    }
+    Ok(())
+}
+
+pub fn sio_dva_claim(sio: &mut SIO) -> Result<(), SIOError> {
+    for dva in valid_dvas(&sio.bp) {
+        sio.pool.claim(dva, sio.txg)?;
+    }
+    Ok(())
+}
+
+/// Moves a read that failed on one copy on to the next, if there is one.
+/// The members of a gang block are read from whichever of their own copies
+/// works, so reading it again would not help.
+fn retry_read(sio: &mut SIO, err: SIOError) -> Result<(), SIOError> {
+    if !sio.bp.is_gang() && sio.dva + 1 < valid_dvas(&sio.bp).count() {
+        sio.dva += 1;
+        sio.redo_from(SIOStage::VDEV_IO);
+        return Ok(());
+    }
+    Err(err)
+}
+
+/// Reads the header of a gang block from the first copy that verifies, and
+/// adds a child reading each member. The header is read here rather than
+/// by a child since nothing else can happen until it is in.
+fn gang_issue(sio: &mut SIO) -> Result<(), SIOError> {
+    let mut header = Err(SIOError::NoDva);
+    for dva in valid_dvas(&sio.bp) {
+        header = sio
+            .pool
+            .read(dva.get_vdev(), dva.get_offset(), SPA_GANGBLOCKSIZE)
+            .and_then(|buf| {
+                <[u8; SPA_GANGBLOCKSIZE]>::try_from(buf.as_slice())
+                    .map_err(|_| io::ErrorKind::UnexpectedEof.into())
+            })
+            .map_err(SIOError::from)
+            .and_then(|buf| {
+                Ok(GangHeader::from_bytes(
+                    &buf,
+                    &sio.bp,
+                    sio.pool.checksum_templates(),
+                )?)
+            });
+        if header.is_ok() {
+            break;
+        }
+    }
+
+    let flags = (sio.flags & SIOFlags::INHERITED) | SIOFlags::GANG_CHILD | SIOFlags::RAW_COMPRESS;
+    for member in header?.gh_bp.iter().filter(|bp| !bp.is_hole()) {
+        let mut child = SIO::read(sio.pool.clone(), member, sio.priority, flags);
+        child.bookmark = sio.bookmark;
+        sio.add_child(child);
+    }
+    Ok(())
+}
+
+/// Does the I/O of a physical request, with any faults the pool injects. A
+/// logical read adds a child reading the copy it is trying, or the members
+/// of a gang block; a logical write adds a child writing each copy, and
+/// succeeds if any of them does.
+pub fn sio_vdev_io(sio: &mut SIO) -> Result<(), SIOError> {
+    if let Some(vd) = sio.vd {
+        let pool = sio.pool.clone();
+        let injector = pool.injector();
+        if let Some(inj) = injector {
+            inj.before_io(sio)?;
+        }
+        match sio.io_type {
+            SIOType::READ => sio.pdata = pool.read(vd, sio.offset, sio.size as usize)?,
+            SIOType::WRITE => pool.write(vd, sio.offset, &sio.pdata)?,
+            SIOType::IOCTL => pool.flush(vd)?,
+            _ => {}
+        }
+        if let Some(inj) = injector {
+            inj.after_read(sio);
+        }
+        return Ok(());
+    }
+
+    let psize = sio.bp.get_psize();
+    match sio.io_type {
+        SIOType::READ if sio.bp.is_gang() => gang_issue(sio)?,
+        SIOType::READ => {
+            let dva = valid_dvas(&sio.bp).nth(sio.dva).ok_or(SIOError::NoDva)?;
+            let mut child = sio.vdev_child(
+                SIOType::READ,
+                dva.get_vdev(),
+                dva.get_offset(),
+                psize,
+                Vec::new(),
+            );
+            // A failed copy is retried by the parent, not reported.
+            child.flags |= SIOFlags::DONT_PROPAGATE;
+            sio.add_child(child);
+        }
+        SIOType::WRITE => {
+            sio.child_errors = ErrorPolicy::AllFail;
+            let dvas: Vec<Dva> = valid_dvas(&sio.bp).cloned().collect();
+            for dva in dvas {
+                let data = sio.pdata.clone();
+                let child = sio.vdev_child(
+                    SIOType::WRITE,
+                    dva.get_vdev(),
+                    dva.get_offset(),
+                    psize,
+                    data,
+                );
+                sio.add_child(child);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Takes what the children of a logical read returned: the copy that was
+/// read, or the members of a gang block in order.
+pub fn sio_vdev_io_done(sio: &mut SIO) -> Result<(), SIOError> {
+    if sio.io_type != SIOType::READ || sio.vd.is_some() {
+        return Ok(());
+    }
+    let mut children = mem::take(&mut sio.children);
+    if sio.bp.is_gang() {
+        sio.pdata = children.into_iter().flat_map(|c| c.data).collect();
+        return Ok(());
+    }
+    match children.pop() {
+        Some(SIO { error: Some(e), .. }) => retry_read(sio, e),
+        Some(child) => {
+            sio.pdata = child.pdata;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Checks what a read returned against the bp, trying the next copy on a
+/// mismatch.
+pub fn sio_checksum_verify(sio: &mut SIO) -> Result<(), SIOError> {
+    let bp = &sio.bp;
+    let checksum = bp.get_checksum()?;
+    let res = checksum_verify(
+        checksum,
+        &sio.pdata,
+        bp.should_byteswap(),
+        &bp.blk_cksum,
+        sio.pool.checksum_templates(),
+    );
+    match res {
+        Ok(()) => Ok(()),
+        Err(e) => retry_read(sio, e.into()),
+    }
+}
+
+/// Decompresses what a read returned, swaps it into host byte order, and
+/// runs the done callback. This runs whether or not the request failed.
+pub fn sio_done(sio: &mut SIO) -> Result<(), SIOError> {
+    let logical = sio.vd.is_none();
+    if logical && sio.io_type == SIOType::READ && sio.error.is_none() && !sio.bp.is_hole() {
+        if sio.flags.contains(SIOFlags::RAW_COMPRESS) {
+            sio.data = mem::take(&mut sio.pdata);
+        } else {
+            match decompress_blkptr(&sio.bp, &sio.pdata) {
+                Ok(mut data) => match bp_byteswap(&sio.bp, &mut data) {
+                    Ok(()) => sio.data = data,
+                    Err(e) => sio.error = Some(e.into()),
+                },
+                Err(e) => sio.error = Some(e.into()),
+            }
+        }
+    }
+    if let Some(done) = sio.done.take() {
+        done(sio);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::dmu::DmuObjectType;
+    use crate::sio::checksum::{fletcher_4_byteswap, sha256};
+    use crate::sio::request::{SIOPriority, WriteProps};
+    use crate::sio::test_pool::MemPool;
+
+    fn props(copies: u8) -> WriteProps {
+        let mut props = WriteProps::new(
+            SIOChecksum::SHA256,
+            SIOCompress::LZ4,
+            DmuObjectType::PLAIN_FILE_CONTENTS.into(),
+        );
+        props.copies = copies;
+        props
+    }
+
+    fn text(len: usize) -> Vec<u8> {
+        b"the quick brown fox jumps over the lazy dog\n"
+            .iter()
+            .cycle()
+            .take(len)
+            .copied()
+            .collect()
+    }
+
+    fn write(pool: &Arc<MemPool>, txg: u64, data: Vec<u8>, props: WriteProps) -> SIO {
+        let mut sio = SIO::write(
+            pool.clone(),
+            txg,
+            data,
+            props,
+            None,
+            SIOPriority::SYNC_WRITE,
+            SIOFlags::empty(),
+        );
+        sio.wait().unwrap();
+        sio
+    }
+
+    fn read(pool: &Arc<MemPool>, bp: &Blkptr) -> SIO {
+        let mut sio = SIO::read(pool.clone(), bp, SIOPriority::SYNC_READ, SIOFlags::empty());
+        let _ = sio.wait();
+        sio
+    }
+
+    #[test]
+    fn sio_stages_alone() {
+        let pool = Arc::new(MemPool::new());
+        let data = text(16384);
+        let mut sio = SIO::write(
+            pool.clone(),
+            7,
+            data.clone(),
+            props(1),
+            None,
+            SIOPriority::ASYNC_WRITE,
+            SIOFlags::empty(),
+        );
+
+        sio_write_compress(&mut sio).unwrap();
+        assert_eq!(sio.bp.get_compress(), u8::from(SIOCompress::LZ4) as u64);
+        assert_eq!(sio.bp.get_lsize(), 16384);
+        assert_eq!(sio.bp.get_psize(), sio.pdata.len() as u64);
+        assert!(sio.pdata.len() < 1024);
+        assert_eq!(sio.bp.get_checksum(), Ok(SIOChecksum::SHA256));
+
+        sio_checksum_generate(&mut sio).unwrap();
+        assert_eq!(sio.bp.blk_cksum, sha256(&sio.pdata));
+
+        sio_dva_allocate(&mut sio).unwrap();
+        assert_eq!(sio.bp.get_ndvas(), 1);
+        assert_eq!(sio.bp.blk_birth, 7);
+        assert!(pool.disk.lock().unwrap().is_empty());
+
+        // The write itself goes to a child, one per copy.
+        sio_vdev_io(&mut sio).unwrap();
+        assert_eq!(sio.links.issue.len(), 1);
+        assert!(pool.disk.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sio_write_then_read() {
+        let pool = Arc::new(MemPool::new());
+        let data = text(32768);
+        let sio = write(&pool, 10, data.clone(), props(2));
+        assert_eq!(sio.stage, SIOStage::DONE);
+        assert_eq!(sio.bp.get_ndvas(), 2);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut r = SIO::read(
+            pool.clone(),
+            &sio.bp,
+            SIOPriority::SYNC_READ,
+            SIOFlags::empty(),
+        )
+        .on_done(move |sio| tx.send(sio.data.clone()).unwrap());
+        r.wait().unwrap();
+        assert_eq!(rx.try_recv().unwrap(), data);
+
+        // Raw reads return the block as stored.
+        let mut r = SIO::read(
+            pool.clone(),
+            &sio.bp,
+            SIOPriority::SYNC_READ,
+            SIOFlags::RAW_COMPRESS,
+        );
+        r.wait().unwrap();
+        assert_eq!(r.data, sio.pdata);
+    }
+
+    #[test]
+    fn sio_read_swaps_foreign_blocks() {
+        let pool = Arc::new(MemPool::new());
+        let values: Vec<u64> = (0..1024u64).map(|i| i * 0x0101_0101 + 7).collect();
+        let foreign: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.swap_bytes().to_ne_bytes())
+            .collect();
+        let mut p = WriteProps::new(
+            SIOChecksum::FLETCHER_4,
+            SIOCompress::OFF,
+            DmuObjectType::UINT64_OTHER.into(),
+        );
+        p.copies = 1;
+
+        // Make the block look as if a host of the other byte order wrote it.
+        let mut bp = write(&pool, 10, foreign.clone(), p).bp;
+        bp.set_byteorder(1 - HOST_BYTEORDER);
+        bp.blk_cksum = fletcher_4_byteswap(&foreign);
+        let sio = read(&pool, &bp);
+        assert_eq!(sio.error, None);
+        let words: Vec<u64> = sio
+            .data
+            .chunks_exact(8)
+            .map(|w| u64::from_ne_bytes(w.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, values);
+
+        // Raw reads return the block as stored.
+        let mut r = SIO::read(
+            pool.clone(),
+            &bp,
+            SIOPriority::SYNC_READ,
+            SIOFlags::RAW_COMPRESS,
+        );
+        r.wait().unwrap();
+        assert_eq!(r.data, foreign);
+
+        bp.set_type(crate::dmu::DMU_OT_NEWTYPE | 0x1f);
+        let mut r = SIO::read(pool.clone(), &bp, SIOPriority::SYNC_READ, SIOFlags::empty());
+        assert!(matches!(r.wait(), Err(SIOError::ObjectType(_))));
+    }
+
+    #[test]
+    fn sio_zeros_are_holes() {
+        let pool = Arc::new(MemPool::new());
+        let sio = write(&pool, 10, vec![0; 8192], props(1));
+        assert!(sio.bp.is_hole());
+        assert_eq!(sio.bp.get_lsize(), 8192);
+        assert!(pool.disk.lock().unwrap().is_empty());
+        assert_eq!(read(&pool, &sio.bp).data, vec![0; 8192]);
+
+        // Unless compression is off.
+        let mut p = props(1);
+        p.compress = SIOCompress::OFF;
+        let sio = write(&pool, 10, vec![0; 8192], p);
+        assert!(!sio.bp.is_hole());
+        assert_eq!(sio.bp.get_psize(), 8192);
+    }
+
+    #[test]
+    fn sio_read_tries_each_copy() {
+        let pool = Arc::new(MemPool::new());
+        let data = text(8192);
+        let bp = write(&pool, 10, data.clone(), props(2)).bp;
+
+        pool.corrupt(&bp.blk_dva[0]);
+        let sio = read(&pool, &bp);
+        assert_eq!(sio.error, None);
+        assert_eq!(sio.dva, 1);
+        assert_eq!(sio.data, data);
+
+        pool.corrupt(&bp.blk_dva[1]);
+        let sio = read(&pool, &bp);
+        assert!(matches!(sio.error, Some(SIOError::Checksum(_))));
+        assert_eq!(sio.stage, SIOStage::DONE);
+    }
+
+    #[test]
+    fn sio_nopwrite_keeps_bp() {
+        let pool = Arc::new(MemPool::new());
+        let mut p = props(1);
+        p.nopwrite = true;
+        let orig = write(&pool, 10, text(8192), p).bp;
+
+        let mut sio = SIO::write(
+            pool.clone(),
+            11,
+            text(8192),
+            p,
+            Some(&orig),
+            SIOPriority::ASYNC_WRITE,
+            SIOFlags::empty(),
+        );
+        sio.wait().unwrap();
+        assert!(sio.flags.contains(SIOFlags::NOPWRITE));
+        assert_eq!(sio.bp, orig);
+        assert_eq!(pool.stats.snapshot().nopwrite_hits, 1);
+        assert_eq!(pool.disk.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sio_free_and_errors() {
+        let pool = Arc::new(MemPool::new());
+        let bp = write(&pool, 10, text(8192), props(2)).bp;
+        SIO::free(pool.clone(), 12, &bp).wait().unwrap();
+        assert_eq!(pool.freed.lock().unwrap().len(), 2);
+        assert_eq!(
+            read(&pool, &bp).error,
+            Some(SIOError::Io(io::ErrorKind::NotFound))
+        );
+
+        let mut p = props(1);
+        p.encrypt = true;
+        let mut sio = SIO::write(
+            pool.clone(),
+            10,
+            text(8192),
+            p,
+            None,
+            SIOPriority::ASYNC_WRITE,
+            SIOFlags::empty(),
+        );
+        assert_eq!(sio.wait(), Err(SIOError::NotSupported("encryption")));
+        assert_eq!(sio.bp.get_ndvas(), 0);
+    }
+
+    #[test]
+    fn sio_replaced_stage() {
+        fn no_space(_: &mut SIO) -> Result<(), SIOError> {
+            Err(SIOError::NoSpace)
+        }
+
+        let mut pool = MemPool::new();
+        pool.stages.set(SIOStage::DVA_ALLOCATE, no_space);
+        let pool = Arc::new(pool);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut sio = SIO::write(
+            pool.clone(),
+            10,
+            text(8192),
+            props(1),
+            None,
+            SIOPriority::ASYNC_WRITE,
+            SIOFlags::empty(),
+        )
+        .on_done(move |sio| tx.send(sio.error.clone()).unwrap());
+        assert_eq!(sio.wait(), Err(SIOError::NoSpace));
+        assert_eq!(rx.try_recv().unwrap(), Some(SIOError::NoSpace));
+        assert!(pool.disk.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,663 @@
+//! Per-vdev I/O scheduling.
+//!
+//! Physical reads and writes wait in their vdev's queue until it lets them
+//! run. Each priority class has its own queue sorted by offset, and a number
+//! of requests it may have active at once: the class is always allowed its
+//! `min_active`, and up to its `max_active` while no class before it has
+//! anything waiting. Classes are considered in `SIOPriority` order, so
+//! synchronous I/O goes first.
+//!
+//! Scrub, trim, initializing and rebuild I/O is not interactive: nobody is
+//! waiting on it. While interactive I/O is active these classes only get a
+//! few requests through on credit, and once it stops they are kept to one
+//! request at a time for a while in case more arrives.
+//!
+//! When a request is issued, waiting reads or writes next to it on either
+//! side, whatever their class, are taken along and done as one larger I/O.
+//! Reads may also be joined across a small gap, which is read and thrown
+//! away: one longer read costs a disk less than two seeks. The aggregate is
+//! split back up when it completes, and if it fails each part is retried on
+//! its own.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::mem;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Mutex;
+
+use rb_tree::RBQueue;
+
+use super::pipeline::{run, SIOStage};
+use super::request::{SIOFlags, SIOPriority, SIOType, SIO, SIO_PRIORITY_NUM_QUEUEABLE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassLimits {
+    pub min_active: usize,
+    pub max_active: usize,
+}
+
+impl ClassLimits {
+    pub const fn new(min_active: usize, max_active: usize) -> Self {
+        ClassLimits {
+            min_active,
+            max_active,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VdevQueueLimits {
+    /// Limits for each class, indexed by priority.
+    pub class: [ClassLimits; SIO_PRIORITY_NUM_QUEUEABLE],
+    /// Requests active at once across all classes.
+    pub max_active: usize,
+    /// Non-interactive requests let through when interactive I/O starts,
+    /// and again each time an interactive request completes.
+    pub nia_credit: usize,
+    /// Non-interactive requests that must complete after interactive I/O
+    /// stops before those classes get their `max_active` back.
+    pub nia_delay: usize,
+    /// Largest I/O aggregation may build.
+    pub aggregation_limit: u64,
+    /// Largest gap between reads that may still be joined.
+    pub read_gap_limit: u64,
+}
+
+impl Default for VdevQueueLimits {
+    fn default() -> Self {
+        VdevQueueLimits {
+            class: [
+                ClassLimits::new(10, 10), // SYNC_READ
+                ClassLimits::new(10, 10), // SYNC_WRITE
+                ClassLimits::new(1, 3),   // ASYNC_READ
+                ClassLimits::new(2, 10),  // ASYNC_WRITE
+                ClassLimits::new(1, 3),   // SCRUB
+                ClassLimits::new(1, 2),   // TRIM
+                ClassLimits::new(1, 1),   // INITIALIZING
+                ClassLimits::new(1, 3),   // REBUILD
+            ],
+            max_active: 1000,
+            nia_credit: 5,
+            nia_delay: 5,
+            aggregation_limit: 1 << 20,
+            read_gap_limit: 32 << 10,
+        }
+    }
+}
+
+/// Whether a class has somebody waiting on it.
+fn is_interactive(p: SIOPriority) -> bool {
+    matches!(
+        p,
+        SIOPriority::SYNC_READ
+            | SIOPriority::SYNC_WRITE
+            | SIOPriority::ASYNC_READ
+            | SIOPriority::ASYNC_WRITE
+    )
+}
+
+/// A waiting request: its offset, then the order it arrived in.
+type Key = (u64, u64);
+
+type Compare = fn(&Key, &Key) -> Ordering;
+
+/// Sorts by offset, then by arrival so requests for the same offset are
+/// all kept.
+fn offset_compare(a: &Key, b: &Key) -> Ordering {
+    a.0.cmp(&b.0).then(a.1.cmp(&b.1))
+}
+
+/// Index into `QueueState::by_offset`.
+fn type_index(io_type: SIOType) -> usize {
+    match io_type {
+        SIOType::READ => 0,
+        _ => 1,
+    }
+}
+
+fn end(sio: &SIO) -> u64 {
+    sio.offset + sio.size
+}
+
+struct QueueState {
+    /// Waiting requests of each class.
+    queued: Vec<RBQueue<Key, Compare>>,
+    /// Waiting reads and writes, whatever their class, for finding
+    /// neighbours to aggregate.
+    by_offset: [BTreeSet<Key>; 2],
+    waiting: HashMap<u64, SIO>,
+    active: [usize; SIO_PRIORITY_NUM_QUEUEABLE],
+    total_active: usize,
+    /// Interactive requests active.
+    ia_active: usize,
+    /// See `VdevQueueLimits::nia_credit` and `nia_delay`.
+    nia_credit: usize,
+}
+
+impl QueueState {
+    /// Takes a waiting request out of every queue it is in.
+    fn take(&mut self, key: Key) -> SIO {
+        let sio = self.waiting.remove(&key.1).unwrap();
+        self.queued[sio.priority as usize].take(&key);
+        self.by_offset[type_index(sio.io_type)].remove(&key);
+        sio
+    }
+}
+
+pub struct VdevQueue {
+    limits: VdevQueueLimits,
+    seq: AtomicU64,
+    state: Mutex<QueueState>,
+}
+
+impl VdevQueue {
+    pub fn new(limits: VdevQueueLimits) -> Self {
+        let queued = (0..SIO_PRIORITY_NUM_QUEUEABLE)
+            .map(|_| RBQueue::new(offset_compare as Compare))
+            .collect();
+        VdevQueue {
+            limits,
+            seq: AtomicU64::new(0),
+            state: Mutex::new(QueueState {
+                queued,
+                by_offset: Default::default(),
+                waiting: HashMap::new(),
+                active: [0; SIO_PRIORITY_NUM_QUEUEABLE],
+                total_active: 0,
+                ia_active: 0,
+                nia_credit: 0,
+            }),
+        }
+    }
+
+    pub fn limits(&self) -> &VdevQueueLimits {
+        &self.limits
+    }
+
+    /// Queues `sio` and returns the requests, possibly including it, that
+    /// may now be issued.
+    pub fn add(&self, mut sio: SIO) -> Vec<SIO> {
+        let p = sio.priority as usize;
+        assert!(
+            p < SIO_PRIORITY_NUM_QUEUEABLE,
+            "{:?} is not queued",
+            sio.priority
+        );
+        let seq = *sio
+            .queue_seq
+            .get_or_insert_with(|| self.seq.fetch_add(1, atomic::Ordering::Relaxed));
+        let key = (sio.offset, seq);
+        let mut state = self.state.lock().unwrap();
+        state.queued[p].insert(key);
+        state.by_offset[type_index(sio.io_type)].insert(key);
+        state.waiting.insert(seq, sio);
+        self.issue(&mut state)
+    }
+
+    /// Notes that an active request of priority `p` has completed and
+    /// returns the requests that may now be issued.
+    pub fn done(&self, p: SIOPriority) -> Vec<SIO> {
+        let mut state = self.state.lock().unwrap();
+        state.active[p as usize] -= 1;
+        state.total_active -= 1;
+        if is_interactive(p) {
+            state.ia_active -= 1;
+            // Background work gets a fresh allowance each time user I/O
+            // completes, so it keeps moving under sustained load.
+            state.nia_credit = if state.ia_active == 0 {
+                0
+            } else {
+                self.limits.nia_credit
+            };
+        } else if state.ia_active == 0 {
+            state.nia_credit += 1;
+        }
+        self.issue(&mut state)
+    }
+
+    /// Requests of priority `p` active.
+    pub fn active(&self, p: SIOPriority) -> usize {
+        self.state.lock().unwrap().active[p as usize]
+    }
+
+    /// Requests of priority `p` waiting to be issued.
+    pub fn queued(&self, p: SIOPriority) -> usize {
+        self.state.lock().unwrap().queued[p as usize].len()
+    }
+
+    fn min_active(&self, state: &QueueState, p: SIOPriority) -> usize {
+        let min = self.limits.class[p as usize].min_active;
+        if is_interactive(p) {
+            min
+        } else {
+            min.min(state.nia_credit)
+        }
+    }
+
+    fn max_active(&self, state: &QueueState, p: SIOPriority) -> usize {
+        let limits = self.limits.class[p as usize];
+        if is_interactive(p) {
+            limits.max_active
+        } else if state.ia_active > 0 {
+            limits.min_active.min(state.nia_credit)
+        } else if state.nia_credit < self.limits.nia_delay {
+            limits.min_active.max(1)
+        } else {
+            limits.max_active
+        }
+    }
+
+    /// The class to issue from next, if any may.
+    fn class_to_issue(&self, state: &QueueState) -> Option<SIOPriority> {
+        if state.total_active >= self.limits.max_active {
+            return None;
+        }
+        let classes = || {
+            (0..SIO_PRIORITY_NUM_QUEUEABLE as u8)
+                .map(|p| SIOPriority::try_from(p).unwrap())
+                .filter(|&p| !state.queued[p as usize].is_empty())
+        };
+        // Every class gets its minimum before any gets more.
+        classes()
+            .find(|&p| state.active[p as usize] < self.min_active(state, p))
+            .or_else(|| classes().find(|&p| state.active[p as usize] < self.max_active(state, p)))
+    }
+
+    fn issue(&self, state: &mut QueueState) -> Vec<SIO> {
+        let mut issued = Vec::new();
+        while let Some(p) = self.class_to_issue(state) {
+            let key = state.queued[p as usize].pop().unwrap();
+            let sio = self.aggregate(state, key);
+            state.active[p as usize] += 1;
+            state.total_active += 1;
+            if is_interactive(p) {
+                if state.ia_active == 0 {
+                    state.nia_credit = self.limits.nia_credit;
+                }
+                state.ia_active += 1;
+            } else if state.ia_active > 0 {
+                state.nia_credit -= 1;
+            }
+            issued.push(sio);
+        }
+        issued
+    }
+
+    /// Takes the waiting request `key` out of the queue, along with any
+    /// neighbours it can be done in one I/O with. A single request is
+    /// returned as it is; otherwise the aggregate carries the parts in
+    /// `agg`.
+    fn aggregate(&self, state: &mut QueueState, key: Key) -> SIO {
+        let head = state.take(key);
+        let t = type_index(head.io_type);
+
+        let limit = self.limits.aggregation_limit;
+        if head.flags.contains(SIOFlags::DONT_AGGREGATE) || head.size >= limit {
+            return head;
+        }
+        let maxgap = match head.io_type {
+            SIOType::READ => self.limits.read_gap_limit,
+            _ => 0,
+        };
+        let fits = |sio: &SIO, start: u64, stop: u64| {
+            !sio.flags.contains(SIOFlags::DONT_AGGREGATE)
+                && stop.max(end(sio)) - start.min(sio.offset) <= limit
+        };
+
+        let (mut start, mut stop) = (head.offset, end(&head));
+        let mut before = Vec::new();
+        while let Some(&k) = state.by_offset[t].range(..(start, 0)).next_back() {
+            let sio = &state.waiting[&k.1];
+            if end(sio) > start || start - end(sio) > maxgap || !fits(sio, start, stop) {
+                break;
+            }
+            start = sio.offset;
+            before.push(state.take(k));
+        }
+        let mut after = Vec::new();
+        while let Some(&k) = state.by_offset[t].range((stop, 0)..).next() {
+            let sio = &state.waiting[&k.1];
+            if sio.offset - stop > maxgap || !fits(sio, start, stop) {
+                break;
+            }
+            stop = end(sio);
+            after.push(state.take(k));
+        }
+        if before.is_empty() && after.is_empty() {
+            return head;
+        }
+
+        let h = before.len();
+        let mut parts = before;
+        parts.reverse();
+        parts.push(head);
+        parts.append(&mut after);
+        let data = match parts[0].io_type {
+            // Writes are only joined when they touch.
+            SIOType::WRITE => parts.iter().flat_map(|s| s.pdata.iter().copied()).collect(),
+            _ => Vec::new(),
+        };
+        let head = &parts[h];
+        let mut agg = head.vdev_child(head.io_type, head.vd.unwrap(), start, stop - start, data);
+        agg.queue_seq = Some(self.seq.fetch_add(1, atomic::Ordering::Relaxed));
+        agg.agg = parts;
+        agg
+    }
+}
+
+impl Default for VdevQueue {
+    fn default() -> Self {
+        VdevQueue::new(VdevQueueLimits::default())
+    }
+}
+
+impl SIO {
+    /// The vdev whose queue this request goes through, if it is queued.
+    fn queued_vdev(&self) -> Option<u64> {
+        if self.flags.contains(SIOFlags::DONT_QUEUE)
+            || !matches!(self.io_type, SIOType::READ | SIOType::WRITE)
+            || self.priority as usize >= SIO_PRIORITY_NUM_QUEUEABLE
+        {
+            return None;
+        }
+        self.vd
+    }
+
+    /// Passes a physical request through its vdev's queue on the way to
+    /// `VDEV_IO`. Returns it if it may go on now; otherwise it stays queued
+    /// and is run again from the taskq when the queue issues it. Requests
+    /// the queue lets go ahead of it are sent to the taskq.
+    pub(super) fn vdev_queue_io(mut self) -> Option<SIO> {
+        if self.queue_seq.is_some() {
+            // Already issued.
+            return Some(self);
+        }
+        let pool = self.pool.clone();
+        let vq = match self.queued_vdev().and_then(|vd| pool.vdev_queue(vd)) {
+            Some(vq) => vq,
+            None => return Some(self),
+        };
+        let seq = vq.seq.fetch_add(1, atomic::Ordering::Relaxed);
+        self.queue_seq = Some(seq);
+
+        let mut me = None;
+        for s in vq.add(self) {
+            if s.queue_seq == Some(seq) {
+                me = Some(s);
+            } else {
+                pool.taskq().dispatch(move || run(s));
+            }
+        }
+        me
+    }
+
+    /// Tells the queue a request it issued is done, and sends whatever that
+    /// lets through to the taskq. An aggregate also sends its parts on,
+    /// each with its share of the data.
+    pub(super) fn vdev_queue_io_done(&mut self) {
+        if self.queue_seq.take().is_none() {
+            return;
+        }
+        let pool = self.pool.clone();
+        let vq = self
+            .queued_vdev()
+            .and_then(|vd| pool.vdev_queue(vd))
+            .expect("queued request lost its queue");
+        for s in vq.done(self.priority) {
+            pool.taskq().dispatch(move || run(s));
+        }
+
+        for mut part in mem::take(&mut self.agg) {
+            part.queue_seq = None;
+            if self.error.is_some() {
+                // Back through the queue alone, so one bad sector does not
+                // fail its neighbours.
+                part.flags |= SIOFlags::DONT_AGGREGATE;
+            } else {
+                if part.io_type == SIOType::READ {
+                    let at = (part.offset - self.offset) as usize;
+                    part.pdata = self.pdata[at..at + part.size as usize].to_vec();
+                }
+                part.stage = SIOStage::VDEV_IO;
+            }
+            pool.taskq().dispatch(move || run(part));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::sio::request::SIOPool;
+    use crate::sio::test_pool::MemPool;
+
+    fn io(pool: &Arc<MemPool>, io_type: SIOType, p: SIOPriority, offset: u64, size: u64) -> SIO {
+        let data = match io_type {
+            SIOType::WRITE => vec![(offset >> 12) as u8; size as usize],
+            _ => Vec::new(),
+        };
+        let mut sio = SIO::null(pool.clone()).vdev_child(io_type, 0, offset, size, data);
+        sio.priority = p;
+        sio
+    }
+
+    fn sio(pool: &Arc<MemPool>, p: SIOPriority, offset: u64) -> SIO {
+        io(pool, SIOType::READ, p, offset, 512)
+    }
+
+    fn offsets(sios: &[SIO]) -> Vec<u64> {
+        sios.iter().map(|s| s.offset).collect()
+    }
+
+    #[test]
+    fn queue_class_limits_and_order() {
+        let pool = Arc::new(MemPool::new());
+        let vq = VdevQueue::new(VdevQueueLimits {
+            max_active: 4,
+            ..Default::default()
+        });
+
+        // ASYNC_READ may have 3 active when nothing else is waiting.
+        let mut active = Vec::new();
+        for off in [5, 4, 3, 2, 1] {
+            active.extend(vq.add(sio(&pool, SIOPriority::ASYNC_READ, off << 20)));
+        }
+        assert_eq!(offsets(&active), [5 << 20, 4 << 20, 3 << 20]);
+        assert_eq!(vq.queued(SIOPriority::ASYNC_READ), 2);
+
+        // The pool-wide limit holds back even sync reads.
+        assert_eq!(vq.add(sio(&pool, SIOPriority::SYNC_READ, 0x9000)).len(), 1);
+        assert!(vq
+            .add(sio(&pool, SIOPriority::SYNC_READ, 0x8000))
+            .is_empty());
+
+        // Which go first when room is made, then the rest by offset.
+        assert_eq!(offsets(&vq.done(SIOPriority::ASYNC_READ)), [0x8000]);
+        assert_eq!(offsets(&vq.done(SIOPriority::SYNC_READ)), [1 << 20]);
+        assert!(vq.done(SIOPriority::SYNC_READ).is_empty());
+        assert_eq!(offsets(&vq.done(SIOPriority::ASYNC_READ)), [2 << 20]);
+        assert_eq!(vq.active(SIOPriority::ASYNC_READ), 3);
+    }
+
+    #[test]
+    fn queue_scrub_yields() {
+        let pool = Arc::new(MemPool::new());
+        let vq = VdevQueue::default();
+
+        // Alone, a scrub runs one at a time until it has earned nia_delay
+        // completions.
+        let mut issued = Vec::new();
+        for i in 0..20 {
+            issued.extend(vq.add(sio(&pool, SIOPriority::SCRUB, i << 20)));
+        }
+        assert_eq!(issued.len(), 1);
+        for _ in 0..4 {
+            assert_eq!(vq.done(SIOPriority::SCRUB).len(), 1);
+        }
+        assert_eq!(vq.done(SIOPriority::SCRUB).len(), 3);
+        assert_eq!(vq.active(SIOPriority::SCRUB), 3);
+
+        // User I/O goes straight through, and holds scrubs to min_active
+        // and what credit they have.
+        for i in 0..3 {
+            assert_eq!(vq.add(sio(&pool, SIOPriority::SYNC_READ, i << 20)).len(), 1);
+        }
+        let mut scrubs = 0;
+        for _ in 0..8 {
+            scrubs += vq.done(SIOPriority::SCRUB).len();
+        }
+        assert_eq!(scrubs, 5);
+        assert_eq!(vq.active(SIOPriority::SCRUB), 0);
+        assert_eq!(vq.queued(SIOPriority::SCRUB), 7);
+
+        // Out of credit, until user I/O completes and refills it.
+        assert_eq!(vq.done(SIOPriority::SYNC_READ).len(), 1);
+        let mut scrubs = 0;
+        for _ in 0..5 {
+            scrubs += vq.done(SIOPriority::SCRUB).len();
+        }
+        assert_eq!(scrubs, 4);
+        assert_eq!(vq.active(SIOPriority::SCRUB), 0);
+        assert_eq!(vq.done(SIOPriority::SYNC_READ).len(), 1);
+
+        // Once user I/O stops, scrubs earn their credit back one at a time.
+        assert!(vq.done(SIOPriority::SYNC_READ).is_empty());
+        assert_eq!(vq.active(SIOPriority::SCRUB), 1);
+    }
+
+    #[test]
+    fn queue_in_pipeline() {
+        let pool = Arc::new(MemPool::new());
+        let mut root = SIO::null(pool.clone());
+        for i in 0..64u64 {
+            let mut child = root.vdev_child(SIOType::WRITE, i % 2, i << 12, 8, vec![i as u8; 8]);
+            child.priority = if i % 3 == 0 {
+                SIOPriority::SCRUB
+            } else {
+                SIOPriority::ASYNC_WRITE
+            };
+            root.add_child(child);
+        }
+        root.wait().unwrap();
+        for i in 0..64u64 {
+            assert_eq!(pool.read_raw(i % 2, i << 12), Some(vec![i as u8; 8]));
+        }
+        for vd in 0..2 {
+            let vq = pool.vdev_queue(vd).unwrap();
+            assert_eq!(vq.active(SIOPriority::ASYNC_WRITE), 0);
+            assert_eq!(vq.queued(SIOPriority::SCRUB), 0);
+        }
+    }
+
+    #[test]
+    fn queue_aggregates_neighbours() {
+        let pool = Arc::new(MemPool::new());
+        let vq = VdevQueue::new(VdevQueueLimits {
+            max_active: 1,
+            ..Default::default()
+        });
+        let (read, write) = (SIOType::READ, SIOType::WRITE);
+        let (sync, async_read) = (SIOPriority::SYNC_READ, SIOPriority::ASYNC_READ);
+        assert_eq!(vq.add(sio(&pool, async_read, 100 << 20)).len(), 1);
+
+        for (p, off) in [
+            (async_read, 0x10000),
+            (sync, 0x11000),
+            (async_read, 0x13000),
+        ] {
+            assert!(vq.add(io(&pool, read, p, off, 0x1000)).is_empty());
+        }
+        let mut alone = io(&pool, read, async_read, 0x14000, 0x1000);
+        alone.flags |= SIOFlags::DONT_AGGREGATE;
+        vq.add(alone);
+        vq.add(io(&pool, read, async_read, 0x40000, 0x1000));
+        vq.add(io(&pool, write, SIOPriority::ASYNC_WRITE, 0x12000, 0x1000));
+
+        // The sync read goes first and takes the reads on either side, across
+        // the gap, but not the write in it.
+        let agg = vq.done(async_read).pop().unwrap();
+        assert_eq!(
+            (agg.offset, agg.size, agg.priority),
+            (0x10000, 0x4000, sync)
+        );
+        assert_eq!(offsets(&agg.agg), [0x10000, 0x11000, 0x13000]);
+        assert_eq!(vq.queued(async_read), 2);
+        assert_eq!(vq.active(sync), 1);
+        for p in [sync, async_read, async_read] {
+            assert!(vq.done(p).pop().unwrap().agg.is_empty());
+        }
+
+        // Writes only join when they touch. The lone write from above is
+        // still active.
+        let w = SIOPriority::ASYNC_WRITE;
+        for off in [0x20000, 0x21000, 0x23000] {
+            vq.add(io(&pool, write, w, off, 0x1000));
+        }
+        let agg = vq.done(w).pop().unwrap();
+        assert_eq!((agg.offset, agg.size), (0x20000, 0x2000));
+        assert_eq!(agg.pdata, [vec![0x20; 0x1000], vec![0x21; 0x1000]].concat());
+        assert_eq!(vq.queued(w), 1);
+    }
+
+    #[test]
+    fn queue_aggregate_in_pipeline() {
+        let pool = Arc::new(MemPool::with_queue_limits(VdevQueueLimits {
+            max_active: 1,
+            ..Default::default()
+        }));
+        let blocks: Vec<u64> = (0..8).filter(|&k| k != 5).collect();
+        for &k in &blocks {
+            pool.write(0, k << 12, &[k as u8; 0x1000]).unwrap();
+        }
+        let vq = pool.vdev_queue(0).unwrap();
+
+        let read_all = |pool: &Arc<MemPool>| {
+            // Hold the queue so every read is waiting before any is issued.
+            let plug = vq.add(sio(pool, SIOPriority::ASYNC_READ, 100 << 20));
+            assert_eq!(plug.len(), 1);
+            let (tx, rx) = mpsc::channel();
+            let mut root = SIO::null(pool.clone()).on_done(move |sio| {
+                let parts = sio
+                    .children
+                    .iter()
+                    .map(|c| (c.pdata.clone(), c.error.clone()));
+                tx.send(parts.collect::<Vec<_>>()).unwrap();
+            });
+            for &k in &blocks {
+                let child = io(
+                    pool,
+                    SIOType::READ,
+                    SIOPriority::ASYNC_READ,
+                    k << 12,
+                    0x1000,
+                );
+                root.add_child(child);
+            }
+            root.nowait();
+            while vq.queued(SIOPriority::ASYNC_READ) < blocks.len() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            for s in vq.done(SIOPriority::ASYNC_READ) {
+                run(s);
+            }
+            rx.recv().unwrap()
+        };
+
+        let parts = read_all(&pool);
+        assert_eq!(pool.reads.load(atomic::Ordering::Relaxed), 1);
+        for (&k, (data, error)) in blocks.iter().zip(parts) {
+            assert_eq!(error, None);
+            assert_eq!(data, vec![k as u8; 0x1000]);
+        }
+
+        // A failed aggregate is retried a part at a time.
+        pool.fail_vdev(0);
+        let parts = read_all(&pool);
+        assert!(parts.iter().all(|(_, e)| e.is_some()));
+        assert_eq!(vq.queued(SIOPriority::ASYNC_READ), 0);
+    }
+}
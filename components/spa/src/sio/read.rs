@@ -0,0 +1,224 @@
+//! Reading a block.
+//!
+//! The physical data behind a bp is fetched from the first copy that
+//! verifies, reassembled if it is a gang, decompressed to its logical size,
+//! and finally converted to host byte order if the bp says it was written on
+//! a host of the other endianness.
+
+use std::fmt;
+use std::io;
+
+use super::checksum::ChecksumTemplates;
+use super::compress::{decompress_blkptr, DecompressError};
+use super::gang::{read_block, GangError};
+use crate::blkptr::blkptr::{Blkptr, Dva};
+use crate::blkptr::error::BlkptrError;
+use crate::dmu::dmu_byteswap_block;
+
+#[derive(Debug)]
+pub enum ReadError {
+    /// No copy of the physical data could be read and verified.
+    Physical(GangError),
+    Blkptr(BlkptrError),
+    Decompress(DecompressError),
+    /// The block needs swapping but its object type has no byteswap
+    /// function.
+    ObjectType(u8),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Physical(e) => write!(f, "{}", e),
+            ReadError::Blkptr(e) => write!(f, "{}", e),
+            ReadError::Decompress(e) => write!(f, "{}", e),
+            ReadError::ObjectType(ot) => write!(f, "cannot byteswap object type {:#x}", ot),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<GangError> for ReadError {
+    fn from(e: GangError) -> Self {
+        ReadError::Physical(e)
+    }
+}
+
+impl From<BlkptrError> for ReadError {
+    fn from(e: BlkptrError) -> Self {
+        ReadError::Blkptr(e)
+    }
+}
+
+impl From<DecompressError> for ReadError {
+    fn from(e: DecompressError) -> Self {
+        ReadError::Decompress(e)
+    }
+}
+
+/// Converts `buf`, the logical contents of `bp`, to host byte order.
+pub fn bp_byteswap(bp: &Blkptr, buf: &mut [u8]) -> Result<(), ReadError> {
+    if bp.should_byteswap() && !dmu_byteswap_block(bp.get_type(), bp.get_level(), buf) {
+        return Err(ReadError::ObjectType(bp.get_type()));
+    }
+    Ok(())
+}
+
+/// Returns the logical contents of `bp` in host byte order. Holes read as
+/// zeros and embedded bps are read from the payload; everything else is
+/// fetched through `read`, which returns `size` bytes at a DVA.
+pub fn block_read<F>(
+    bp: &Blkptr,
+    tmpls: &ChecksumTemplates,
+    mut read: F,
+) -> Result<Vec<u8>, ReadError>
+where
+    F: FnMut(&Dva, usize) -> io::Result<Vec<u8>>,
+{
+    if bp.is_hole() {
+        return Ok(vec![0; bp.get_lsize() as usize]);
+    }
+    let physical = if bp.is_embedded() {
+        bp.get_embedded_payload()?
+    } else {
+        read_block(bp, tmpls, &mut read)?
+    };
+    let mut data = decompress_blkptr(bp, &physical)?;
+    bp_byteswap(bp, &mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blkptr::blkptr::{EmbeddedType, SIOCheckSumSalt};
+    use crate::blkptr::HOST_BYTEORDER;
+    use crate::dmu::DmuObjectType;
+    use crate::sio::checksum::{fletcher_4_byteswap, fletcher_4_native};
+    use crate::sio::compress::compress_block;
+    use crate::sio::request::SIOError;
+    use crate::sio::{SIOChecksum, SIOCompress};
+
+    fn words(buf: &[u8]) -> Vec<u64> {
+        buf.chunks_exact(8)
+            .map(|w| u64::from_ne_bytes(w.try_into().unwrap()))
+            .collect()
+    }
+
+    /// A block of `ot` stored at offset 0x4000, written by a host whose byte
+    /// order is `byteorder`.
+    fn written(ot: DmuObjectType, level: u8, data: &[u8], byteorder: u8) -> (Blkptr, Vec<u8>) {
+        let blk = compress_block(SIOCompress::LZ4, data, 512);
+        let mut bp = Blkptr::new();
+        bp.blk_dva[0].set_asize(blk.psize as u64);
+        bp.blk_dva[0].set_offset(0x4000);
+        blk.fill_blkptr(&mut bp, data.len());
+        bp.set_object_type(ot);
+        bp.set_level(level);
+        bp.set_byteorder(byteorder);
+        bp.set_checksum(SIOChecksum::FLETCHER_4);
+        bp.blk_cksum = if byteorder == HOST_BYTEORDER {
+            fletcher_4_native(&blk.data)
+        } else {
+            fletcher_4_byteswap(&blk.data)
+        };
+        (bp, blk.data)
+    }
+
+    fn reader(disk: &[u8]) -> impl FnMut(&Dva, usize) -> io::Result<Vec<u8>> + '_ {
+        move |dva, size| {
+            assert_eq!(dva.get_offset(), 0x4000);
+            Ok(disk[..size].to_vec())
+        }
+    }
+
+    #[test]
+    fn block_read_foreign_byteorder() {
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let values: Vec<u64> = (0..512u64).map(|i| i * 0x0101_0101 + 7).collect();
+        let native: Vec<u8> = values.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        let foreign: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.swap_bytes().to_ne_bytes())
+            .collect();
+
+        let (bp, disk) = written(DmuObjectType::UINT64_OTHER, 0, &native, HOST_BYTEORDER);
+        assert!(!bp.should_byteswap());
+        assert_eq!(
+            words(&block_read(&bp, &tmpls, reader(&disk)).unwrap()),
+            values
+        );
+
+        let (bp, disk) = written(DmuObjectType::UINT64_OTHER, 0, &foreign, 1 - HOST_BYTEORDER);
+        assert!(bp.should_byteswap());
+        assert_eq!(
+            words(&block_read(&bp, &tmpls, reader(&disk)).unwrap()),
+            values
+        );
+
+        // File contents are bytes, so they are never swapped.
+        let (bp, disk) = written(
+            DmuObjectType::PLAIN_FILE_CONTENTS,
+            0,
+            &foreign,
+            1 - HOST_BYTEORDER,
+        );
+        assert_eq!(block_read(&bp, &tmpls, reader(&disk)).unwrap(), foreign);
+        // Unless they are an indirect block, which holds blkptrs.
+        let (bp, disk) = written(
+            DmuObjectType::PLAIN_FILE_CONTENTS,
+            1,
+            &foreign,
+            1 - HOST_BYTEORDER,
+        );
+        assert_eq!(
+            words(&block_read(&bp, &tmpls, reader(&disk)).unwrap()),
+            values
+        );
+    }
+
+    #[test]
+    fn block_read_holes_and_embedded() {
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let no_read = |_: &Dva, _: usize| -> io::Result<Vec<u8>> { panic!("nothing to read") };
+
+        let mut hole = Blkptr::new();
+        hole.set_lsize(4096);
+        assert_eq!(block_read(&hole, &tmpls, no_read).unwrap(), vec![0; 4096]);
+
+        let mut bp = Blkptr::new();
+        bp.set_embedded_payload(EmbeddedType::Data, SIOCompress::OFF, &[0xab; 64], 64)
+            .unwrap();
+        assert_eq!(block_read(&bp, &tmpls, no_read).unwrap(), vec![0xab; 64]);
+    }
+
+    #[test]
+    fn block_read_errors() {
+        let tmpls = ChecksumTemplates::new(SIOCheckSumSalt::new());
+        let data = vec![1u8; 4096];
+        let (mut bp, disk) = written(DmuObjectType::UINT64_OTHER, 0, &data, 1 - HOST_BYTEORDER);
+        bp.set_type(crate::dmu::DMU_OT_NEWTYPE | 0x1f);
+        let err = block_read(&bp, &tmpls, reader(&disk)).unwrap_err();
+        assert!(matches!(err, ReadError::ObjectType(_)));
+        assert_eq!(SIOError::from(err), SIOError::ObjectType(bp.get_type()));
+
+        let mut corrupt = disk.clone();
+        corrupt[10] ^= 1;
+        let err = block_read(&bp, &tmpls, reader(&corrupt)).unwrap_err();
+        assert!(matches!(err, ReadError::Physical(GangError::Checksum(_))));
+        assert!(matches!(SIOError::from(err), SIOError::Checksum(_)));
+
+        let size = GangError::Size {
+            expected: 0x2000,
+            actual: 0x1000,
+        };
+        assert_eq!(
+            SIOError::from(ReadError::Physical(size)),
+            SIOError::GangSize {
+                expected: 0x2000,
+                actual: 0x1000
+            }
+        );
+    }
+}
@@ -0,0 +1,496 @@
+//! I/O requests.
+//!
+//! Every block read, write, free or claim, and every cache flush, is an
+//! `SIO`. A request carries its bp and data through an ordered pipeline of
+//! stages (see `pipeline`); which stages run depends on the type of request
+//! and is decided when it is created, though a stage may drop later ones
+//! (a write of zeros, for example, needs no allocation).
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use bitflags::bitflags;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+use super::checksum::ChecksumTemplates;
+use super::compress::DecompressError;
+use super::deadman::Deadman;
+use super::gang::GangError;
+use super::inject::Injector;
+use super::pipeline::{SIOStage, StageTable, SIO_STAGE_TABLE};
+use super::queue::VdevQueue;
+use super::read::ReadError;
+use super::taskq::Taskq;
+use super::tree::{ErrorPolicy, Links};
+use super::{SIOChecksum, SIOCompress};
+use crate::blkptr::blkptr::{Blkptr, Dva};
+use crate::blkptr::checksum::ChecksumMismatch;
+use crate::blkptr::error::BlkptrError;
+use crate::blkptr::SPA_MINBLOCKSIZE;
+use crate::stat::PoolStats;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum SIOType {
+    /// Does no I/O of its own.
+    NULL,
+    READ,
+    WRITE,
+    FREE,
+    CLAIM,
+    IOCTL,
+}
+
+/// Priority classes, in the order the vdev queue favours them. `NOW` is not
+/// queued at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+#[allow(non_camel_case_types)]
+pub enum SIOPriority {
+    SYNC_READ,
+    SYNC_WRITE,
+    ASYNC_READ,
+    ASYNC_WRITE,
+    SCRUB,
+    TRIM,
+    INITIALIZING,
+    REBUILD,
+    NOW,
+}
+
+/// Number of priorities the vdev queue schedules.
+pub const SIO_PRIORITY_NUM_QUEUEABLE: usize = SIOPriority::NOW as usize;
+
+bitflags! {
+    pub struct SIOFlags: u32 {
+        /// Do not merge with neighbouring I/O in the vdev queue.
+        const DONT_AGGREGATE = 1 << 0;
+        /// A write rewriting a bad copy with good data.
+        const IO_REPAIR = 1 << 1;
+        const SELF_HEAL = 1 << 2;
+        const RESILVER = 1 << 3;
+        const SCRUB = 1 << 4;
+        /// Failure is expected and handled by the caller; do not report it.
+        const CANFAIL = 1 << 5;
+        const SPECULATIVE = 1 << 6;
+        /// Bypass the vdev queue.
+        const DONT_QUEUE = 1 << 7;
+        /// Do not pass errors up to the parent.
+        const DONT_PROPAGATE = 1 << 8;
+        /// Padding read to fill a gap in an aggregated I/O; the data is
+        /// thrown away.
+        const NODATA = 1 << 9;
+        /// The data is already compressed; `psize` and the compress field of
+        /// the bp are set by the caller.
+        const RAW_COMPRESS = 1 << 10;
+        /// The data is already encrypted.
+        const RAW_ENCRYPT = 1 << 11;
+        const GANG_CHILD = 1 << 12;
+        /// Set by the pipeline when a write was satisfied by nopwrite.
+        const NOPWRITE = 1 << 13;
+        /// Issued again by the deadman in place of a request that hung. It
+        /// goes around the vdev queue and is not watched itself.
+        const REEXECUTED = 1 << 14;
+
+        /// Flags a vdev child takes from its parent.
+        const INHERITED = Self::DONT_AGGREGATE.bits
+            | Self::IO_REPAIR.bits
+            | Self::SELF_HEAL.bits
+            | Self::RESILVER.bits
+            | Self::SCRUB.bits
+            | Self::CANFAIL.bits
+            | Self::SPECULATIVE.bits
+            | Self::DONT_QUEUE.bits;
+    }
+}
+
+/// Where a block sits in the pool: its dataset, object, indirection level
+/// and block number within that level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Bookmark {
+    pub objset: u64,
+    pub object: u64,
+    pub level: i64,
+    pub blkid: u64,
+}
+
+/// Properties of a write, derived from the dataset it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteProps {
+    pub checksum: SIOChecksum,
+    pub compress: SIOCompress,
+    pub object_type: u8,
+    pub level: u8,
+    /// Number of DVAs to allocate.
+    pub copies: u8,
+    pub dedup: bool,
+    pub nopwrite: bool,
+    pub encrypt: bool,
+    /// Birth txg of the newest snapshot; blocks born after it may be
+    /// overwritten in place by nopwrite.
+    pub prev_snap_txg: u64,
+}
+
+impl WriteProps {
+    pub fn new(checksum: SIOChecksum, compress: SIOCompress, object_type: u8) -> Self {
+        WriteProps {
+            checksum,
+            compress,
+            object_type,
+            level: 0,
+            copies: 1,
+            dedup: false,
+            nopwrite: false,
+            encrypt: false,
+            prev_snap_txg: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SIOError {
+    Io(io::ErrorKind),
+    Checksum(ChecksumMismatch),
+    Blkptr(BlkptrError),
+    Decompress(DecompressError),
+    /// No space could be allocated for a write.
+    NoSpace,
+    /// A block has no DVA to read from.
+    NoDva,
+    /// Valid, but not something the pipeline can do.
+    NotSupported(&'static str),
+    /// A block needs swapping but its object type has no byteswap function.
+    ObjectType(u8),
+    /// A gang read was asked of a bp that is not a gang.
+    NotGang,
+    /// The members of a gang block do not add up to its size.
+    GangSize {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for SIOError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SIOError::Io(kind) => write!(f, "i/o error: {}", io::Error::from(*kind)),
+            SIOError::Checksum(e) => write!(f, "{}", e),
+            SIOError::Blkptr(e) => write!(f, "{}", e),
+            SIOError::Decompress(e) => write!(f, "{}", e),
+            SIOError::NoSpace => write!(f, "out of space"),
+            SIOError::NoDva => write!(f, "block has no valid dva"),
+            SIOError::NotSupported(what) => write!(f, "{} not supported", what),
+            SIOError::ObjectType(ot) => write!(f, "cannot byteswap object type {:#x}", ot),
+            SIOError::NotGang => write!(f, "not a gang block"),
+            SIOError::GangSize { expected, actual } => write!(
+                f,
+                "gang members hold {:#x} bytes, expected {:#x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SIOError {}
+
+impl From<io::Error> for SIOError {
+    fn from(e: io::Error) -> Self {
+        SIOError::Io(e.kind())
+    }
+}
+
+impl From<ChecksumMismatch> for SIOError {
+    fn from(e: ChecksumMismatch) -> Self {
+        SIOError::Checksum(e)
+    }
+}
+
+impl From<BlkptrError> for SIOError {
+    fn from(e: BlkptrError) -> Self {
+        SIOError::Blkptr(e)
+    }
+}
+
+impl From<DecompressError> for SIOError {
+    fn from(e: DecompressError) -> Self {
+        SIOError::Decompress(e)
+    }
+}
+
+impl From<GangError> for SIOError {
+    fn from(e: GangError) -> Self {
+        match e {
+            GangError::NotGang => SIOError::NotGang,
+            GangError::NoDva => SIOError::NoDva,
+            GangError::Io(e) => e.into(),
+            GangError::Checksum(e) => SIOError::Checksum(e),
+            GangError::Blkptr(e) => SIOError::Blkptr(e),
+            GangError::Size { expected, actual } => SIOError::GangSize { expected, actual },
+        }
+    }
+}
+
+impl From<ReadError> for SIOError {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::Physical(e) => e.into(),
+            ReadError::Blkptr(e) => SIOError::Blkptr(e),
+            ReadError::Decompress(e) => SIOError::Decompress(e),
+            ReadError::ObjectType(ot) => SIOError::ObjectType(ot),
+        }
+    }
+}
+
+/// What the pipeline needs from the pool a request runs in: space
+/// allocation, physical I/O on its vdevs, and the pool-wide state the stages
+/// use.
+pub trait SIOPool: Send + Sync {
+    fn checksum_templates(&self) -> &ChecksumTemplates;
+
+    fn stats(&self) -> &PoolStats;
+
+    /// The smallest allocation; compressed blocks are rounded up to it.
+    fn min_alloc(&self) -> usize {
+        SPA_MINBLOCKSIZE as usize
+    }
+
+    /// The stage functions. Pools replace entries to change how a stage is
+    /// carried out.
+    fn stages(&self) -> &StageTable {
+        &SIO_STAGE_TABLE
+    }
+
+    /// Allocates `copies` DVAs of at least `psize` bytes each, on distinct
+    /// vdevs where possible.
+    fn alloc(&self, psize: u64, copies: usize, txg: u64) -> Result<Vec<Dva>, SIOError>;
+
+    fn free(&self, dva: &Dva, txg: u64);
+
+    /// Marks space allocated by a write that was logged but never synced
+    /// as in use.
+    fn claim(&self, dva: &Dva, txg: u64) -> Result<(), SIOError>;
+
+    /// Reads `size` bytes at `offset` on top-level vdev `vdev`.
+    fn read(&self, vdev: u64, offset: u64, size: usize) -> io::Result<Vec<u8>>;
+
+    fn write(&self, vdev: u64, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Flushes the write cache of top-level vdev `vdev`.
+    fn flush(&self, vdev: u64) -> io::Result<()>;
+
+    /// Where requests waiting on children are resumed.
+    fn taskq(&self) -> &Taskq;
+
+    /// The queue physical I/O to `vdev` waits in, if it has one.
+    fn vdev_queue(&self, _vdev: u64) -> Option<&VdevQueue> {
+        None
+    }
+
+    /// Faults to inject into physical I/O, if any.
+    fn injector(&self) -> Option<&Injector> {
+        None
+    }
+
+    /// What watches physical I/O for hangs, if anything.
+    fn deadman(&self) -> Option<&Deadman> {
+        None
+    }
+}
+
+pub type SIODone = Box<dyn FnOnce(&mut SIO) + Send>;
+
+pub struct SIO {
+    pub io_type: SIOType,
+    pub priority: SIOPriority,
+    pub flags: SIOFlags,
+    pub txg: u64,
+    pub bp: Blkptr,
+    /// The block the request is for, passed on to its children.
+    pub bookmark: Bookmark,
+    /// The bp a write replaces, if any. Nopwrite compares against it.
+    pub bp_orig: Option<Blkptr>,
+    pub props: WriteProps,
+    /// The logical contents of the block: the data to write, or where a
+    /// read leaves what it read.
+    pub data: Vec<u8>,
+    /// The physical contents, as stored on disk after compression.
+    pub pdata: Vec<u8>,
+    /// The vdev a physical request or ioctl is for. Logical requests, which
+    /// work through their bp, have none.
+    pub vd: Option<u64>,
+    pub offset: u64,
+    pub size: u64,
+    /// Index of the DVA a read is trying.
+    pub dva: usize,
+    /// The stage last run, empty before the first.
+    pub stage: SIOStage,
+    /// The stages still to run.
+    pub pipeline: SIOStage,
+    pub error: Option<SIOError>,
+    /// How errors of children become errors of this request.
+    pub child_errors: ErrorPolicy,
+    /// Children that have completed and not yet been looked at, in the
+    /// order they were added.
+    pub children: Vec<SIO>,
+    pub done: Option<SIODone>,
+    pub pool: Arc<dyn SIOPool>,
+    pub(super) links: Links,
+    /// Set once the request has gone into a vdev queue, until the queue
+    /// hears it is done.
+    pub(super) queue_seq: Option<u64>,
+    /// The requests an aggregate I/O was built from.
+    pub(super) agg: Vec<SIO>,
+    /// The request's id with the pool's deadman, while it is tracked.
+    pub(super) deadman_id: Option<u64>,
+}
+
+impl SIO {
+    fn new(pool: Arc<dyn SIOPool>, io_type: SIOType, bp: Blkptr, pipeline: SIOStage) -> Self {
+        SIO {
+            io_type,
+            priority: SIOPriority::NOW,
+            flags: SIOFlags::empty(),
+            txg: 0,
+            bp,
+            bookmark: Bookmark::default(),
+            bp_orig: None,
+            props: WriteProps::new(SIOChecksum::OFF, SIOCompress::OFF, 0),
+            data: Vec::new(),
+            pdata: Vec::new(),
+            vd: None,
+            offset: 0,
+            size: 0,
+            dva: 0,
+            stage: SIOStage::empty(),
+            pipeline,
+            error: None,
+            child_errors: ErrorPolicy::AnyFails,
+            children: Vec::new(),
+            done: None,
+            pool,
+            links: Links::default(),
+            queue_seq: None,
+            agg: Vec::new(),
+            deadman_id: None,
+        }
+    }
+
+    /// A request that does no I/O, used to collect others.
+    pub fn null(pool: Arc<dyn SIOPool>) -> Self {
+        SIO::new(pool, SIOType::NULL, Blkptr::new(), SIOStage::INTERLOCK)
+    }
+
+    /// Reads the block behind `bp` into `data`.
+    pub fn read(
+        pool: Arc<dyn SIOPool>,
+        bp: &Blkptr,
+        priority: SIOPriority,
+        flags: SIOFlags,
+    ) -> Self {
+        let mut sio = SIO::new(pool, SIOType::READ, bp.clone(), SIOStage::READ_PIPELINE);
+        sio.priority = priority;
+        sio.flags = flags;
+        sio
+    }
+
+    /// Writes `data` in `txg`, filling in `bp`. `bp_orig` is the bp of the
+    /// block being overwritten, if any.
+    pub fn write(
+        pool: Arc<dyn SIOPool>,
+        txg: u64,
+        data: Vec<u8>,
+        props: WriteProps,
+        bp_orig: Option<&Blkptr>,
+        priority: SIOPriority,
+        flags: SIOFlags,
+    ) -> Self {
+        let mut pipeline = SIOStage::WRITE_PIPELINE;
+        if props.nopwrite && bp_orig.is_some() {
+            pipeline |= SIOStage::NOP_WRITE;
+        }
+        let mut sio = SIO::new(pool, SIOType::WRITE, Blkptr::new(), pipeline);
+        sio.txg = txg;
+        sio.data = data;
+        sio.props = props;
+        sio.bp_orig = bp_orig.cloned();
+        sio.priority = priority;
+        sio.flags = flags;
+        sio
+    }
+
+    /// Frees the space behind `bp` in `txg`.
+    pub fn free(pool: Arc<dyn SIOPool>, txg: u64, bp: &Blkptr) -> Self {
+        let mut sio = SIO::new(pool, SIOType::FREE, bp.clone(), SIOStage::FREE_PIPELINE);
+        sio.txg = txg;
+        sio
+    }
+
+    pub fn claim(pool: Arc<dyn SIOPool>, txg: u64, bp: &Blkptr) -> Self {
+        let mut sio = SIO::new(pool, SIOType::CLAIM, bp.clone(), SIOStage::CLAIM_PIPELINE);
+        sio.txg = txg;
+        sio
+    }
+
+    /// Flushes the write cache of `vdev`.
+    pub fn ioctl(pool: Arc<dyn SIOPool>, vdev: u64) -> Self {
+        let mut sio = SIO::new(
+            pool,
+            SIOType::IOCTL,
+            Blkptr::new(),
+            SIOStage::IOCTL_PIPELINE,
+        );
+        sio.vd = Some(vdev);
+        sio
+    }
+
+    /// A child of `self` doing `io_type` I/O of `size` bytes at `offset` on
+    /// vdev `vd`. A write child writes `data`.
+    pub fn vdev_child(
+        &self,
+        io_type: SIOType,
+        vd: u64,
+        offset: u64,
+        size: u64,
+        data: Vec<u8>,
+    ) -> SIO {
+        let pipeline = SIOStage::INTERLOCK | SIOStage::VDEV_IO;
+        let mut sio = SIO::new(self.pool.clone(), io_type, self.bp.clone(), pipeline);
+        sio.priority = self.priority;
+        sio.flags = self.flags & SIOFlags::INHERITED;
+        sio.txg = self.txg;
+        sio.bookmark = self.bookmark;
+        sio.vd = Some(vd);
+        sio.offset = offset;
+        sio.size = size;
+        sio.pdata = data;
+        sio
+    }
+
+    /// Sets a callback to run when the request completes, successfully or
+    /// not.
+    pub fn on_done<F>(mut self, done: F) -> Self
+    where
+        F: FnOnce(&mut SIO) + Send + 'static,
+    {
+        self.done = Some(Box::new(done));
+        self
+    }
+}
+
+impl fmt::Debug for SIO {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SIO")
+            .field("io_type", &self.io_type)
+            .field("priority", &self.priority)
+            .field("flags", &self.flags)
+            .field("txg", &self.txg)
+            .field("vd", &self.vd)
+            .field("offset", &self.offset)
+            .field("size", &self.size)
+            .field("stage", &self.stage)
+            .field("pipeline", &self.pipeline)
+            .field("error", &self.error)
+            .finish()
+    }
+}
@@ -0,0 +1,167 @@
+//! Task queues.
+//!
+//! A fixed set of worker threads running closures in the order they were
+//! dispatched. Requests that have to wait for their children are resumed
+//! here, so the thread that finishes the last child never runs the rest of
+//! its parent's pipeline on its own stack.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Number of dispatched tasks that have not finished running.
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    idle: Condvar,
+}
+
+impl Pending {
+    fn add(&self, n: isize) {
+        let mut count = self.count.lock().unwrap();
+        *count = (*count as isize + n) as usize;
+        if *count == 0 {
+            self.idle.notify_all();
+        }
+    }
+}
+
+/// Marks a task finished even if it panics, so `wait` cannot hang.
+struct Finished<'a>(&'a Pending);
+
+impl Drop for Finished<'_> {
+    fn drop(&mut self) {
+        self.0.add(-1);
+    }
+}
+
+pub struct Taskq {
+    name: String,
+    tx: Mutex<Option<Sender<Task>>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: Arc<Pending>,
+}
+
+impl Taskq {
+    pub fn new(name: &str, nthreads: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<Task>();
+        let rx = Arc::new(Mutex::new(rx));
+        let pending = Arc::new(Pending::default());
+        let workers = (0..nthreads.max(1))
+            .map(|i| {
+                let rx = rx.clone();
+                let pending = pending.clone();
+                thread::Builder::new()
+                    .name(format!("{}_{}", name, i))
+                    .spawn(move || worker(&rx, &pending))
+                    .expect("failed to spawn taskq thread")
+            })
+            .collect();
+        Taskq {
+            name: name.to_string(),
+            tx: Mutex::new(Some(tx)),
+            workers,
+            pending,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn nthreads(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn dispatch<F>(&self, task: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pending.add(1);
+        let tx = self.tx.lock().unwrap();
+        tx.as_ref()
+            .expect("taskq is being destroyed")
+            .send(Box::new(task))
+            .expect("taskq workers have exited");
+    }
+
+    /// Waits until every task dispatched so far, and any they dispatch in
+    /// turn, has run.
+    pub fn wait(&self) {
+        let mut count = self.pending.count.lock().unwrap();
+        while *count > 0 {
+            count = self.pending.idle.wait(count).unwrap();
+        }
+    }
+}
+
+fn worker(rx: &Mutex<Receiver<Task>>, pending: &Pending) {
+    loop {
+        let task = match rx.lock().unwrap().recv() {
+            Ok(task) => task,
+            Err(_) => return,
+        };
+        let _finished = Finished(pending);
+        task();
+    }
+}
+
+impl Drop for Taskq {
+    /// Lets the workers drain the queue and exit. A taskq dropped by one of
+    /// its own tasks cannot join that thread, which exits by itself once the
+    /// task returns.
+    fn drop(&mut self) {
+        self.tx.lock().unwrap().take();
+        let me = thread::current().id();
+        for w in self.workers.drain(..) {
+            if w.thread().id() != me {
+                let _ = w.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn taskq_runs_everything() {
+        let tq = Arc::new(Taskq::new("sio_test", 4));
+        assert_eq!(tq.nthreads(), 4);
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let (tq2, ran) = (tq.clone(), ran.clone());
+            tq.dispatch(move || {
+                // Tasks may dispatch more work; wait covers it too.
+                let ran2 = ran.clone();
+                tq2.dispatch(move || {
+                    ran2.fetch_add(1, Ordering::SeqCst);
+                });
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        tq.wait();
+        assert_eq!(ran.load(Ordering::SeqCst), 200);
+    }
+
+    #[test]
+    fn taskq_dropped_by_its_own_task() {
+        let tq = Arc::new(Taskq::new("sio_test", 1));
+        let (tx, rx) = mpsc::channel();
+        let tq2 = tq.clone();
+        drop(tq);
+        // The task holds the last reference.
+        let task_tq = tq2.clone();
+        tq2.dispatch(move || {
+            drop(task_tq);
+            tx.send(()).unwrap();
+        });
+        drop(tq2);
+        rx.recv().unwrap();
+    }
+}
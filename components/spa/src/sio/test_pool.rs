@@ -0,0 +1,166 @@
+//! An in-memory pool for pipeline tests.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::checksum::ChecksumTemplates;
+use super::deadman::Deadman;
+use super::inject::Injector;
+use super::pipeline::StageTable;
+use super::queue::{VdevQueue, VdevQueueLimits};
+use super::request::{SIOError, SIOPool};
+use super::taskq::Taskq;
+use crate::blkptr::blkptr::{Dva, SIOCheckSumSalt};
+use crate::stat::PoolStats;
+
+/// Each copy goes to its own vdev, all at the same offset. Vdevs 0 to 3
+/// have queues. The disk keeps each write as it was made, and reads are put
+/// together from whatever writes they overlap.
+pub struct MemPool {
+    tmpls: ChecksumTemplates,
+    pub stats: PoolStats,
+    pub stages: StageTable,
+    taskq: Taskq,
+    queues: Vec<VdevQueue>,
+    next: AtomicU64,
+    pub disk: Mutex<BTreeMap<(u64, u64), Vec<u8>>>,
+    /// Reads done on any vdev.
+    pub reads: AtomicU64,
+    pub freed: Mutex<Vec<(u64, u64)>>,
+    failing: Mutex<HashSet<u64>>,
+    pub injector: Injector,
+    pub deadman: Option<Arc<Deadman>>,
+}
+
+impl MemPool {
+    pub fn new() -> Self {
+        MemPool::with_queue_limits(VdevQueueLimits::default())
+    }
+
+    pub fn with_queue_limits(limits: VdevQueueLimits) -> Self {
+        MemPool {
+            tmpls: ChecksumTemplates::new(SIOCheckSumSalt::new()),
+            stats: PoolStats::new(),
+            stages: StageTable::default(),
+            taskq: Taskq::new("sio_test", 4),
+            queues: (0..4).map(|_| VdevQueue::new(limits)).collect(),
+            next: AtomicU64::new(0x40_0000),
+            disk: Mutex::new(BTreeMap::new()),
+            reads: AtomicU64::new(0),
+            freed: Mutex::new(Vec::new()),
+            failing: Mutex::new(HashSet::new()),
+            injector: Injector::with_seed(1),
+            deadman: None,
+        }
+    }
+
+    pub fn corrupt(&self, dva: &Dva) {
+        let mut disk = self.disk.lock().unwrap();
+        disk.get_mut(&(dva.get_vdev(), dva.get_offset())).unwrap()[0] ^= 0xff;
+    }
+
+    pub fn read_raw(&self, vdev: u64, offset: u64) -> Option<Vec<u8>> {
+        self.disk.lock().unwrap().get(&(vdev, offset)).cloned()
+    }
+
+    /// Makes every later I/O to `vdev` fail with EIO.
+    pub fn fail_vdev(&self, vdev: u64) {
+        self.failing.lock().unwrap().insert(vdev);
+    }
+
+    fn check(&self, vdev: u64) -> io::Result<()> {
+        if self.failing.lock().unwrap().contains(&vdev) {
+            return Err(io::Error::from_raw_os_error(5));
+        }
+        Ok(())
+    }
+}
+
+impl SIOPool for MemPool {
+    fn checksum_templates(&self) -> &ChecksumTemplates {
+        &self.tmpls
+    }
+
+    fn stats(&self) -> &PoolStats {
+        &self.stats
+    }
+
+    fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    fn alloc(&self, psize: u64, copies: usize, _txg: u64) -> Result<Vec<Dva>, SIOError> {
+        let offset = self.next.fetch_add(psize, Ordering::Relaxed);
+        Ok((0..copies as u64)
+            .map(|vdev| {
+                let mut dva = Dva::new();
+                dva.set_vdev(vdev);
+                dva.set_offset(offset);
+                dva.set_asize(psize);
+                dva
+            })
+            .collect())
+    }
+
+    fn free(&self, dva: &Dva, _txg: u64) {
+        let key = (dva.get_vdev(), dva.get_offset());
+        self.disk.lock().unwrap().remove(&key);
+        self.freed.lock().unwrap().push(key);
+    }
+
+    fn claim(&self, _dva: &Dva, _txg: u64) -> Result<(), SIOError> {
+        Ok(())
+    }
+
+    fn read(&self, vdev: u64, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+        self.check(vdev)?;
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        let end = offset + size as u64;
+        let mut buf = vec![0; size];
+        let mut found = false;
+        let disk = self.disk.lock().unwrap();
+        for (&(_, at), data) in disk.range((vdev, 0)..(vdev, end)) {
+            let (from, to) = (at.max(offset), (at + data.len() as u64).min(end));
+            if from < to {
+                buf[(from - offset) as usize..(to - offset) as usize]
+                    .copy_from_slice(&data[(from - at) as usize..(to - at) as usize]);
+                found = true;
+            }
+        }
+        if !found {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(buf)
+    }
+
+    fn write(&self, vdev: u64, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.check(vdev)?;
+        self.disk
+            .lock()
+            .unwrap()
+            .insert((vdev, offset), data.to_vec());
+        Ok(())
+    }
+
+    fn flush(&self, vdev: u64) -> io::Result<()> {
+        self.check(vdev)
+    }
+
+    fn taskq(&self) -> &Taskq {
+        &self.taskq
+    }
+
+    fn vdev_queue(&self, vdev: u64) -> Option<&VdevQueue> {
+        self.queues.get(vdev as usize)
+    }
+
+    fn injector(&self) -> Option<&Injector> {
+        Some(&self.injector)
+    }
+
+    fn deadman(&self) -> Option<&Deadman> {
+        self.deadman.as_deref()
+    }
+}
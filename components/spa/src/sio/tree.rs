@@ -0,0 +1,256 @@
+//! Parent and child requests.
+//!
+//! A stage may split its work into children: a logical write becomes one
+//! write per DVA, a gang read one read per member. Children are issued when
+//! the stage returns and run on the pool's taskq, while the parent goes on
+//! with its pipeline until it reaches a stage in `SIOStage::CHILD_WAIT`. If
+//! children are still running it parks there, and the last one to complete
+//! sends it back to the taskq. Completed children are handed to the parent
+//! in `SIO::children`, and their errors become the parent's according to its
+//! `ErrorPolicy`.
+
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::pipeline::run;
+use super::request::{SIOError, SIOFlags, SIO};
+
+/// How the errors of a request's children decide its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// The request fails if any child does.
+    AnyFails,
+    /// The request fails only if every child does, as when writing the
+    /// copies of a block.
+    AllFail,
+}
+
+impl ErrorPolicy {
+    /// The error to give a request whose children are `children`, if any.
+    /// Children with `DONT_PROPAGATE` are left out.
+    pub fn apply(&self, children: &[SIO]) -> Option<SIOError> {
+        let mut counted = children
+            .iter()
+            .filter(|c| !c.flags.contains(SIOFlags::DONT_PROPAGATE));
+        match self {
+            ErrorPolicy::AnyFails => counted.find_map(|c| c.error.clone()),
+            ErrorPolicy::AllFail => {
+                let mut first = None;
+                for c in counted {
+                    // One good child is enough.
+                    let e = c.error.as_ref()?;
+                    first.get_or_insert(e);
+                }
+                first.cloned()
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ChildState {
+    /// Children issued and not yet completed.
+    outstanding: usize,
+    /// Completed children, with the order they were added in.
+    done: Vec<(usize, SIO)>,
+    /// The parent, while it waits for `outstanding` to drop to zero.
+    parked: Option<SIO>,
+}
+
+/// Shared by a request and its children.
+#[derive(Default)]
+pub(super) struct Children {
+    state: Mutex<ChildState>,
+}
+
+/// Where `wait` collects a completed request.
+type Waiter = (Mutex<Option<SIO>>, Condvar);
+
+#[derive(Default)]
+pub(super) struct Links {
+    parent: Option<(Arc<Children>, usize)>,
+    children: Option<Arc<Children>>,
+    /// Children added by the running stage.
+    pub(super) issue: Vec<SIO>,
+    next: usize,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl SIO {
+    /// Adds a child, to be issued when the running stage returns.
+    pub fn add_child(&mut self, child: SIO) {
+        self.links.issue.push(child);
+    }
+
+    pub(super) fn issue_children(&mut self) {
+        if self.links.issue.is_empty() {
+            return;
+        }
+        let children = self
+            .links
+            .children
+            .get_or_insert_with(Default::default)
+            .clone();
+        children.state.lock().unwrap().outstanding += self.links.issue.len();
+        for mut child in mem::take(&mut self.links.issue) {
+            child.links.parent = Some((children.clone(), self.links.next));
+            self.links.next += 1;
+            self.pool.taskq().dispatch(move || run(child));
+        }
+    }
+
+    /// Returns `self` once every child issued so far has completed, with
+    /// them moved to `children` and their errors applied. If some are still
+    /// running, parks `self` and returns `None`.
+    pub(super) fn wait_for_children(mut self) -> Option<SIO> {
+        let children = match &self.links.children {
+            Some(c) => c.clone(),
+            None => return Some(self),
+        };
+        let mut state = children.state.lock().unwrap();
+        if state.outstanding > 0 {
+            state.parked = Some(self);
+            return None;
+        }
+        let mut done = mem::take(&mut state.done);
+        drop(state);
+
+        done.sort_by_key(|(i, _)| *i);
+        let done: Vec<SIO> = done.into_iter().map(|(_, c)| c).collect();
+        if let Some(e) = self.child_errors.apply(&done) {
+            self.fail(e);
+        }
+        self.children.extend(done);
+        Some(self)
+    }
+
+    /// Hands a finished request to its parent, resuming the parent if it was
+    /// the last child, or to whoever is waiting for it.
+    pub(super) fn complete(mut self) {
+        if let Some((children, index)) = self.links.parent.take() {
+            let pool = self.pool.clone();
+            let mut state = children.state.lock().unwrap();
+            state.done.push((index, self));
+            state.outstanding -= 1;
+            if state.outstanding == 0 {
+                if let Some(parent) = state.parked.take() {
+                    drop(state);
+                    pool.taskq().dispatch(move || run(parent));
+                }
+            }
+        } else if let Some(waiter) = self.links.waiter.take() {
+            *waiter.0.lock().unwrap() = Some(self);
+            waiter.1.notify_all();
+        }
+    }
+
+    /// Issues the request and blocks until it and all its children have
+    /// completed. Returns its error, if any.
+    pub fn wait(&mut self) -> Result<(), SIOError> {
+        let waiter = Arc::new(Waiter::default());
+        let mut sio = mem::replace(self, SIO::null(self.pool.clone()));
+        sio.links.waiter = Some(waiter.clone());
+        run(sio);
+
+        let mut slot = waiter.0.lock().unwrap();
+        *self = loop {
+            match slot.take() {
+                Some(sio) => break sio,
+                None => slot = waiter.1.wait(slot).unwrap(),
+            }
+        };
+        match &self.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Issues the request without waiting for it. The whole pipeline,
+    /// including the done callback, runs on the pool's taskq.
+    pub fn nowait(self) {
+        let pool = self.pool.clone();
+        pool.taskq().dispatch(move || run(self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+    use crate::sio::test_pool::MemPool;
+    use crate::sio::SIOType;
+
+    fn writes(pool: &Arc<MemPool>, vdevs: &[u64]) -> SIO {
+        let mut root = SIO::null(pool.clone());
+        for &vd in vdevs {
+            let child = root.vdev_child(SIOType::WRITE, vd, 0x1000, 4, vec![vd as u8; 4]);
+            root.add_child(child);
+        }
+        root
+    }
+
+    fn run_root(mut root: SIO) -> SIO {
+        let _ = root.wait();
+        root
+    }
+
+    #[test]
+    fn tree_children_complete_before_parent() {
+        let pool = Arc::new(MemPool::new());
+        let root = run_root(writes(&pool, &[0, 1, 2, 3]));
+        assert_eq!(root.error, None);
+        assert_eq!(root.children.len(), 4);
+        for (i, c) in root.children.iter().enumerate() {
+            assert_eq!(c.vd, Some(i as u64));
+        }
+        for vd in 0..4 {
+            assert_eq!(pool.read_raw(vd, 0x1000), Some(vec![vd as u8; 4]));
+        }
+    }
+
+    #[test]
+    fn tree_error_policies() {
+        let pool = Arc::new(MemPool::new());
+        pool.fail_vdev(1);
+
+        let root = run_root(writes(&pool, &[0, 1, 2]));
+        assert!(matches!(root.error, Some(SIOError::Io(_))));
+
+        let mut root = writes(&pool, &[0, 1, 2]);
+        root.child_errors = ErrorPolicy::AllFail;
+        let root = run_root(root);
+        assert_eq!(root.error, None);
+
+        pool.fail_vdev(0);
+        pool.fail_vdev(2);
+        let mut root = writes(&pool, &[0, 1, 2]);
+        root.child_errors = ErrorPolicy::AllFail;
+        assert!(run_root(root).error.is_some());
+
+        // Children may opt out.
+        let mut root = SIO::null(pool.clone());
+        let mut child = root.vdev_child(SIOType::WRITE, 1, 0, 4, vec![0; 4]);
+        child.flags |= SIOFlags::DONT_PROPAGATE;
+        root.add_child(child);
+        let root = run_root(root);
+        assert_eq!(root.error, None);
+        assert!(root.children[0].error.is_some());
+    }
+
+    #[test]
+    fn tree_completion_on_taskq() {
+        let pool = Arc::new(MemPool::new());
+        let (tx, rx) = mpsc::channel();
+        let root = writes(&pool, &[0, 1]).on_done(move |sio| {
+            let name = thread::current().name().map(String::from);
+            tx.send((name, sio.children.len())).unwrap();
+        });
+        root.nowait();
+
+        let (name, children) = rx.recv().unwrap();
+        assert!(name.unwrap().starts_with("sio_test"));
+        assert_eq!(children, 2);
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use bitflags::bitflags;
 
 bitflags! {
@@ -10,3 +12,37 @@ bitflags! {
         const COMMITTED = 5;
     }
 }
+
+/// Counters kept for a pool. They are bumped from the I/O pipeline, which may
+/// run on several threads, so each is an atomic.
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    /// Writes that kept the existing bp because the data was unchanged.
+    pub nopwrite_hits: AtomicU64,
+    /// Nopwrite candidates whose data had changed, and so were written.
+    pub nopwrite_misses: AtomicU64,
+    /// Physical bytes not written thanks to nopwrite.
+    pub nopwrite_bytes: AtomicU64,
+}
+
+/// A point-in-time copy of `PoolStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStatsSnapshot {
+    pub nopwrite_hits: u64,
+    pub nopwrite_misses: u64,
+    pub nopwrite_bytes: u64,
+}
+
+impl PoolStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> PoolStatsSnapshot {
+        PoolStatsSnapshot {
+            nopwrite_hits: self.nopwrite_hits.load(Ordering::Relaxed),
+            nopwrite_misses: self.nopwrite_misses.load(Ordering::Relaxed),
+            nopwrite_bytes: self.nopwrite_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
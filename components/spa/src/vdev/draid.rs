@@ -0,0 +1,752 @@
+//! dRAID vdevs.
+//!
+//! A dRAID vdev splits each child into rows of `row_height` bytes. In every
+//! row the children are shuffled by one of a fixed set of permutations
+//! drawn from `seed`: the first children of the shuffle make up redundancy
+//! groups of `ndata + nparity` columns each, and the last `nspares` hold
+//! spare space. Since the shuffle changes from row to row, data, parity
+//! and spare space are spread over every child.
+//!
+//! A block always fills whole stripes of its group, padded with zeros, so
+//! every column of a group lines up at the same child offsets. That is
+//! what lets a failed child be rebuilt sequentially: row by row, its
+//! columns are reconstructed from the rest of their group and written to
+//! a distributed spare, which lives on a different child in each row, so
+//! every child shares the work.
+//!
+//! Groups must fill the non-spare children exactly.
+
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, RwLock};
+
+use sys::{P2Ext, XorShift64};
+
+use super::raidz::{all_children, open_children, Column, Row, RAIDZ_MAX_CHILDREN};
+use super::{check_io, Child, Vdev, VdevError, VdevStatsSnapshot};
+use crate::SpaAsync;
+
+/// The permutations rows cycle through.
+const DRAID_NPERMS: usize = 64;
+
+/// How much of a row a rebuild works on at once.
+const REBUILD_CHUNK: u64 = 1 << 17;
+
+/// Locks that writes to a row take shared and a rebuild of it exclusive,
+/// picked by row number.
+const DRAID_ROW_LOCKS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DraidConfig {
+    pub ndata: usize,
+    pub nparity: usize,
+    pub nspares: usize,
+    /// Picks the permutations. Must stay the same for the life of the vdev.
+    pub seed: u64,
+    /// The bytes of each child one permutation covers.
+    pub row_height: u64,
+}
+
+impl DraidConfig {
+    pub fn new(ndata: usize, nparity: usize, nspares: usize) -> Self {
+        DraidConfig {
+            ndata,
+            nparity,
+            nspares,
+            seed: 0x5eed_d4a1d,
+            row_height: 16 << 20,
+        }
+    }
+
+    fn group_width(&self) -> usize {
+        self.ndata + self.nparity
+    }
+}
+
+/// A failed child and the distributed spare taking its place.
+#[derive(Debug, Clone, Copy)]
+struct Replacement {
+    failed: usize,
+    spare: usize,
+    /// Rows rebuilt so far; the spare is read only for those.
+    rows: u64,
+    done: bool,
+}
+
+/// What a rebuild did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RebuildStats {
+    /// Rows in which the failed child held a column.
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+pub struct DraidVdev {
+    config: DraidConfig,
+    children: Vec<Child>,
+    perms: Vec<Vec<usize>>,
+    replacements: Mutex<Vec<Replacement>>,
+    /// Keeps writes from landing between a rebuild's read of a stripe and
+    /// its write of the rebuilt column, which would then be stale.
+    row_locks: Vec<RwLock<()>>,
+    /// Work to hand to the pool, such as noting a finished rebuild.
+    pending_async: Mutex<SpaAsync>,
+    ashift: u32,
+    nrows: u64,
+}
+
+/// `DRAID_NPERMS` shuffles of `0..n`, from `seed`.
+fn permutations(n: usize, seed: u64) -> Vec<Vec<usize>> {
+    let mut rng = XorShift64::new(seed);
+    (0..DRAID_NPERMS)
+        .map(|_| {
+            let mut perm: Vec<usize> = (0..n).collect();
+            for i in (1..n).rev() {
+                perm.swap(i, rng.below(i + 1));
+            }
+            perm
+        })
+        .collect()
+}
+
+impl DraidVdev {
+    pub fn new(config: DraidConfig, children: Vec<Box<dyn Vdev>>) -> Self {
+        DraidVdev {
+            config,
+            perms: permutations(children.len(), config.seed),
+            children: children.into_iter().map(Child::new).collect(),
+            replacements: Mutex::new(Vec::new()),
+            row_locks: (0..DRAID_ROW_LOCKS).map(|_| RwLock::new(())).collect(),
+            pending_async: Mutex::new(SpaAsync::empty()),
+            ashift: 0,
+            nrows: 0,
+        }
+    }
+
+    pub fn config(&self) -> DraidConfig {
+        self.config
+    }
+
+    pub fn children(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn child(&self, i: usize) -> &dyn Vdev {
+        self.children[i].vdev.as_ref()
+    }
+
+    pub fn child_stats(&self, i: usize) -> VdevStatsSnapshot {
+        self.children[i].stats.snapshot()
+    }
+
+    /// Children holding groups, as opposed to spare space, in each row.
+    fn ndisks(&self) -> usize {
+        self.children.len() - self.config.nspares
+    }
+
+    fn groups_per_row(&self) -> u64 {
+        (self.ndisks() / self.config.group_width()) as u64
+    }
+
+    /// The space one group takes in one row, parity included.
+    fn group_size(&self) -> u64 {
+        self.config.row_height * self.config.group_width() as u64
+    }
+
+    fn row_lock(&self, offset: u64) -> &RwLock<()> {
+        let row = offset / self.group_size() / self.groups_per_row();
+        &self.row_locks[(row % DRAID_ROW_LOCKS as u64) as usize]
+    }
+
+    /// The shuffle of the children in `row`.
+    pub fn permutation(&self, row: u64) -> &[usize] {
+        &self.perms[(row % DRAID_NPERMS as u64) as usize]
+    }
+
+    /// The child holding distributed spare `spare` in `row`.
+    pub fn spare_child(&self, row: u64, spare: usize) -> usize {
+        self.permutation(row)[self.ndisks() + spare]
+    }
+
+    /// The space a block of `psize` bytes takes up: whole stripes of its
+    /// group, parity included.
+    pub fn psize_to_asize(&self, psize: u64) -> u64 {
+        let sectors = ((psize.max(1) - 1) >> self.ashift) + 1;
+        let stripes = sectors.p2roundup(self.config.ndata as u64) / self.config.ndata as u64;
+        (stripes * self.config.group_width() as u64) << self.ashift
+    }
+
+    /// Where column `col` of group `group` in `row` lives: the child it was
+    /// laid out on, or the spare that replaced it. The flag is false if
+    /// that spare has not been rebuilt that far yet.
+    fn locate(
+        &self,
+        replacements: &[Replacement],
+        row: u64,
+        group: u64,
+        col: usize,
+    ) -> (usize, bool) {
+        let logical = group as usize * self.config.group_width() + col;
+        let child = self.permutation(row)[logical];
+        match replacements.iter().find(|r| r.failed == child) {
+            Some(r) => (self.spare_child(row, r.spare), row < r.rows),
+            None => (child, true),
+        }
+    }
+
+    /// Lays out `size` bytes at `offset`. Reads leave the columns of
+    /// spares not yet rebuilt marked missing.
+    fn map(&self, offset: u64, size: u64, read: bool) -> Result<Row, VdevError> {
+        let (gw, ashift) = (self.config.group_width() as u64, self.ashift);
+        let g = offset / self.group_size();
+        let (row, group) = (g / self.groups_per_row(), g % self.groups_per_row());
+        let b = (offset % self.group_size()) >> ashift;
+        let stripes = (self.psize_to_asize(size) >> ashift) / gw;
+        if !b.is_multiple_of(gw) || b / gw + stripes > self.config.row_height >> ashift {
+            return Err(VdevError::Misaligned { offset, size });
+        }
+
+        let coff = row * self.config.row_height + ((b / gw) << ashift);
+        let replacements = self.replacements.lock().unwrap();
+        let cols = (0..gw as usize)
+            .map(|c| {
+                let (child, rebuilt) = self.locate(&replacements, row, group, c);
+                let mut col = Column::new(child, coff, stripes << ashift);
+                if read && !rebuilt {
+                    col.error = Some(VdevError::NotOpen);
+                }
+                col
+            })
+            .collect();
+        Ok(Row {
+            nparity: self.config.nparity,
+            cols,
+        })
+    }
+
+    fn check(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        if self.nrows == 0 {
+            return Err(VdevError::NotOpen);
+        }
+        check_io(offset, size, self.ashift, self.asize())
+    }
+
+    /// Rebuilds child `failed` onto distributed spare `spare`, row by row.
+    /// From then on the spare stands in for the child, and reads of each
+    /// row go to it as soon as that row is done. Once all rows are done,
+    /// `SpaAsync::BEBUILD_DONE` is raised. A rebuild that fails part way
+    /// leaves the spare in place, and calling this again with the same
+    /// child and spare resumes it.
+    pub fn rebuild(&self, failed: usize, spare: usize) -> Result<RebuildStats, VdevError> {
+        if self.nrows == 0 {
+            return Err(VdevError::NotOpen);
+        }
+        if failed >= self.children.len() || spare >= self.config.nspares {
+            return Err(VdevError::InvalidConfig("no such child or spare"));
+        }
+        let start = {
+            let mut replacements = self.replacements.lock().unwrap();
+            let r = replacements
+                .iter()
+                .find(|r| r.failed == failed || r.spare == spare);
+            match r {
+                Some(r) if r.failed == failed && r.spare == spare && !r.done => r.rows,
+                Some(_) => return Err(VdevError::InvalidConfig("child or spare already in use")),
+                None => {
+                    replacements.push(Replacement {
+                        failed,
+                        spare,
+                        rows: 0,
+                        done: false,
+                    });
+                    0
+                }
+            }
+        };
+
+        let gw = self.config.group_width();
+        let mut stats = RebuildStats::default();
+        for row in start..self.nrows {
+            let perm = self.permutation(row);
+            let logical = perm.iter().position(|&c| c == failed).unwrap();
+            if logical < self.ndisks() {
+                let (group, col) = ((logical / gw) as u64, logical % gw);
+                let g = (row * self.groups_per_row() + group) * self.group_size();
+                let mut at = 0;
+                while at < self.config.row_height {
+                    let len = REBUILD_CHUNK.min(self.config.row_height - at);
+                    let offset = g + at * gw as u64;
+                    self.rebuild_stripes(offset, len, col)?;
+                    at += len;
+                }
+                stats.rows += 1;
+                stats.bytes += self.config.row_height;
+            }
+            let mut replacements = self.replacements.lock().unwrap();
+            let r = replacements
+                .iter_mut()
+                .find(|r| r.failed == failed)
+                .unwrap();
+            r.rows = row + 1;
+            r.done = r.rows == self.nrows;
+        }
+        *self.pending_async.lock().unwrap() |= SpaAsync::BEBUILD_DONE;
+        Ok(stats)
+    }
+
+    /// Rebuilds column `col` of the stripes at `offset`, `len` bytes deep,
+    /// onto the spare now mapped in its place.
+    fn rebuild_stripes(&self, offset: u64, len: u64, col: usize) -> Result<(), VdevError> {
+        let _row = self.row_lock(offset).write().unwrap();
+        let size = len * self.config.ndata as u64;
+        let mut row = self.map(offset, size, true)?;
+        row.cols[col].error = Some(VdevError::NotOpen);
+        for c in 0..row.cols.len() {
+            row.read_col(&self.children, c);
+        }
+        let tgts: Vec<usize> = (0..row.cols.len())
+            .filter(|&c| row.cols[c].error.is_some())
+            .collect();
+        if tgts.len() > self.config.nparity {
+            let c = tgts.into_iter().find(|&c| c != col).unwrap();
+            return Err(row.cols[c].error.take().unwrap());
+        }
+        row.reconstruct(&tgts);
+
+        let target = &row.cols[col];
+        let child = &self.children[target.child];
+        if let Err(e) = child.io(|vd| vd.write(target.offset, &target.data)) {
+            child.stats.write_errors.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        child.stats.repaired_bytes.fetch_add(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Takes the async work raised so far.
+    pub fn take_async(&self) -> SpaAsync {
+        let mut pending = self.pending_async.lock().unwrap();
+        let flags = *pending;
+        *pending = SpaAsync::empty();
+        flags
+    }
+}
+
+impl Vdev for DraidVdev {
+    /// Opens the children, and succeeds unless more fail than there is
+    /// parity for. Only whole rows of the smallest child are used.
+    fn open(&mut self) -> Result<(), VdevError> {
+        let c = self.config;
+        if !(1..=3).contains(&c.nparity) || c.ndata == 0 {
+            return Err(VdevError::InvalidConfig(
+                "draid needs data and 1 to 3 parity",
+            ));
+        }
+        if self.children.len() > RAIDZ_MAX_CHILDREN {
+            return Err(VdevError::InvalidConfig("draid has too many children"));
+        }
+        let ndisks = self.children.len().saturating_sub(c.nspares);
+        if ndisks == 0 || !ndisks.is_multiple_of(c.group_width()) {
+            return Err(VdevError::InvalidConfig(
+                "draid groups must fill the children",
+            ));
+        }
+        open_children(&mut self.children, c.nparity)?;
+        let open = || self.children.iter().filter(|c| c.open);
+        let ashift = open().map(|c| c.vdev.ashift()).max().unwrap();
+        let child_asize = open().map(|c| c.vdev.asize()).min().unwrap();
+        if !c.row_height.is_p2aligned(1 << ashift) || c.row_height == 0 {
+            self.close();
+            return Err(VdevError::InvalidConfig(
+                "draid row height is not in sectors",
+            ));
+        }
+        if child_asize < c.row_height {
+            self.close();
+            return Err(VdevError::TooSmall(child_asize));
+        }
+        self.ashift = ashift;
+        self.nrows = child_asize / c.row_height;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        for c in self.children.iter_mut() {
+            c.vdev.close();
+            c.open = false;
+        }
+        self.nrows = 0;
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), VdevError> {
+        self.read_verified(offset, buf, &|_| true)
+    }
+
+    fn read_verified(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+        verify: &dyn Fn(&[u8]) -> bool,
+    ) -> Result<(), VdevError> {
+        self.check(offset, buf.len() as u64)?;
+        self.map(offset, buf.len() as u64, true)?
+            .read(&self.children, buf, verify)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), VdevError> {
+        self.check(offset, data.len() as u64)?;
+        let _row = self.row_lock(offset).read().unwrap();
+        let mut row = self.map(offset, data.len() as u64, false)?;
+        row.fill(data);
+        row.generate_parity();
+        row.write(&self.children)
+    }
+
+    fn flush(&self) -> Result<(), VdevError> {
+        all_children(&self.children, self.config.nparity, |vd| vd.flush())
+    }
+
+    /// Trims the whole stripes the range covers, in each group it touches.
+    fn trim(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        self.check(offset, size)?;
+        let gw = self.config.group_width() as u64;
+        let stripe = gw << self.ashift;
+        let (mut at, end) = (offset.p2roundup(stripe), offset + size);
+        let mut errors = Vec::new();
+        while at + stripe <= end {
+            let group_end = (at / self.group_size() + 1) * self.group_size();
+            let len = (group_end.min(end) - at).p2align(stripe);
+            let _row = self.row_lock(at).read().unwrap();
+            let row = self.map(at, len / gw * self.config.ndata as u64, false)?;
+            for col in &row.cols {
+                let child = &self.children[col.child];
+                let len = col.data.len() as u64;
+                if let Err(e) = child.io(|vd| vd.trim(col.offset, len)) {
+                    errors.push(e);
+                }
+            }
+            at += len.max(stripe);
+        }
+        if errors.len() > self.config.nparity {
+            return Err(errors.swap_remove(0));
+        }
+        Ok(())
+    }
+
+    fn ashift(&self) -> u32 {
+        self.ashift
+    }
+
+    /// Every row gives each group its share; spare space is not counted.
+    fn asize(&self) -> u64 {
+        self.nrows * self.groups_per_row() * self.group_size()
+    }
+
+    fn physical_sector_size(&self) -> u64 {
+        let open = self.children.iter().filter(|c| c.open);
+        open.map(|c| c.vdev.physical_sector_size())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::vdev::RamVdev;
+
+    /// A `RamVdev` that runs `hook` before each read, failing the read if
+    /// it does.
+    struct Hooked {
+        ram: RamVdev,
+        hook: Box<dyn Fn() -> Result<(), VdevError> + Send + Sync>,
+    }
+
+    impl Hooked {
+        fn boxed(
+            hook: impl Fn() -> Result<(), VdevError> + Send + Sync + 'static,
+        ) -> Box<dyn Vdev> {
+            Box::new(Hooked {
+                ram: RamVdev::new(256 << 10, 9),
+                hook: Box::new(hook),
+            })
+        }
+    }
+
+    impl Vdev for Hooked {
+        fn open(&mut self) -> Result<(), VdevError> {
+            self.ram.open()
+        }
+
+        fn close(&mut self) {
+            self.ram.close()
+        }
+
+        fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), VdevError> {
+            (self.hook)()?;
+            self.ram.read(offset, buf)
+        }
+
+        fn write(&self, offset: u64, data: &[u8]) -> Result<(), VdevError> {
+            self.ram.write(offset, data)
+        }
+
+        fn flush(&self) -> Result<(), VdevError> {
+            self.ram.flush()
+        }
+
+        fn trim(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+            self.ram.trim(offset, size)
+        }
+
+        fn ashift(&self) -> u32 {
+            self.ram.ashift()
+        }
+
+        fn asize(&self) -> u64 {
+            self.ram.asize()
+        }
+
+        fn physical_sector_size(&self) -> u64 {
+            self.ram.physical_sector_size()
+        }
+    }
+
+    /// 4 data, 1 parity and 1 spare over 11 children: two groups a row,
+    /// and rows of 16K.
+    fn draid() -> DraidVdev {
+        draid_with(|_| None)
+    }
+
+    /// `draid()`, with child `i` replaced by `child(i)` where it gives one.
+    fn draid_with(child: impl Fn(usize) -> Option<Box<dyn Vdev>>) -> DraidVdev {
+        let children = (0..11)
+            .map(|i| child(i).unwrap_or_else(|| Box::new(RamVdev::new(256 << 10, 9))))
+            .collect();
+        let config = DraidConfig {
+            row_height: 16 << 10,
+            ..DraidConfig::new(4, 1, 1)
+        };
+        let mut vd = DraidVdev::new(config, children);
+        vd.open().unwrap();
+        vd
+    }
+
+    fn block(size: usize, seed: u8) -> Vec<u8> {
+        (0..size).map(|i| (i * 13 + seed as usize) as u8).collect()
+    }
+
+    /// Writes blocks of 1 to 12 sectors all over the vdev, returning where.
+    fn fill(vd: &DraidVdev) -> Vec<(u64, Vec<u8>)> {
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        for i in 0.. {
+            let data = block(((i % 12) + 1) << 9, i as u8);
+            let asize = vd.psize_to_asize(data.len() as u64);
+            let group_end = (offset / vd.group_size() + 1) * vd.group_size();
+            if offset + asize > group_end {
+                offset = group_end;
+            }
+            if offset + asize > vd.asize() {
+                break;
+            }
+            vd.write(offset, &data).unwrap();
+            blocks.push((offset, data));
+            offset += asize;
+        }
+        blocks
+    }
+
+    fn check_blocks(vd: &DraidVdev, blocks: &[(u64, Vec<u8>)]) {
+        for (offset, data) in blocks {
+            let mut buf = vec![0; data.len()];
+            vd.read(*offset, &mut buf).unwrap();
+            assert_eq!(&buf, data, "block at {:#x}", offset);
+        }
+    }
+
+    #[test]
+    fn draid_layout() {
+        let vd = draid();
+        assert_eq!(vd.asize(), 16 * 2 * (16 << 10) * 5);
+        assert_eq!(vd.psize_to_asize(1 << 9), 5 << 9);
+        assert_eq!(vd.psize_to_asize(4 << 9), 5 << 9);
+        assert_eq!(vd.psize_to_asize(5 << 9), 10 << 9);
+
+        let mut spares = Vec::new();
+        for row in 0..16 {
+            let mut perm = vd.permutation(row).to_vec();
+            spares.push(vd.spare_child(row, 0));
+            perm.sort_unstable();
+            assert_eq!(perm, (0..11).collect::<Vec<_>>());
+        }
+        spares.sort_unstable();
+        spares.dedup();
+        assert!(spares.len() > 5, "spare space is spread: {:?}", spares);
+
+        // The second group of row 1 starts two groups in.
+        let row = vd
+            .map(3 * vd.group_size() + (5 << 9), 4 << 9, false)
+            .unwrap();
+        let perm = vd.permutation(1);
+        for (c, col) in row.cols.iter().enumerate() {
+            assert_eq!(col.child, perm[5 + c]);
+            assert_eq!(col.offset, (16 << 10) + 512);
+            assert_eq!(col.data.len(), 512);
+        }
+        assert!(vd.map(1 << 9, 1 << 9, false).is_err());
+        assert!(vd.map(vd.group_size() - (5 << 9), 8 << 9, false).is_err());
+    }
+
+    #[test]
+    fn draid_round_trip_and_degraded_reads() {
+        let vd = draid();
+        let blocks = fill(&vd);
+        assert!(blocks.len() > 100);
+        check_blocks(&vd, &blocks);
+
+        // A child returning garbage is found out by verification.
+        vd.child(7).write(0, &vec![0xee; 256 << 10]).unwrap();
+        for (offset, data) in &blocks {
+            let mut buf = vec![0; data.len()];
+            let verify = |b: &[u8]| b == data.as_slice();
+            vd.read_verified(*offset, &mut buf, &verify).unwrap();
+        }
+        assert!(vd.child_stats(7).repaired_bytes > 0);
+        check_blocks(&vd, &blocks);
+
+        let mut config = DraidConfig::new(4, 1, 2);
+        let children = (0..11)
+            .map(|_| Box::new(RamVdev::new(256 << 10, 9)) as Box<dyn Vdev>)
+            .collect();
+        config.row_height = 16 << 10;
+        let mut vd = DraidVdev::new(config, children);
+        assert!(matches!(vd.open(), Err(VdevError::InvalidConfig(_))));
+        let children = (0..RAIDZ_MAX_CHILDREN + 1)
+            .map(|_| Box::new(RamVdev::new(4096, 9)) as Box<dyn Vdev>)
+            .collect();
+        let mut vd = DraidVdev::new(DraidConfig::new(253, 2, 1), children);
+        assert!(matches!(vd.open(), Err(VdevError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn draid_rebuilds_onto_distributed_spare() {
+        let vd = draid();
+        let blocks = fill(&vd);
+        // Child 3 dies, its contents gone.
+        vd.child(3).write(0, &vec![0; 256 << 10]).unwrap();
+        let before: Vec<u64> = (0..11).map(|c| vd.child_stats(c).repaired_bytes).collect();
+
+        let stats = vd.rebuild(3, 0).unwrap();
+        let rows = (0..16).filter(|&r| vd.spare_child(r, 0) != 3).count() as u64;
+        assert_eq!(stats.rows, rows);
+        assert_eq!(stats.bytes, rows * (16 << 10));
+        assert_eq!(vd.take_async(), SpaAsync::BEBUILD_DONE);
+        assert_eq!(vd.take_async(), SpaAsync::empty());
+
+        // The rebuild wrote to many children, never the failed one.
+        let helped = (0..11)
+            .filter(|&c| vd.child_stats(c).repaired_bytes > before[c])
+            .count();
+        assert!(helped > 5);
+        assert_eq!(vd.child_stats(3).repaired_bytes, before[3]);
+
+        // Plain reads see the data without touching child 3.
+        let reads = vd.child_stats(3).reads;
+        check_blocks(&vd, &blocks);
+        assert_eq!(vd.child_stats(3).reads, reads);
+
+        let data = block(4 << 9, 99);
+        vd.write(0, &data).unwrap();
+        let mut buf = vec![0; data.len()];
+        vd.read(0, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        assert!(vd.rebuild(3, 0).is_err());
+    }
+
+    #[test]
+    fn draid_rebuild_holds_off_writes() {
+        // Rebuild data column 1 of the first group of row 0, and stall it
+        // reading column 2.
+        let perm = &permutations(11, DraidConfig::new(4, 1, 1).seed)[0];
+        let (failed, stalled) = (perm[1], perm[2]);
+        let armed = Arc::new(AtomicBool::new(false));
+        let (entered, entered_rx) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel::<()>();
+        let hook = {
+            let (armed, entered) = (armed.clone(), Mutex::new(entered));
+            let release_rx = Mutex::new(release_rx);
+            move || {
+                if armed.swap(false, Ordering::Relaxed) {
+                    entered.lock().unwrap().send(()).unwrap();
+                    release_rx.lock().unwrap().recv().unwrap();
+                }
+                Ok(())
+            }
+        };
+        let hook = Mutex::new(Some(hook));
+        let vd = draid_with(|i| {
+            (i == stalled).then(|| Hooked::boxed(hook.lock().unwrap().take().unwrap()))
+        });
+        let blocks = fill(&vd);
+        assert_eq!(blocks[0].0, 0);
+        vd.child(failed).write(0, &vec![0; 256 << 10]).unwrap();
+
+        // A write to the row must wait until the rebuild is done with it,
+        // or the rebuild would put back what it overwrote.
+        let data = block(blocks[0].1.len(), 0xff);
+        armed.store(true, Ordering::Relaxed);
+        thread::scope(|s| {
+            let rebuild = s.spawn(|| vd.rebuild(failed, 0));
+            entered_rx.recv().unwrap();
+            let write = s.spawn(|| vd.write(0, &data));
+            thread::sleep(Duration::from_millis(50));
+            let raced = write.is_finished();
+            release.send(()).unwrap();
+            write.join().unwrap().unwrap();
+            rebuild.join().unwrap().unwrap();
+            assert!(!raced);
+        });
+        let mut buf = vec![0; data.len()];
+        vd.read(0, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        check_blocks(&vd, &blocks[1..]);
+    }
+
+    #[test]
+    fn draid_resumes_failed_rebuild() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let vd = draid_with(|i| {
+            let failing = failing.clone();
+            (i == 5).then(|| {
+                Hooked::boxed(move || {
+                    if failing.load(Ordering::Relaxed) {
+                        return Err(VdevError::Io(io::ErrorKind::Other));
+                    }
+                    Ok(())
+                })
+            })
+        });
+        let blocks = fill(&vd);
+        vd.child(3).write(0, &vec![0; 256 << 10]).unwrap();
+
+        // With child 5 failing too, rows where it shares a group with
+        // child 3 cannot be rebuilt.
+        failing.store(true, Ordering::Relaxed);
+        assert!(matches!(vd.rebuild(3, 0), Err(VdevError::Io(_))));
+        assert!(matches!(vd.rebuild(3, 0), Err(VdevError::Io(_))));
+        assert!(vd.take_async().is_empty());
+
+        failing.store(false, Ordering::Relaxed);
+        let stats = vd.rebuild(3, 0).unwrap();
+        assert!(stats.rows > 0);
+        assert_eq!(vd.take_async(), SpaAsync::BEBUILD_DONE);
+        check_blocks(&vd, &blocks);
+        assert!(matches!(vd.rebuild(3, 0), Err(VdevError::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,237 @@
+//! Leaf vdevs backed by a plain file or a block device.
+
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use sys::P2Ext;
+
+use super::{check_io, choose_ashift, Vdev, VdevError};
+use crate::blkptr::SPA_MINBLOCKSIZE;
+
+/// `_IO(0x12, 119)`, discards a byte range of a block device.
+const BLKDISCARD: u64 = 0x1277;
+
+struct Opened {
+    file: File,
+    block: bool,
+    ashift: u32,
+    asize: u64,
+    psector: u64,
+}
+
+/// A file or block device, read and written with pread and pwrite.
+pub struct FileVdev {
+    path: PathBuf,
+    /// The ashift asked for; without one it follows the physical sector size.
+    ashift: Option<u32>,
+    opened: Option<Opened>,
+}
+
+impl FileVdev {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileVdev {
+            path: path.into(),
+            ashift: None,
+            opened: None,
+        }
+    }
+
+    pub fn with_ashift(path: impl Into<PathBuf>, ashift: u32) -> Self {
+        FileVdev {
+            ashift: Some(ashift),
+            ..FileVdev::new(path)
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn opened(&self) -> Result<&Opened, VdevError> {
+        self.opened.as_ref().ok_or(VdevError::NotOpen)
+    }
+
+    fn check(&self, offset: u64, size: u64) -> Result<&Opened, VdevError> {
+        let o = self.opened()?;
+        check_io(offset, size, o.ashift, o.asize)?;
+        Ok(o)
+    }
+}
+
+/// The physical sector size of a block device, as the kernel reports it.
+/// Partitions take it from the disk they are on.
+fn block_psector(rdev: u64) -> u64 {
+    let dev = format!("/sys/dev/block/{}:{}", libc::major(rdev), libc::minor(rdev));
+    ["queue", "../queue"]
+        .iter()
+        .find_map(|q| fs::read_to_string(format!("{}/{}/physical_block_size", dev, q)).ok())
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(SPA_MINBLOCKSIZE)
+}
+
+impl Vdev for FileVdev {
+    fn open(&mut self) -> Result<(), VdevError> {
+        let mut file = File::options().read(true).write(true).open(&self.path)?;
+        let meta = file.metadata()?;
+        let block = meta.file_type().is_block_device();
+        let (size, psector) = if block {
+            (file.seek(SeekFrom::End(0))?, block_psector(meta.rdev()))
+        } else {
+            (meta.len(), SPA_MINBLOCKSIZE)
+        };
+        let ashift = choose_ashift(self.ashift, psector)?;
+        let asize = size.p2align(1 << ashift);
+        if asize == 0 {
+            return Err(VdevError::TooSmall(size));
+        }
+        self.opened = Some(Opened {
+            file,
+            block,
+            ashift,
+            asize,
+            psector,
+        });
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.opened = None;
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), VdevError> {
+        let o = self.check(offset, buf.len() as u64)?;
+        Ok(o.file.read_exact_at(buf, offset)?)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), VdevError> {
+        let o = self.check(offset, data.len() as u64)?;
+        Ok(o.file.write_all_at(data, offset)?)
+    }
+
+    fn flush(&self) -> Result<(), VdevError> {
+        Ok(self.opened()?.file.sync_data()?)
+    }
+
+    fn trim(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        let o = self.check(offset, size)?;
+        let fd = o.file.as_raw_fd();
+        let ret = if o.block {
+            let range = [offset, size];
+            unsafe { libc::ioctl(fd, BLKDISCARD as _, range.as_ptr()) }
+        } else {
+            let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+            unsafe { libc::fallocate(fd, mode, offset as i64, size as i64) }
+        };
+        if ret != 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                return Err(VdevError::NotSupported("trim"));
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn ashift(&self) -> u32 {
+        self.opened.as_ref().map_or(0, |o| o.ashift)
+    }
+
+    fn asize(&self) -> u64 {
+        self.opened.as_ref().map_or(0, |o| o.asize)
+    }
+
+    fn physical_sector_size(&self) -> u64 {
+        self.opened.as_ref().map_or(0, |o| o.psector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    /// A sparse file of `size` bytes, removed on drop.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, size: u64) -> Self {
+            let path = env::temp_dir().join(format!("stone-{}-{}", process::id(), name));
+            File::create(&path).unwrap().set_len(size).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn file_vdev_io() {
+        let tmp = TempFile::new("file-io", (1 << 20) + 100);
+        let mut vd = FileVdev::new(&tmp.0);
+        assert_eq!(vd.read(0, &mut [0; 512]), Err(VdevError::NotOpen));
+
+        vd.open().unwrap();
+        assert_eq!(vd.ashift(), 9);
+        assert_eq!(vd.physical_sector_size(), 512);
+        assert_eq!(vd.asize(), 1 << 20);
+
+        let data: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        vd.write(0x1000, &data).unwrap();
+        vd.flush().unwrap();
+        let mut buf = vec![0; 4096];
+        vd.read(0x1000, &mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        vd.trim(0x1000, 0x800).unwrap();
+        vd.read(0x1000, &mut buf).unwrap();
+        assert_eq!(buf[..0x800], [0; 0x800]);
+        assert_eq!(buf[0x800..], data[0x800..]);
+
+        vd.close();
+        assert_eq!(vd.flush(), Err(VdevError::NotOpen));
+        vd.open().unwrap();
+        vd.read(0x1800, &mut buf[..0x800]).unwrap();
+        assert_eq!(buf[..0x800], data[0x800..]);
+    }
+
+    #[test]
+    fn file_vdev_alignment() {
+        let tmp = TempFile::new("file-align", 1 << 20);
+        let mut vd = FileVdev::with_ashift(&tmp.0, 12);
+        vd.open().unwrap();
+        assert_eq!(vd.ashift(), 12);
+
+        let misaligned = [(0x200, 0x1000), (0x1000, 0x200)];
+        for (offset, size) in misaligned {
+            let err = vd.write(offset, &vec![0; size as usize]).unwrap_err();
+            assert_eq!(err, VdevError::Misaligned { offset, size });
+        }
+        assert_eq!(
+            vd.read(0xff000, &mut [0; 0x2000]),
+            Err(VdevError::OutOfRange {
+                offset: 0xff000,
+                size: 0x2000,
+                asize: 1 << 20
+            })
+        );
+        assert!(vd.trim(0, 0x1800).is_err());
+
+        for bad in [8, 17] {
+            let mut vd = FileVdev::with_ashift(&tmp.0, bad);
+            assert_eq!(vd.open(), Err(VdevError::InvalidAshift(bad)));
+        }
+        let small = TempFile::new("file-small", 0x800);
+        let mut vd = FileVdev::with_ashift(&small.0, 12);
+        assert_eq!(vd.open(), Err(VdevError::TooSmall(0x800)));
+        let mut vd = FileVdev::new(tmp.0.with_extension("missing"));
+        assert_eq!(vd.open(), Err(VdevError::Io(io::ErrorKind::NotFound)));
+    }
+}
@@ -0,0 +1,308 @@
+//! Mirror vdevs.
+//!
+//! Every child of a mirror holds the same data. Writes go to all of them
+//! and succeed if any child takes them. A read goes to the child with the
+//! fewest I/Os outstanding; if that child fails, or what it returns fails
+//! verification, the others are tried in turn, and once a good copy turns
+//! up it is written back over the bad ones. Each child keeps `VdevStats`,
+//! so the errors and repairs can be traced to the disk they came from.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sys::P2Ext;
+
+use super::{check_io, Child, Vdev, VdevError, VdevStatsSnapshot};
+
+pub struct MirrorVdev {
+    children: Vec<Child>,
+    /// Where ties between equally loaded children start being broken.
+    rotor: AtomicUsize,
+    ashift: u32,
+    asize: u64,
+}
+
+impl MirrorVdev {
+    pub fn new(children: Vec<Box<dyn Vdev>>) -> Self {
+        MirrorVdev {
+            children: children.into_iter().map(Child::new).collect(),
+            rotor: AtomicUsize::new(0),
+            ashift: 0,
+            asize: 0,
+        }
+    }
+
+    pub fn children(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn child(&self, i: usize) -> &dyn Vdev {
+        self.children[i].vdev.as_ref()
+    }
+
+    pub fn child_stats(&self, i: usize) -> VdevStatsSnapshot {
+        self.children[i].stats.snapshot()
+    }
+
+    fn open_children(&self) -> impl Iterator<Item = &Child> {
+        self.children.iter().filter(|c| c.open)
+    }
+
+    fn check(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        if self.asize == 0 {
+            return Err(VdevError::NotOpen);
+        }
+        check_io(offset, size, self.ashift, self.asize)
+    }
+
+    /// The open children, least loaded first. Ties go round robin.
+    fn read_order(&self) -> Vec<usize> {
+        let n = self.children.len();
+        let start = self.rotor.fetch_add(1, Ordering::Relaxed) % n.max(1);
+        let mut order: Vec<usize> = (0..n).filter(|&i| self.children[i].open).collect();
+        order.sort_by_key(|&i| {
+            let pending = self.children[i].pending.load(Ordering::Relaxed);
+            (pending, (i + n - start) % n)
+        });
+        order
+    }
+
+    /// Runs `io` on every open child, succeeding if any child does. Writes
+    /// are counted in the children's stats.
+    fn all(
+        &self,
+        write: bool,
+        io: impl Fn(&dyn Vdev) -> Result<(), VdevError>,
+    ) -> Result<(), VdevError> {
+        let mut ok = false;
+        let mut error = VdevError::NotOpen;
+        for c in self.open_children() {
+            let ret = c.io(&io);
+            if write {
+                let counter = match ret {
+                    Ok(()) => &c.stats.writes,
+                    Err(_) => &c.stats.write_errors,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            match ret {
+                Ok(()) => ok = true,
+                Err(e) => error = e,
+            }
+        }
+        if !ok {
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Writes the good copy in `buf` over the children in `bad`.
+    fn repair(&self, bad: &[usize], offset: u64, buf: &[u8]) {
+        for &i in bad {
+            let c = &self.children[i];
+            if c.io(|vd| vd.write(offset, buf)).is_ok() {
+                let repaired = buf.len() as u64;
+                c.stats
+                    .repaired_bytes
+                    .fetch_add(repaired, Ordering::Relaxed);
+            } else {
+                c.stats.write_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Vdev for MirrorVdev {
+    /// Opens the children, and succeeds if any of them does. The mirror
+    /// uses the largest ashift among them and the smallest asize.
+    fn open(&mut self) -> Result<(), VdevError> {
+        let mut error = VdevError::NotOpen;
+        for c in self.children.iter_mut() {
+            match c.vdev.open() {
+                Ok(()) => c.open = true,
+                Err(e) => error = e,
+            }
+        }
+        let ashift = self.open_children().map(|c| c.vdev.ashift()).max();
+        let ashift = match ashift {
+            Some(a) => a,
+            None => return Err(error),
+        };
+        let asize = self.open_children().map(|c| c.vdev.asize()).min();
+        self.ashift = ashift;
+        self.asize = asize.unwrap_or(0).p2align(1 << ashift);
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        for c in self.children.iter_mut() {
+            c.vdev.close();
+            c.open = false;
+        }
+        self.asize = 0;
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), VdevError> {
+        self.read_verified(offset, buf, &|_| true)
+    }
+
+    fn read_verified(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+        verify: &dyn Fn(&[u8]) -> bool,
+    ) -> Result<(), VdevError> {
+        self.check(offset, buf.len() as u64)?;
+        let mut bad = Vec::new();
+        let mut error = VdevError::NotOpen;
+        for i in self.read_order() {
+            let c = &self.children[i];
+            c.stats.reads.fetch_add(1, Ordering::Relaxed);
+            match c.io(|vd| vd.read_verified(offset, buf, verify)) {
+                Ok(()) => {
+                    self.repair(&bad, offset, buf);
+                    return Ok(());
+                }
+                Err(e) => {
+                    let counter = match e {
+                        VdevError::Checksum => &c.stats.checksum_errors,
+                        _ => &c.stats.read_errors,
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    bad.push(i);
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), VdevError> {
+        self.check(offset, data.len() as u64)?;
+        self.all(true, |vd| vd.write(offset, data))
+    }
+
+    fn flush(&self) -> Result<(), VdevError> {
+        self.all(false, |vd| vd.flush())
+    }
+
+    fn trim(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        self.check(offset, size)?;
+        self.all(false, |vd| vd.trim(offset, size))
+    }
+
+    fn ashift(&self) -> u32 {
+        self.ashift
+    }
+
+    fn asize(&self) -> u64 {
+        self.asize
+    }
+
+    fn physical_sector_size(&self) -> u64 {
+        self.open_children()
+            .map(|c| c.vdev.physical_sector_size())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdev::RamVdev;
+
+    fn mirror(sizes: &[u64]) -> MirrorVdev {
+        let children = sizes
+            .iter()
+            .map(|&size| Box::new(RamVdev::new(size, 9)) as Box<dyn Vdev>)
+            .collect();
+        let mut m = MirrorVdev::new(children);
+        m.open().unwrap();
+        m
+    }
+
+    fn read_child(m: &MirrorVdev, i: usize, offset: u64, size: usize) -> Vec<u8> {
+        let mut buf = vec![0; size];
+        m.child(i).read(offset, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn mirror_writes_every_child() {
+        let m = mirror(&[0x10000, 0x8400, 0x20000]);
+        assert_eq!(m.asize(), 0x8400);
+        assert_eq!(m.ashift(), 9);
+
+        m.write(0x400, &[7; 0x400]).unwrap();
+        for i in 0..3 {
+            assert_eq!(read_child(&m, i, 0x400, 0x400), [7; 0x400]);
+            assert_eq!(m.child_stats(i).writes, 1);
+        }
+        assert!(m.write(0x8400, &[7; 0x200]).is_err());
+    }
+
+    #[test]
+    fn mirror_balances_reads() {
+        let m = mirror(&[0x10000, 0x10000]);
+        let mut buf = [0; 0x200];
+        for _ in 0..6 {
+            m.read(0, &mut buf).unwrap();
+        }
+        assert_eq!(m.child_stats(0).reads, 3);
+        assert_eq!(m.child_stats(1).reads, 3);
+
+        // A busy child is passed over while the other is idle.
+        m.children[0].pending.fetch_add(2, Ordering::Relaxed);
+        for _ in 0..4 {
+            m.read(0, &mut buf).unwrap();
+        }
+        assert_eq!(m.child_stats(0).reads, 3);
+        assert_eq!(m.child_stats(1).reads, 7);
+    }
+
+    #[test]
+    fn mirror_repairs_bad_copy() {
+        let m = mirror(&[0x10000, 0x10000]);
+        let good = vec![5; 0x400];
+        m.write(0x1000, &good).unwrap();
+        m.child(0).write(0x1200, &[6; 0x200]).unwrap();
+
+        let verify = |b: &[u8]| b.iter().all(|&x| x == 5);
+        let mut buf = vec![0; 0x400];
+        for _ in 0..2 {
+            m.read_verified(0x1000, &mut buf, &verify).unwrap();
+            assert_eq!(buf, good);
+        }
+        assert_eq!(read_child(&m, 0, 0x1000, 0x400), good);
+        let s = m.child_stats(0);
+        assert_eq!((s.checksum_errors, s.repaired_bytes), (1, 0x400));
+        assert_eq!(m.child_stats(1).checksum_errors, 0);
+        assert_eq!(m.child_stats(1).repaired_bytes, 0);
+
+        // With no good copy left there is nothing to repair from.
+        m.child(1).write(0x1000, &[6; 0x200]).unwrap();
+        m.child(0).write(0x1000, &[6; 0x200]).unwrap();
+        let err = m.read_verified(0x1000, &mut buf, &verify);
+        assert_eq!(err, Err(VdevError::Checksum));
+        assert_eq!(m.child_stats(0).repaired_bytes, 0x400);
+    }
+
+    #[test]
+    fn mirror_runs_degraded() {
+        let children: Vec<Box<dyn Vdev>> = vec![
+            Box::new(RamVdev::new(0x10000, 9)),
+            Box::new(RamVdev::new(0x10000, 8)),
+        ];
+        let mut m = MirrorVdev::new(children);
+        m.open().unwrap();
+        m.write(0, &[1; 0x200]).unwrap();
+        let mut buf = [0; 0x200];
+        m.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [1; 0x200]);
+        assert_eq!(m.child_stats(1), VdevStatsSnapshot::default());
+
+        let mut m = MirrorVdev::new(vec![Box::new(RamVdev::new(0x10000, 8))]);
+        assert_eq!(m.open(), Err(VdevError::InvalidAshift(8)));
+        assert_eq!(m.read(0, &mut buf), Err(VdevError::NotOpen));
+    }
+}
@@ -0,0 +1,251 @@
+//! Virtual devices.
+//!
+//! A vdev stores bytes at offsets. Leaves sit on real media or memory;
+//! later kinds combine children for redundancy. All I/O to a vdev is in
+//! whole sectors of `1 << ashift` bytes, and must fall within its `asize`.
+
+pub mod draid;
+pub mod file;
+pub mod mirror;
+pub mod raidz;
+pub mod ram;
+
+pub use draid::*;
+pub use file::*;
+pub use mirror::*;
+pub use raidz::*;
+pub use ram::*;
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sys::P2Ext;
+
+use crate::blkptr::{ASHIFT_MAX, ASHIFT_MIN};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VdevError {
+    Io(io::ErrorKind),
+    /// The vdev has not been opened, or has been closed.
+    NotOpen,
+    /// The ashift is outside `ASHIFT_MIN..=ASHIFT_MAX`.
+    InvalidAshift(u32),
+    /// The device has no room for a single sector.
+    TooSmall(u64),
+    /// An I/O is not in whole sectors.
+    Misaligned {
+        offset: u64,
+        size: u64,
+    },
+    /// An I/O runs past the end of the vdev.
+    OutOfRange {
+        offset: u64,
+        size: u64,
+        asize: u64,
+    },
+    /// Valid, but not something the vdev can do.
+    NotSupported(&'static str),
+    /// What was read failed verification, on every copy there was.
+    Checksum,
+    /// The vdev is not set up in a way it can work.
+    InvalidConfig(&'static str),
+}
+
+impl fmt::Display for VdevError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VdevError::Io(kind) => write!(f, "i/o error: {}", io::Error::from(*kind)),
+            VdevError::NotOpen => write!(f, "vdev is not open"),
+            VdevError::InvalidAshift(a) => write!(f, "invalid ashift {}", a),
+            VdevError::TooSmall(size) => write!(f, "device of {} bytes is too small", size),
+            VdevError::Misaligned { offset, size } => {
+                write!(
+                    f,
+                    "i/o at {:#x} size {:#x} is not sector aligned",
+                    offset, size
+                )
+            }
+            VdevError::OutOfRange {
+                offset,
+                size,
+                asize,
+            } => write!(
+                f,
+                "i/o at {:#x} size {:#x} is past the end of the vdev (asize {:#x})",
+                offset, size, asize
+            ),
+            VdevError::NotSupported(what) => write!(f, "{} not supported", what),
+            VdevError::Checksum => write!(f, "checksum mismatch"),
+            VdevError::InvalidConfig(why) => write!(f, "invalid vdev config: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for VdevError {}
+
+impl From<io::Error> for VdevError {
+    fn from(e: io::Error) -> Self {
+        VdevError::Io(e.kind())
+    }
+}
+
+impl From<VdevError> for io::Error {
+    fn from(e: VdevError) -> Self {
+        match e {
+            VdevError::Io(kind) => kind.into(),
+            VdevError::NotOpen => io::ErrorKind::NotConnected.into(),
+            VdevError::NotSupported(_) => io::ErrorKind::Unsupported.into(),
+            VdevError::Checksum => io::Error::new(io::ErrorKind::InvalidData, e),
+            e => io::Error::new(io::ErrorKind::InvalidInput, e),
+        }
+    }
+}
+
+/// A device I/O is done against. `open` must succeed before any I/O, and
+/// `ashift`, `asize` and `physical_sector_size` are only meaningful while
+/// the vdev is open.
+pub trait Vdev: Send + Sync {
+    fn open(&mut self) -> Result<(), VdevError>;
+
+    fn close(&mut self);
+
+    /// Reads `buf.len()` bytes at `offset`.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), VdevError>;
+
+    /// Reads like `read`, with `verify` telling good data from bad, as a
+    /// block checksum does. Vdevs with redundancy use it to look for a good
+    /// copy and repair the bad ones; others only check what they read.
+    fn read_verified(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+        verify: &dyn Fn(&[u8]) -> bool,
+    ) -> Result<(), VdevError> {
+        self.read(offset, buf)?;
+        if !verify(buf) {
+            return Err(VdevError::Checksum);
+        }
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), VdevError>;
+
+    /// Makes the writes done so far durable.
+    fn flush(&self) -> Result<(), VdevError>;
+
+    /// Tells the device the `size` bytes at `offset` are no longer in use.
+    fn trim(&self, offset: u64, size: u64) -> Result<(), VdevError>;
+
+    /// Log2 of the smallest I/O.
+    fn ashift(&self) -> u32;
+
+    /// The bytes usable for I/O, a whole number of sectors.
+    fn asize(&self) -> u64;
+
+    /// The sector size the media writes in, which may be larger than the
+    /// one it accepts.
+    fn physical_sector_size(&self) -> u64;
+}
+
+/// Counters kept for a child of a vdev with redundancy.
+#[derive(Debug, Default)]
+pub struct VdevStats {
+    pub reads: AtomicU64,
+    pub writes: AtomicU64,
+    pub read_errors: AtomicU64,
+    pub write_errors: AtomicU64,
+    /// Reads that completed but failed verification.
+    pub checksum_errors: AtomicU64,
+    /// Bytes rewritten from a good copy after a failed read.
+    pub repaired_bytes: AtomicU64,
+}
+
+/// A point-in-time copy of `VdevStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VdevStatsSnapshot {
+    pub reads: u64,
+    pub writes: u64,
+    pub read_errors: u64,
+    pub write_errors: u64,
+    pub checksum_errors: u64,
+    pub repaired_bytes: u64,
+}
+
+impl VdevStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> VdevStatsSnapshot {
+        VdevStatsSnapshot {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            read_errors: self.read_errors.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            checksum_errors: self.checksum_errors.load(Ordering::Relaxed),
+            repaired_bytes: self.repaired_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A child of a vdev with redundancy.
+pub(super) struct Child {
+    pub(super) vdev: Box<dyn Vdev>,
+    /// Set once the child has opened; the parent runs without those that
+    /// did not, as far as its redundancy allows.
+    pub(super) open: bool,
+    /// I/Os issued to the child and not yet returned.
+    pub(super) pending: AtomicU64,
+    pub(super) stats: VdevStats,
+}
+
+impl Child {
+    pub(super) fn new(vdev: Box<dyn Vdev>) -> Self {
+        Child {
+            vdev,
+            open: false,
+            pending: AtomicU64::new(0),
+            stats: VdevStats::new(),
+        }
+    }
+
+    /// Runs `io` against the child, counting it as pending meanwhile.
+    pub(super) fn io<T>(&self, io: impl FnOnce(&dyn Vdev) -> T) -> T {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        let ret = io(self.vdev.as_ref());
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        ret
+    }
+}
+
+/// Picks the ashift for a device with sectors of `psector` bytes, or
+/// checks the one asked for.
+pub fn choose_ashift(requested: Option<u32>, psector: u64) -> Result<u32, VdevError> {
+    let range = ASHIFT_MIN as u32..=ASHIFT_MAX as u32;
+    match requested {
+        Some(a) if range.contains(&a) => Ok(a),
+        Some(a) => Err(VdevError::InvalidAshift(a)),
+        None => Ok(psector
+            .max(1)
+            .trailing_zeros()
+            .clamp(*range.start(), *range.end())),
+    }
+}
+
+/// Checks that an I/O of `size` bytes at `offset` is in whole sectors and
+/// within `asize`.
+pub fn check_io(offset: u64, size: u64, ashift: u32, asize: u64) -> Result<(), VdevError> {
+    let sector = 1u64 << ashift;
+    if !offset.is_p2aligned(sector) || !size.is_p2aligned(sector) {
+        return Err(VdevError::Misaligned { offset, size });
+    }
+    match offset.checked_add(size) {
+        Some(end) if end <= asize => Ok(()),
+        _ => Err(VdevError::OutOfRange {
+            offset,
+            size,
+            asize,
+        }),
+    }
+}
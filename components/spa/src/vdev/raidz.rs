@@ -0,0 +1,761 @@
+//! RAID-Z vdevs.
+//!
+//! A block written to a RAID-Z vdev is split into columns, one per child,
+//! with `nparity` parity columns in front of the data columns. Parity is
+//! computed over GF(2^8): P is the XOR of the data columns, and Q and R
+//! weight data column `d` of `n` by `2^(n-1-d)` and `4^(n-1-d)`. With
+//! those, any `nparity` columns can be rebuilt from the rest.
+//!
+//! Normal reads touch only the data columns. If a child fails, or the
+//! block fails verification, the parity is read too. Columns that could
+//! not be read are rebuilt first; if the block still does not verify,
+//! combinations of the other columns are assumed bad in turn, up to what
+//! the parity can cover, until one gives a block that verifies. The
+//! columns that turned out bad are then rewritten.
+
+use std::sync::atomic::Ordering;
+
+use sys::P2Ext;
+
+use super::{check_io, Child, Vdev, VdevError, VdevStatsSnapshot};
+
+/// The most children a RAID-Z or dRAID vdev may have. With more data
+/// columns the parity coefficients repeat, and lost columns could no longer
+/// be told apart.
+pub const RAIDZ_MAX_CHILDREN: usize = 255;
+
+const fn gf_tables() -> ([u8; 256], [u8; 256]) {
+    let mut exp = [0u8; 256];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+        i += 1;
+    }
+    exp[255] = exp[0];
+    (exp, log)
+}
+
+const GF_EXP: [u8; 256] = gf_tables().0;
+const GF_LOG: [u8; 256] = gf_tables().1;
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    GF_EXP[(GF_LOG[a as usize] as usize + GF_LOG[b as usize] as usize) % 255]
+}
+
+fn gf_inv(a: u8) -> u8 {
+    GF_EXP[(255 - GF_LOG[a as usize] as usize) % 255]
+}
+
+/// The weight of data column `d` of `ndata` in parity column `p`.
+fn coef(p: usize, d: usize, ndata: usize) -> u8 {
+    GF_EXP[(p * (ndata - 1 - d)) % 255]
+}
+
+/// `dst ^= c * src`, over the bytes they have in common.
+fn mul_add(dst: &mut [u8], c: u8, src: &[u8]) {
+    for (x, &b) in dst.iter_mut().zip(src) {
+        *x ^= gf_mul(c, b);
+    }
+}
+
+/// Inverts a square matrix over GF(2^8).
+fn gf_invert(mut a: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let k = a.len();
+    let mut inv: Vec<Vec<u8>> = (0..k)
+        .map(|r| (0..k).map(|c| (r == c) as u8).collect())
+        .collect();
+    for col in 0..k {
+        let pivot = (col..k)
+            .find(|&r| a[r][col] != 0)
+            .expect("parity matrix is singular");
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let f = gf_inv(a[col][col]);
+        for x in 0..k {
+            a[col][x] = gf_mul(a[col][x], f);
+            inv[col][x] = gf_mul(inv[col][x], f);
+        }
+        for r in 0..k {
+            let f = a[r][col];
+            if r == col || f == 0 {
+                continue;
+            }
+            for x in 0..k {
+                a[r][x] ^= gf_mul(f, a[col][x]);
+                inv[r][x] ^= gf_mul(f, inv[col][x]);
+            }
+        }
+    }
+    inv
+}
+
+/// The `k`-element subsets of `items`, in order.
+fn combinations(items: &[usize], k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    let mut out = Vec::new();
+    for (i, &first) in items.iter().enumerate() {
+        for mut rest in combinations(&items[i + 1..], k - 1) {
+            rest.insert(0, first);
+            out.push(rest);
+        }
+    }
+    out
+}
+
+/// Part of a block on one child.
+#[derive(Debug, Clone)]
+pub(super) struct Column {
+    pub(super) child: usize,
+    pub(super) offset: u64,
+    pub(super) data: Vec<u8>,
+    /// The error reading the column failed with, or why it could not be
+    /// read at all.
+    pub(super) error: Option<VdevError>,
+}
+
+impl Column {
+    pub(super) fn new(child: usize, offset: u64, size: u64) -> Self {
+        Column {
+            child,
+            offset,
+            data: vec![0; size as usize],
+            error: None,
+        }
+    }
+}
+
+/// The columns of a block, parity first. Data columns may be shorter than
+/// the parity ones, and count as zero-padded.
+#[derive(Debug, Clone)]
+pub(super) struct Row {
+    pub(super) nparity: usize,
+    pub(super) cols: Vec<Column>,
+}
+
+impl Row {
+    fn ndata(&self) -> usize {
+        self.cols.len() - self.nparity
+    }
+
+    /// Splits `data` across the data columns, in order.
+    pub(super) fn fill(&mut self, mut data: &[u8]) {
+        for c in &mut self.cols[self.nparity..] {
+            let n = c.data.len().min(data.len());
+            c.data[..n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+    }
+
+    /// Puts the data columns back together into `buf`.
+    pub(super) fn assemble(&self, buf: &mut [u8]) {
+        let mut at = 0;
+        for c in &self.cols[self.nparity..] {
+            let n = c.data.len().min(buf.len() - at);
+            buf[at..at + n].copy_from_slice(&c.data[..n]);
+            at += n;
+        }
+    }
+
+    fn parity(&self, p: usize) -> Vec<u8> {
+        let ndata = self.ndata();
+        let mut out = vec![0; self.cols[p].data.len()];
+        for d in 0..ndata {
+            mul_add(
+                &mut out,
+                coef(p, d, ndata),
+                &self.cols[self.nparity + d].data,
+            );
+        }
+        out
+    }
+
+    pub(super) fn generate_parity(&mut self) {
+        for p in 0..self.nparity {
+            self.cols[p].data = self.parity(p);
+        }
+    }
+
+    /// Rebuilds the columns in `tgts` from the others. There must be no
+    /// more of them than parity columns.
+    pub(super) fn reconstruct(&mut self, tgts: &[usize]) {
+        let (np, ndata) = (self.nparity, self.ndata());
+        let missing: Vec<usize> = tgts.iter().filter(|&&c| c >= np).map(|c| c - np).collect();
+        let rows: Vec<usize> = (0..np)
+            .filter(|p| !tgts.contains(p))
+            .take(missing.len())
+            .collect();
+        assert_eq!(rows.len(), missing.len(), "too many columns to rebuild");
+
+        if !missing.is_empty() {
+            // What each parity column holds beyond the surviving data.
+            let syndromes: Vec<Vec<u8>> = rows
+                .iter()
+                .map(|&p| {
+                    let mut s = self.cols[p].data.clone();
+                    for d in (0..ndata).filter(|d| !missing.contains(d)) {
+                        mul_add(&mut s, coef(p, d, ndata), &self.cols[np + d].data);
+                    }
+                    s
+                })
+                .collect();
+            let matrix = rows
+                .iter()
+                .map(|&p| missing.iter().map(|&d| coef(p, d, ndata)).collect())
+                .collect();
+            let inv = gf_invert(matrix);
+            for (m, &d) in missing.iter().enumerate() {
+                let mut out = vec![0; self.cols[np + d].data.len()];
+                for (r, s) in syndromes.iter().enumerate() {
+                    mul_add(&mut out, inv[m][r], s);
+                }
+                self.cols[np + d].data = out;
+            }
+        }
+        for &p in tgts.iter().filter(|&&c| c < np) {
+            self.cols[p].data = self.parity(p);
+        }
+    }
+
+    /// Reads column `c`, unless it is already known to be missing.
+    pub(super) fn read_col(&mut self, children: &[Child], c: usize) {
+        let col = &mut self.cols[c];
+        let child = &children[col.child];
+        if col.error.is_some() {
+            return;
+        }
+        if !child.open {
+            col.error = Some(VdevError::NotOpen);
+            return;
+        }
+        child.stats.reads.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = child.io(|vd| vd.read(col.offset, &mut col.data)) {
+            child.stats.read_errors.fetch_add(1, Ordering::Relaxed);
+            col.error = Some(e);
+        }
+    }
+
+    /// Reads the block into `buf`, rebuilding and rewriting bad columns
+    /// as described in the module docs.
+    pub(super) fn read(
+        &mut self,
+        children: &[Child],
+        buf: &mut [u8],
+        verify: &dyn Fn(&[u8]) -> bool,
+    ) -> Result<(), VdevError> {
+        let np = self.nparity;
+        for c in np..self.cols.len() {
+            self.read_col(children, c);
+        }
+        if self.cols.iter().all(|c| c.error.is_none()) {
+            self.assemble(buf);
+            if verify(buf) {
+                return Ok(());
+            }
+        }
+        for c in 0..np {
+            self.read_col(children, c);
+        }
+
+        let known: Vec<usize> = (0..self.cols.len())
+            .filter(|&c| self.cols[c].error.is_some())
+            .collect();
+        if known.len() > np {
+            return Err(self.cols[known[0]].error.clone().unwrap());
+        }
+        let others: Vec<usize> = (0..self.cols.len())
+            .filter(|c| !known.contains(c))
+            .collect();
+        for extra in 0..=np - known.len() {
+            for combo in combinations(&others, extra) {
+                // Rebuilding only parity leaves the data as it was.
+                if extra > 0 && combo.iter().all(|&c| c < np) {
+                    continue;
+                }
+                let tgts: Vec<usize> = known.iter().chain(&combo).copied().collect();
+                let mut trial = self.clone();
+                trial.reconstruct(&tgts);
+                trial.assemble(buf);
+                if !verify(buf) {
+                    continue;
+                }
+                for &c in &combo {
+                    let child = &children[self.cols[c].child];
+                    child.stats.checksum_errors.fetch_add(1, Ordering::Relaxed);
+                }
+                trial.repair(children, &tgts);
+                *self = trial;
+                return Ok(());
+            }
+        }
+        Err(VdevError::Checksum)
+    }
+
+    /// Writes the columns in `tgts` back to their children.
+    fn repair(&self, children: &[Child], tgts: &[usize]) {
+        for &c in tgts {
+            let col = &self.cols[c];
+            let child = &children[col.child];
+            if !child.open {
+                continue;
+            }
+            if child.io(|vd| vd.write(col.offset, &col.data)).is_ok() {
+                let repaired = col.data.len() as u64;
+                child
+                    .stats
+                    .repaired_bytes
+                    .fetch_add(repaired, Ordering::Relaxed);
+            } else {
+                child.stats.write_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Writes every column, succeeding unless more columns fail than there
+    /// is parity for.
+    pub(super) fn write(&self, children: &[Child]) -> Result<(), VdevError> {
+        let mut errors = Vec::new();
+        for col in &self.cols {
+            let child = &children[col.child];
+            if !child.open {
+                errors.push(VdevError::NotOpen);
+                continue;
+            }
+            let counter = match child.io(|vd| vd.write(col.offset, &col.data)) {
+                Ok(()) => &child.stats.writes,
+                Err(e) => {
+                    errors.push(e);
+                    &child.stats.write_errors
+                }
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        if errors.len() > self.nparity {
+            return Err(errors.swap_remove(0));
+        }
+        Ok(())
+    }
+}
+
+/// Opens `children`, succeeding unless more than `tolerate` fail.
+pub(super) fn open_children(children: &mut [Child], tolerate: usize) -> Result<(), VdevError> {
+    let mut errors = Vec::new();
+    for c in children.iter_mut() {
+        match c.vdev.open() {
+            Ok(()) => c.open = true,
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.len() > tolerate {
+        for c in children.iter_mut() {
+            c.vdev.close();
+            c.open = false;
+        }
+        return Err(errors.swap_remove(0));
+    }
+    Ok(())
+}
+
+/// Runs `io` on every open child, succeeding unless more than `tolerate`
+/// fail or are not open.
+pub(super) fn all_children(
+    children: &[Child],
+    tolerate: usize,
+    io: impl Fn(&dyn Vdev) -> Result<(), VdevError>,
+) -> Result<(), VdevError> {
+    let mut errors = Vec::new();
+    for c in children {
+        if !c.open {
+            errors.push(VdevError::NotOpen);
+        } else if let Err(e) = c.io(&io) {
+            errors.push(e);
+        }
+    }
+    if errors.len() > tolerate {
+        return Err(errors.swap_remove(0));
+    }
+    Ok(())
+}
+
+pub struct RaidzVdev {
+    children: Vec<Child>,
+    nparity: usize,
+    ashift: u32,
+    asize: u64,
+}
+
+impl RaidzVdev {
+    /// A RAID-Z vdev with `nparity` of 1, 2 or 3 over `children`.
+    pub fn new(nparity: usize, children: Vec<Box<dyn Vdev>>) -> Self {
+        RaidzVdev {
+            children: children.into_iter().map(Child::new).collect(),
+            nparity,
+            ashift: 0,
+            asize: 0,
+        }
+    }
+
+    pub fn nparity(&self) -> usize {
+        self.nparity
+    }
+
+    pub fn children(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn child(&self, i: usize) -> &dyn Vdev {
+        self.children[i].vdev.as_ref()
+    }
+
+    pub fn child_stats(&self, i: usize) -> VdevStatsSnapshot {
+        self.children[i].stats.snapshot()
+    }
+
+    /// The space a block of `psize` bytes takes up, parity and padding
+    /// included. Allocations are padded to a multiple of `nparity + 1`
+    /// sectors so that no free gap is too small to use.
+    pub fn psize_to_asize(&self, psize: u64) -> u64 {
+        let (cols, np) = (self.children.len() as u64, self.nparity as u64);
+        let sectors = ((psize.max(1) - 1) >> self.ashift) + 1;
+        let sectors = sectors + np * ((sectors + cols - np - 1) / (cols - np));
+        sectors.p2roundup(np + 1) << self.ashift
+    }
+
+    /// Lays out `size` bytes at `offset`. The block starts on the child
+    /// its first sector falls on, and wraps around to the next row.
+    fn map(&self, offset: u64, size: u64) -> Row {
+        let (dcols, np) = (self.children.len() as u64, self.nparity as u64);
+        let b = offset >> self.ashift;
+        let s = size >> self.ashift;
+        let (f, o) = (b % dcols, (b / dcols) << self.ashift);
+        // Every column gets q sectors, and the first bc get one more.
+        let q = s / (dcols - np);
+        let r = s - q * (dcols - np);
+        let bc = if r == 0 { 0 } else { r + np };
+        let acols = if q == 0 { bc } else { dcols };
+
+        let mut cols: Vec<Column> = (0..acols)
+            .map(|c| {
+                let (mut child, mut coff) = (f + c, o);
+                if child >= dcols {
+                    child -= dcols;
+                    coff += 1 << self.ashift;
+                }
+                let size = if c < bc { q + 1 } else { q };
+                Column::new(child as usize, coff, size << self.ashift)
+            })
+            .collect();
+        // Single parity is never read in normal operation, so it moves
+        // every 1M to spread the reads over all children.
+        if np == 1 && offset & (1 << 20) != 0 {
+            let (child, offset) = (cols[0].child, cols[0].offset);
+            cols[0].child = cols[1].child;
+            cols[0].offset = cols[1].offset;
+            cols[1].child = child;
+            cols[1].offset = offset;
+        }
+        Row {
+            nparity: self.nparity,
+            cols,
+        }
+    }
+
+    fn check(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        if self.asize == 0 {
+            return Err(VdevError::NotOpen);
+        }
+        check_io(offset, size, self.ashift, self.asize)?;
+        check_io(offset, self.psize_to_asize(size), self.ashift, self.asize)
+    }
+}
+
+impl Vdev for RaidzVdev {
+    /// Opens the children, and succeeds unless more fail than there is
+    /// parity for. Every child gives up as much space as the smallest.
+    fn open(&mut self) -> Result<(), VdevError> {
+        if !(1..=3).contains(&self.nparity) {
+            return Err(VdevError::InvalidConfig("raidz parity must be 1 to 3"));
+        }
+        if self.children.len() <= self.nparity {
+            return Err(VdevError::InvalidConfig(
+                "raidz needs more children than parity",
+            ));
+        }
+        if self.children.len() > RAIDZ_MAX_CHILDREN {
+            return Err(VdevError::InvalidConfig("raidz has too many children"));
+        }
+        open_children(&mut self.children, self.nparity)?;
+        let open = || self.children.iter().filter(|c| c.open);
+        let ashift = open().map(|c| c.vdev.ashift()).max().unwrap();
+        let child_asize = open().map(|c| c.vdev.asize()).min().unwrap();
+        self.ashift = ashift;
+        self.asize = child_asize.p2align(1 << ashift) * self.children.len() as u64;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        for c in self.children.iter_mut() {
+            c.vdev.close();
+            c.open = false;
+        }
+        self.asize = 0;
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), VdevError> {
+        self.read_verified(offset, buf, &|_| true)
+    }
+
+    fn read_verified(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+        verify: &dyn Fn(&[u8]) -> bool,
+    ) -> Result<(), VdevError> {
+        self.check(offset, buf.len() as u64)?;
+        self.map(offset, buf.len() as u64)
+            .read(&self.children, buf, verify)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), VdevError> {
+        self.check(offset, data.len() as u64)?;
+        let mut row = self.map(offset, data.len() as u64);
+        row.fill(data);
+        row.generate_parity();
+        row.write(&self.children)
+    }
+
+    fn flush(&self) -> Result<(), VdevError> {
+        all_children(&self.children, self.nparity, |vd| vd.flush())
+    }
+
+    /// Trims on each child the rows the range covers there.
+    fn trim(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        if self.asize == 0 {
+            return Err(VdevError::NotOpen);
+        }
+        check_io(offset, size, self.ashift, self.asize)?;
+        let dcols = self.children.len() as u64;
+        let (start, end) = (offset >> self.ashift, (offset + size) >> self.ashift);
+        let mut errors = Vec::new();
+        for (i, c) in self.children.iter().enumerate() {
+            // The first and last sectors of the range on this child.
+            let first = start + (i as u64 + dcols - start % dcols) % dcols;
+            if first >= end || !c.open {
+                continue;
+            }
+            let last = end - 1 - (end - 1 + dcols - i as u64) % dcols;
+            let rows = (last - first) / dcols + 1;
+            let ret = c.io(|vd| vd.trim((first / dcols) << self.ashift, rows << self.ashift));
+            if let Err(e) = ret {
+                errors.push(e);
+            }
+        }
+        if errors.len() > self.nparity {
+            return Err(errors.swap_remove(0));
+        }
+        Ok(())
+    }
+
+    fn ashift(&self) -> u32 {
+        self.ashift
+    }
+
+    fn asize(&self) -> u64 {
+        self.asize
+    }
+
+    fn physical_sector_size(&self) -> u64 {
+        let open = self.children.iter().filter(|c| c.open);
+        open.map(|c| c.vdev.physical_sector_size())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdev::RamVdev;
+
+    fn raidz(nparity: usize, n: usize) -> RaidzVdev {
+        let children = (0..n)
+            .map(|_| Box::new(RamVdev::new(1 << 20, 9)) as Box<dyn Vdev>)
+            .collect();
+        let mut vd = RaidzVdev::new(nparity, children);
+        vd.open().unwrap();
+        vd
+    }
+
+    fn block(size: usize, seed: u8) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 + seed as usize) as u8).collect()
+    }
+
+    fn layout(row: &Row) -> Vec<(usize, u64, usize)> {
+        let cols = row.cols.iter();
+        cols.map(|c| (c.child, c.offset, c.data.len())).collect()
+    }
+
+    #[test]
+    fn raidz_layout() {
+        let vd = raidz(1, 5);
+        assert_eq!(vd.asize(), 5 << 20);
+        assert_eq!(
+            layout(&vd.map(0, 7 << 9)),
+            [
+                (0, 0, 1024),
+                (1, 0, 1024),
+                (2, 0, 1024),
+                (3, 0, 1024),
+                (4, 0, 512)
+            ]
+        );
+        assert_eq!(
+            layout(&vd.map(3 << 9, 2 << 9)),
+            [(3, 0, 512), (4, 0, 512), (0, 512, 512)]
+        );
+        // Parity swaps places in odd megabytes.
+        assert_eq!(
+            layout(&vd.map(1 << 20, 1 << 9)),
+            [(4, 0x33200, 512), (3, 0x33200, 512)]
+        );
+        assert_eq!(vd.psize_to_asize(7 << 9), 10 << 9);
+        assert_eq!(vd.psize_to_asize(1 << 9), 2 << 9);
+
+        let vd = raidz(3, 7);
+        assert_eq!(vd.psize_to_asize(1 << 9), 4 << 9);
+        assert_eq!(vd.psize_to_asize(8 << 9), 16 << 9);
+    }
+
+    #[test]
+    fn raidz_parity_rebuilds_any_columns() {
+        for np in 1..=3 {
+            let mut row = Row {
+                nparity: np,
+                cols: (0..np + 5).map(|c| Column::new(c, 0, 64)).collect(),
+            };
+            row.cols.last_mut().unwrap().data.truncate(32);
+            row.fill(&block(64 * 4 + 32, np as u8));
+            row.generate_parity();
+
+            let all: Vec<usize> = (0..row.cols.len()).collect();
+            for k in 1..=np {
+                for tgts in combinations(&all, k) {
+                    let mut damaged = row.clone();
+                    for &c in &tgts {
+                        damaged.cols[c].data.fill(0xa5);
+                    }
+                    damaged.reconstruct(&tgts);
+                    for (a, b) in damaged.cols.iter().zip(&row.cols) {
+                        assert_eq!(a.data, b.data, "np={} tgts={:?}", np, tgts);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raidz_round_trip() {
+        for np in 1..=3 {
+            let vd = raidz(np, np + 4);
+            let mut offset = 0;
+            for (i, sectors) in [1, 3, 4, 9, 32].into_iter().enumerate() {
+                let data = block(sectors << 9, i as u8);
+                vd.write(offset, &data).unwrap();
+                let mut buf = vec![0; data.len()];
+                vd.read(offset, &mut buf).unwrap();
+                assert_eq!(buf, data, "np={} sectors={}", np, sectors);
+                offset += vd.psize_to_asize(data.len() as u64);
+            }
+            assert!(vd.write(1 << 9, &[0; 100]).is_err());
+            assert!(vd.write(vd.asize() - 512, &[0; 512]).is_err());
+        }
+    }
+
+    #[test]
+    fn raidz_runs_degraded() {
+        let mut children: Vec<Box<dyn Vdev>> = (0..6)
+            .map(|_| Box::new(RamVdev::new(1 << 20, 9)) as Box<dyn Vdev>)
+            .collect();
+        children[1] = Box::new(RamVdev::new(1 << 20, 8));
+        children[4] = Box::new(RamVdev::new(1 << 20, 8));
+        let mut vd = RaidzVdev::new(2, children);
+        vd.open().unwrap();
+
+        let data = block(12 << 9, 3);
+        vd.write(0x2000, &data).unwrap();
+        let mut buf = vec![0; data.len()];
+        vd.read(0x2000, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(vd.child_stats(1), VdevStatsSnapshot::default());
+        assert!(vd.child_stats(0).reads > 0);
+
+        let children: Vec<Box<dyn Vdev>> = (0..4)
+            .map(|i| Box::new(RamVdev::new(1 << 20, 8 + (i & 1))) as Box<dyn Vdev>)
+            .collect();
+        let mut vd = RaidzVdev::new(1, children);
+        assert_eq!(vd.open(), Err(VdevError::InvalidAshift(8)));
+        let mut vd = RaidzVdev::new(3, vec![Box::new(RamVdev::new(1 << 20, 9))]);
+        assert!(matches!(vd.open(), Err(VdevError::InvalidConfig(_))));
+        let children = (0..RAIDZ_MAX_CHILDREN + 1)
+            .map(|_| Box::new(RamVdev::new(4096, 9)) as Box<dyn Vdev>)
+            .collect();
+        let mut vd = RaidzVdev::new(1, children);
+        assert!(matches!(vd.open(), Err(VdevError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn raidz_finds_and_repairs_bad_columns() {
+        let vd = raidz(3, 8);
+        let data = block(20 << 9, 9);
+        vd.write(0, &data).unwrap();
+        let verify = |b: &[u8]| b == data.as_slice();
+
+        // Silently damage three children, data and parity alike.
+        for child in [0, 4, 6] {
+            vd.child(child).write(0, &[0xee; 512]).unwrap();
+        }
+        let mut buf = vec![0; data.len()];
+        vd.read_verified(0, &mut buf, &verify).unwrap();
+        assert_eq!(buf, data);
+        for child in [0, 4, 6] {
+            let s = vd.child_stats(child);
+            assert_eq!((s.checksum_errors, s.repaired_bytes), (1, 4 << 9));
+        }
+        assert_eq!(vd.child_stats(2).checksum_errors, 0);
+
+        // The repair stuck: the data columns alone now verify.
+        vd.read_verified(0, &mut buf, &verify).unwrap();
+        assert_eq!(vd.child_stats(0).reads, 1);
+
+        for child in [1, 2, 3, 5] {
+            vd.child(child).write(0, &[0xee; 512]).unwrap();
+        }
+        let err = vd.read_verified(0, &mut buf, &verify);
+        assert_eq!(err, Err(VdevError::Checksum));
+    }
+
+    #[test]
+    fn raidz_trim_covers_each_child() {
+        let vd = raidz(1, 3);
+        let data = block(9 << 9, 1);
+        vd.write(0x400, &data).unwrap();
+        vd.trim(0x400, vd.psize_to_asize(data.len() as u64))
+            .unwrap();
+        for i in 0..3 {
+            let mut buf = vec![0xff; 0x1000];
+            vd.child(i).read(0, &mut buf).unwrap();
+            assert!(buf.iter().all(|&b| b == 0), "child {}", i);
+        }
+    }
+}
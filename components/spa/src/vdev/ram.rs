@@ -0,0 +1,283 @@
+//! Leaf vdevs kept in memory, for tests.
+//!
+//! A `RamVdev` remembers which writes have not been flushed yet, so a test
+//! can cut the power and see what a real device might have kept: nothing
+//! since the last flush, or some of those writes, in any order, some of
+//! them only partly.
+
+use std::mem;
+use std::sync::Mutex;
+
+use sys::{P2Ext, XorShift64};
+
+use super::{check_io, choose_ashift, Vdev, VdevError};
+
+/// What survives a power cut among the writes not yet flushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerCut {
+    /// None of them.
+    DropAll,
+    /// Each may be lost, land whole, or be torn with only some of its
+    /// sectors landing, and they land in any order. The same seed gives the
+    /// same outcome.
+    Torn { seed: u64 },
+}
+
+/// The contents of a `RamVdev` at some point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamSnapshot(Vec<u8>);
+
+impl RamSnapshot {
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+struct Unflushed {
+    offset: u64,
+    /// What the write replaced.
+    old: Vec<u8>,
+    new: Vec<u8>,
+}
+
+struct RamState {
+    data: Vec<u8>,
+    unflushed: Vec<Unflushed>,
+}
+
+pub struct RamVdev {
+    ashift: u32,
+    open: bool,
+    state: Mutex<RamState>,
+}
+
+impl RamVdev {
+    /// A zeroed device of `size` bytes doing I/O in sectors of `1 << ashift`.
+    pub fn new(size: u64, ashift: u32) -> Self {
+        RamVdev {
+            ashift,
+            open: false,
+            state: Mutex::new(RamState {
+                data: vec![0; size as usize],
+                unflushed: Vec::new(),
+            }),
+        }
+    }
+
+    fn check(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        if !self.open {
+            return Err(VdevError::NotOpen);
+        }
+        check_io(offset, size, self.ashift, self.asize())
+    }
+
+    /// The contents as a read would see them now, flushed or not.
+    pub fn snapshot(&self) -> RamSnapshot {
+        RamSnapshot(self.state.lock().unwrap().data.clone())
+    }
+
+    /// Puts back the contents of `snap`, as if flushed.
+    pub fn restore(&self, snap: &RamSnapshot) {
+        let mut state = self.state.lock().unwrap();
+        assert_eq!(state.data.len(), snap.0.len(), "snapshot of another size");
+        state.data.copy_from_slice(&snap.0);
+        state.unflushed.clear();
+    }
+
+    /// How many writes have not been flushed.
+    pub fn unflushed(&self) -> usize {
+        self.state.lock().unwrap().unflushed.len()
+    }
+
+    /// Loses the unflushed writes `how` says, and keeps the rest as if they
+    /// had been flushed. The device stays open.
+    pub fn power_cut(&self, how: PowerCut) {
+        let mut state = self.state.lock().unwrap();
+        let mut writes = mem::take(&mut state.unflushed);
+        for w in writes.iter().rev() {
+            let at = w.offset as usize;
+            state.data[at..at + w.old.len()].copy_from_slice(&w.old);
+        }
+        let seed = match how {
+            PowerCut::DropAll => return,
+            PowerCut::Torn { seed } => seed,
+        };
+
+        let mut rng = XorShift64::new(seed);
+        for i in (1..writes.len()).rev() {
+            let j = rng.below(i + 1);
+            writes.swap(i, j);
+        }
+        let sector = 1usize << self.ashift;
+        for w in writes {
+            let torn = match rng.below(3) {
+                0 => continue,
+                1 => false,
+                _ => true,
+            };
+            for (i, chunk) in w.new.chunks(sector).enumerate() {
+                if torn && rng.below(2) == 0 {
+                    continue;
+                }
+                let at = w.offset as usize + i * sector;
+                state.data[at..at + chunk.len()].copy_from_slice(chunk);
+            }
+        }
+    }
+
+    fn store(&self, offset: u64, new: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        let at = offset as usize;
+        let old = state.data[at..at + new.len()].to_vec();
+        state.data[at..at + new.len()].copy_from_slice(&new);
+        state.unflushed.push(Unflushed { offset, old, new });
+    }
+}
+
+impl Vdev for RamVdev {
+    fn open(&mut self) -> Result<(), VdevError> {
+        choose_ashift(Some(self.ashift), 0)?;
+        let size = self.state.lock().unwrap().data.len() as u64;
+        if self.asize() == 0 {
+            return Err(VdevError::TooSmall(size));
+        }
+        self.open = true;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.open = false;
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), VdevError> {
+        self.check(offset, buf.len() as u64)?;
+        let at = offset as usize;
+        buf.copy_from_slice(&self.state.lock().unwrap().data[at..at + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), VdevError> {
+        self.check(offset, data.len() as u64)?;
+        self.store(offset, data.to_vec());
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), VdevError> {
+        if !self.open {
+            return Err(VdevError::NotOpen);
+        }
+        self.state.lock().unwrap().unflushed.clear();
+        Ok(())
+    }
+
+    /// Trimmed space reads back as zeros, and like a write is only sure to
+    /// stay that way once flushed.
+    fn trim(&self, offset: u64, size: u64) -> Result<(), VdevError> {
+        self.check(offset, size)?;
+        self.store(offset, vec![0; size as usize]);
+        Ok(())
+    }
+
+    fn ashift(&self) -> u32 {
+        self.ashift
+    }
+
+    fn asize(&self) -> u64 {
+        let size = self.state.lock().unwrap().data.len() as u64;
+        size.p2align(1 << self.ashift)
+    }
+
+    fn physical_sector_size(&self) -> u64 {
+        1 << self.ashift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ram(size: u64, ashift: u32) -> RamVdev {
+        let mut vd = RamVdev::new(size, ashift);
+        vd.open().unwrap();
+        vd
+    }
+
+    fn sectors(vd: &RamVdev, offset: u64, n: usize) -> Vec<u8> {
+        let mut buf = vec![0; n << vd.ashift()];
+        vd.read(offset, &mut buf).unwrap();
+        buf.chunks(1 << vd.ashift()).map(|s| s[0]).collect()
+    }
+
+    #[test]
+    fn ram_vdev_io_and_snapshots() {
+        let mut vd = RamVdev::new(0x10100, 12);
+        assert_eq!(vd.write(0, &[1; 0x1000]), Err(VdevError::NotOpen));
+        vd.open().unwrap();
+        assert_eq!(vd.asize(), 0x10000);
+        assert_eq!(vd.physical_sector_size(), 0x1000);
+
+        vd.write(0x1000, &[1; 0x2000]).unwrap();
+        let snap = vd.snapshot();
+        vd.write(0x2000, &[2; 0x1000]).unwrap();
+        vd.trim(0x1000, 0x1000).unwrap();
+        assert_eq!(sectors(&vd, 0, 4), [0, 0, 2, 0]);
+        assert!(vd.write(0x800, &[0; 0x1000]).is_err());
+        assert!(vd.read(0x10000, &mut [0; 0x1000]).is_err());
+
+        vd.restore(&snap);
+        assert_eq!(sectors(&vd, 0, 4), [0, 1, 1, 0]);
+        assert_eq!(snap.bytes()[0x1000..0x3000], [1; 0x2000]);
+        assert_eq!(vd.unflushed(), 0);
+
+        let mut bad = RamVdev::new(0x1000, 8);
+        assert_eq!(bad.open(), Err(VdevError::InvalidAshift(8)));
+        let mut small = RamVdev::new(0x800, 12);
+        assert_eq!(small.open(), Err(VdevError::TooSmall(0x800)));
+    }
+
+    #[test]
+    fn ram_vdev_power_cut_drops_unflushed() {
+        let vd = ram(0x10000, 9);
+        vd.write(0, &[1; 0x400]).unwrap();
+        vd.flush().unwrap();
+        vd.write(0x200, &[2; 0x400]).unwrap();
+        vd.write(0, &[3; 0x200]).unwrap();
+        assert_eq!(vd.unflushed(), 2);
+        assert_eq!(sectors(&vd, 0, 3), [3, 2, 2]);
+
+        vd.power_cut(PowerCut::DropAll);
+        assert_eq!(sectors(&vd, 0, 3), [1, 1, 0]);
+        assert_eq!(vd.unflushed(), 0);
+    }
+
+    #[test]
+    fn ram_vdev_power_cut_tears_writes() {
+        let cut = |seed| {
+            let vd = ram(0x10000, 9);
+            vd.write(0, &[9; 0x800]).unwrap();
+            vd.flush().unwrap();
+            vd.write(0, &[1; 0x800]).unwrap();
+            vd.write(0x400, &[2; 0x800]).unwrap();
+            vd.power_cut(PowerCut::Torn { seed });
+            sectors(&vd, 0, 6)
+        };
+
+        assert_eq!(cut(7), cut(7));
+        let outcomes: Vec<Vec<u8>> = (0..64).map(cut).collect();
+        for s in &outcomes {
+            // Each sector holds what was flushed or one of the writes to it.
+            assert!(s[..2].iter().all(|b| [9, 1].contains(b)));
+            assert!(s[2..4].iter().all(|b| [9, 1, 2].contains(b)));
+            assert!(s[4..].iter().all(|b| [0, 2].contains(b)));
+        }
+        // Some writes are lost, some torn, and the overlap lands either way.
+        assert!(outcomes.contains(&vec![9, 9, 9, 9, 0, 0]));
+        assert!(outcomes
+            .iter()
+            .any(|s| s[..4].contains(&9) && s[..4].contains(&1)));
+        assert!(outcomes
+            .iter()
+            .any(|s| s[2..4] == [1, 1] && s[4..] == [2, 2]));
+        assert!(outcomes.iter().any(|s| s[2..4] == [2, 2]));
+    }
+}
@@ -1,8 +1,10 @@
 pub mod p2;
 pub mod bitops;
+pub mod rand;
 
 pub use p2::*;
 pub use bitops::*;
+pub use rand::*;
 
 
 
@@ -0,0 +1,23 @@
+/// The xorshift64 generator: cheap and reproducible from its seed, for
+/// shuffles and fault injection. Not for anything that must be unpredictable.
+#[derive(Debug, Clone)]
+pub struct XorShift64(u64);
+
+impl XorShift64 {
+    /// Zero is a fixed point of xorshift, so the low bit of `seed` is forced.
+    pub fn new(seed: u64) -> Self {
+        XorShift64(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A value in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
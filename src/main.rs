@@ -1,3 +1,91 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+use spa::sio::{parse_handlers, FaultHandler};
+
+const USAGE: &str = "usage: stone inject [-f <file>] <handler>
+       stone inject [-f <file>] -l
+       stone inject [-f <file>] -c <n>|all
+
+    Adds a fault handler, lists them, or clears handler <n> or all of
+    them. Handlers live in <file>, one per line, which pools follow with
+    an InjectWatcher. It defaults to $STONE_INJECT_FILE, or
+    /run/stone/inject.
+
+    handler fault=<fault> <target> [type=<type>] [freq=<percent>]
+    fault   io, checksum:<bits>, delay:<ms> or gone
+    target  vdev=<id>, bookmark=<objset>:<object>:<level>:<blkid>
+            or range=<vdev>:<start>-<end>
+    type    read, write, flush or all (default)
+    freq    percent of matching I/Os to fail (default 100)";
+
+const DEFAULT_INJECT_FILE: &str = "/run/stone/inject";
+
 fn main() {
-    println!("Hello, world!");
-}
\ No newline at end of file
+    let args: Vec<String> = env::args().skip(1).collect();
+    let res = match args.first().map(String::as_str) {
+        Some("inject") => inject(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(msg) = res {
+        eprintln!("{}", msg);
+        process::exit(2);
+    }
+}
+
+fn inject(mut args: &[String]) -> Result<(), String> {
+    let usage = |msg: &dyn std::fmt::Display| format!("stone inject: {}\n\n{}", msg, USAGE);
+    let path = match args {
+        [flag, file, rest @ ..] if flag == "-f" => {
+            args = rest;
+            PathBuf::from(file)
+        }
+        _ => env::var_os("STONE_INJECT_FILE")
+            .map_or_else(|| PathBuf::from(DEFAULT_INJECT_FILE), PathBuf::from),
+    };
+    let fail = |e: &dyn std::fmt::Display| format!("stone inject: {}: {}", path.display(), e);
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(fail(&e)),
+    };
+    let mut handlers = parse_handlers(&text).map_err(|e| fail(&e))?;
+
+    match args {
+        [] => return Err(USAGE.to_string()),
+        [flag] if flag == "-l" => {
+            for (i, h) in handlers.iter().enumerate() {
+                println!("{:4}  {}", i + 1, h);
+            }
+            return Ok(());
+        }
+        [flag, which] if flag == "-c" => {
+            if which == "all" {
+                handlers.clear();
+            } else {
+                let n: usize = which
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=handlers.len()).contains(n))
+                    .ok_or_else(|| usage(&format!("no handler '{}'", which)))?;
+                handlers.remove(n - 1);
+            }
+        }
+        _ => {
+            let handler: FaultHandler = args.join(" ").parse().map_err(|e| usage(&e))?;
+            handlers.push(handler);
+            println!("{:4}  {}", handlers.len(), handler);
+        }
+    }
+
+    // Replace the file whole, so a watcher never reads it half written.
+    let text: String = handlers.iter().map(|h| format!("{}\n", h)).collect();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, text)
+        .and_then(|()| fs::rename(&tmp, &path))
+        .map_err(|e| fail(&e))
+}